@@ -16,6 +16,22 @@ pub fn check_deadline(deadline: i64) -> Result<()> {
     Ok(())
 }
 
+/// Checks whether the current slot has not crossed the deadline
+///
+/// A deadline of 0 is never crossed.
+///
+/// # Arguments
+///
+/// * `deadline` - The last slot at which the transaction can execute, specified by a user
+///
+pub fn check_slot_deadline(deadline: u64) -> Result<()> {
+    require!(
+        deadline == 0 || Clock::get()?.slot <= deadline,
+        ErrorCode::TransactionTooOld
+    );
+    Ok(())
+}
+
 /// Ensures that the signer is the owner or a delgated authority for the position NFT
 ///
 /// # Arguments
@@ -3,12 +3,14 @@ use crate::program::CyclosCore;
 use crate::states::factory::FactoryState;
 use crate::states::fee::{FeeState, FEE_SEED};
 use crate::states::oracle::{ObservationState, OBSERVATION_SEED};
-use crate::states::pool::{PoolState, POOL_SEED};
+use crate::states::owner_liquidity::{OwnerLiquidityState, OWNER_LIQUIDITY_SEED};
+use crate::states::pool::{sort_tokens, PoolState, POOL_SEED};
 use crate::states::position::{PositionState, POSITION_SEED};
-use crate::states::tick::{TickState, TICK_SEED};
+use crate::states::tick::{tick_seed, TickState, TICK_SEED};
 use crate::states::tick_bitmap::{TickBitmapState, BITMAP_SEED};
 use crate::states::tokenized_position::TokenizedPositionState;
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::program_option::COption;
 use anchor_spl::associated_token::get_associated_token_address;
 use anchor_spl::associated_token::AssociatedToken;
 use anchor_spl::token::{Mint, Token, TokenAccount};
@@ -82,13 +84,17 @@ pub struct CreateAndInitPool<'info> {
     pub pool_creator: Signer<'info>,
 
     /// Desired token pair for the pool
-    /// token_0 mint address should be smaller than token_1 address
+    /// token_0 mint address should be smaller than token_1 address. Identical tokens
+    /// are also rejected, since they are reported as swapped
     #[account(
-        constraint = token_0.key() < token_1.key()
+        constraint = !sort_tokens(token_0.key(), token_1.key()).2
     )]
     pub token_0: Box<Account<'info, Mint>>,
     pub token_1: Box<Account<'info, Mint>>,
 
+    /// Stores the protocol fee copied to the pool
+    pub factory_state: AccountLoader<'info, FactoryState>,
+
     /// Stores the desired fee for the pool
     pub fee_state: AccountLoader<'info, FeeState>,
 
@@ -154,6 +160,107 @@ pub struct SetFeeProtocol<'info> {
     pub factory_state: AccountLoader<'info, FactoryState>,
 }
 
+#[derive(Accounts)]
+pub struct SetPoolFeeProtocol<'info> {
+    /// Valid protocol owner
+    #[account(address = factory_state.load()?.owner)]
+    pub owner: Signer<'info>,
+
+    /// Factory state stores the protocol owner address
+    pub factory_state: AccountLoader<'info, FactoryState>,
+
+    /// The pool for which the protocol fee is set
+    #[account(mut)]
+    pub pool_state: AccountLoader<'info, PoolState>,
+}
+
+#[derive(Accounts)]
+pub struct SetMaxLiquidityPerOwner<'info> {
+    /// Valid protocol owner
+    #[account(address = factory_state.load()?.owner)]
+    pub owner: Signer<'info>,
+
+    /// Factory state stores the protocol owner address
+    pub factory_state: AccountLoader<'info, FactoryState>,
+
+    /// The pool for which the limit is set
+    #[account(mut)]
+    pub pool_state: AccountLoader<'info, PoolState>,
+}
+
+#[derive(Accounts)]
+pub struct SetMinTickWidth<'info> {
+    /// Valid protocol owner
+    #[account(address = factory_state.load()?.owner)]
+    pub owner: Signer<'info>,
+
+    /// Factory state stores the protocol owner address
+    pub factory_state: AccountLoader<'info, FactoryState>,
+
+    /// The pool for which the minimum width is set
+    #[account(mut)]
+    pub pool_state: AccountLoader<'info, PoolState>,
+}
+
+#[derive(Accounts)]
+pub struct SetMinHoldingSlots<'info> {
+    /// Valid protocol owner
+    #[account(address = factory_state.load()?.owner)]
+    pub owner: Signer<'info>,
+
+    /// Factory state stores the protocol owner address
+    pub factory_state: AccountLoader<'info, FactoryState>,
+
+    /// The pool for which the holding period is set
+    #[account(mut)]
+    pub pool_state: AccountLoader<'info, PoolState>,
+}
+
+#[derive(Accounts)]
+pub struct SetMaxReferralFee<'info> {
+    /// Valid protocol owner
+    #[account(address = factory_state.load()?.owner)]
+    pub owner: Signer<'info>,
+
+    /// Factory state stores the protocol owner address
+    pub factory_state: AccountLoader<'info, FactoryState>,
+
+    /// The pool for which the maximum referral fee is set
+    #[account(mut)]
+    pub pool_state: AccountLoader<'info, PoolState>,
+}
+
+#[derive(Accounts)]
+pub struct SetQuoteToken<'info> {
+    /// Valid protocol owner
+    #[account(address = factory_state.load()?.owner)]
+    pub owner: Signer<'info>,
+
+    /// Factory state stores the protocol owner address
+    pub factory_state: AccountLoader<'info, FactoryState>,
+
+    /// The pool for which the quote token is set
+    #[account(mut)]
+    pub pool_state: AccountLoader<'info, PoolState>,
+}
+
+#[derive(Accounts)]
+pub struct SetRewardMint<'info> {
+    /// Valid protocol owner
+    #[account(address = factory_state.load()?.owner)]
+    pub owner: Signer<'info>,
+
+    /// Factory state stores the protocol owner address
+    pub factory_state: AccountLoader<'info, FactoryState>,
+
+    /// The pool distributing the rewards
+    #[account(mut)]
+    pub pool_state: AccountLoader<'info, PoolState>,
+
+    /// The reward token
+    pub reward_mint: Account<'info, Mint>,
+}
+
 #[derive(Accounts)]
 pub struct CollectProtocol<'info> {
     /// Valid protocol owner
@@ -194,6 +301,47 @@ pub struct CollectProtocol<'info> {
     pub token_program: Program<'info, Token>,
 }
 
+#[derive(Accounts)]
+pub struct ProtocolFees<'info> {
+    /// Pool state stores accumulated protocol fee amount
+    pub pool_state: AccountLoader<'info, PoolState>,
+}
+
+#[derive(Accounts)]
+pub struct TotalFees<'info> {
+    /// Pool state stores the lifetime swap fee totals
+    pub pool_state: AccountLoader<'info, PoolState>,
+}
+
+#[derive(Accounts)]
+#[instruction(mint: Pubkey)]
+pub struct RecoverToken<'info> {
+    /// Valid protocol owner
+    #[account(address = factory_state.load()?.owner)]
+    pub owner: Signer<'info>,
+
+    /// Factory state stores the protocol owner address
+    pub factory_state: AccountLoader<'info, FactoryState>,
+
+    /// The pool holding the stuck tokens
+    pub pool_state: AccountLoader<'info, PoolState>,
+
+    /// The pool owned account holding the stuck tokens
+    #[account(
+        mut,
+        constraint = token_account.owner == pool_state.key(),
+        constraint = token_account.mint == mint,
+    )]
+    pub token_account: Box<Account<'info, TokenAccount>>,
+
+    /// The address that receives the recovered tokens
+    #[account(mut)]
+    pub recipient: Box<Account<'info, TokenAccount>>,
+
+    /// The SPL program to perform token transfers
+    pub token_program: Program<'info, Token>,
+}
+
 #[derive(Accounts)]
 #[instruction(tick: i32)]
 pub struct InitTickAccount<'info> {
@@ -212,7 +360,7 @@ pub struct InitTickAccount<'info> {
             pool_state.load()?.token_0.as_ref(),
             pool_state.load()?.token_1.as_ref(),
             &pool_state.load()?.fee.to_be_bytes(),
-            &tick.to_be_bytes()
+            &tick_seed(tick)
         ],
         bump,
         payer = signer,
@@ -301,8 +449,8 @@ pub struct InitPositionAccount<'info> {
             pool_state.load()?.token_1.as_ref(),
             &pool_state.load()?.fee.to_be_bytes(),
             recipient.key().as_ref(),
-            &tick_lower_state.load()?.tick.to_be_bytes(),
-            &tick_upper_state.load()?.tick.to_be_bytes(),
+            &tick_seed(tick_lower_state.load()?.tick),
+            &tick_seed(tick_upper_state.load()?.tick),
         ],
         bump,
         payer = signer,
@@ -314,6 +462,158 @@ pub struct InitPositionAccount<'info> {
     pub system_program: Program<'info, System>,
 }
 
+#[derive(Accounts)]
+pub struct InitOwnerLiquidityAccount<'info> {
+    /// Pays to create the owner's liquidity account
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    /// The owner whose liquidity is tracked
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    pub owner: UncheckedAccount<'info>,
+
+    /// Create the owner's liquidity account for this pool
+    pub pool_state: AccountLoader<'info, PoolState>,
+
+    /// The owner's liquidity account to be initialized
+    #[account(
+        init,
+        seeds = [
+            OWNER_LIQUIDITY_SEED.as_bytes(),
+            pool_state.load()?.token_0.as_ref(),
+            pool_state.load()?.token_1.as_ref(),
+            &pool_state.load()?.fee.to_be_bytes(),
+            owner.key().as_ref(),
+        ],
+        bump,
+        payer = signer,
+        space = 8 + size_of::<OwnerLiquidityState>()
+    )]
+    pub owner_liquidity_state: AccountLoader<'info, OwnerLiquidityState>,
+
+    /// Program to initialize the owner's liquidity account
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct TransferPosition<'info> {
+    /// The current position owner, who pays to create the new position account and
+    /// receives the rent of the old one
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    /// The address of the new position owner
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    pub new_owner: UncheckedAccount<'info>,
+
+    /// The pool of the position
+    pub pool_state: AccountLoader<'info, PoolState>,
+
+    /// The lower tick boundary of the position
+    pub tick_lower_state: AccountLoader<'info, TickState>,
+
+    /// The upper tick boundary of the position
+    #[account(
+        constraint = tick_lower_state.load()?.tick < tick_upper_state.load()?.tick @ErrorCode::TLU
+    )]
+    pub tick_upper_state: AccountLoader<'info, TickState>,
+
+    /// The position account of the current owner, closed by the transfer
+    #[account(
+        mut,
+        seeds = [
+            POSITION_SEED.as_bytes(),
+            pool_state.load()?.token_0.as_ref(),
+            pool_state.load()?.token_1.as_ref(),
+            &pool_state.load()?.fee.to_be_bytes(),
+            owner.key().as_ref(),
+            &tick_seed(tick_lower_state.load()?.tick),
+            &tick_seed(tick_upper_state.load()?.tick),
+        ],
+        bump = PositionState::load_current(&position_state)?.bump,
+        close = owner
+    )]
+    pub position_state: AccountLoader<'info, PositionState>,
+
+    /// The position account of the new owner, to be initialized
+    #[account(
+        init,
+        seeds = [
+            POSITION_SEED.as_bytes(),
+            pool_state.load()?.token_0.as_ref(),
+            pool_state.load()?.token_1.as_ref(),
+            &pool_state.load()?.fee.to_be_bytes(),
+            new_owner.key().as_ref(),
+            &tick_seed(tick_lower_state.load()?.tick),
+            &tick_seed(tick_upper_state.load()?.tick),
+        ],
+        bump,
+        payer = owner,
+        space = 8 + size_of::<PositionState>()
+    )]
+    pub new_position_state: AccountLoader<'info, PositionState>,
+
+    /// The liquidity of the current owner in the pool
+    #[account(
+        mut,
+        seeds = [
+            OWNER_LIQUIDITY_SEED.as_bytes(),
+            pool_state.load()?.token_0.as_ref(),
+            pool_state.load()?.token_1.as_ref(),
+            &pool_state.load()?.fee.to_be_bytes(),
+            owner.key().as_ref(),
+        ],
+        bump = owner_liquidity_state.load()?.bump,
+    )]
+    pub owner_liquidity_state: AccountLoader<'info, OwnerLiquidityState>,
+
+    /// The liquidity of the new owner in the pool, checked against the pool's limit
+    #[account(
+        mut,
+        seeds = [
+            OWNER_LIQUIDITY_SEED.as_bytes(),
+            pool_state.load()?.token_0.as_ref(),
+            pool_state.load()?.token_1.as_ref(),
+            &pool_state.load()?.fee.to_be_bytes(),
+            new_owner.key().as_ref(),
+        ],
+        bump = new_owner_liquidity_state.load()?.bump,
+    )]
+    pub new_owner_liquidity_state: AccountLoader<'info, OwnerLiquidityState>,
+
+    /// Program to initialize the new position account
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigratePosition<'info> {
+    /// Pays rent for the bytes added to the position account
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// The position account to upgrade. It is checked to be a position account after
+    /// being resized to the current layout, since older layouts cannot be deserialized.
+    /// CHECK: Owner is validated here, discriminator in the instruction
+    #[account(mut, owner = crate::ID)]
+    pub position_state: UncheckedAccount<'info>,
+
+    /// The owner of the position, to validate the position address
+    /// CHECK: Validated through the position address in the instruction
+    pub owner: UncheckedAccount<'info>,
+
+    /// The pool of the position, to checkpoint its reward growth
+    pub pool_state: AccountLoader<'info, PoolState>,
+
+    /// The lower tick of the position
+    pub tick_lower_state: AccountLoader<'info, TickState>,
+
+    /// The upper tick of the position
+    pub tick_upper_state: AccountLoader<'info, TickState>,
+
+    /// Program to transfer rent lamports
+    pub system_program: Program<'info, System>,
+}
+
 #[derive(Accounts)]
 pub struct MintContext<'info> {
     /// Pays to mint liquidity
@@ -379,6 +679,11 @@ pub struct MintContext<'info> {
     /// Program which receives mint_callback
     /// CHECK: Allow arbitrary callback handlers
     pub callback_handler: UncheckedAccount<'info>,
+
+    /// The liquidity of the recipient across their positions in the pool
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub owner_liquidity_state: UncheckedAccount<'info>,
 }
 
 #[derive(Accounts)]
@@ -470,6 +775,11 @@ pub struct BurnContext<'info> {
     /// The program account for the most recent oracle observation
     /// CHECK: Safety check performed inside function body
     pub last_observation_state: UncheckedAccount<'info>,
+
+    /// The liquidity of the owner across their positions in the pool
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub owner_liquidity_state: UncheckedAccount<'info>,
 }
 
 #[derive(Accounts)]
@@ -518,42 +828,108 @@ pub struct CollectContext<'info> {
 }
 
 #[derive(Accounts)]
-pub struct SwapContext<'info> {
-    /// The user performing the swap
-    pub signer: Signer<'info>,
-
-    /// The user token account for input token
-    /// CHECK: Account validation is performed by the token program
-    #[account(mut)]
-    pub input_token_account: UncheckedAccount<'info>,
+pub struct AddRewards<'info> {
+    /// Pays the rewards
+    pub funder: Signer<'info>,
 
-    /// The user token account for output token
+    /// The token account paying the rewards
     /// CHECK: Account validation is performed by the token program
     #[account(mut)]
-    pub output_token_account: UncheckedAccount<'info>,
+    pub funder_wallet: UncheckedAccount<'info>,
 
-    /// The vault token account for input token
+    /// The pool distributing the rewards
     #[account(mut)]
-    pub input_vault: Box<Account<'info, TokenAccount>>,
+    pub pool_state: AccountLoader<'info, PoolState>,
 
-    /// The vault token account for output token
-    #[account(mut)]
-    pub output_vault: Box<Account<'info, TokenAccount>>,
+    /// The account holding pool tokens for the reward token
+    #[account(
+        mut,
+        constraint = reward_vault.key() == get_associated_token_address(&pool_state.key(), &pool_state.load()?.reward_mint),
+    )]
+    pub reward_vault: Box<Account<'info, TokenAccount>>,
 
-    /// SPL program for token transfers
+    /// SPL program to transfer in tokens
     pub token_program: Program<'info, Token>,
+}
 
-    /// The factory state to read protocol fees
-    /// CHECK: Safety check performed inside function body
-    pub factory_state: UncheckedAccount<'info>,
+#[derive(Accounts)]
+pub struct CollectRewardsContext<'info> {
+    /// The position owner
+    pub owner: Signer<'info>,
 
-    /// The program account of the pool in which the swap will be performed
-    /// CHECK: Safety check performed inside function body
+    /// The program account for the liquidity pool from which rewards are collected
     #[account(mut)]
-    pub pool_state: UncheckedAccount<'info>,
+    pub pool_state: AccountLoader<'info, PoolState>,
 
-    /// The program account for the most recent oracle observation
-    /// CHECK: Safety check performed inside function body
+    /// The lower tick of the position for which to collect rewards
+    pub tick_lower_state: AccountLoader<'info, TickState>,
+
+    /// The upper tick of the position for which to collect rewards
+    pub tick_upper_state: AccountLoader<'info, TickState>,
+
+    /// The position program account to collect rewards from
+    #[account(mut)]
+    pub position_state: AccountLoader<'info, PositionState>,
+
+    /// The account holding pool tokens for the reward token
+    #[account(
+        mut,
+        constraint = reward_vault.key() == get_associated_token_address(&pool_state.key(), &pool_state.load()?.reward_mint),
+    )]
+    pub reward_vault: Box<Account<'info, TokenAccount>>,
+
+    /// The destination token account for the collected rewards
+    /// CHECK: Account validation is performed by the token program
+    #[account(mut)]
+    pub recipient_wallet: UncheckedAccount<'info>,
+
+    /// SPL program to transfer out tokens
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct PokePositions<'info> {
+    /// The pool whose positions are poked
+    pub pool_state: AccountLoader<'info, PoolState>,
+}
+
+#[derive(Accounts)]
+pub struct SwapContext<'info> {
+    /// The user performing the swap
+    pub signer: Signer<'info>,
+
+    /// The user token account for input token
+    /// CHECK: Account validation is performed by the token program
+    #[account(mut)]
+    pub input_token_account: UncheckedAccount<'info>,
+
+    /// The user token account for output token
+    /// CHECK: Account validation is performed by the token program
+    #[account(mut)]
+    pub output_token_account: UncheckedAccount<'info>,
+
+    /// The vault token account for input token
+    #[account(mut)]
+    pub input_vault: Box<Account<'info, TokenAccount>>,
+
+    /// The vault token account for output token
+    #[account(mut)]
+    pub output_vault: Box<Account<'info, TokenAccount>>,
+
+    /// SPL program for token transfers
+    pub token_program: Program<'info, Token>,
+
+    /// The factory state to read protocol fees
+    /// CHECK: Safety check performed inside function body
+    pub factory_state: UncheckedAccount<'info>,
+
+    /// The program account of the pool in which the swap will be performed
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub pool_state: UncheckedAccount<'info>,
+
+    /// The program account for the most recent oracle observation
+    /// CHECK: Safety check performed inside function body
     #[account(mut)]
     pub last_observation_state: UncheckedAccount<'info>,
 
@@ -562,6 +938,83 @@ pub struct SwapContext<'info> {
     pub callback_handler: UncheckedAccount<'info>,
 }
 
+impl<'info> SwapContext<'info> {
+    /// Validates the vaults and user token accounts of a swap against the pool.
+    ///
+    /// Swaps made by other instructions construct the context directly, skipping account
+    /// constraints, so the checks are made here for every swap. Returns the direction of the
+    /// swap, and the input and output token accounts of the user.
+    ///
+    /// # Arguments
+    ///
+    /// * `pool_key` - The address of the pool
+    /// * `pool` - The pool in which the swap is performed
+    ///
+    pub fn validate(
+        &self,
+        pool_key: &Pubkey,
+        pool: &PoolState,
+    ) -> Result<(
+        bool,
+        Account<'info, TokenAccount>,
+        Account<'info, TokenAccount>,
+    )> {
+        let zero_for_one = self.input_vault.mint == pool.token_0;
+        let (input_mint, output_mint) = if zero_for_one {
+            (pool.token_0, pool.token_1)
+        } else {
+            (pool.token_1, pool.token_0)
+        };
+        require!(
+            self.input_vault.key() == get_associated_token_address(pool_key, &input_mint)
+                && self.output_vault.key() == get_associated_token_address(pool_key, &output_mint),
+            ErrorCode::SwapAccounts
+        );
+
+        let input_token_account = Account::<TokenAccount>::try_from(&self.input_token_account)?;
+        let output_token_account = Account::<TokenAccount>::try_from(&self.output_token_account)?;
+        require!(
+            input_token_account.mint == input_mint && output_token_account.mint == output_mint,
+            ErrorCode::SwapAccounts
+        );
+
+        Ok((zero_for_one, input_token_account, output_token_account))
+    }
+}
+
+impl<'info> MintCallback<'info> {
+    /// Checks that the minter can pay the amounts owed from the token accounts.
+    ///
+    /// A minter may pay from a token account it owns, or from any token account for which
+    /// it is the approved delegate. This lets an integrator mint liquidity for a recipient
+    /// without the token account owner signing.
+    ///
+    /// # Arguments
+    ///
+    /// * `amount_0_owed` - The amount of token_0 due to the pool
+    /// * `amount_1_owed` - The amount of token_1 due to the pool
+    ///
+    pub fn check_allowance(&self, amount_0_owed: u64, amount_1_owed: u64) -> Result<()> {
+        for (token_account, amount_owed) in [
+            (&self.token_account_0, amount_0_owed),
+            (&self.token_account_1, amount_1_owed),
+        ] {
+            if amount_owed == 0 {
+                continue;
+            }
+            let token_account = Account::<TokenAccount>::try_from(token_account)?;
+            if token_account.owner != self.minter.key() {
+                require!(
+                    token_account.delegate == COption::Some(self.minter.key())
+                        && token_account.delegated_amount >= amount_owed,
+                    ErrorCode::InsufficientAllowance
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
 // Non fungible position manager
 
 #[derive(Accounts)]
@@ -606,6 +1059,11 @@ pub struct MintTokenizedPosition<'info> {
     #[account(mut)]
     pub core_position_state: UncheckedAccount<'info>,
 
+    /// The liquidity of the factory, which holds the core positions of tokenized positions
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub owner_liquidity_state: UncheckedAccount<'info>,
+
     /// Account to store data for the position's lower tick
     /// CHECK: Safety check performed inside function body
     #[account(mut)]
@@ -737,6 +1195,11 @@ pub struct IncreaseLiquidity<'info> {
     #[account(mut)]
     pub core_position_state: UncheckedAccount<'info>,
 
+    /// The liquidity of the factory, which holds the core positions of tokenized positions
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub owner_liquidity_state: UncheckedAccount<'info>,
+
     /// Account to store data for the position's lower tick
     /// CHECK: Safety check performed inside function body
     #[account(mut)]
@@ -815,6 +1278,11 @@ pub struct DecreaseLiquidity<'info> {
     #[account(mut)]
     pub core_position_state: UncheckedAccount<'info>,
 
+    /// The liquidity of the factory, which holds the core positions of tokenized positions
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub owner_liquidity_state: UncheckedAccount<'info>,
+
     /// Account to store data for the position's lower tick
     /// CHECK: Safety check performed inside function body
     #[account(mut)]
@@ -872,6 +1340,11 @@ pub struct CollectFromTokenized<'info> {
     #[account(mut)]
     pub core_position_state: UncheckedAccount<'info>,
 
+    /// The liquidity of the factory, which holds the core positions of tokenized positions
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub owner_liquidity_state: UncheckedAccount<'info>,
+
     /// The program account for the position's lower tick
     /// CHECK: Safety check performed inside function body
     #[account(mut)]
@@ -924,6 +1397,238 @@ pub struct CollectFromTokenized<'info> {
     pub token_program: Program<'info, Token>,
 }
 
+#[derive(Accounts)]
+pub struct DecreaseLiquidityAndCollect<'info> {
+    /// The position owner or delegated authority
+    pub owner_or_delegate: Signer<'info>,
+
+    /// The token account for the tokenized position
+    #[account(
+        constraint = nft_account.mint == tokenized_position_state.load()?.mint
+    )]
+    pub nft_account: Box<Account<'info, TokenAccount>>,
+
+    /// Decrease liquidity and collect tokens for this position
+    #[account(mut)]
+    pub tokenized_position_state: AccountLoader<'info, TokenizedPositionState>,
+
+    /// The program account acting as the core liquidity custodian for token holder
+    pub factory_state: AccountLoader<'info, FactoryState>,
+
+    /// Burn liquidity and collect tokens from this pool
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub pool_state: UncheckedAccount<'info>,
+
+    /// The program account to access the core program position state
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub core_position_state: UncheckedAccount<'info>,
+
+    /// The liquidity of the factory, which holds the core positions of tokenized positions
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub owner_liquidity_state: UncheckedAccount<'info>,
+
+    /// The program account for the position's lower tick
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub tick_lower_state: UncheckedAccount<'info>,
+
+    /// The program account for the position's upper tick
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub tick_upper_state: UncheckedAccount<'info>,
+
+    /// The bitmap program account for the init state of the lower tick
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub bitmap_lower_state: UncheckedAccount<'info>,
+
+    /// Stores init state for the upper tick
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub bitmap_upper_state: UncheckedAccount<'info>,
+
+    /// The latest observation state
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub last_observation_state: UncheckedAccount<'info>,
+
+    /// The pool's token account for token_0
+    /// CHECK: Account validation is performed by the token program
+    #[account(mut)]
+    pub vault_0: Box<Account<'info, TokenAccount>>,
+
+    /// The pool's token account for token_1
+    /// CHECK: Account validation is performed by the token program
+    #[account(mut)]
+    pub vault_1: Box<Account<'info, TokenAccount>>,
+
+    /// The destination token account for the withdrawn and collected token_0
+    /// CHECK: Account validation is performed by the token program
+    #[account(mut)]
+    pub recipient_wallet_0: UncheckedAccount<'info>,
+
+    /// The destination token account for the withdrawn and collected token_1
+    /// CHECK: Account validation is performed by the token program
+    #[account(mut)]
+    pub recipient_wallet_1: UncheckedAccount<'info>,
+
+    /// The core program where liquidity is burned
+    pub core_program: Program<'info, CyclosCore>,
+
+    /// SPL program to transfer out tokens
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CollectAndZap<'info> {
+    /// The position owner or delegated authority
+    pub owner_or_delegate: Signer<'info>,
+
+    /// The token account for the tokenized position
+    #[account(
+        constraint = nft_account.mint == tokenized_position_state.load()?.mint
+    )]
+    pub nft_account: Box<Account<'info, TokenAccount>>,
+
+    /// The program account of the NFT for which fees are collected
+    #[account(mut)]
+    pub tokenized_position_state: AccountLoader<'info, TokenizedPositionState>,
+
+    /// The program account acting as the core liquidity custodian for token holder
+    pub factory_state: AccountLoader<'info, FactoryState>,
+
+    /// The pool from which fees are collected, and through which they are swapped
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub pool_state: UncheckedAccount<'info>,
+
+    /// The program account to access the core program position state
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub core_position_state: UncheckedAccount<'info>,
+
+    /// The liquidity of the factory, which holds the core positions of tokenized positions
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub owner_liquidity_state: UncheckedAccount<'info>,
+
+    /// The program account for the position's lower tick
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub tick_lower_state: UncheckedAccount<'info>,
+
+    /// The program account for the position's upper tick
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub tick_upper_state: UncheckedAccount<'info>,
+
+    /// The bitmap program account for the init state of the lower tick
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub bitmap_lower_state: UncheckedAccount<'info>,
+
+    /// Stores init state for the upper tick
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub bitmap_upper_state: UncheckedAccount<'info>,
+
+    /// The latest observation state
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub last_observation_state: UncheckedAccount<'info>,
+
+    /// The pool's token account for token_0
+    /// CHECK: Account validation is performed by the token program
+    #[account(mut)]
+    pub vault_0: Box<Account<'info, TokenAccount>>,
+
+    /// The pool's token account for token_1
+    /// CHECK: Account validation is performed by the token program
+    #[account(mut)]
+    pub vault_1: Box<Account<'info, TokenAccount>>,
+
+    /// The signer's token account for token_0, receiving the fees and paying or receiving
+    /// the swap
+    #[account(mut)]
+    pub token_account_0: Box<Account<'info, TokenAccount>>,
+
+    /// The signer's token account for token_1, receiving the fees and paying or receiving
+    /// the swap
+    #[account(mut)]
+    pub token_account_1: Box<Account<'info, TokenAccount>>,
+
+    /// The core program where fees are collected and swapped
+    pub core_program: Program<'info, CyclosCore>,
+
+    /// SPL program to transfer out tokens
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CollectFromTokenizedBatch<'info> {
+    /// The owner or delegated authority of every position NFT
+    pub owner_or_delegate: Signer<'info>,
+
+    /// The program account acting as the core liquidity custodian for token holder
+    pub factory_state: AccountLoader<'info, FactoryState>,
+
+    /// The program account for the liquidity pool of every position
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub pool_state: UncheckedAccount<'info>,
+
+    /// The liquidity of the factory, which holds the core positions of tokenized positions
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub owner_liquidity_state: UncheckedAccount<'info>,
+
+    /// The latest observation state
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub last_observation_state: UncheckedAccount<'info>,
+
+    /// The pool's token account for token_0
+    #[account(mut)]
+    pub vault_0: Box<Account<'info, TokenAccount>>,
+
+    /// The pool's token account for token_1
+    #[account(mut)]
+    pub vault_1: Box<Account<'info, TokenAccount>>,
+
+    /// The destination token account for the collected amount_0 of all positions
+    /// CHECK: Account validation is performed by the token program
+    #[account(mut)]
+    pub recipient_wallet_0: UncheckedAccount<'info>,
+
+    /// The destination token account for the collected amount_1 of all positions
+    /// CHECK: Account validation is performed by the token program
+    #[account(mut)]
+    pub recipient_wallet_1: UncheckedAccount<'info>,
+
+    /// The core program where liquidity is burned
+    pub core_program: Program<'info, CyclosCore>,
+
+    /// SPL program to transfer out tokens
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct TokenizedPositionInfoContext<'info> {
+    /// The mint of the position NFT
+    /// CHECK: Only used as a seed of the tokenized position
+    pub nft_mint: UncheckedAccount<'info>,
+
+    /// The tokenized position of the NFT
+    #[account(
+        seeds = [POSITION_SEED.as_bytes(), nft_mint.key().as_ref()],
+        bump = tokenized_position_state.load()?.bump
+    )]
+    pub tokenized_position_state: AccountLoader<'info, TokenizedPositionState>,
+}
+
 #[derive(Accounts)]
 pub struct ExactInputSingle<'info> {
     /// The user performing the swap
@@ -968,6 +1673,115 @@ pub struct ExactInputSingle<'info> {
     pub token_program: Program<'info, Token>,
 }
 
+#[derive(Accounts)]
+pub struct ExactInputSingleWithOracleCheck<'info> {
+    /// The user performing the swap
+    pub signer: Signer<'info>,
+
+    /// The factory state to read protocol fees
+    /// CHECK: Safety check performed inside function body
+    pub factory_state: UncheckedAccount<'info>,
+
+    /// The program account of the pool in which the swap will be performed
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub pool_state: UncheckedAccount<'info>,
+
+    /// The user token account for input token
+    /// CHECK: Account validation is performed by the token program
+    #[account(mut)]
+    pub input_token_account: UncheckedAccount<'info>,
+
+    /// The user token account for output token
+    /// CHECK: Account validation is performed by the token program
+    #[account(mut)]
+    pub output_token_account: UncheckedAccount<'info>,
+
+    /// The vault token account for input token
+    #[account(mut)]
+    pub input_vault: Box<Account<'info, TokenAccount>>,
+
+    /// The vault token account for output token
+    #[account(mut)]
+    pub output_vault: Box<Account<'info, TokenAccount>>,
+
+    /// The program account for the most recent oracle observation
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub last_observation_state: UncheckedAccount<'info>,
+
+    /// A past oracle observation, marking the start of the TWAP window
+    /// CHECK: Safety check performed inside function body
+    pub past_observation_state: UncheckedAccount<'info>,
+
+    /// The core program where swap is performed
+    pub core_program: Program<'info, CyclosCore>,
+
+    /// SPL program for token transfers
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ExactInputSplit<'info> {
+    /// The user performing the swap
+    pub signer: Signer<'info>,
+
+    /// The factory state to read protocol fees
+    /// CHECK: Safety check performed inside function body
+    pub factory_state: UncheckedAccount<'info>,
+
+    /// The user token account for input token
+    /// CHECK: Account validation is performed by the token program
+    #[account(mut)]
+    pub input_token_account: UncheckedAccount<'info>,
+
+    /// The user token account for output token, read to measure the combined output
+    #[account(mut)]
+    pub output_token_account: Box<Account<'info, TokenAccount>>,
+
+    /// The first pool in which the swap will be performed
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub pool_state_a: UncheckedAccount<'info>,
+
+    /// The vault token account for input token of the first pool
+    #[account(mut)]
+    pub input_vault_a: Box<Account<'info, TokenAccount>>,
+
+    /// The vault token account for output token of the first pool
+    #[account(mut)]
+    pub output_vault_a: Box<Account<'info, TokenAccount>>,
+
+    /// The program account for the most recent oracle observation of the first pool
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub last_observation_state_a: UncheckedAccount<'info>,
+
+    /// The second pool in which the swap will be performed, for the same pair as the first pool
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub pool_state_b: UncheckedAccount<'info>,
+
+    /// The vault token account for input token of the second pool
+    #[account(mut)]
+    pub input_vault_b: Box<Account<'info, TokenAccount>>,
+
+    /// The vault token account for output token of the second pool
+    #[account(mut)]
+    pub output_vault_b: Box<Account<'info, TokenAccount>>,
+
+    /// The program account for the most recent oracle observation of the second pool
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub last_observation_state_b: UncheckedAccount<'info>,
+
+    /// The core program where swap is performed
+    pub core_program: Program<'info, CyclosCore>,
+
+    /// SPL program for token transfers
+    pub token_program: Program<'info, Token>,
+}
+
 #[derive(Accounts)]
 pub struct ExactInput<'info> {
     /// The user performing the swap
@@ -988,3 +1802,447 @@ pub struct ExactInput<'info> {
     /// SPL program for token transfers
     pub token_program: Program<'info, Token>,
 }
+
+#[derive(Accounts)]
+pub struct Rebalance<'info> {
+    /// The owner of the old and new positions
+    pub owner: Signer<'info>,
+
+    /// The factory state to read protocol fees
+    /// CHECK: Safety check performed inside function body
+    pub factory_state: UncheckedAccount<'info>,
+
+    /// The pool in which liquidity is rebalanced
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub pool_state: UncheckedAccount<'info>,
+
+    /// The position from which liquidity is burned
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub position_state: UncheckedAccount<'info>,
+
+    /// The lower tick boundary of the old position
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub tick_lower_state: UncheckedAccount<'info>,
+
+    /// The upper tick boundary of the old position
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub tick_upper_state: UncheckedAccount<'info>,
+
+    /// The bitmap storing initialization state of the old lower tick
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub bitmap_lower_state: UncheckedAccount<'info>,
+
+    /// The bitmap storing initialization state of the old upper tick
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub bitmap_upper_state: UncheckedAccount<'info>,
+
+    /// The position into which liquidity is minted
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub new_position_state: UncheckedAccount<'info>,
+
+    /// The liquidity of the owner across their positions in the pool
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub owner_liquidity_state: UncheckedAccount<'info>,
+
+    /// The lower tick boundary of the new position
+    #[account(mut)]
+    pub new_tick_lower_state: AccountLoader<'info, TickState>,
+
+    /// The upper tick boundary of the new position
+    #[account(mut)]
+    pub new_tick_upper_state: AccountLoader<'info, TickState>,
+
+    /// The bitmap storing initialization state of the new lower tick
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub new_bitmap_lower_state: UncheckedAccount<'info>,
+
+    /// The bitmap storing initialization state of the new upper tick
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub new_bitmap_upper_state: UncheckedAccount<'info>,
+
+    /// The owner's token account for token_0
+    #[account(mut)]
+    pub token_account_0: Box<Account<'info, TokenAccount>>,
+
+    /// The owner's token account for token_1
+    #[account(mut)]
+    pub token_account_1: Box<Account<'info, TokenAccount>>,
+
+    /// The address that holds pool tokens for token_0
+    #[account(mut)]
+    pub vault_0: Box<Account<'info, TokenAccount>>,
+
+    /// The address that holds pool tokens for token_1
+    #[account(mut)]
+    pub vault_1: Box<Account<'info, TokenAccount>>,
+
+    /// The program account for the most recent oracle observation, at index = pool.observation_index
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub last_observation_state: UncheckedAccount<'info>,
+
+    /// The program account for the next oracle observation, at index = pool.observation_index + 1
+    /// CHECK: Safety check performed inside function body
+    #[account(mut)]
+    pub next_observation_state: UncheckedAccount<'info>,
+
+    /// The core program where liquidity is burned, swapped and minted
+    pub core_program: Program<'info, CyclosCore>,
+
+    /// SPL program for token transfers
+    pub token_program: Program<'info, Token>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anchor_lang::solana_program::program_pack::Pack;
+
+    struct TestAccount {
+        key: Pubkey,
+        owner: Pubkey,
+        lamports: u64,
+        data: Vec<u8>,
+        is_signer: bool,
+        executable: bool,
+    }
+
+    impl TestAccount {
+        fn new(key: Pubkey, owner: Pubkey) -> Self {
+            TestAccount {
+                key,
+                owner,
+                lamports: 0,
+                data: vec![],
+                is_signer: false,
+                executable: false,
+            }
+        }
+
+        fn token_account(key: Pubkey, mint: Pubkey) -> Self {
+            TestAccount::packed_token_account(
+                key,
+                spl_token::state::Account {
+                    mint,
+                    owner: Pubkey::new_unique(),
+                    state: spl_token::state::AccountState::Initialized,
+                    ..Default::default()
+                },
+            )
+        }
+
+        fn packed_token_account(key: Pubkey, token_account: spl_token::state::Account) -> Self {
+            let mut data = vec![0; spl_token::state::Account::LEN];
+            token_account.pack_into_slice(&mut data);
+            TestAccount {
+                data,
+                ..TestAccount::new(key, spl_token::ID)
+            }
+        }
+
+        fn info(&mut self) -> AccountInfo<'_> {
+            AccountInfo::new(
+                &self.key,
+                self.is_signer,
+                true,
+                &mut self.lamports,
+                &mut self.data,
+                &self.owner,
+                self.executable,
+                0,
+            )
+        }
+    }
+
+    mod swap_context_validate {
+        use super::*;
+
+        fn token(seed: u8) -> Pubkey {
+            Pubkey::new_from_array([seed; 32])
+        }
+
+        fn pool() -> PoolState {
+            PoolState {
+                token_0: token(1),
+                token_1: token(2),
+                ..Default::default()
+            }
+        }
+
+        /// The address and mint of the vault of a pool for the mint
+        fn vault(pool_key: &Pubkey, mint: Pubkey) -> (Pubkey, Pubkey) {
+            (get_associated_token_address(pool_key, &mint), mint)
+        }
+
+        /// Validates a swap with the given input and output vaults, and mints of the user
+        /// input and output token accounts
+        fn validate(
+            pool_key: &Pubkey,
+            vaults: [(Pubkey, Pubkey); 2],
+            user_mints: [Pubkey; 2],
+        ) -> Result<bool> {
+            let mut signer = TestAccount::new(Pubkey::new_unique(), System::id());
+            signer.is_signer = true;
+            let mut token_program = TestAccount::new(spl_token::ID, Pubkey::default());
+            token_program.executable = true;
+
+            let mut accounts = [
+                signer,
+                TestAccount::token_account(Pubkey::new_unique(), user_mints[0]),
+                TestAccount::token_account(Pubkey::new_unique(), user_mints[1]),
+                TestAccount::token_account(vaults[0].0, vaults[0].1),
+                TestAccount::token_account(vaults[1].0, vaults[1].1),
+                token_program,
+                TestAccount::new(Pubkey::new_unique(), crate::ID),
+            ];
+            let infos: Vec<AccountInfo<'_>> =
+                accounts.iter_mut().map(|account| account.info()).collect();
+
+            let swap_accounts = SwapContext {
+                signer: Signer::try_from(&infos[0])?,
+                input_token_account: UncheckedAccount::try_from(infos[1].clone()),
+                output_token_account: UncheckedAccount::try_from(infos[2].clone()),
+                input_vault: Box::new(Account::try_from(&infos[3])?),
+                output_vault: Box::new(Account::try_from(&infos[4])?),
+                token_program: Program::try_from(&infos[5])?,
+                factory_state: UncheckedAccount::try_from(infos[6].clone()),
+                pool_state: UncheckedAccount::try_from(infos[6].clone()),
+                last_observation_state: UncheckedAccount::try_from(infos[6].clone()),
+                callback_handler: UncheckedAccount::try_from(infos[6].clone()),
+            };
+            let (zero_for_one, _, _) = swap_accounts.validate(pool_key, &pool())?;
+            Ok(zero_for_one)
+        }
+
+        fn assert_rejected(result: Result<bool>) {
+            match result.err().unwrap() {
+                anchor_lang::error::Error::AnchorError(error) => {
+                    assert_eq!(error.error_code_number, u32::from(ErrorCode::SwapAccounts))
+                }
+                error => panic!("unexpected error {}", error),
+            }
+        }
+
+        #[test]
+        fn accepts_the_pool_vaults_in_both_directions() {
+            let pool_key = Pubkey::new_unique();
+            let (token_0, token_1) = (token(1), token(2));
+            assert!(validate(
+                &pool_key,
+                [vault(&pool_key, token_0), vault(&pool_key, token_1)],
+                [token_0, token_1]
+            )
+            .unwrap());
+            assert!(!validate(
+                &pool_key,
+                [vault(&pool_key, token_1), vault(&pool_key, token_0)],
+                [token_1, token_0]
+            )
+            .unwrap());
+        }
+
+        #[test]
+        fn rejects_vaults_of_another_pool() {
+            let pool_key = Pubkey::new_unique();
+            let other_pool_key = Pubkey::new_unique();
+            assert_rejected(validate(
+                &pool_key,
+                [vault(&other_pool_key, token(1)), vault(&pool_key, token(2))],
+                [token(1), token(2)],
+            ));
+            assert_rejected(validate(
+                &pool_key,
+                [vault(&pool_key, token(1)), vault(&other_pool_key, token(2))],
+                [token(1), token(2)],
+            ));
+        }
+
+        #[test]
+        fn rejects_vaults_of_the_same_token() {
+            let pool_key = Pubkey::new_unique();
+            for mint in [token(1), token(2)] {
+                assert_rejected(validate(
+                    &pool_key,
+                    [vault(&pool_key, mint), vault(&pool_key, mint)],
+                    [mint, mint],
+                ));
+            }
+        }
+
+        #[test]
+        fn rejects_a_vault_of_a_token_outside_the_pool() {
+            let pool_key = Pubkey::new_unique();
+            assert_rejected(validate(
+                &pool_key,
+                [vault(&pool_key, token(1)), vault(&pool_key, token(3))],
+                [token(1), token(3)],
+            ));
+            assert_rejected(validate(
+                &pool_key,
+                [vault(&pool_key, token(3)), vault(&pool_key, token(2))],
+                [token(3), token(2)],
+            ));
+        }
+
+        #[test]
+        fn rejects_user_token_accounts_of_other_mints() {
+            let pool_key = Pubkey::new_unique();
+            let vaults = [vault(&pool_key, token(1)), vault(&pool_key, token(2))];
+            assert_rejected(validate(&pool_key, vaults, [token(2), token(2)]));
+            assert_rejected(validate(&pool_key, vaults, [token(1), token(3)]));
+            assert_rejected(validate(&pool_key, vaults, [token(2), token(1)]));
+        }
+    }
+
+    mod mint_callback_check_allowance {
+        use super::*;
+
+        /// A token account of the owner, with an optional delegate and its allowance
+        fn token_account(owner: Pubkey, delegate: Option<(Pubkey, u64)>) -> TestAccount {
+            let (delegate, delegated_amount) = match delegate {
+                Some((delegate, delegated_amount)) => (COption::Some(delegate), delegated_amount),
+                None => (COption::None, 0),
+            };
+            TestAccount::packed_token_account(
+                Pubkey::new_unique(),
+                spl_token::state::Account {
+                    mint: Pubkey::new_unique(),
+                    owner,
+                    amount: u64::MAX,
+                    delegate,
+                    delegated_amount,
+                    state: spl_token::state::AccountState::Initialized,
+                    ..Default::default()
+                },
+            )
+        }
+
+        /// Checks that the minter can pay the amounts owed from the token accounts
+        fn check_allowance(
+            minter: Pubkey,
+            token_accounts: [TestAccount; 2],
+            amounts_owed: (u64, u64),
+        ) -> Result<()> {
+            let mut signer = TestAccount::new(minter, System::id());
+            signer.is_signer = true;
+            let [token_account_0, token_account_1] = token_accounts;
+
+            let mut accounts = [
+                signer,
+                token_account_0,
+                token_account_1,
+                TestAccount::new(Pubkey::new_unique(), spl_token::ID),
+            ];
+            let infos: Vec<AccountInfo<'_>> =
+                accounts.iter_mut().map(|account| account.info()).collect();
+
+            let callback_accounts = MintCallback {
+                minter: Signer::try_from(&infos[0])?,
+                token_account_0: UncheckedAccount::try_from(infos[1].clone()),
+                token_account_1: UncheckedAccount::try_from(infos[2].clone()),
+                vault_0: UncheckedAccount::try_from(infos[3].clone()),
+                vault_1: UncheckedAccount::try_from(infos[3].clone()),
+                token_program: UncheckedAccount::try_from(infos[3].clone()),
+            };
+            callback_accounts.check_allowance(amounts_owed.0, amounts_owed.1)
+        }
+
+        fn assert_rejected(result: Result<()>) {
+            match result.err().unwrap() {
+                anchor_lang::error::Error::AnchorError(error) => assert_eq!(
+                    error.error_code_number,
+                    u32::from(ErrorCode::InsufficientAllowance)
+                ),
+                error => panic!("unexpected error {}", error),
+            }
+        }
+
+        #[test]
+        fn accepts_an_owner_paying_from_its_accounts() {
+            let minter = Pubkey::new_unique();
+            check_allowance(
+                minter,
+                [token_account(minter, None), token_account(minter, None)],
+                (1_000, 2_000),
+            )
+            .unwrap();
+        }
+
+        #[test]
+        fn accepts_a_delegate_with_sufficient_allowance() {
+            let (owner, delegate) = (Pubkey::new_unique(), Pubkey::new_unique());
+            check_allowance(
+                delegate,
+                [
+                    token_account(owner, Some((delegate, 1_000))),
+                    token_account(owner, Some((delegate, 5_000))),
+                ],
+                (1_000, 2_000),
+            )
+            .unwrap();
+        }
+
+        #[test]
+        fn rejects_a_delegate_with_insufficient_allowance() {
+            let (owner, delegate) = (Pubkey::new_unique(), Pubkey::new_unique());
+            assert_rejected(check_allowance(
+                delegate,
+                [
+                    token_account(owner, Some((delegate, 999))),
+                    token_account(owner, Some((delegate, 5_000))),
+                ],
+                (1_000, 2_000),
+            ));
+            assert_rejected(check_allowance(
+                delegate,
+                [
+                    token_account(owner, Some((delegate, 1_000))),
+                    token_account(owner, Some((delegate, 1_999))),
+                ],
+                (1_000, 2_000),
+            ));
+        }
+
+        #[test]
+        fn rejects_a_minter_that_is_not_the_delegate() {
+            let (owner, delegate) = (Pubkey::new_unique(), Pubkey::new_unique());
+            assert_rejected(check_allowance(
+                Pubkey::new_unique(),
+                [
+                    token_account(owner, Some((delegate, u64::MAX))),
+                    token_account(owner, Some((delegate, u64::MAX))),
+                ],
+                (1_000, 2_000),
+            ));
+            assert_rejected(check_allowance(
+                delegate,
+                [token_account(owner, None), token_account(owner, None)],
+                (1_000, 2_000),
+            ));
+        }
+
+        #[test]
+        fn skips_token_accounts_with_nothing_owed() {
+            let (owner, delegate) = (Pubkey::new_unique(), Pubkey::new_unique());
+            check_allowance(
+                delegate,
+                [
+                    token_account(owner, None),
+                    token_account(owner, Some((delegate, 2_000))),
+                ],
+                (0, 2_000),
+            )
+            .unwrap();
+        }
+    }
+}
@@ -6,6 +6,8 @@ pub enum ErrorCode {
     LOK,
     #[msg("Minting amount should be greater than 0")]
     ZeroMintAmount,
+    #[msg("Minted liquidity should be greater than 0")]
+    ZeroLiquidity,
 
     // states/pool.rs
 
@@ -35,6 +37,11 @@ pub enum ErrorCode {
     #[msg("M1")]
     M1,
 
+    // A minter paying from a token account it does not own must be its delegate, approved
+    // for at least the amount owed
+    #[msg("Insufficient delegated allowance")]
+    InsufficientAllowance,
+
     // Observation state seed should be valid
     #[msg("OS")]
     OS,
@@ -50,18 +57,83 @@ pub enum ErrorCode {
     #[msg("IIA")]
     IIA,
 
+    // The next tick account in remaining accounts must be the pool's account for the tick
+    // being crossed
+    #[msg("Missing tick account")]
+    MissingTickAccount,
+
+    // A tick account may be passed at most once to a swap, since each tick is crossed once
+    #[msg("Duplicate tick account")]
+    DuplicateTickAccount,
+
+    // The next bitmap account in remaining accounts must be the pool's account for the word
+    // being searched, unless the in range liquidity has run out
+    #[msg("Missing bitmap account")]
+    MissingBitmapAccount,
+
+    // The swap found no liquidity to swap against up to its price limit, neither in range
+    // nor at initialized ticks in the swap direction
+    #[msg("Insufficient liquidity")]
+    InsufficientLiquidity,
+
+    // The vaults and user token accounts of a swap must hold the pool's tokens, in the
+    // direction of the swap
+    #[msg("Invalid swap accounts")]
+    SwapAccounts,
+
+    // A swap's referral fee cannot exceed the pool's maximum, nor a pool's maximum exceed
+    // MAX_REFERRAL_FEE_BPS
+    #[msg("Referral fee too high")]
+    ReferralFeeTooHigh,
+
+    // The referral token account of a swap must be passed in remaining accounts
+    #[msg("Missing referral account")]
+    MissingReferralAccount,
+
+    // states/oracle.rs
+
+    // The past observation must be initialized, and older than the current block timestamp
+    #[msg("OLD")]
+    OLD,
+
     // states/position.rs
 
-    // No poke/burn for a position with 0 liquidity
+    // No position. Missing positions fail at the account layer, see CannotPokeEmptyPosition
+    // for pokes of positions with 0 liquidity
     #[msg("NP")]
     NP,
 
+    // Position account size does not match a known layout version
+    #[msg("PL")]
+    PL,
+
+    // The stored position bump must be the canonical bump of the position address
+    #[msg("Invalid bump")]
+    InvalidBump,
+
+    // Positions to poke or collect must be passed as complete groups of their accounts
+    #[msg("Invalid position accounts")]
+    PositionAccounts,
+
     // states/tick.rs
 
     // liquidity_gross_after must be less than max_liquidity
     #[msg("LO")]
     LO,
 
+    // A tick must hold liquidity if and only if its bit is set in the bitmap
+    #[msg("Tick bitmap desync")]
+    TickBitmapDesync,
+
+    // Every position adds its liquidity to the net liquidity of its lower tick and removes it
+    // from its upper tick, so the net liquidity of all initialized ticks sums to zero
+    #[msg("Liquidity net imbalance")]
+    LiquidityNetImbalance,
+
+    // The fee growth inside a range can differ from zero by no more than the global fee growth
+    #[msg("Fee growth inconsistent")]
+    FeeGrowthInconsistent,
+
     // libraries/tick_math.rs
 
     // second inequality must be < because the price can never reach the price at the max tick
@@ -81,6 +153,36 @@ pub enum ErrorCode {
     #[msg("LA")]
     LA,
 
+    // libraries/sqrt_price_math.rs
+
+    // The token amount for a liquidity delta does not fit in an i64
+    #[msg("Amount overflow")]
+    AmountOverflow,
+
+    // Protocol fee denominator must be 0, or lie in [4, 10]
+    #[msg("Invalid protocol fee")]
+    InvalidProtocolFee,
+
+    // Liquidity held by the owner across positions exceeds the pool's per owner limit
+    #[msg("Owner limit exceeded")]
+    OwnerLimitExceeded,
+
+    // The tick range of a position is narrower than the pool's minimum tick width
+    #[msg("Range too narrow")]
+    RangeTooNarrow,
+
+    // The reward token must be set once, to a token other than token_0 and token_1
+    #[msg("Invalid reward token")]
+    RewardMint,
+
+    // Rewards are distributed to in range liquidity, and cannot be added without any
+    #[msg("No liquidity to reward")]
+    NoRewardLiquidity,
+
+    // Only tokens other than token_0 and token_1 can be recovered from a pool
+    #[msg("Cannot recover pool token")]
+    RecoverPoolToken,
+
     // Non fungible position manager
     #[msg("Transaction too old")]
     TransactionTooOld,
@@ -94,4 +196,55 @@ pub enum ErrorCode {
     // Swap router
     #[msg("Too little received")]
     TooLittleReceived,
+
+    #[msg("Price deviation")]
+    PriceDeviation,
+
+    #[msg("Pool mismatch")]
+    PoolMismatch,
+
+    #[msg("Invalid split")]
+    InvalidSplit,
+
+    // Each hop must spend the output token of the previous hop, through a pool not used before
+    #[msg("Invalid route")]
+    InvalidRoute,
+
+    // Swap quotes, a pool snapshot can be no older than the tolerated number of slots
+    #[msg("Stale snapshot")]
+    StaleSnapshot,
+
+    // The swap price limit is the current price, so the swap cannot make progress
+    #[msg("Invalid price limit")]
+    InvalidPriceLimit,
+
+    // The swap reached a price range without liquidity before the specified amount was filled
+    #[msg("Liquidity gap traversed")]
+    LiquidityGapTraversed,
+
+    // More positions were passed than a batched instruction processes in one call
+    #[msg("Batch too large")]
+    BatchTooLarge,
+
+    // No poke/burn of 0 liquidity for a position with 0 liquidity
+    #[msg("Cannot poke empty position")]
+    CannotPokeEmptyPosition,
+
+    // Liquidity moved out of a position by a rebalance must be greater than 0
+    #[msg("Burned liquidity should be greater than 0")]
+    ZeroBurnLiquidity,
+
+    // Rewards added per unit of in range liquidity must fit in a Q32.32
+    #[msg("Reward growth overflow")]
+    RewardGrowthOverflow,
+
+    // The position account must be upgraded to the current layout with #migrate_position
+    #[msg("Position not migrated")]
+    PositionNotMigrated,
+
+    // libraries/liquidity_amounts.rs
+
+    // The intermediates of the swap amount for a rebalance must fit in their types
+    #[msg("Rebalance amount overflow")]
+    RebalanceAmountOverflow,
 }
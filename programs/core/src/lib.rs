@@ -10,7 +10,8 @@ use crate::libraries::tick_math;
 use crate::states::oracle;
 use crate::states::oracle::ObservationState;
 use crate::states::tokenized_position::{
-    CollectTokenizedEvent, DecreaseLiquidityEvent, IncreaseLiquidityEvent,
+    CollectBudget, CollectTokenizedEvent, DecreaseLiquidityEvent, IncreaseLiquidityEvent,
+    TokenizedPositionState,
 };
 use crate::{
     libraries::{fixed_point_32, swap_math},
@@ -26,13 +27,16 @@ use anchor_spl::token::TokenAccount;
 use context::*;
 use libraries::full_math::MulDiv;
 use libraries::liquidity_math;
+use libraries::sqrt_price::SqrtPriceX32;
 use libraries::sqrt_price_math;
 use metaplex_token_metadata::{instruction::create_metadata_accounts, state::Creator};
 use spl_token::instruction::AuthorityType;
 use states::factory::*;
 use states::fee::*;
+use states::owner_liquidity::*;
 use states::pool::*;
 use states::position::*;
+use states::swap_router::{validate_swap_path, SwapHop};
 use states::tick;
 use states::tick::*;
 use states::tick_bitmap::*;
@@ -140,21 +144,33 @@ pub mod cyclos_core {
     /// * `pool_state_bump` - Bump to validate Pool State address
     /// * `observation_state_bump` - Bump to validate Observation State address
     /// * `sqrt_price_x32` - the initial sqrt price (amount_token_1 / amount_token_0) of the pool as a Q32.32
+    /// * `initial_cardinality` - Number of oracle observation slots to allocate upfront, so that
+    /// #increase_observation_cardinality_next is not needed. Slots past the first are created
+    /// from the observation accounts with indices `1..initial_cardinality`, passed in order as
+    /// remaining accounts.
     ///
-    pub fn create_and_init_pool(
-        ctx: Context<CreateAndInitPool>,
+    pub fn create_and_init_pool<'a, 'b, 'c, 'info>(
+        ctx: Context<'a, 'b, 'c, 'info, CreateAndInitPool<'info>>,
         sqrt_price_x32: u64,
+        initial_cardinality: u16,
     ) -> Result<()> {
+        require!(
+            initial_cardinality >= 1
+                && ctx.remaining_accounts.len() == initial_cardinality as usize - 1,
+            ErrorCode::OS
+        );
         let mut pool_state = ctx.accounts.pool_state.load_init()?;
         let fee_state = ctx.accounts.fee_state.load()?;
-        let tick = tick_math::get_tick_at_sqrt_ratio(sqrt_price_x32)?;
+        let sqrt_price = SqrtPriceX32::new(sqrt_price_x32)?;
+        let tick = sqrt_price.to_tick()?;
 
         pool_state.bump = *ctx.bumps.get("pool_state").unwrap();
         pool_state.token_0 = ctx.accounts.token_0.key();
         pool_state.token_1 = ctx.accounts.token_1.key();
         pool_state.fee = fee_state.fee;
+        pool_state.fee_protocol = ctx.accounts.factory_state.load()?.fee_protocol;
         pool_state.tick_spacing = fee_state.tick_spacing;
-        pool_state.sqrt_price_x32 = sqrt_price_x32;
+        pool_state.sqrt_price_x32 = *sqrt_price;
         pool_state.tick = tick;
         pool_state.unlocked = true;
         pool_state.observation_cardinality = 1;
@@ -167,6 +183,31 @@ pub mod cyclos_core {
 
         // default value 0 for remaining variables
 
+        let observation_account_bumps: Vec<u8> = (1..initial_cardinality)
+            .map(|index| {
+                Pubkey::find_program_address(
+                    &[
+                        &OBSERVATION_SEED.as_bytes(),
+                        pool_state.token_0.as_ref(),
+                        pool_state.token_1.as_ref(),
+                        &pool_state.fee.to_be_bytes(),
+                        &index.to_be_bytes(),
+                    ],
+                    ctx.program_id,
+                )
+                .1
+            })
+            .collect();
+        create_observation_accounts(
+            &pool_state,
+            &ctx.accounts.pool_creator.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            ctx.remaining_accounts,
+            &observation_account_bumps,
+            ctx.program_id,
+        )?;
+        pool_state.observation_cardinality_next = initial_cardinality;
+
         emit!(PoolCreatedAndInitialized {
             token_0: ctx.accounts.token_0.key(),
             token_1: ctx.accounts.token_1.key(),
@@ -201,67 +242,18 @@ pub mod cyclos_core {
         require!(pool_state.unlocked, ErrorCode::LOK);
         pool_state.unlocked = false;
 
-        let mut i: usize = 0;
-        while i < observation_account_bumps.len() {
-            let observation_account_seeds = [
-                &OBSERVATION_SEED.as_bytes(),
-                pool_state.token_0.as_ref(),
-                pool_state.token_1.as_ref(),
-                &pool_state.fee.to_be_bytes(),
-                &(pool_state.observation_cardinality_next + i as u16).to_be_bytes(),
-                &[observation_account_bumps[i]],
-            ];
-
-            require!(
-                ctx.remaining_accounts[i].key()
-                    == Pubkey::create_program_address(
-                        &observation_account_seeds[..],
-                        &ctx.program_id
-                    )
-                    .unwrap(),
-                ErrorCode::OS
-            );
-
-            let space = 8 + size_of::<ObservationState>();
-            let rent = Rent::get()?;
-            let lamports = rent.minimum_balance(space);
-            let ix = create_account(
-                ctx.accounts.payer.key,
-                &ctx.remaining_accounts[i].key,
-                lamports,
-                space as u64,
-                ctx.program_id,
-            );
-
-            solana_program::program::invoke_signed(
-                &ix,
-                &[
-                    ctx.accounts.payer.to_account_info(),
-                    ctx.remaining_accounts[i].to_account_info(),
-                    ctx.accounts.system_program.to_account_info(),
-                ],
-                &[&observation_account_seeds[..]],
-            )?;
-
-            let observation_state_loader = AccountLoader::<ObservationState>::try_from_unchecked(
-                &cyclos_core::id(),
-                &ctx.remaining_accounts[i].to_account_info(),
-            )?;
-            let mut observation_state = observation_state_loader.load_init()?;
-            // this data will not be used because the initialized boolean is still false
-            observation_state.bump = observation_account_bumps[i];
-            observation_state.index = pool_state.observation_cardinality_next + i as u16;
-            observation_state.block_timestamp = 1;
-
-            drop(observation_state);
-            observation_state_loader.exit(ctx.program_id)?;
-
-            i += 1;
-        }
+        create_observation_accounts(
+            &pool_state,
+            &ctx.accounts.payer.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            ctx.remaining_accounts,
+            &observation_account_bumps,
+            ctx.program_id,
+        )?;
         let observation_cardinality_next_old = pool_state.observation_cardinality_next;
         pool_state.observation_cardinality_next = pool_state
             .observation_cardinality_next
-            .checked_add(i as u16)
+            .checked_add(observation_account_bumps.len() as u16)
             .unwrap();
 
         emit!(oracle::IncreaseObservationCardinalityNext {
@@ -278,14 +270,14 @@ pub mod cyclos_core {
 
     /// Set the denominator of the protocol's % share of the fees.
     ///
-    /// Unlike Uniswap, protocol fee is globally set. It can be updated by factory owner
-    /// at any time.
+    /// The factory protocol fee is copied to pools on creation. It can be updated by factory owner
+    /// at any time, and set for existing pools with #set_pool_fee_protocol.
     ///
     /// # Arguments
     ///
     /// * `ctx` - Checks for valid owner by looking at signer and factory owner addresses.
     /// Holds the Factory State account where protocol fee will be saved.
-    /// * `fee_protocol` - new protocol fee for all pools
+    /// * `fee_protocol` - new protocol fee for pools created hereafter
     ///
     pub fn set_fee_protocol(ctx: Context<SetFeeProtocol>, fee_protocol: u8) -> Result<()> {
         assert!(fee_protocol >= 2 && fee_protocol <= 10);
@@ -301,6 +293,237 @@ pub mod cyclos_core {
         Ok(())
     }
 
+    /// Transfers out the full balance of a token sent to a pool by mistake.
+    /// The pool tokens token_0 and token_1, and the reward token, can never be recovered.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - Checks for valid owner by looking at signer and factory owner addresses.
+    /// Holds the pool owned token account to empty, and the recipient token account.
+    /// * `mint` - The mint of the token to recover
+    ///
+    pub fn recover_token(ctx: Context<RecoverToken>, mint: Pubkey) -> Result<()> {
+        let pool_state = ctx.accounts.pool_state.load()?;
+        require!(
+            mint != pool_state.token_0
+                && mint != pool_state.token_1
+                && mint != pool_state.reward_mint,
+            ErrorCode::RecoverPoolToken
+        );
+
+        let pool_state_seeds = [
+            POOL_SEED.as_bytes(),
+            &pool_state.token_0.to_bytes() as &[u8],
+            &pool_state.token_1.to_bytes() as &[u8],
+            &pool_state.fee.to_be_bytes(),
+            &[pool_state.bump],
+        ];
+        drop(pool_state);
+
+        let amount = ctx.accounts.token_account.amount;
+        if amount > 0 {
+            token::transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info().clone(),
+                    token::Transfer {
+                        from: ctx.accounts.token_account.to_account_info().clone(),
+                        to: ctx.accounts.recipient.to_account_info().clone(),
+                        authority: ctx.accounts.pool_state.to_account_info().clone(),
+                    },
+                    &[&pool_state_seeds[..]],
+                ),
+                amount,
+            )?;
+        }
+
+        emit!(RecoverTokenEvent {
+            pool_state: ctx.accounts.pool_state.key(),
+            mint,
+            recipient: ctx.accounts.recipient.key(),
+            amount,
+        });
+
+        Ok(())
+    }
+
+    /// Updates the protocol fee of a single pool. Fees accrued to the protocol so far
+    /// remain collectable.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - Checks for valid owner by looking at signer and factory owner addresses.
+    /// Holds the Pool State account where the protocol fee is saved.
+    /// * `fee_protocol` - The new protocol fee denominator in [4, 10], or 0 to turn off the protocol fee
+    ///
+    pub fn set_pool_fee_protocol(ctx: Context<SetPoolFeeProtocol>, fee_protocol: u8) -> Result<()> {
+        let fee_protocol_old = ctx
+            .accounts
+            .pool_state
+            .load_mut()?
+            .set_fee_protocol(fee_protocol)?;
+
+        emit!(SetPoolFeeProtocolEvent {
+            pool_state: ctx.accounts.pool_state.key(),
+            fee_protocol_old,
+            fee_protocol
+        });
+
+        Ok(())
+    }
+
+    /// Sets the maximum liquidity an owner may hold across positions in a pool.
+    /// Can be used to cap exposure per owner in restricted pools
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - Checks for valid owner by looking at signer and factory owner addresses.
+    /// Holds the Pool State account where the limit is saved.
+    /// * `max_liquidity_per_owner` - The new limit, or zero to remove the limit
+    ///
+    pub fn set_max_liquidity_per_owner(
+        ctx: Context<SetMaxLiquidityPerOwner>,
+        max_liquidity_per_owner: u64,
+    ) -> Result<()> {
+        let mut pool = ctx.accounts.pool_state.load_mut()?;
+        let max_liquidity_per_owner_old = pool.max_liquidity_per_owner;
+        pool.max_liquidity_per_owner = max_liquidity_per_owner;
+
+        emit!(SetMaxLiquidityPerOwnerEvent {
+            pool_state: ctx.accounts.pool_state.key(),
+            max_liquidity_per_owner_old,
+            max_liquidity_per_owner
+        });
+
+        Ok(())
+    }
+
+    /// Sets the minimum width of tick ranges minted in a pool, in multiples of tick spacing.
+    /// Existing positions narrower than the minimum are unaffected.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - Checks for valid owner by looking at signer and factory owner addresses.
+    /// Holds the Pool State account where the minimum is saved.
+    /// * `min_tick_width` - The new minimum width, or zero to remove the minimum
+    ///
+    pub fn set_min_tick_width(ctx: Context<SetMinTickWidth>, min_tick_width: u32) -> Result<()> {
+        let mut pool = ctx.accounts.pool_state.load_mut()?;
+        let min_tick_width_old = pool.min_tick_width;
+        pool.min_tick_width = min_tick_width;
+
+        emit!(SetMinTickWidthEvent {
+            pool_state: ctx.accounts.pool_state.key(),
+            min_tick_width_old,
+            min_tick_width
+        });
+
+        Ok(())
+    }
+
+    /// Sets the number of slots liquidity must be held after a mint for a position to keep its
+    /// fees. Positions burning or adding liquidity before then forfeit the fees credited by that
+    /// update to the pool's in range liquidity.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - Checks for valid owner by looking at signer and factory owner addresses.
+    /// Holds the Pool State account where the holding period is saved.
+    /// * `min_holding_slots` - The new holding period, or zero to remove it
+    ///
+    pub fn set_min_holding_slots(
+        ctx: Context<SetMinHoldingSlots>,
+        min_holding_slots: u64,
+    ) -> Result<()> {
+        let mut pool = ctx.accounts.pool_state.load_mut()?;
+        let min_holding_slots_old = pool.min_holding_slots;
+        pool.min_holding_slots = min_holding_slots;
+
+        emit!(SetMinHoldingSlotsEvent {
+            pool_state: ctx.accounts.pool_state.key(),
+            min_holding_slots_old,
+            min_holding_slots
+        });
+
+        Ok(())
+    }
+
+    /// Sets the highest referral fee a swap in the pool may skim from its output.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - Checks for valid owner by looking at signer and factory owner addresses.
+    /// Holds the Pool State account where the maximum is saved.
+    /// * `max_referral_fee_bps` - The new maximum in basis points, at most MAX_REFERRAL_FEE_BPS,
+    /// or zero to disable referrals
+    ///
+    pub fn set_max_referral_fee(
+        ctx: Context<SetMaxReferralFee>,
+        max_referral_fee_bps: u16,
+    ) -> Result<()> {
+        require!(
+            max_referral_fee_bps <= MAX_REFERRAL_FEE_BPS,
+            ErrorCode::ReferralFeeTooHigh
+        );
+        let mut pool = ctx.accounts.pool_state.load_mut()?;
+        let max_referral_fee_bps_old = pool.max_referral_fee_bps;
+        pool.max_referral_fee_bps = max_referral_fee_bps;
+
+        emit!(SetMaxReferralFeeEvent {
+            pool_state: ctx.accounts.pool_state.key(),
+            max_referral_fee_bps_old,
+            max_referral_fee_bps
+        });
+
+        Ok(())
+    }
+
+    /// Sets the token in which the swap events of a pool value swaps, so that integrators can
+    /// aggregate volume across pools in a common quote asset.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - Checks for valid owner by looking at signer and factory owner addresses.
+    /// Holds the Pool State account where the setting is saved.
+    /// * `quote_is_token_1` - Whether swaps are valued in token_1, else in token_0
+    ///
+    pub fn set_quote_token(ctx: Context<SetQuoteToken>, quote_is_token_1: bool) -> Result<()> {
+        ctx.accounts.pool_state.load_mut()?.quote_is_token_1 = quote_is_token_1;
+
+        emit!(SetQuoteTokenEvent {
+            pool_state: ctx.accounts.pool_state.key(),
+            quote_is_token_1
+        });
+
+        Ok(())
+    }
+
+    /// Sets the bonus reward token distributed to in range liquidity of a pool.
+    /// The reward token can be set once, and must differ from the pool tokens.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - Checks for valid owner by looking at signer and factory owner addresses.
+    /// Holds the Pool State account where the reward token is saved.
+    ///
+    pub fn set_reward_mint(ctx: Context<SetRewardMint>) -> Result<()> {
+        let mut pool = ctx.accounts.pool_state.load_mut()?;
+        let reward_mint = ctx.accounts.reward_mint.key();
+        require!(
+            pool.reward_mint == Pubkey::default()
+                && reward_mint != pool.token_0
+                && reward_mint != pool.token_1,
+            ErrorCode::RewardMint
+        );
+        pool.reward_mint = reward_mint;
+
+        emit!(SetRewardMintEvent {
+            pool_state: ctx.accounts.pool_state.key(),
+            reward_mint
+        });
+
+        Ok(())
+    }
+
     /// Collect the protocol fee accrued to the pool
     ///
     /// # Arguments
@@ -378,6 +601,40 @@ pub mod cyclos_core {
         Ok(())
     }
 
+    /// Returns the protocol fees accrued to the pool and not yet collected
+    ///
+    /// The amounts of token_0 and token_1 are set as borsh encoded return data.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - Holds the Pool State account where accrued protocol fee is saved
+    ///
+    pub fn protocol_fees(ctx: Context<ProtocolFees>) -> Result<()> {
+        let pool_state = ctx.accounts.pool_state.load()?;
+        let fees = (
+            pool_state.protocol_fees_token_0,
+            pool_state.protocol_fees_token_1,
+        );
+        solana_program::program::set_return_data(&fees.try_to_vec()?);
+        Ok(())
+    }
+
+    /// Returns the fees paid by swaps over the lifetime of the pool, including the protocol fee
+    ///
+    /// The amounts of token_0 and token_1 are set as borsh encoded u128 return data. Unlike
+    /// the fee growth, the totals do not wrap around.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - Holds the Pool State account where the fee totals are saved
+    ///
+    pub fn total_fees(ctx: Context<TotalFees>) -> Result<()> {
+        let pool_state = ctx.accounts.pool_state.load()?;
+        let fees = (pool_state.total_fees_token_0, pool_state.total_fees_token_1);
+        solana_program::program::set_return_data(&fees.try_to_vec()?);
+        Ok(())
+    }
+
     /// ---------------------------------------------------------------------
     /// Account init instructions
     ///
@@ -447,6 +704,151 @@ pub mod cyclos_core {
     pub fn init_position_account(ctx: Context<InitPositionAccount>) -> Result<()> {
         let mut position_account = ctx.accounts.position_state.load_init()?;
         position_account.bump = *ctx.bumps.get("position_state").unwrap();
+        position_account.version = POSITION_VERSION;
+        Ok(())
+    }
+
+    /// Initializes the account tracking an owner's liquidity in a pool, needed to mint to
+    /// or burn from the owner's positions
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - Contains accounts to initialize the owner's liquidity account
+    ///
+    pub fn init_owner_liquidity_account(ctx: Context<InitOwnerLiquidityAccount>) -> Result<()> {
+        let mut owner_liquidity = ctx.accounts.owner_liquidity_state.load_init()?;
+        owner_liquidity.bump = *ctx.bumps.get("owner_liquidity_state").unwrap();
+        Ok(())
+    }
+
+    /// Upgrades a position account to the current layout version
+    ///
+    /// Accounts on an older layout are resized and topped up to stay rent exempt,
+    /// with the added fields initialized to their defaults. Liquidity, fee checkpoints
+    /// and tokens owed are preserved byte for byte. Positions from before rewards are
+    /// checkpointed at the current reward growth inside, so rewards distributed before
+    /// the migration cannot be claimed.
+    ///
+    /// Resizing needs a runtime with account reallocation enabled.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - Contains the position account, its pool and ticks, and the rent payer
+    ///
+    pub fn migrate_position(ctx: Context<MigratePosition>) -> Result<()> {
+        let position_info = ctx.accounts.position_state.to_account_info();
+        let len = 8 + std::mem::size_of::<PositionState>();
+        let old_len = position_info.data_len();
+        require!(
+            old_len == POSITION_LEN_V0
+                || old_len == POSITION_LEN_V1
+                || old_len == POSITION_LEN_V2
+                || old_len == len,
+            ErrorCode::PL
+        );
+
+        if old_len < len {
+            let rent_due = Rent::get()?
+                .minimum_balance(len)
+                .saturating_sub(position_info.lamports());
+            if rent_due > 0 {
+                solana_program::program::invoke(
+                    &solana_program::system_instruction::transfer(
+                        &ctx.accounts.payer.key(),
+                        &position_info.key(),
+                        rent_due,
+                    ),
+                    &[
+                        ctx.accounts.payer.to_account_info(),
+                        position_info.clone(),
+                        ctx.accounts.system_program.to_account_info(),
+                    ],
+                )?;
+            }
+            realloc_zeroed(&position_info, len)?;
+        }
+
+        // checks the discriminator
+        let position_state = AccountLoader::<PositionState>::try_from(&position_info)?;
+        let mut position = position_state.load_mut()?;
+
+        let pool = *ctx.accounts.pool_state.load()?.deref();
+        let tick_lower = *ctx.accounts.tick_lower_state.load()?.deref();
+        pool.validate_tick_address(
+            &ctx.accounts.tick_lower_state.key(),
+            tick_lower.bump,
+            tick_lower.tick,
+        )?;
+        let tick_upper = *ctx.accounts.tick_upper_state.load()?.deref();
+        pool.validate_tick_address(
+            &ctx.accounts.tick_upper_state.key(),
+            tick_upper.bump,
+            tick_upper.tick,
+        )?;
+        pool.validate_position_address(
+            &position_info.key(),
+            position.bump,
+            &ctx.accounts.owner.key(),
+            tick_lower.tick,
+            tick_upper.tick,
+        )?;
+
+        position.migrate(tick::get_reward_growth_inside(
+            &tick_lower,
+            &tick_upper,
+            pool.tick,
+            pool.reward_growth_global_x32,
+        ));
+
+        Ok(())
+    }
+
+    /// Transfers a position to a new owner, by moving its state to the position account of
+    /// the new owner and closing the old account
+    ///
+    /// Liquidity, fee and reward checkpoints, tokens owed and the mint slot are carried over,
+    /// so that the new owner can collect the fees earned so far. The new owner must not hold a
+    /// position over the same range, and the position must be on the current layout version.
+    /// Positions on an older layout fail with `PositionNotMigrated` until #migrate_position
+    /// upgrades them.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - Holds the old and new position accounts, and the liquidity accounts of both
+    /// owners. The liquidity moves between the owners' accounts, and if the pool limits
+    /// liquidity per owner, the new owner's liquidity is checked against the limit
+    ///
+    pub fn transfer_position(ctx: Context<TransferPosition>) -> Result<()> {
+        let position = *ctx.accounts.position_state.load()?.deref();
+        let liquidity_delta = i64::try_from(position.liquidity).unwrap();
+        ctx.accounts
+            .owner_liquidity_state
+            .load_mut()?
+            .update(-liquidity_delta)?;
+        {
+            let mut new_owner_liquidity = ctx.accounts.new_owner_liquidity_state.load_mut()?;
+            new_owner_liquidity.update(liquidity_delta)?;
+            ctx.accounts
+                .pool_state
+                .load()?
+                .check_liquidity_per_owner(new_owner_liquidity.liquidity)?;
+        }
+
+        let mut new_position = ctx.accounts.new_position_state.load_init()?;
+        *new_position = PositionState {
+            bump: *ctx.bumps.get("new_position_state").unwrap(),
+            ..position
+        };
+
+        emit!(TransferPositionEvent {
+            pool_state: ctx.accounts.pool_state.key(),
+            owner: ctx.accounts.owner.key(),
+            new_owner: ctx.accounts.new_owner.key(),
+            tick_lower: ctx.accounts.tick_lower_state.load()?.tick,
+            tick_upper: ctx.accounts.tick_upper_state.load()?.tick,
+            liquidity: position.liquidity,
+        });
+
         Ok(())
     }
 
@@ -458,6 +860,8 @@ pub mod cyclos_core {
     /// Callback function lies in core program instead of non_fungible_position_manager since
     /// reentrancy is disallowed in Solana. Integrators can use a second program to handle callbacks.
     ///
+    /// The minter must own the token accounts, or be their delegate approved for the amounts owed.
+    ///
     /// # Arguments
     ///
     /// * `amount_0_owed` - The amount of token_0 due to the pool for the minted liquidity
@@ -468,6 +872,7 @@ pub mod cyclos_core {
         amount_0_owed: u64,
         amount_1_owed: u64,
     ) -> Result<()> {
+        ctx.accounts.check_allowance(amount_0_owed, amount_1_owed)?;
         if amount_0_owed > 0 {
             token::transfer(
                 CpiContext::new(
@@ -501,6 +906,11 @@ pub mod cyclos_core {
     /// The caller of this method must be checked to be the core program.
     /// amount_0_delta and amount_1_delta can both be 0 if no tokens were swapped.
     ///
+    /// For exact input swaps the callback is made before swapping, with the specified input
+    /// as the delta of the input token and 0 for the output token, i.e. `(amount_specified, 0)`
+    /// if zero for one and `(0, amount_specified)` otherwise. The pool is locked during the
+    /// callback. For exact output swaps it is made after swapping, with the final deltas.
+    ///
     /// # Arguments
     ///
     /// * `ctx` - Token accounts for payment
@@ -547,15 +957,26 @@ pub mod cyclos_core {
 
     /// Adds liquidity for the given pool/recipient/tickLower/tickUpper position
     ///
+    /// The minted liquidity is added to the recipient's liquidity account for the pool, which
+    /// must be created with #init_owner_liquidity_account. If the pool limits liquidity per
+    /// owner, the limit is checked against the recipient's liquidity after the mint.
+    ///
+    /// The recipient is independent of the minter, and only the minter signs. When the core
+    /// program is the callback handler, the minter pays either as owner of the token accounts or
+    /// as their delegate, approved for the amounts owed.
+    ///
     /// # Arguments
     ///
     /// * `ctx` - Holds the recipient's address and program accounts for
     /// pool, position and ticks.
     /// * `amount` - The amount of liquidity to mint
+    /// * `deadline` - The last slot at which the mint can execute, or 0 for no deadline
     ///
+    #[access_control(check_slot_deadline(deadline))]
     pub fn mint<'a, 'b, 'c, 'info>(
         ctx: Context<'a, 'b, 'c, 'info, MintContext<'info>>,
         amount: u64,
+        deadline: u64,
     ) -> Result<()> {
         let mut pool = ctx.accounts.pool_state.load_mut()?;
 
@@ -609,6 +1030,15 @@ pub mod cyclos_core {
             tick_upper.tick,
         )?;
 
+        let owner_liquidity_state = AccountLoader::<OwnerLiquidityState>::try_from(
+            &ctx.accounts.owner_liquidity_state.to_account_info(),
+        )?;
+        pool.validate_owner_liquidity_address(
+            &ctx.accounts.owner_liquidity_state.key(),
+            owner_liquidity_state.load()?.bump,
+            &ctx.accounts.recipient.key(),
+        )?;
+
         let last_observation_state = AccountLoader::<ObservationState>::try_from(
             &ctx.accounts.last_observation_state.to_account_info(),
         )?;
@@ -621,8 +1051,13 @@ pub mod cyclos_core {
         require!(pool.unlocked, ErrorCode::LOK);
         pool.unlocked = false;
 
-        assert!(amount > 0);
+        require!(amount > 0, ErrorCode::ZeroLiquidity);
+        pool.check_tick_width(tick_lower.tick, tick_upper.tick)?;
 
+        let (tokens_owed_0_before, tokens_owed_1_before) = {
+            let position = position_state.load()?;
+            (position.tokens_owed_0, position.tokens_owed_1)
+        };
         let (amount_0_int, amount_1_int) = _modify_position(
             i64::try_from(amount).unwrap(),
             pool.deref_mut(),
@@ -635,6 +1070,35 @@ pub mod cyclos_core {
             ctx.remaining_accounts,
         )?;
 
+        let slot = Clock::get()?.slot;
+        let (forfeited_0, forfeited_1) = {
+            let mut position = position_state.load_mut()?;
+            let forfeited = position.forfeit_unheld_fees(
+                pool.deref_mut(),
+                tokens_owed_0_before,
+                tokens_owed_1_before,
+                slot,
+            );
+            position.mint_slot = slot;
+            forfeited
+        };
+        if forfeited_0 > 0 || forfeited_1 > 0 {
+            emit!(FeesForfeitedEvent {
+                pool_state: ctx.accounts.pool_state.key(),
+                owner: ctx.accounts.recipient.key(),
+                tick_lower: tick_lower.tick,
+                tick_upper: tick_upper.tick,
+                amount_0: forfeited_0,
+                amount_1: forfeited_1,
+            });
+        }
+
+        {
+            let mut owner_liquidity = owner_liquidity_state.load_mut()?;
+            owner_liquidity.update(i64::try_from(amount).unwrap())?;
+            pool.check_liquidity_per_owner(owner_liquidity.liquidity)?;
+        }
+
         let amount_0 = amount_0_int as u64;
         let amount_1 = amount_1_int as u64;
 
@@ -697,14 +1161,22 @@ pub mod cyclos_core {
     /// Can be used to trigger a recalculation of fees owed to a position by calling with an amount of 0 (poke).
     /// Fees must be collected separately via a call to #collect
     ///
+    /// Burning before the pool's minimum holding period has passed since the last mint forfeits
+    /// the fees credited by the burn to the remaining in range liquidity.
+    ///
+    /// The burned liquidity is removed from the owner's liquidity account for the pool.
+    ///
     /// # Arguments
     ///
     /// * `ctx` - Holds position and other validated accounts need to burn liquidity
     /// * `amount` - Amount of liquidity to be burned
+    /// * `deadline` - The last slot at which the burn can execute, or 0 for no deadline
     ///
+    #[access_control(check_slot_deadline(deadline))]
     pub fn burn<'a, 'b, 'c, 'info>(
         ctx: Context<'a, 'b, 'c, 'info, BurnContext<'info>>,
         amount: u64,
+        deadline: u64,
     ) -> Result<()> {
         let pool_state =
             AccountLoader::<PoolState>::try_from(&ctx.accounts.pool_state.to_account_info())?;
@@ -756,10 +1228,19 @@ pub mod cyclos_core {
             tick_upper.tick,
         )?;
 
-        let last_observation_state = AccountLoader::<ObservationState>::try_from(
-            &ctx.accounts.last_observation_state.to_account_info(),
+        let owner_liquidity_state = AccountLoader::<OwnerLiquidityState>::try_from(
+            &ctx.accounts.owner_liquidity_state.to_account_info(),
         )?;
-        pool.validate_observation_address(
+        pool.validate_owner_liquidity_address(
+            &ctx.accounts.owner_liquidity_state.key(),
+            owner_liquidity_state.load()?.bump,
+            &ctx.accounts.owner.key(),
+        )?;
+
+        let last_observation_state = AccountLoader::<ObservationState>::try_from(
+            &ctx.accounts.last_observation_state.to_account_info(),
+        )?;
+        pool.validate_observation_address(
             &ctx.accounts.last_observation_state.key(),
             last_observation_state.load()?.bump,
             false,
@@ -770,6 +1251,10 @@ pub mod cyclos_core {
         require!(pool.unlocked, ErrorCode::LOK);
         pool.unlocked = false;
 
+        let (tokens_owed_0_before, tokens_owed_1_before) = {
+            let position = position_state.load()?;
+            (position.tokens_owed_0, position.tokens_owed_1)
+        };
         let (amount_0_int, amount_1_int) = _modify_position(
             -i64::try_from(amount).unwrap(),
             pool.deref_mut(),
@@ -781,6 +1266,26 @@ pub mod cyclos_core {
             &last_observation_state,
             ctx.remaining_accounts,
         )?;
+        owner_liquidity_state
+            .load_mut()?
+            .update(-i64::try_from(amount).unwrap())?;
+
+        let (forfeited_0, forfeited_1) = position_state.load_mut()?.forfeit_unheld_fees(
+            pool.deref_mut(),
+            tokens_owed_0_before,
+            tokens_owed_1_before,
+            Clock::get()?.slot,
+        );
+        if forfeited_0 > 0 || forfeited_1 > 0 {
+            emit!(FeesForfeitedEvent {
+                pool_state: ctx.accounts.pool_state.key(),
+                owner: ctx.accounts.owner.key(),
+                tick_lower: tick_lower.tick,
+                tick_upper: tick_upper.tick,
+                amount_0: forfeited_0,
+                amount_1: forfeited_1,
+            });
+        }
 
         let amount_0 = (-amount_0_int) as u64;
         let amount_1 = (-amount_1_int) as u64;
@@ -806,20 +1311,34 @@ pub mod cyclos_core {
 
     /// Collects tokens owed to a position.
     ///
-    /// Does not recompute fees earned, which must be done either via mint or burn of any amount of liquidity.
+    /// Pokes the position first, so that fees earned up to the current fee growth are collected
+    /// without a prior mint or burn.
+    /// The collected amounts of token_0 and token_1 are set as borsh encoded return data, for
+    /// callers that cannot read the emitted event.
     /// Collect must be called by the position owner. To withdraw only token_0 or only token_1, amount_0_requested or
     /// amount_1_requested may be set to zero. To withdraw all tokens owed, caller may pass any value greater than the
     /// actual tokens owed, e.g. u64::MAX. Tokens owed may be from accumulated swap fees or burned liquidity.
     ///
+    /// A token owed less than `min_collect_amount` is not transferred and stays owed, to avoid
+    /// spending a transfer on dust. Pass None or zero to sweep everything owed.
+    ///
+    /// A transfer exceeding the vault balance fails the collect. Clients can check the owed
+    /// amounts with #can_cover_collect, and request at most the vault balance to collect in part.
+    ///
     /// # Arguments
     ///
     /// * `amount_0_requested` - How much token_0 should be withdrawn from the fees owed
     /// * `amount_1_requested` - How much token_1 should be withdrawn from the fees owed
+    /// * `min_collect_amount` - The smallest amount owed of a token that is worth collecting
+    /// * `deadline` - The last slot at which the collect can execute, or 0 for no deadline
     ///
+    #[access_control(check_slot_deadline(deadline))]
     pub fn collect(
         ctx: Context<CollectContext>,
         amount_0_requested: u64,
         amount_1_requested: u64,
+        min_collect_amount: Option<u64>,
+        deadline: u64,
     ) -> Result<()> {
         let pool_state =
             AccountLoader::<PoolState>::try_from(&ctx.accounts.pool_state.to_account_info())?;
@@ -859,8 +1378,50 @@ pub mod cyclos_core {
 
         let mut position = position_state.load_mut()?;
 
-        let amount_0 = amount_0_requested.min(position.tokens_owed_0);
-        let amount_1 = amount_1_requested.min(position.tokens_owed_1);
+        // realize the fees earned since the last update of the position
+        let (fee_growth_inside_0_x32, fee_growth_inside_1_x32) = tick::get_fee_growth_inside(
+            &tick_lower,
+            &tick_upper,
+            pool.tick,
+            pool.fee_growth_global_0_x32,
+            pool.fee_growth_global_1_x32,
+        );
+        let reward_growth_inside_x32 = tick::get_reward_growth_inside(
+            &tick_lower,
+            &tick_upper,
+            pool.tick,
+            pool.reward_growth_global_x32,
+        );
+        let (forfeited_0, forfeited_1) = position.poke_and_forfeit_unheld_fees(
+            pool.deref_mut(),
+            fee_growth_inside_0_x32,
+            fee_growth_inside_1_x32,
+            reward_growth_inside_x32,
+            Clock::get()?.slot,
+        )?;
+        if forfeited_0 > 0 || forfeited_1 > 0 {
+            emit!(FeesForfeitedEvent {
+                pool_state: ctx.accounts.pool_state.key(),
+                owner: ctx.accounts.owner.key(),
+                tick_lower: tick_lower.tick,
+                tick_upper: tick_upper.tick,
+                amount_0: forfeited_0,
+                amount_1: forfeited_1,
+            });
+        }
+
+        // dust below the threshold stays owed
+        let min_collect_amount = min_collect_amount.unwrap_or_default();
+        let amount_0 = if position.tokens_owed_0 < min_collect_amount {
+            0
+        } else {
+            amount_0_requested.min(position.tokens_owed_0)
+        };
+        let amount_1 = if position.tokens_owed_1 < min_collect_amount {
+            0
+        } else {
+            amount_1_requested.min(position.tokens_owed_1)
+        };
 
         let pool_state_seeds = [
             &POOL_SEED.as_bytes(),
@@ -910,11 +1471,253 @@ pub mod cyclos_core {
             amount_0,
             amount_1,
         });
+        solana_program::program::set_return_data(&(amount_0, amount_1).try_to_vec()?);
 
         pool_state.load_mut()?.unlocked = true;
         Ok(())
     }
 
+    /// Distributes bonus rewards to the liquidity in range of a pool. Anyone can add rewards.
+    ///
+    /// Rewards accrue to positions per unit of liquidity like swap fees, and are collected
+    /// with #collect_rewards. Positions still on a layout from before rewards earn only the
+    /// rewards added after their #migrate_position.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - Holds the pool, and the token accounts to transfer the rewards
+    /// * `amount` - The amount of the reward token to distribute
+    ///
+    pub fn add_rewards(ctx: Context<AddRewards>, amount: u64) -> Result<()> {
+        let mut pool = ctx.accounts.pool_state.load_mut()?;
+        require!(pool.unlocked, ErrorCode::LOK);
+        pool.add_rewards(amount)?;
+
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                token::Transfer {
+                    from: ctx.accounts.funder_wallet.to_account_info(),
+                    to: ctx.accounts.reward_vault.to_account_info(),
+                    authority: ctx.accounts.funder.to_account_info(),
+                },
+            ),
+            amount,
+        )?;
+
+        emit!(AddRewardsEvent {
+            pool_state: ctx.accounts.pool_state.key(),
+            funder: ctx.accounts.funder.key(),
+            amount,
+            liquidity: pool.liquidity,
+            reward_growth_global_x32: pool.reward_growth_global_x32,
+        });
+
+        Ok(())
+    }
+
+    /// Collects bonus rewards owed to a position.
+    ///
+    /// Pokes the position first, so that rewards earned up to the current reward growth are
+    /// collected. The collected amount is set as borsh encoded return data.
+    /// Must be called by the position owner. To withdraw all rewards owed, caller may pass any
+    /// value greater than the actual rewards owed, e.g. u64::MAX.
+    ///
+    /// # Arguments
+    ///
+    /// * `amount_requested` - How much of the reward token should be withdrawn
+    ///
+    pub fn collect_rewards(
+        ctx: Context<CollectRewardsContext>,
+        amount_requested: u64,
+    ) -> Result<()> {
+        let mut pool = ctx.accounts.pool_state.load_mut()?;
+
+        let tick_lower = *ctx.accounts.tick_lower_state.load()?.deref();
+        pool.validate_tick_address(
+            &ctx.accounts.tick_lower_state.key(),
+            tick_lower.bump,
+            tick_lower.tick,
+        )?;
+        let tick_upper = *ctx.accounts.tick_upper_state.load()?.deref();
+        pool.validate_tick_address(
+            &ctx.accounts.tick_upper_state.key(),
+            tick_upper.bump,
+            tick_upper.tick,
+        )?;
+
+        let mut position = ctx.accounts.position_state.load_mut()?;
+        pool.validate_position_address(
+            &ctx.accounts.position_state.key(),
+            position.bump,
+            &ctx.accounts.owner.key(),
+            tick_lower.tick,
+            tick_upper.tick,
+        )?;
+
+        require!(pool.unlocked, ErrorCode::LOK);
+        pool.unlocked = false;
+
+        // realize the fees and rewards earned since the last update of the position
+        let (fee_growth_inside_0_x32, fee_growth_inside_1_x32) = tick::get_fee_growth_inside(
+            &tick_lower,
+            &tick_upper,
+            pool.tick,
+            pool.fee_growth_global_0_x32,
+            pool.fee_growth_global_1_x32,
+        );
+        let reward_growth_inside_x32 = tick::get_reward_growth_inside(
+            &tick_lower,
+            &tick_upper,
+            pool.tick,
+            pool.reward_growth_global_x32,
+        );
+        let (forfeited_0, forfeited_1) = position.poke_and_forfeit_unheld_fees(
+            pool.deref_mut(),
+            fee_growth_inside_0_x32,
+            fee_growth_inside_1_x32,
+            reward_growth_inside_x32,
+            Clock::get()?.slot,
+        )?;
+        if forfeited_0 > 0 || forfeited_1 > 0 {
+            emit!(FeesForfeitedEvent {
+                pool_state: ctx.accounts.pool_state.key(),
+                owner: ctx.accounts.owner.key(),
+                tick_lower: tick_lower.tick,
+                tick_upper: tick_upper.tick,
+                amount_0: forfeited_0,
+                amount_1: forfeited_1,
+            });
+        }
+
+        let amount = amount_requested.min(position.rewards_owed);
+
+        let pool_state_seeds = [
+            &POOL_SEED.as_bytes(),
+            &pool.token_0.to_bytes() as &[u8],
+            &pool.token_1.to_bytes() as &[u8],
+            &pool.fee.to_be_bytes(),
+            &[pool.bump],
+        ];
+
+        drop(pool);
+        if amount > 0 {
+            position.rewards_owed -= amount;
+            token::transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    token::Transfer {
+                        from: ctx.accounts.reward_vault.to_account_info(),
+                        to: ctx.accounts.recipient_wallet.to_account_info(),
+                        authority: ctx.accounts.pool_state.to_account_info(),
+                    },
+                    &[&pool_state_seeds[..]],
+                ),
+                amount,
+            )?;
+        }
+
+        emit!(CollectRewardsEvent {
+            pool_state: ctx.accounts.pool_state.key(),
+            owner: ctx.accounts.owner.key(),
+            tick_lower: tick_lower.tick,
+            tick_upper: tick_upper.tick,
+            amount,
+        });
+        solana_program::program::set_return_data(&amount.try_to_vec()?);
+
+        ctx.accounts.pool_state.load_mut()?.unlocked = true;
+        Ok(())
+    }
+
+    /// Pokes many positions of a pool, crediting fees earned up to the current fee growth to
+    /// tokens owed. No tokens are transferred.
+    ///
+    /// Positions without liquidity earn no fees and are skipped, instead of failing with
+    /// CannotPokeEmptyPosition. Anyone can poke a position, since poking only realizes fees owed
+    /// to its owner. Positions still within the pool's holding period are skipped too.
+    ///
+    /// A PokeEvent is emitted for every group, skipped positions included, in the order the
+    /// groups are passed. Each event carries the index of its group, so indexers can correlate
+    /// events with the input positions.
+    ///
+    /// At most MAX_BATCH positions can be poked in one call.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - Holds the pool state. Each position is passed in remaining accounts as the
+    /// group `[owner, tick_lower_state, tick_upper_state, position_state]`
+    ///
+    pub fn poke_positions<'a, 'b, 'c, 'info>(
+        ctx: Context<'a, 'b, 'c, 'info, PokePositions<'info>>,
+    ) -> Result<()> {
+        let pool = *ctx.accounts.pool_state.load()?.deref();
+        require!(pool.unlocked, ErrorCode::LOK);
+        let slot = Clock::get()?.slot;
+
+        let groups = ctx.remaining_accounts.chunks_exact(4);
+        require!(groups.remainder().is_empty(), ErrorCode::PositionAccounts);
+        require!(groups.len() <= MAX_BATCH, ErrorCode::BatchTooLarge);
+
+        for (index, group) in groups.enumerate() {
+            let (owner, tick_lower_info, tick_upper_info, position_info) =
+                (&group[0], &group[1], &group[2], &group[3]);
+
+            let tick_lower_state = AccountLoader::<TickState>::try_from(tick_lower_info)?;
+            let tick_lower = *tick_lower_state.load()?.deref();
+            pool.validate_tick_address(tick_lower_info.key, tick_lower.bump, tick_lower.tick)?;
+
+            let tick_upper_state = AccountLoader::<TickState>::try_from(tick_upper_info)?;
+            let tick_upper = *tick_upper_state.load()?.deref();
+            pool.validate_tick_address(tick_upper_info.key, tick_upper.bump, tick_upper.tick)?;
+
+            let position_state = AccountLoader::<PositionState>::try_from(position_info)?;
+            let mut position = position_state.load_mut()?;
+            pool.validate_position_address(
+                position_info.key,
+                position.bump,
+                owner.key,
+                tick_lower.tick,
+                tick_upper.tick,
+            )?;
+
+            let (fee_growth_inside_0_x32, fee_growth_inside_1_x32) = tick::get_fee_growth_inside(
+                &tick_lower,
+                &tick_upper,
+                pool.tick,
+                pool.fee_growth_global_0_x32,
+                pool.fee_growth_global_1_x32,
+            );
+            let reward_growth_inside_x32 = tick::get_reward_growth_inside(
+                &tick_lower,
+                &tick_upper,
+                pool.tick,
+                pool.reward_growth_global_x32,
+            );
+            // fees of a position within its holding period are left to its owner's next
+            // update, which forfeits them, so that third parties cannot forfeit them instead
+            if position.is_held(slot, pool.min_holding_slots) {
+                position.poke(
+                    fee_growth_inside_0_x32,
+                    fee_growth_inside_1_x32,
+                    reward_growth_inside_x32,
+                )?;
+            }
+
+            // emitted within the iteration, so events follow the order of the groups
+            emit!(PokeEvent {
+                pool_state: ctx.accounts.pool_state.key(),
+                owner: owner.key(),
+                tick_lower: tick_lower.tick,
+                tick_upper: tick_upper.tick,
+                position_index: u16::try_from(index).unwrap(),
+                tokens_owed_0: position.tokens_owed_0,
+                tokens_owed_1: position.tokens_owed_1,
+            });
+        }
+        Ok(())
+    }
+
     // ---------------------------------------------------------------------
     // 4. Swap instructions
 
@@ -948,6 +1751,8 @@ pub mod cyclos_core {
         pub fee_growth_global_x32: u64,
         // amount of input token paid as protocol fee
         pub protocol_fee: u64,
+        // amount of input token paid as fees, including the protocol fee
+        pub fee_amount: u64,
         // the current liquidity in range
         pub liquidity: u64,
     }
@@ -974,6 +1779,21 @@ pub mod cyclos_core {
     ///
     /// Outstanding tokens must be paid in #swap_callback
     ///
+    /// Exact input is collected through the callback before swapping, and the amount received
+    /// by the vault is swapped. This supports tokens which take a fee on transfer. Input left
+    /// unswapped at the price limit is refunded.
+    ///
+    /// A swap larger than the liquidity up to the price limit is partially filled. Once no
+    /// liquidity is left in range, bitmaps past the last initialized tick can be omitted, and
+    /// the swap ends at the end of the bitmaps passed. A swap which fills nothing, since the
+    /// pool has no liquidity up to the price limit, fails with InsufficientLiquidity.
+    ///
+    /// Each tick account may be passed once, else the swap fails with DuplicateTickAccount.
+    ///
+    /// A referral fee, at most the pool's maximum referral fee, can be skimmed from the output
+    /// to a referral token account passed after the tick and bitmap accounts. The recipient
+    /// receives the rest of the output.
+    ///
     /// # Arguments
     ///
     /// * `ctx` - Accounts required for the swap. Remaining accounts should contain each bitmap leading to
@@ -985,27 +1805,62 @@ pub mod cyclos_core {
     /// * `sqrt_price_limit` - The Q32.32 sqrt price √P limit. If zero for one, the price cannot
     /// be less than this value after the swap.  If one for zero, the price cannot be greater than
     /// this value after the swap.
+    /// * `referral` - The referral token account and fee, if the swap pays a referral fee
+    /// * `no_gap` - Revert if the swap reaches a price range without liquidity before the specified
+    /// amount is filled, instead of partially filling
     ///
-    pub fn swap(
-        ctx: Context<SwapContext>,
+    pub fn swap<'a, 'b, 'c, 'info>(
+        ctx: Context<'a, 'b, 'c, 'info, SwapContext<'info>>,
         amount_specified: i64,
         sqrt_price_limit_x32: u64,
+        referral: Option<Referral>,
+        no_gap: bool,
     ) -> Result<()> {
         require!(amount_specified != 0, ErrorCode::AS);
-
-        let factory_state =
-            AccountLoader::<FactoryState>::try_from(&ctx.accounts.factory_state.to_account_info())?;
+        tick::check_unique_tick_accounts(ctx.remaining_accounts)?;
 
         let pool_loader =
             AccountLoader::<PoolState>::try_from(&ctx.accounts.pool_state.to_account_info())?;
-        let mut pool = pool_loader.load_mut()?;
+        let exact_input = amount_specified > 0;
+        let (zero_for_one, input_token_account, output_token_account) = ctx
+            .accounts
+            .validate(&pool_loader.key(), &*pool_loader.load()?)?;
+
+        // lock before the up front callback, so that it cannot reenter the pool
+        {
+            let mut pool = pool_loader.load_mut()?;
+            require!(pool.unlocked, ErrorCode::LOK);
+            pool.unlocked = false;
+        }
 
-        let input_token_account =
-            Account::<TokenAccount>::try_from(&ctx.accounts.input_token_account)?;
-        let output_token_account =
-            Account::<TokenAccount>::try_from(&ctx.accounts.output_token_account)?;
+        // Collect exact input up front and swap the amount the vault actually received,
+        // since tokens with a transfer fee deliver less than the amount sent
+        let amount_specified = if exact_input {
+            let balance_before = ctx.accounts.input_vault.amount;
+            let swap_callback_ix = if zero_for_one {
+                cyclos_core::instruction::SwapCallback {
+                    amount_0_delta: amount_specified,
+                    amount_1_delta: 0,
+                }
+            } else {
+                cyclos_core::instruction::SwapCallback {
+                    amount_0_delta: 0,
+                    amount_1_delta: amount_specified,
+                }
+            };
+            let ix = Instruction::new_with_bytes(
+                ctx.accounts.callback_handler.key(),
+                &swap_callback_ix.data(),
+                ctx.accounts.to_account_metas(None),
+            );
+            solana_program::program::invoke(&ix, &ctx.accounts.to_account_infos())?;
+            ctx.accounts.input_vault.reload()?;
+            received_amount_in(balance_before, ctx.accounts.input_vault.amount)?
+        } else {
+            amount_specified
+        };
 
-        let zero_for_one = ctx.accounts.input_vault.mint == pool.token_0;
+        let mut pool = pool_loader.load_mut()?;
 
         let (token_account_0, token_account_1, mut vault_0, mut vault_1) = if zero_for_one {
             (
@@ -1022,8 +1877,6 @@ pub mod cyclos_core {
                 ctx.accounts.input_vault.clone(),
             )
         };
-        assert!(vault_0.key() == get_associated_token_address(&pool_loader.key(), &pool.token_0));
-        assert!(vault_1.key() == get_associated_token_address(&pool_loader.key(), &pool.token_1));
 
         let last_observation_state = AccountLoader::<ObservationState>::try_from(
             &ctx.accounts.last_observation_state.to_account_info(),
@@ -1034,7 +1887,11 @@ pub mod cyclos_core {
             false,
         )?;
 
-        require!(pool.unlocked, ErrorCode::LOK);
+        // a swap must make progress, a limit at the current price fills nothing
+        require!(
+            sqrt_price_limit_x32 != pool.sqrt_price_x32,
+            ErrorCode::InvalidPriceLimit
+        );
         require!(
             if zero_for_one {
                 sqrt_price_limit_x32 < pool.sqrt_price_x32
@@ -1046,18 +1903,15 @@ pub mod cyclos_core {
             ErrorCode::SPL
         );
 
-        pool.unlocked = false;
         let mut cache = SwapCache {
             liquidity_start: pool.liquidity,
             block_timestamp: oracle::_block_timestamp(),
-            fee_protocol: factory_state.load()?.fee_protocol,
+            fee_protocol: pool.fee_protocol,
             seconds_per_liquidity_cumulative_x32: 0,
             tick_cumulative: 0,
             computed_latest_observation: false,
         };
 
-        let exact_input = amount_specified > 0;
-
         let mut state = SwapState {
             amount_specified_remaining: amount_specified,
             amount_calculated: 0,
@@ -1069,6 +1923,7 @@ pub mod cyclos_core {
                 pool.fee_growth_global_1_x32
             },
             protocol_fee: 0,
+            fee_amount: 0,
             liquidity: cache.liquidity_start,
         };
 
@@ -1082,6 +1937,11 @@ pub mod cyclos_core {
         // reached the price limit
         while state.amount_specified_remaining != 0 && state.sqrt_price_x32 != sqrt_price_limit_x32
         {
+            // the swap has entered a zero liquidity region with amount still remaining
+            if no_gap && state.liquidity == 0 {
+                return Err(ErrorCode::LiquidityGapTraversed.into());
+            }
+
             let mut step = StepComputations::default();
             step.sqrt_price_start_x32 = state.sqrt_price_x32;
 
@@ -1101,23 +1961,28 @@ pub mod cyclos_core {
             // load the next bitmap account if cache is empty (first loop instance), or if we have
             // crossed out of this bitmap
             if bitmap_cache.is_none() || bitmap_cache.unwrap().word_pos != word_pos {
-                let bitmap_account = remaining_accounts.next().unwrap();
                 msg!("check bitmap {}", word_pos);
                 // ensure this is a valid PDA, even if account is not initialized
-                assert!(
-                    bitmap_account.key()
-                        == Pubkey::find_program_address(
-                            &[
-                                BITMAP_SEED.as_bytes(),
-                                pool.token_0.as_ref(),
-                                pool.token_1.as_ref(),
-                                &pool.fee.to_be_bytes(),
-                                &word_pos.to_be_bytes(),
-                            ],
-                            &cyclos_core::id()
-                        )
-                        .0
-                );
+                let bitmap_key = Pubkey::find_program_address(
+                    &[
+                        BITMAP_SEED.as_bytes(),
+                        pool.token_0.as_ref(),
+                        pool.token_1.as_ref(),
+                        &pool.fee.to_be_bytes(),
+                        &word_pos.to_be_bytes(),
+                    ],
+                    &cyclos_core::id(),
+                )
+                .0;
+                let bitmap_account = match remaining_accounts.as_slice().first() {
+                    Some(account) if account.key() == bitmap_key => {
+                        remaining_accounts.next().unwrap()
+                    }
+                    // all liquidity up to the searched price was consumed, and no bitmaps beyond
+                    // it were passed. The swap is partially filled, refunding the unswapped input
+                    _ if state.liquidity == 0 => break,
+                    _ => return Err(ErrorCode::MissingBitmapAccount.into()),
+                };
 
                 msg!("check if bitmap is initialized");
                 // read from bitmap if account is initialized, else use default values for next initialized bit
@@ -1178,6 +2043,7 @@ pub mod cyclos_core {
             step.amount_in = swap_step.amount_in;
             step.amount_out = swap_step.amount_out;
             step.fee_amount = swap_step.fee_amount;
+            state.fee_amount += step.fee_amount;
 
             if exact_input {
                 state.amount_specified_remaining -=
@@ -1195,18 +2061,32 @@ pub mod cyclos_core {
             }
 
             // if the protocol fee is on, calculate how much is owed, decrement fee_amount, and increment protocol_fee
-            if cache.fee_protocol > 0 {
+            if cache.fee_protocol > 0 && step.fee_amount > 0 {
                 let delta = step.fee_amount / cache.fee_protocol as u64;
                 step.fee_amount -= delta;
                 state.protocol_fee += delta;
             }
 
             // update global fee tracker
-            if state.liquidity > 0 {
-                state.fee_growth_global_x32 += step
-                    .fee_amount
-                    .mul_div_floor(fixed_point_32::Q32, state.liquidity)
-                    .unwrap();
+            if state.liquidity > 0 && step.fee_amount > 0 {
+                let (fee_growth_global_x32, wrapped) = add_fee_growth(
+                    state.fee_growth_global_x32,
+                    step.fee_amount
+                        .mul_div_floor(fixed_point_32::Q32, state.liquidity)
+                        .unwrap(),
+                );
+                state.fee_growth_global_x32 = fee_growth_global_x32;
+                if wrapped {
+                    emit!(FeeGrowthRolloverEvent {
+                        pool_state: pool_loader.key(),
+                        token: if zero_for_one {
+                            pool.token_0
+                        } else {
+                            pool.token_1
+                        },
+                        new_value: fee_growth_global_x32,
+                    });
+                }
             }
 
             // shift tick if we reached the next price
@@ -1229,13 +2109,8 @@ pub mod cyclos_core {
 
                     msg!("loading tick {}", step.tick_next);
                     let tick_loader =
-                        AccountLoader::<TickState>::try_from(remaining_accounts.next().unwrap())?;
+                        pool.load_next_tick_account(&mut remaining_accounts, step.tick_next)?;
                     let mut tick_state = tick_loader.load_mut()?;
-                    pool.validate_tick_address(
-                        &tick_loader.key(),
-                        tick_state.bump,
-                        step.tick_next,
-                    )?;
                     let mut liquidity_net = tick_state.deref_mut().cross(
                         if zero_for_one {
                             state.fee_growth_global_x32
@@ -1247,11 +2122,19 @@ pub mod cyclos_core {
                         } else {
                             state.fee_growth_global_x32
                         },
+                        pool.reward_growth_global_x32,
                         cache.seconds_per_liquidity_cumulative_x32,
                         cache.tick_cumulative,
                         cache.block_timestamp,
                     );
 
+                    emit!(TickCrossEvent {
+                        pool_state: pool_loader.key(),
+                        tick: step.tick_next,
+                        liquidity_net,
+                        zero_for_one,
+                    });
+
                     // if we're moving leftward, we interpret liquidity_net as the opposite sign
                     // safe because liquidity_net cannot be i64::MIN
                     if zero_for_one {
@@ -1274,6 +2157,11 @@ pub mod cyclos_core {
                 state.tick = tick_math::get_tick_at_sqrt_ratio(state.sqrt_price_x32)?;
             }
         }
+        // a swap that finds no liquidity at all fails, unlike a partial fill
+        require!(
+            state.amount_specified_remaining != amount_specified,
+            ErrorCode::InsufficientLiquidity
+        );
         let partition_current_timestamp = cache.block_timestamp / 14;
         let partition_last_timestamp = latest_observation.block_timestamp / 14;
         drop(latest_observation);
@@ -1302,13 +2190,14 @@ pub mod cyclos_core {
                 last_observation_state.load_mut()?
             };
             pool.tick = state.tick;
-            pool.observation_cardinality_next = next_observation.update(
+            pool.observation_cardinality = next_observation.update(
                 cache.block_timestamp,
                 pool.tick,
                 cache.liquidity_start,
                 pool.observation_cardinality,
                 pool.observation_cardinality_next,
             );
+            pool.observation_index = next_observation.index;
         }
         pool.sqrt_price_x32 = state.sqrt_price_x32;
 
@@ -1330,6 +2219,7 @@ pub mod cyclos_core {
                 pool.protocol_fees_token_1 += state.protocol_fee;
             }
         }
+        pool.record_swap_fees(zero_for_one, state.fee_amount);
 
         let (amount_0, amount_1) = if zero_for_one == exact_input {
             (
@@ -1343,6 +2233,25 @@ pub mod cyclos_core {
             )
         };
 
+        // skim the referral fee from the output paid by the pool
+        let referral_fee = match referral {
+            Some(referral) => {
+                let amount_out = if zero_for_one { amount_1 } else { amount_0 }.neg() as u64;
+                let referral_account = ctx
+                    .remaining_accounts
+                    .iter()
+                    .find(|account| account.key() == referral.token_account)
+                    .ok_or(ErrorCode::MissingReferralAccount)?;
+                Some((
+                    referral,
+                    referral_account,
+                    pool.referral_fee(amount_out, referral.fee_bps)?,
+                ))
+            }
+            None => None,
+        };
+        let referral_amount = referral_fee.map_or(0, |(_, _, amount)| amount);
+
         // do the transfers and collect payment
         let pool_state_seeds = [
             &POOL_SEED.as_bytes(),
@@ -1368,27 +2277,29 @@ pub mod cyclos_core {
                         },
                         &[&pool_state_seeds[..]],
                     ),
-                    amount_1.neg() as u64,
+                    amount_1.neg() as u64 - referral_amount,
                 )?;
             }
-            let balance_0_before = vault_0.amount;
+            if !exact_input {
+                let balance_0_before = vault_0.amount;
 
-            // transfer tokens to pool in callback
-            let swap_callback_ix = cyclos_core::instruction::SwapCallback {
-                amount_0_delta: amount_0,
-                amount_1_delta: amount_1,
-            };
-            let ix = Instruction::new_with_bytes(
-                ctx.accounts.callback_handler.key(),
-                &swap_callback_ix.data(),
-                ctx.accounts.to_account_metas(None),
-            );
-            solana_program::program::invoke(&ix, &ctx.accounts.to_account_infos())?;
-            vault_0.reload()?;
-            require!(
-                balance_0_before.checked_add(amount_0 as u64).unwrap() <= vault_0.amount,
-                ErrorCode::IIA
-            );
+                // transfer tokens to pool in callback
+                let swap_callback_ix = cyclos_core::instruction::SwapCallback {
+                    amount_0_delta: amount_0,
+                    amount_1_delta: amount_1,
+                };
+                let ix = Instruction::new_with_bytes(
+                    ctx.accounts.callback_handler.key(),
+                    &swap_callback_ix.data(),
+                    ctx.accounts.to_account_metas(None),
+                );
+                solana_program::program::invoke(&ix, &ctx.accounts.to_account_infos())?;
+                vault_0.reload()?;
+                require!(
+                    balance_0_before.checked_add(amount_0 as u64).unwrap() <= vault_0.amount,
+                    ErrorCode::IIA
+                );
+            }
         } else {
             if amount_0 < 0 {
                 msg!("paying {}", amount_0.neg());
@@ -1402,26 +2313,73 @@ pub mod cyclos_core {
                         },
                         &[&pool_state_seeds[..]],
                     ),
-                    amount_0.neg() as u64,
+                    amount_0.neg() as u64 - referral_amount,
+                )?;
+            }
+            if !exact_input {
+                let balance_1_before = vault_1.amount;
+                // transfer tokens to pool in callback
+                let swap_callback_ix = cyclos_core::instruction::SwapCallback {
+                    amount_0_delta: amount_0,
+                    amount_1_delta: amount_1,
+                };
+                let ix = Instruction::new_with_bytes(
+                    ctx.accounts.callback_handler.key(),
+                    &swap_callback_ix.data(),
+                    ctx.accounts.to_account_metas(None),
+                );
+                solana_program::program::invoke(&ix, &ctx.accounts.to_account_infos())?;
+                vault_1.reload()?;
+                require!(
+                    balance_1_before.checked_add(amount_1 as u64).unwrap() <= vault_1.amount,
+                    ErrorCode::IIA
+                );
+            }
+        }
+
+        if let Some((referral, referral_account, amount)) = referral_fee {
+            if amount > 0 {
+                let output_vault = if zero_for_one { &vault_1 } else { &vault_0 };
+                token::transfer(
+                    CpiContext::new_with_signer(
+                        ctx.accounts.token_program.to_account_info().clone(),
+                        token::Transfer {
+                            from: output_vault.to_account_info().clone(),
+                            to: referral_account.clone(),
+                            authority: ctx.accounts.pool_state.to_account_info().clone(),
+                        },
+                        &[&pool_state_seeds[..]],
+                    ),
+                    amount,
                 )?;
             }
-            let balance_1_before = vault_1.amount;
-            // transfer tokens to pool in callback
-            let swap_callback_ix = cyclos_core::instruction::SwapCallback {
-                amount_0_delta: amount_0,
-                amount_1_delta: amount_1,
+            emit!(ReferralFeeEvent {
+                pool_state: pool_loader.key(),
+                referral_token_account: referral.token_account,
+                fee_bps: referral.fee_bps,
+                amount
+            });
+        }
+
+        // refund input collected up front but left unswapped at the price limit
+        if exact_input && state.amount_specified_remaining > 0 {
+            let (input_vault, input_token_account) = if zero_for_one {
+                (&vault_0, &token_account_0)
+            } else {
+                (&vault_1, &token_account_1)
             };
-            let ix = Instruction::new_with_bytes(
-                ctx.accounts.callback_handler.key(),
-                &swap_callback_ix.data(),
-                ctx.accounts.to_account_metas(None),
-            );
-            solana_program::program::invoke(&ix, &ctx.accounts.to_account_infos())?;
-            vault_1.reload()?;
-            require!(
-                balance_1_before.checked_add(amount_1 as u64).unwrap() <= vault_1.amount,
-                ErrorCode::IIA
-            );
+            token::transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info().clone(),
+                    token::Transfer {
+                        from: input_vault.to_account_info().clone(),
+                        to: input_token_account.to_account_info().clone(),
+                        authority: ctx.accounts.pool_state.to_account_info().clone(),
+                    },
+                    &[&pool_state_seeds[..]],
+                ),
+                state.amount_specified_remaining as u64,
+            )?;
         }
 
         emit!(SwapEvent {
@@ -1433,7 +2391,8 @@ pub mod cyclos_core {
             amount_1,
             sqrt_price_x32: state.sqrt_price_x32,
             liquidity: state.liquidity,
-            tick: state.tick
+            tick: state.tick,
+            value_in_quote: pool_loader.load()?.value_in_quote(amount_0, amount_1)
         });
         pool_loader.load_mut()?.unlocked = true;
 
@@ -1512,6 +2471,7 @@ pub mod cyclos_core {
             callback_handler: UncheckedAccount::try_from(
                 ctx.accounts.core_program.to_account_info(),
             ),
+            owner_liquidity_state: ctx.accounts.owner_liquidity_state.clone(),
         };
 
         let (liquidity, amount_0, amount_1) = add_liquidity(
@@ -1678,6 +2638,7 @@ pub mod cyclos_core {
             callback_handler: UncheckedAccount::try_from(
                 ctx.accounts.core_program.to_account_info(),
             ),
+            owner_liquidity_state: ctx.accounts.owner_liquidity_state.clone(),
         };
 
         let (liquidity, amount_0, amount_1) = add_liquidity(
@@ -1699,13 +2660,13 @@ pub mod cyclos_core {
 
         // Update tokenized position metadata
         let mut position = ctx.accounts.tokenized_position_state.load_mut()?;
-        position.tokens_owed_0 += (fee_growth_inside_0_last_x32
-            - position.fee_growth_inside_0_last_x32)
+        position.tokens_owed_0 += fee_growth_inside_0_last_x32
+            .wrapping_sub(position.fee_growth_inside_0_last_x32)
             .mul_div_floor(position.liquidity, fixed_point_32::Q32)
             .unwrap();
 
-        position.tokens_owed_1 += (fee_growth_inside_1_last_x32
-            - position.fee_growth_inside_1_last_x32)
+        position.tokens_owed_1 += fee_growth_inside_1_last_x32
+            .wrapping_sub(position.fee_growth_inside_1_last_x32)
             .mul_div_floor(position.liquidity, fixed_point_32::Q32)
             .unwrap();
 
@@ -1761,6 +2722,7 @@ pub mod cyclos_core {
             bitmap_upper_state: ctx.accounts.bitmap_upper_state.clone(),
             position_state,
             last_observation_state: ctx.accounts.last_observation_state.clone(),
+            owner_liquidity_state: ctx.accounts.owner_liquidity_state.clone(),
         };
         burn(
             Context::new(
@@ -1770,6 +2732,7 @@ pub mod cyclos_core {
                 BTreeMap::default(),
             ),
             liquidity,
+            0,
         )?;
         let updated_core_position = accounts.position_state.load()?;
         let amount_0 = updated_core_position.tokens_owed_0 - tokens_owed_0_before;
@@ -1785,12 +2748,14 @@ pub mod cyclos_core {
 
         let mut tokenized_position = ctx.accounts.tokenized_position_state.load_mut()?;
         tokenized_position.tokens_owed_0 += amount_0
-            + (fee_growth_inside_0_last_x32 - tokenized_position.fee_growth_inside_0_last_x32)
+            + fee_growth_inside_0_last_x32
+                .wrapping_sub(tokenized_position.fee_growth_inside_0_last_x32)
                 .mul_div_floor(tokenized_position.liquidity, fixed_point_32::Q32)
                 .unwrap();
 
         tokenized_position.tokens_owed_1 += amount_1
-            + (fee_growth_inside_1_last_x32 - tokenized_position.fee_growth_inside_1_last_x32)
+            + fee_growth_inside_1_last_x32
+                .wrapping_sub(tokenized_position.fee_growth_inside_1_last_x32)
                 .mul_div_floor(tokenized_position.liquidity, fixed_point_32::Q32)
                 .unwrap();
 
@@ -1825,97 +2790,301 @@ pub mod cyclos_core {
     ) -> Result<()> {
         assert!(amount_0_max > 0 || amount_1_max > 0);
 
-        let mut tokenized_position = ctx.accounts.tokenized_position_state.load_mut()?;
-        let mut tokens_owed_0 = tokenized_position.tokens_owed_0;
-        let mut tokens_owed_1 = tokenized_position.tokens_owed_1;
-
-        let position_state = AccountLoader::<PositionState>::try_from(
-            &ctx.accounts.core_position_state.to_account_info(),
-        )?;
-
-        // trigger an update of the position fees owed and fee growth snapshots if it has any liquidity
-        if tokenized_position.liquidity > 0 {
-            let mut core_position_owner = ctx.accounts.factory_state.to_account_info();
-            core_position_owner.is_signer = true;
-            let mut burn_accounts = BurnContext {
-                owner: Signer::try_from(&core_position_owner)?,
-                pool_state: ctx.accounts.pool_state.clone(),
-                tick_lower_state: ctx.accounts.tick_lower_state.clone(),
-                tick_upper_state: ctx.accounts.tick_upper_state.clone(),
-                bitmap_lower_state: ctx.accounts.bitmap_lower_state.clone(),
-                bitmap_upper_state: ctx.accounts.bitmap_upper_state.clone(),
-                position_state,
-                last_observation_state: ctx.accounts.last_observation_state.clone(),
-            };
-            burn(
-                Context::new(
-                    &ID,
-                    &mut burn_accounts,
-                    ctx.remaining_accounts,
-                    BTreeMap::default(),
-                ),
-                0,
-            )?;
-
-            let core_position = *burn_accounts.position_state.load()?.deref();
-
-            tokens_owed_0 += (core_position.fee_growth_inside_0_last_x32
-                - tokenized_position.fee_growth_inside_0_last_x32)
-                .mul_div_floor(tokenized_position.liquidity, fixed_point_32::Q32)
-                .unwrap();
-            tokens_owed_1 += (core_position.fee_growth_inside_1_last_x32
-                - tokenized_position.fee_growth_inside_1_last_x32)
-                .mul_div_floor(tokenized_position.liquidity, fixed_point_32::Q32)
-                .unwrap();
-
-            tokenized_position.fee_growth_inside_0_last_x32 =
-                core_position.fee_growth_inside_0_last_x32;
-            tokenized_position.fee_growth_inside_1_last_x32 =
-                core_position.fee_growth_inside_1_last_x32;
-        }
-
-        // adjust amounts to the max for the position
-        let amount_0 = amount_0_max.min(tokens_owed_0);
-        let amount_1 = amount_1_max.min(tokens_owed_1);
-
-        let mut core_position_owner = ctx.accounts.factory_state.to_account_info().clone();
-        core_position_owner.is_signer = true;
+        collect_tokenized_position(
+            ctx.accounts,
+            ctx.remaining_accounts,
+            &mut CollectBudget {
+                amount_0: amount_0_max,
+                amount_1: amount_1_max,
+            },
+        )
+    }
 
-        msg!("withdrawing amounts {} {}", amount_0, amount_1);
-        msg!("vault balances {} {}", ctx.accounts.vault_0.amount, ctx.accounts.vault_1.amount);
+    /// Decreases the liquidity of a tokenized position and collects everything it is owed, the
+    /// withdrawn principal along with the fees realized by the burn, in one instruction
+    ///
+    /// Runs #decrease_liquidity followed by #collect_from_tokenized for all owed tokens, emitting
+    /// a BurnEvent and a CollectEvent among their events.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - Holds the pool, tick, bitmap, position and token accounts
+    /// * `liquidity` - The amount by which liquidity will be decreased
+    /// * `amount_0_min` - The minimum amount of token_0 that should be accounted for the burned liquidity
+    /// * `amount_1_min` - The minimum amount of token_1 that should be accounted for the burned liquidity
+    /// * `deadline` - The time by which the transaction must be included to effect the change
+    ///
+    pub fn decrease_liquidity_and_collect<'a, 'b, 'c, 'info>(
+        ctx: Context<'a, 'b, 'c, 'info, DecreaseLiquidityAndCollect<'info>>,
+        liquidity: u64,
+        amount_0_min: u64,
+        amount_1_min: u64,
+        deadline: i64,
+    ) -> Result<()> {
+        let mut decrease_accounts = DecreaseLiquidity {
+            owner_or_delegate: ctx.accounts.owner_or_delegate.clone(),
+            nft_account: ctx.accounts.nft_account.clone(),
+            tokenized_position_state: ctx.accounts.tokenized_position_state.clone(),
+            factory_state: ctx.accounts.factory_state.clone(),
+            pool_state: ctx.accounts.pool_state.clone(),
+            core_position_state: ctx.accounts.core_position_state.clone(),
+            owner_liquidity_state: ctx.accounts.owner_liquidity_state.clone(),
+            tick_lower_state: ctx.accounts.tick_lower_state.clone(),
+            tick_upper_state: ctx.accounts.tick_upper_state.clone(),
+            bitmap_lower_state: ctx.accounts.bitmap_lower_state.clone(),
+            bitmap_upper_state: ctx.accounts.bitmap_upper_state.clone(),
+            last_observation_state: ctx.accounts.last_observation_state.clone(),
+            core_program: ctx.accounts.core_program.clone(),
+        };
+        decrease_liquidity(
+            Context::new(
+                &ID,
+                &mut decrease_accounts,
+                ctx.remaining_accounts,
+                BTreeMap::default(),
+            ),
+            liquidity,
+            amount_0_min,
+            amount_1_min,
+            deadline,
+        )?;
 
-        let mut accounts = CollectContext {
-            owner: Signer::try_from(&core_position_owner)?,
+        let mut collect_accounts = CollectFromTokenized {
+            owner_or_delegate: ctx.accounts.owner_or_delegate.clone(),
+            nft_account: ctx.accounts.nft_account.clone(),
+            tokenized_position_state: ctx.accounts.tokenized_position_state.clone(),
+            factory_state: ctx.accounts.factory_state.clone(),
             pool_state: ctx.accounts.pool_state.clone(),
+            core_position_state: ctx.accounts.core_position_state.clone(),
+            owner_liquidity_state: ctx.accounts.owner_liquidity_state.clone(),
             tick_lower_state: ctx.accounts.tick_lower_state.clone(),
             tick_upper_state: ctx.accounts.tick_upper_state.clone(),
-            position_state: ctx.accounts.core_position_state.clone(),
+            bitmap_lower_state: ctx.accounts.bitmap_lower_state.clone(),
+            bitmap_upper_state: ctx.accounts.bitmap_upper_state.clone(),
+            last_observation_state: ctx.accounts.last_observation_state.clone(),
             vault_0: ctx.accounts.vault_0.clone(),
             vault_1: ctx.accounts.vault_1.clone(),
             recipient_wallet_0: ctx.accounts.recipient_wallet_0.clone(),
             recipient_wallet_1: ctx.accounts.recipient_wallet_1.clone(),
+            core_program: ctx.accounts.core_program.clone(),
             token_program: ctx.accounts.token_program.clone(),
         };
-        collect(
-            Context::new(&ID, &mut accounts, &[], BTreeMap::default()),
-            amount_0,
-            amount_1,
+        collect_from_tokenized(
+            Context::new(
+                &ID,
+                &mut collect_accounts,
+                ctx.remaining_accounts,
+                BTreeMap::default(),
+            ),
+            u64::MAX,
+            u64::MAX,
+        )
+    }
+
+    /// Collects all tokens owed to a tokenized position to the signer's token accounts, and swaps
+    /// the collected amount of one token for the other through the position's pool
+    ///
+    /// The owed amounts vary with fees, so the swap leg has its own slippage check. Nothing is
+    /// swapped if none of the input token was collected, and the minimum is then not enforced.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - Holds the pool, tick, bitmap, position and token accounts. The tick and
+    /// bitmap accounts crossed by the swap, followed by the next observation account, are
+    /// passed in remaining accounts as for #swap
+    /// * `zero_for_one` - Whether collected token_0 is swapped for token_1, or the reverse
+    /// * `zap_amount_out_minimum` - The minimum amount received from the swap
+    ///
+    pub fn collect_and_zap<'a, 'b, 'c, 'info>(
+        ctx: Context<'a, 'b, 'c, 'info, CollectAndZap<'info>>,
+        zero_for_one: bool,
+        zap_amount_out_minimum: u64,
+    ) -> Result<()> {
+        let accounts = &ctx.accounts;
+        let (input_token_account, output_token_account) = if zero_for_one {
+            (&accounts.token_account_0, &accounts.token_account_1)
+        } else {
+            (&accounts.token_account_1, &accounts.token_account_0)
+        };
+        let (input_vault, output_vault) = if zero_for_one {
+            (&accounts.vault_0, &accounts.vault_1)
+        } else {
+            (&accounts.vault_1, &accounts.vault_0)
+        };
+        let input_balance_before = input_token_account.amount;
+
+        let mut collect_accounts = CollectFromTokenized {
+            owner_or_delegate: ctx.accounts.owner_or_delegate.clone(),
+            nft_account: ctx.accounts.nft_account.clone(),
+            tokenized_position_state: ctx.accounts.tokenized_position_state.clone(),
+            factory_state: ctx.accounts.factory_state.clone(),
+            pool_state: ctx.accounts.pool_state.clone(),
+            core_position_state: ctx.accounts.core_position_state.clone(),
+            owner_liquidity_state: ctx.accounts.owner_liquidity_state.clone(),
+            tick_lower_state: ctx.accounts.tick_lower_state.clone(),
+            tick_upper_state: ctx.accounts.tick_upper_state.clone(),
+            bitmap_lower_state: ctx.accounts.bitmap_lower_state.clone(),
+            bitmap_upper_state: ctx.accounts.bitmap_upper_state.clone(),
+            last_observation_state: ctx.accounts.last_observation_state.clone(),
+            vault_0: ctx.accounts.vault_0.clone(),
+            vault_1: ctx.accounts.vault_1.clone(),
+            recipient_wallet_0: UncheckedAccount::try_from(
+                ctx.accounts.token_account_0.to_account_info(),
+            ),
+            recipient_wallet_1: UncheckedAccount::try_from(
+                ctx.accounts.token_account_1.to_account_info(),
+            ),
+            core_program: ctx.accounts.core_program.clone(),
+            token_program: ctx.accounts.token_program.clone(),
+        };
+        collect_from_tokenized(
+            Context::new(&ID, &mut collect_accounts, &[], BTreeMap::default()),
+            u64::MAX,
+            u64::MAX,
         )?;
 
-        // sometimes there will be a few less wei than expected due to rounding down in core, but
-        // we just subtract the full amount expected
-        // instead of the actual amount so we can burn the token
-        tokenized_position.tokens_owed_0 = tokens_owed_0 - amount_0;
-        tokenized_position.tokens_owed_1 = tokens_owed_1 - amount_1;
+        let mut input_token_account = input_token_account.clone();
+        input_token_account.reload()?;
+        let amount_in = input_token_account.amount - input_balance_before;
+        if amount_in == 0 {
+            return Ok(());
+        }
 
-        emit!(CollectTokenizedEvent {
-            token_id: tokenized_position.mint,
-            recipient_wallet_0: ctx.accounts.recipient_wallet_0.key(),
-            recipient_wallet_1: ctx.accounts.recipient_wallet_1.key(),
-            amount_0,
-            amount_1
-        });
+        // balances cached before the collect are stale
+        let mut output_token_account = output_token_account.clone();
+        output_token_account.reload()?;
+        let output_balance_before = output_token_account.amount;
+        let mut input_vault = input_vault.clone();
+        input_vault.reload()?;
+        let mut output_vault = output_vault.clone();
+        output_vault.reload()?;
+        exact_input_internal(
+            &mut SwapContext {
+                signer: ctx.accounts.owner_or_delegate.clone(),
+                factory_state: UncheckedAccount::try_from(
+                    ctx.accounts.factory_state.to_account_info(),
+                ),
+                input_token_account: UncheckedAccount::try_from(
+                    input_token_account.to_account_info(),
+                ),
+                output_token_account: UncheckedAccount::try_from(
+                    output_token_account.to_account_info(),
+                ),
+                input_vault,
+                output_vault,
+                token_program: ctx.accounts.token_program.clone(),
+                pool_state: ctx.accounts.pool_state.clone(),
+                last_observation_state: ctx.accounts.last_observation_state.clone(),
+                callback_handler: UncheckedAccount::try_from(
+                    ctx.accounts.core_program.to_account_info(),
+                ),
+            },
+            ctx.remaining_accounts,
+            amount_in,
+            0,
+        )?;
 
+        output_token_account.reload()?;
+        require!(
+            output_token_account.amount - output_balance_before >= zap_amount_out_minimum,
+            ErrorCode::TooLittleReceived
+        );
+        Ok(())
+    }
+
+    /// Collects all fees owed to a batch of tokenized positions of one pool to the same recipient
+    /// token accounts, such as the distribution accounts of a vault issuing the position NFTs.
+    ///
+    /// The signer must own, or be the delegate of, every position NFT. Each position is collected
+    /// as by #collect_from_tokenized, emitting a CollectEvent and a CollectTokenizedEvent in the
+    /// order the groups are passed.
+    ///
+    /// Every position is transferred on its own, and the transfers of the batch are bounded by
+    /// the vault balances at the start of the call. Positions are paid in the order passed
+    /// until a vault is empty, and whatever the later positions are owed beyond the balance left
+    /// stays owed to them. A batch whose owed fees add up to more than u64::MAX therefore
+    /// collects the vault balances in full without overflowing. At most MAX_BATCH positions
+    /// can be collected in one call.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - Holds the pool, vaults and recipient token accounts. Each position is passed in
+    /// remaining accounts as the group `[nft_account, tokenized_position_state,
+    /// core_position_state, tick_lower_state, tick_upper_state, bitmap_lower_state,
+    /// bitmap_upper_state]`, and must belong to the pool
+    ///
+    pub fn collect_from_tokenized_batch<'a, 'b, 'c, 'info>(
+        ctx: Context<'a, 'b, 'c, 'info, CollectFromTokenizedBatch<'info>>,
+    ) -> Result<()> {
+        let groups = ctx.remaining_accounts.chunks_exact(7);
+        require!(
+            !ctx.remaining_accounts.is_empty() && groups.remainder().is_empty(),
+            ErrorCode::PositionAccounts
+        );
+        require!(groups.len() <= MAX_BATCH, ErrorCode::BatchTooLarge);
+
+        // the positions share the vault balances, so the batch transfers no more than the
+        // vaults hold however much the positions are owed together
+        let mut budget = CollectBudget {
+            amount_0: ctx.accounts.vault_0.amount,
+            amount_1: ctx.accounts.vault_1.amount,
+        };
+        for group in groups {
+            let nft_account = Box::new(Account::<TokenAccount>::try_from(&group[0])?);
+            let tokenized_position_state =
+                AccountLoader::<TokenizedPositionState>::try_from(&group[1])?;
+            {
+                let tokenized_position = tokenized_position_state.load()?;
+                require!(
+                    tokenized_position.pool_id == ctx.accounts.pool_state.key(),
+                    ErrorCode::PoolMismatch
+                );
+                require!(
+                    nft_account.mint == tokenized_position.mint,
+                    ErrorCode::NotApproved
+                );
+            }
+
+            let mut accounts = CollectFromTokenized {
+                owner_or_delegate: ctx.accounts.owner_or_delegate.clone(),
+                nft_account,
+                tokenized_position_state,
+                factory_state: ctx.accounts.factory_state.clone(),
+                pool_state: ctx.accounts.pool_state.clone(),
+                core_position_state: UncheckedAccount::try_from(group[2].clone()),
+                owner_liquidity_state: ctx.accounts.owner_liquidity_state.clone(),
+                tick_lower_state: UncheckedAccount::try_from(group[3].clone()),
+                tick_upper_state: UncheckedAccount::try_from(group[4].clone()),
+                bitmap_lower_state: UncheckedAccount::try_from(group[5].clone()),
+                bitmap_upper_state: UncheckedAccount::try_from(group[6].clone()),
+                last_observation_state: ctx.accounts.last_observation_state.clone(),
+                vault_0: ctx.accounts.vault_0.clone(),
+                vault_1: ctx.accounts.vault_1.clone(),
+                recipient_wallet_0: ctx.accounts.recipient_wallet_0.clone(),
+                recipient_wallet_1: ctx.accounts.recipient_wallet_1.clone(),
+                core_program: ctx.accounts.core_program.clone(),
+                token_program: ctx.accounts.token_program.clone(),
+            };
+            is_authorized_for_token(&accounts.owner_or_delegate, &accounts.nft_account)?;
+            collect_tokenized_position(&mut accounts, &[], &mut budget)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the pool, range, liquidity and owed fees of a tokenized position, found by the
+    /// mint of its NFT
+    ///
+    /// The `TokenizedPositionInfo` is set as borsh encoded return data. Owed fees are those
+    /// credited to the position by its last update.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - Holds the NFT mint and the tokenized position derived from it
+    ///
+    pub fn tokenized_position_info(ctx: Context<TokenizedPositionInfoContext>) -> Result<()> {
+        let info = ctx.accounts.tokenized_position_state.load()?.info();
+        solana_program::program::set_return_data(&info.try_to_vec()?);
         Ok(())
     }
 
@@ -1967,6 +3136,64 @@ pub mod cyclos_core {
         Ok(())
     }
 
+    /// Swaps `amount_in` of one token for as much as possible of another token,
+    /// across a single pool. Reverts if the spot tick of the pool diverges from the
+    /// time weighted average tick by more than `max_tick_deviation` ticks.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - Accounts required for the swap, and a past observation from which
+    /// the TWAP is measured
+    /// * `deadline` - The time by which the transaction must be included to effect the change
+    /// * `amount_in` - Token amount to be swapped in
+    /// * `amount_out_minimum` - The minimum amount to swap out, which serves as a slippage check
+    /// * `sqrt_price_limit` - The Q32.32 sqrt price √P limit. If zero for one, the price cannot
+    /// be less than this value after the swap.  If one for zero, the price cannot be greater than
+    /// this value after the swap.
+    /// * `max_tick_deviation` - The permitted distance between the spot and TWAP ticks
+    ///
+    #[access_control(check_deadline(deadline))]
+    pub fn exact_input_single_with_oracle_check<'a, 'b, 'c, 'info>(
+        ctx: Context<'a, 'b, 'c, 'info, ExactInputSingleWithOracleCheck<'info>>,
+        deadline: i64,
+        amount_in: u64,
+        amount_out_minimum: u64,
+        sqrt_price_limit_x32: u64,
+        max_tick_deviation: u32,
+    ) -> Result<()> {
+        check_oracle_price_deviation(
+            &ctx.accounts.pool_state,
+            &ctx.accounts.last_observation_state,
+            &ctx.accounts.past_observation_state,
+            max_tick_deviation,
+        )?;
+
+        let amount_out = exact_input_internal(
+            &mut SwapContext {
+                signer: ctx.accounts.signer.clone(),
+                factory_state: ctx.accounts.factory_state.clone(),
+                input_token_account: ctx.accounts.input_token_account.clone(),
+                output_token_account: ctx.accounts.output_token_account.clone(),
+                input_vault: ctx.accounts.input_vault.clone(),
+                output_vault: ctx.accounts.output_vault.clone(),
+                token_program: ctx.accounts.token_program.clone(),
+                pool_state: ctx.accounts.pool_state.clone(),
+                last_observation_state: ctx.accounts.last_observation_state.clone(),
+                callback_handler: UncheckedAccount::try_from(
+                    ctx.accounts.core_program.to_account_info(),
+                ),
+            },
+            ctx.remaining_accounts,
+            amount_in,
+            sqrt_price_limit_x32,
+        )?;
+        require!(
+            amount_out >= amount_out_minimum,
+            ErrorCode::TooLittleReceived
+        );
+        Ok(())
+    }
+
     /// Swaps `amount_in` of one token for as much as possible of another token,
     /// across the path provided
     ///
@@ -1978,6 +3205,8 @@ pub mod cyclos_core {
     /// * `amount_out_minimum` - Panic if output amount is below minimum amount. For slippage.
     /// * `additional_accounts_per_pool` - Additional observation, bitmap and tick accounts per pool
     ///
+    /// The path is validated before any swap, and must be connected through distinct pools.
+    ///
     #[access_control(check_deadline(deadline))]
     pub fn exact_input<'a, 'b, 'c, 'info>(
         ctx: Context<'a, 'b, 'c, 'info, ExactInput<'info>>,
@@ -1986,6 +3215,25 @@ pub mod cyclos_core {
         amount_out_minimum: u64,
         additional_accounts_per_pool: Vec<u8>,
     ) -> Result<()> {
+        let mut hops = Vec::with_capacity(additional_accounts_per_pool.len());
+        let mut path_accounts = ctx.remaining_accounts.iter();
+        for additional_accounts in additional_accounts_per_pool.iter() {
+            let pool = path_accounts.next().unwrap().key();
+            path_accounts.next(); // output token account
+            let input_vault = Account::<TokenAccount>::try_from(path_accounts.next().unwrap())?;
+            let output_vault = Account::<TokenAccount>::try_from(path_accounts.next().unwrap())?;
+            path_accounts.next(); // last observation
+            hops.push(SwapHop {
+                pool,
+                token_in: input_vault.mint,
+                token_out: output_vault.mint,
+            });
+            for _j in 0..*additional_accounts {
+                path_accounts.next();
+            }
+        }
+        validate_swap_path(&hops)?;
+
         let mut remaining_accounts = ctx.remaining_accounts.iter();
 
         let mut amount_in_internal = amount_in;
@@ -2040,6 +3288,309 @@ pub mod cyclos_core {
         Ok(())
     }
 
+    /// Swaps `amount_in` of one token for as much as possible of another token,
+    /// split across two pools of the same pair, e.g. the 0.05% and 0.3% fee tiers
+    ///
+    /// Each leg is an exact input swap in its own pool. The slippage check applies
+    /// to the combined output. A leg with no input is skipped.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - Accounts required for both legs. Remaining accounts hold the bitmap, tick and
+    /// next observation accounts of the first pool, followed by those of the second pool
+    /// * `deadline` - The time by which the transaction must be included to effect the change
+    /// * `amount_in` - Token amount to be swapped in
+    /// * `amount_out_minimum` - The minimum combined amount to swap out, which serves as a
+    /// slippage check
+    /// * `share_a` - The share of `amount_in` swapped in the first pool, expressed in hundredths
+    /// of a bip (1/100 x 0.01% = 10^6)
+    /// * `accounts_a` - The number of remaining accounts used by the first pool, at most the
+    /// number of remaining accounts
+    ///
+    #[access_control(check_deadline(deadline))]
+    pub fn swap_split<'a, 'b, 'c, 'info>(
+        ctx: Context<'a, 'b, 'c, 'info, ExactInputSplit<'info>>,
+        deadline: i64,
+        amount_in: u64,
+        amount_out_minimum: u64,
+        share_a: u32,
+        accounts_a: u8,
+    ) -> Result<()> {
+        require!(share_a <= 1_000_000, ErrorCode::InvalidSplit);
+        require!(
+            accounts_a as usize <= ctx.remaining_accounts.len(),
+            ErrorCode::InvalidSplit
+        );
+        {
+            let pool_a = AccountLoader::<PoolState>::try_from(&ctx.accounts.pool_state_a)?;
+            let pool_b = AccountLoader::<PoolState>::try_from(&ctx.accounts.pool_state_b)?;
+            let (pool_a, pool_b) = (pool_a.load()?, pool_b.load()?);
+            require!(
+                ctx.accounts.pool_state_a.key() != ctx.accounts.pool_state_b.key()
+                    && pool_a.token_0 == pool_b.token_0
+                    && pool_a.token_1 == pool_b.token_1,
+                ErrorCode::PoolMismatch
+            );
+        }
+
+        let (amount_in_a, amount_in_b) = swap_math::split_amount_in(amount_in, share_a);
+        let (remaining_accounts_a, remaining_accounts_b) =
+            ctx.remaining_accounts.split_at(accounts_a as usize);
+        let legs = [
+            (
+                amount_in_a,
+                &ctx.accounts.pool_state_a,
+                &ctx.accounts.input_vault_a,
+                &ctx.accounts.output_vault_a,
+                &ctx.accounts.last_observation_state_a,
+                remaining_accounts_a,
+            ),
+            (
+                amount_in_b,
+                &ctx.accounts.pool_state_b,
+                &ctx.accounts.input_vault_b,
+                &ctx.accounts.output_vault_b,
+                &ctx.accounts.last_observation_state_b,
+                remaining_accounts_b,
+            ),
+        ];
+
+        let balance_before = ctx.accounts.output_token_account.amount;
+        for (amount_in, pool_state, input_vault, output_vault, last_observation_state, remaining) in
+            legs
+        {
+            if amount_in == 0 {
+                continue;
+            }
+            exact_input_internal(
+                &mut SwapContext {
+                    signer: ctx.accounts.signer.clone(),
+                    factory_state: ctx.accounts.factory_state.clone(),
+                    input_token_account: ctx.accounts.input_token_account.clone(),
+                    output_token_account: UncheckedAccount::try_from(
+                        ctx.accounts.output_token_account.to_account_info(),
+                    ),
+                    input_vault: input_vault.clone(),
+                    output_vault: output_vault.clone(),
+                    token_program: ctx.accounts.token_program.clone(),
+                    pool_state: pool_state.clone(),
+                    last_observation_state: last_observation_state.clone(),
+                    callback_handler: UncheckedAccount::try_from(
+                        ctx.accounts.core_program.to_account_info(),
+                    ),
+                },
+                remaining,
+                amount_in,
+                0,
+            )?;
+        }
+
+        ctx.accounts.output_token_account.reload()?;
+        require!(
+            ctx.accounts.output_token_account.amount - balance_before >= amount_out_minimum,
+            ErrorCode::TooLittleReceived
+        );
+        Ok(())
+    }
+
+    /// Moves liquidity of a position into a new price range. Burns liquidity from the old range,
+    /// swaps the excess token to match the token ratio of the new range, and mints the new range.
+    ///
+    /// All tokens owed to the old position, including uncollected fees, are collected to the
+    /// owner's token accounts before the swap. The swap is skipped if the owner already holds
+    /// the token ratio needed, e.g. for a single sided range holding only the required token.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - Holds the old and new positions, and the owner's token accounts. Remaining accounts
+    /// hold the bitmap and tick accounts for the swap, followed by the next observation if needed
+    /// * `liquidity` - The amount of liquidity to burn from the old range
+    /// * `amount_out_minimum` - The minimum amount to swap out, which serves as a slippage check
+    /// * `deadline` - The time by which the transaction must be included to effect the change
+    ///
+    #[access_control(check_deadline(deadline))]
+    pub fn rebalance<'a, 'b, 'c, 'info>(
+        ctx: Context<'a, 'b, 'c, 'info, Rebalance<'info>>,
+        liquidity: u64,
+        amount_out_minimum: u64,
+        deadline: i64,
+    ) -> Result<()> {
+        require!(liquidity > 0, ErrorCode::ZeroBurnLiquidity);
+
+        let pool_state =
+            AccountLoader::<PoolState>::try_from(&ctx.accounts.pool_state.to_account_info())?;
+        let observation_index_before = pool_state.load()?.observation_index;
+        let balance_0_before = ctx.accounts.token_account_0.amount;
+        let balance_1_before = ctx.accounts.token_account_1.amount;
+        let next_observation_state = [ctx.accounts.next_observation_state.to_account_info()];
+
+        // Burn the old range and withdraw all tokens owed
+        burn(
+            Context::new(
+                &ID,
+                &mut BurnContext {
+                    owner: ctx.accounts.owner.clone(),
+                    pool_state: ctx.accounts.pool_state.clone(),
+                    tick_lower_state: ctx.accounts.tick_lower_state.clone(),
+                    tick_upper_state: ctx.accounts.tick_upper_state.clone(),
+                    bitmap_lower_state: ctx.accounts.bitmap_lower_state.clone(),
+                    bitmap_upper_state: ctx.accounts.bitmap_upper_state.clone(),
+                    position_state: AccountLoader::<PositionState>::try_from(
+                        &ctx.accounts.position_state.to_account_info(),
+                    )?,
+                    last_observation_state: ctx.accounts.last_observation_state.clone(),
+                    owner_liquidity_state: ctx.accounts.owner_liquidity_state.clone(),
+                },
+                &next_observation_state,
+                BTreeMap::default(),
+            ),
+            liquidity,
+            0,
+        )?;
+        collect(
+            Context::new(
+                &ID,
+                &mut CollectContext {
+                    owner: ctx.accounts.owner.clone(),
+                    pool_state: ctx.accounts.pool_state.clone(),
+                    tick_lower_state: ctx.accounts.tick_lower_state.clone(),
+                    tick_upper_state: ctx.accounts.tick_upper_state.clone(),
+                    position_state: ctx.accounts.position_state.clone(),
+                    vault_0: ctx.accounts.vault_0.clone(),
+                    vault_1: ctx.accounts.vault_1.clone(),
+                    recipient_wallet_0: UncheckedAccount::try_from(
+                        ctx.accounts.token_account_0.to_account_info(),
+                    ),
+                    recipient_wallet_1: UncheckedAccount::try_from(
+                        ctx.accounts.token_account_1.to_account_info(),
+                    ),
+                    token_program: ctx.accounts.token_program.clone(),
+                },
+                &[],
+                BTreeMap::default(),
+            ),
+            u64::MAX,
+            u64::MAX,
+            None,
+            0,
+        )?;
+        // vault balances cached before the collect are stale
+        ctx.accounts.vault_0.reload()?;
+        ctx.accounts.vault_1.reload()?;
+
+        // The burn writes the next observation if the current tick lies in the old range
+        let last_observation_state =
+            if pool_state.load()?.observation_index != observation_index_before {
+                ctx.accounts.next_observation_state.clone()
+            } else {
+                ctx.accounts.last_observation_state.clone()
+            };
+
+        let tick_lower = ctx.accounts.new_tick_lower_state.load()?.tick;
+        let tick_upper = ctx.accounts.new_tick_upper_state.load()?.tick;
+
+        // Swap the excess token to obtain the token ratio of the new range
+        ctx.accounts.token_account_0.reload()?;
+        ctx.accounts.token_account_1.reload()?;
+        let (zero_for_one, amount_in) = liquidity_amounts::get_rebalance_swap_amount(
+            pool_state.load()?.sqrt_price_x32,
+            tick_math::get_sqrt_ratio_at_tick(tick_lower)?,
+            tick_math::get_sqrt_ratio_at_tick(tick_upper)?,
+            ctx.accounts.token_account_0.amount - balance_0_before,
+            ctx.accounts.token_account_1.amount - balance_1_before,
+        )?;
+        if amount_in > 0 {
+            let (input_token_account, output_token_account, input_vault, output_vault) =
+                if zero_for_one {
+                    (
+                        &ctx.accounts.token_account_0,
+                        &mut ctx.accounts.token_account_1,
+                        &ctx.accounts.vault_0,
+                        &ctx.accounts.vault_1,
+                    )
+                } else {
+                    (
+                        &ctx.accounts.token_account_1,
+                        &mut ctx.accounts.token_account_0,
+                        &ctx.accounts.vault_1,
+                        &ctx.accounts.vault_0,
+                    )
+                };
+            let output_balance_before = output_token_account.amount;
+            exact_input_internal(
+                &mut SwapContext {
+                    signer: ctx.accounts.owner.clone(),
+                    factory_state: ctx.accounts.factory_state.clone(),
+                    input_token_account: UncheckedAccount::try_from(
+                        input_token_account.to_account_info(),
+                    ),
+                    output_token_account: UncheckedAccount::try_from(
+                        output_token_account.to_account_info(),
+                    ),
+                    input_vault: input_vault.clone(),
+                    output_vault: output_vault.clone(),
+                    token_program: ctx.accounts.token_program.clone(),
+                    pool_state: ctx.accounts.pool_state.clone(),
+                    last_observation_state: last_observation_state.clone(),
+                    callback_handler: UncheckedAccount::try_from(
+                        ctx.accounts.core_program.to_account_info(),
+                    ),
+                },
+                ctx.remaining_accounts,
+                amount_in,
+                0,
+            )?;
+            output_token_account.reload()?;
+            require!(
+                output_token_account.amount - output_balance_before >= amount_out_minimum,
+                ErrorCode::TooLittleReceived
+            );
+        }
+
+        // Mint the new range with the rebalanced tokens
+        ctx.accounts.token_account_0.reload()?;
+        ctx.accounts.token_account_1.reload()?;
+        let amount_0_desired = ctx.accounts.token_account_0.amount - balance_0_before;
+        let amount_1_desired = ctx.accounts.token_account_1.amount - balance_1_before;
+        ctx.accounts.vault_0.reload()?;
+        ctx.accounts.vault_1.reload()?;
+        add_liquidity(
+            &mut MintContext {
+                minter: ctx.accounts.owner.clone(),
+                token_account_0: UncheckedAccount::try_from(
+                    ctx.accounts.token_account_0.to_account_info(),
+                ),
+                token_account_1: UncheckedAccount::try_from(
+                    ctx.accounts.token_account_1.to_account_info(),
+                ),
+                vault_0: ctx.accounts.vault_0.clone(),
+                vault_1: ctx.accounts.vault_1.clone(),
+                recipient: UncheckedAccount::try_from(ctx.accounts.owner.to_account_info()),
+                pool_state,
+                tick_lower_state: ctx.accounts.new_tick_lower_state.clone(),
+                tick_upper_state: ctx.accounts.new_tick_upper_state.clone(),
+                bitmap_lower_state: ctx.accounts.new_bitmap_lower_state.clone(),
+                bitmap_upper_state: ctx.accounts.new_bitmap_upper_state.clone(),
+                position_state: ctx.accounts.new_position_state.clone(),
+                last_observation_state,
+                token_program: ctx.accounts.token_program.clone(),
+                callback_handler: UncheckedAccount::try_from(
+                    ctx.accounts.core_program.to_account_info(),
+                ),
+                owner_liquidity_state: ctx.accounts.owner_liquidity_state.clone(),
+            },
+            &next_observation_state,
+            amount_0_desired,
+            amount_1_desired,
+            0,
+            0,
+            tick_lower,
+            tick_upper,
+        )?;
+
+        Ok(())
+    }
+
     //  /// Swaps as little as possible of one token for `amount_out` of another token,
     // /// across a single pool
     // ///
@@ -2083,6 +3634,224 @@ pub mod cyclos_core {
     // }
 }
 
+/// Grows the data of an account owned by the program, zeroing the added bytes
+///
+/// Mirrors `AccountInfo::realloc` from later Solana releases. The serialized data length
+/// lives in the 8 bytes before the data, and the runtime reserves
+/// `MAX_PERMITTED_DATA_INCREASE` bytes after it for the account to grow into.
+///
+/// # Arguments
+///
+/// * `account` - An account passed to the program entrypoint
+/// * `len` - The new data length, no smaller than the current one
+///
+pub fn realloc_zeroed(account: &AccountInfo, len: usize) -> Result<()> {
+    let old_len = account.data_len();
+    require!(
+        len >= old_len && len - old_len <= solana_program::entrypoint::MAX_PERMITTED_DATA_INCREASE,
+        ErrorCode::PL
+    );
+
+    let mut data = account.try_borrow_mut_data()?;
+    let data_ptr = data.as_mut_ptr();
+    // SAFETY: the entrypoint serializes the length right before the account data, and
+    // leaves MAX_PERMITTED_DATA_INCREASE writable bytes after it
+    unsafe {
+        *(data_ptr.offset(-8) as *mut u64) = len as u64;
+        *data = std::slice::from_raw_parts_mut(data_ptr, len);
+    }
+    data[old_len..].fill(0);
+
+    Ok(())
+}
+
+/// Creates and initializes observation accounts for a pool, starting at its
+/// `observation_cardinality_next` index
+///
+/// The slots are left uninitialized, to be written to once the cardinality grows into them.
+/// `observation_cardinality_next` is not updated here.
+///
+/// # Arguments
+///
+/// * `pool_state` - The pool whose observation slots are created
+/// * `payer` - Pays rent for the new accounts
+/// * `system_program` - To create the accounts
+/// * `observation_accounts` - The observation account addresses, in order of their index
+/// * `observation_account_bumps` - Bumps of the observation state PDAs, one per account
+/// * `program_id` - The program owning the accounts
+///
+pub fn create_observation_accounts<'info>(
+    pool_state: &PoolState,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    observation_accounts: &[AccountInfo<'info>],
+    observation_account_bumps: &[u8],
+    program_id: &Pubkey,
+) -> Result<()> {
+    for (i, bump) in observation_account_bumps.iter().enumerate() {
+        let index = pool_state.observation_cardinality_next + i as u16;
+        let observation_account_seeds = [
+            &OBSERVATION_SEED.as_bytes(),
+            pool_state.token_0.as_ref(),
+            pool_state.token_1.as_ref(),
+            &pool_state.fee.to_be_bytes(),
+            &index.to_be_bytes(),
+            &[*bump],
+        ];
+
+        require!(
+            observation_accounts[i].key()
+                == Pubkey::create_program_address(&observation_account_seeds[..], program_id)
+                    .unwrap(),
+            ErrorCode::OS
+        );
+
+        let space = 8 + size_of::<ObservationState>();
+        let rent = Rent::get()?;
+        let lamports = rent.minimum_balance(space);
+        let ix = create_account(
+            payer.key,
+            observation_accounts[i].key,
+            lamports,
+            space as u64,
+            program_id,
+        );
+
+        solana_program::program::invoke_signed(
+            &ix,
+            &[
+                payer.clone(),
+                observation_accounts[i].clone(),
+                system_program.clone(),
+            ],
+            &[&observation_account_seeds[..]],
+        )?;
+
+        let observation_state_loader = AccountLoader::<ObservationState>::try_from_unchecked(
+            program_id,
+            &observation_accounts[i],
+        )?;
+        let mut observation_state = observation_state_loader.load_init()?;
+        // this data will not be used because the initialized boolean is still false
+        observation_state.bump = *bump;
+        observation_state.index = index;
+        observation_state.block_timestamp = 1;
+
+        drop(observation_state);
+        observation_state_loader.exit(program_id)?;
+    }
+    Ok(())
+}
+
+/// Collects the fees owed to a tokenized position, at most the amounts left in the budget,
+/// and deducts the amounts collected from the budget
+pub fn collect_tokenized_position<'info>(
+    accounts: &mut CollectFromTokenized<'info>,
+    remaining_accounts: &[AccountInfo<'info>],
+    budget: &mut CollectBudget,
+) -> Result<()> {
+    let mut tokenized_position = accounts.tokenized_position_state.load_mut()?;
+    let mut tokens_owed_0 = tokenized_position.tokens_owed_0;
+    let mut tokens_owed_1 = tokenized_position.tokens_owed_1;
+
+    let position_state =
+        AccountLoader::<PositionState>::try_from(&accounts.core_position_state.to_account_info())?;
+
+    // trigger an update of the position fees owed and fee growth snapshots if it has any liquidity
+    if tokenized_position.liquidity > 0 {
+        let mut core_position_owner = accounts.factory_state.to_account_info();
+        core_position_owner.is_signer = true;
+        let mut burn_accounts = BurnContext {
+            owner: Signer::try_from(&core_position_owner)?,
+            pool_state: accounts.pool_state.clone(),
+            tick_lower_state: accounts.tick_lower_state.clone(),
+            tick_upper_state: accounts.tick_upper_state.clone(),
+            bitmap_lower_state: accounts.bitmap_lower_state.clone(),
+            bitmap_upper_state: accounts.bitmap_upper_state.clone(),
+            position_state,
+            last_observation_state: accounts.last_observation_state.clone(),
+            owner_liquidity_state: accounts.owner_liquidity_state.clone(),
+        };
+        burn(
+            Context::new(
+                &ID,
+                &mut burn_accounts,
+                remaining_accounts,
+                BTreeMap::default(),
+            ),
+            0,
+            0,
+        )?;
+
+        let core_position = *burn_accounts.position_state.load()?.deref();
+
+        tokens_owed_0 += core_position
+            .fee_growth_inside_0_last_x32
+            .wrapping_sub(tokenized_position.fee_growth_inside_0_last_x32)
+            .mul_div_floor(tokenized_position.liquidity, fixed_point_32::Q32)
+            .unwrap();
+        tokens_owed_1 += core_position
+            .fee_growth_inside_1_last_x32
+            .wrapping_sub(tokenized_position.fee_growth_inside_1_last_x32)
+            .mul_div_floor(tokenized_position.liquidity, fixed_point_32::Q32)
+            .unwrap();
+
+        tokenized_position.fee_growth_inside_0_last_x32 =
+            core_position.fee_growth_inside_0_last_x32;
+        tokenized_position.fee_growth_inside_1_last_x32 =
+            core_position.fee_growth_inside_1_last_x32;
+    }
+
+    // adjust amounts to the max for the position
+    let (amount_0, amount_1) = budget.take(tokens_owed_0, tokens_owed_1);
+
+    let mut core_position_owner = accounts.factory_state.to_account_info().clone();
+    core_position_owner.is_signer = true;
+
+    msg!("withdrawing amounts {} {}", amount_0, amount_1);
+    msg!(
+        "vault balances {} {}",
+        accounts.vault_0.amount,
+        accounts.vault_1.amount
+    );
+
+    let mut accounts = CollectContext {
+        owner: Signer::try_from(&core_position_owner)?,
+        pool_state: accounts.pool_state.clone(),
+        tick_lower_state: accounts.tick_lower_state.clone(),
+        tick_upper_state: accounts.tick_upper_state.clone(),
+        position_state: accounts.core_position_state.clone(),
+        vault_0: accounts.vault_0.clone(),
+        vault_1: accounts.vault_1.clone(),
+        recipient_wallet_0: accounts.recipient_wallet_0.clone(),
+        recipient_wallet_1: accounts.recipient_wallet_1.clone(),
+        token_program: accounts.token_program.clone(),
+    };
+    collect(
+        Context::new(&ID, &mut accounts, &[], BTreeMap::default()),
+        amount_0,
+        amount_1,
+        None,
+        0,
+    )?;
+
+    // sometimes there will be a few less wei than expected due to rounding down in core, but
+    // we just subtract the full amount expected
+    // instead of the actual amount so we can burn the token
+    tokenized_position.tokens_owed_0 = tokens_owed_0 - amount_0;
+    tokenized_position.tokens_owed_1 = tokens_owed_1 - amount_1;
+
+    emit!(CollectTokenizedEvent {
+        token_id: tokenized_position.mint,
+        recipient_wallet_0: accounts.recipient_wallet_0.key(),
+        recipient_wallet_1: accounts.recipient_wallet_1.key(),
+        amount_0,
+        amount_1
+    });
+
+    Ok(())
+}
+
 /// Performs a single exact input swap
 pub fn exact_input_internal<'info>(
     accounts: &mut SwapContext<'info>,
@@ -2106,12 +3875,49 @@ pub fn exact_input_internal<'info>(
         } else {
             sqrt_price_limit_x32
         },
+        None,
+        false,
     )?;
 
     accounts.input_vault.reload()?;
     Ok(accounts.input_vault.amount - balance_before)
 }
 
+/// Compares the spot tick of a pool against the time weighted average tick,
+/// measured from a past observation up to the current block timestamp
+///
+/// # Arguments
+///
+/// * `pool_state` - The pool to check
+/// * `last_observation_state` - The most recent observation of the pool
+/// * `past_observation_state` - An older observation marking the start of the TWAP window
+/// * `max_tick_deviation` - The permitted distance between the spot and TWAP ticks
+///
+pub fn check_oracle_price_deviation(
+    pool_state: &AccountInfo,
+    last_observation_state: &AccountInfo,
+    past_observation_state: &AccountInfo,
+    max_tick_deviation: u32,
+) -> Result<()> {
+    let pool = *AccountLoader::<PoolState>::try_from(pool_state)?.load()?;
+
+    let last_observation =
+        *AccountLoader::<ObservationState>::try_from(last_observation_state)?.load()?;
+    pool.validate_observation_address(last_observation_state.key, last_observation.bump, false)?;
+    let past_observation =
+        *AccountLoader::<ObservationState>::try_from(past_observation_state)?.load()?;
+    pool.validate_observation_address_at(
+        past_observation_state.key,
+        past_observation.bump,
+        past_observation.index,
+    )?;
+
+    let time = oracle::_block_timestamp();
+    let (tick_cumulative, _) = last_observation.observe_latest(time, pool.tick, pool.liquidity);
+    let twap_tick = past_observation.twap_tick_since(tick_cumulative, time)?;
+    oracle::check_price_deviation(pool.tick, twap_tick, max_tick_deviation)
+}
+
 /// Common checks for a valid tick input.
 /// A tick is valid iff it lies within tick boundaries and it is a multiple
 /// of tick spacing.
@@ -2199,7 +4005,7 @@ pub fn _modify_position<'info>(
                 tick_math::get_sqrt_ratio_at_tick(tick_lower)?,
                 tick_math::get_sqrt_ratio_at_tick(tick_upper)?,
                 liquidity_delta,
-            );
+            )?;
         } else if pool_state.tick < tick_upper {
             // current tick is inside the passed range
             // write oracle observation
@@ -2224,7 +4030,7 @@ pub fn _modify_position<'info>(
                 last_observation_state.load_mut()?
             };
 
-            pool_state.observation_cardinality_next = new_observation.update(
+            pool_state.observation_cardinality = new_observation.update(
                 timestamp,
                 pool_state.tick,
                 pool_state.liquidity,
@@ -2238,12 +4044,12 @@ pub fn _modify_position<'info>(
                 pool_state.sqrt_price_x32,
                 tick_math::get_sqrt_ratio_at_tick(tick_upper)?,
                 liquidity_delta,
-            );
+            )?;
             amount_1 = sqrt_price_math::get_amount_1_delta_signed(
                 tick_math::get_sqrt_ratio_at_tick(tick_lower)?,
                 pool_state.sqrt_price_x32,
                 liquidity_delta,
-            );
+            )?;
 
             pool_state.liquidity =
                 liquidity_math::add_delta(pool_state.liquidity, liquidity_delta)?;
@@ -2254,7 +4060,7 @@ pub fn _modify_position<'info>(
                 tick_math::get_sqrt_ratio_at_tick(tick_lower)?,
                 tick_math::get_sqrt_ratio_at_tick(tick_upper)?,
                 liquidity_delta,
-            );
+            )?;
         }
     }
 
@@ -2307,6 +4113,7 @@ pub fn _update_position<'info>(
             liquidity_delta,
             pool_state.fee_growth_global_0_x32,
             pool_state.fee_growth_global_1_x32,
+            pool_state.reward_growth_global_x32,
             seconds_per_liquidity_cumulative_x32,
             tick_cumulative,
             time,
@@ -2318,6 +4125,7 @@ pub fn _update_position<'info>(
             liquidity_delta,
             pool_state.fee_growth_global_0_x32,
             pool_state.fee_growth_global_1_x32,
+            pool_state.reward_growth_global_x32,
             seconds_per_liquidity_cumulative_x32,
             tick_cumulative,
             time,
@@ -2338,7 +4146,7 @@ pub fn _update_position<'info>(
             }
         }
     }
-    // Update fees accrued to the position
+    // Update fees and rewards accrued to the position
     let (fee_growth_inside_0_x32, fee_growth_inside_1_x32) = tick::get_fee_growth_inside(
         tick_lower.deref(),
         tick_upper.deref(),
@@ -2346,10 +4154,17 @@ pub fn _update_position<'info>(
         pool_state.fee_growth_global_0_x32,
         pool_state.fee_growth_global_1_x32,
     );
+    let reward_growth_inside_x32 = tick::get_reward_growth_inside(
+        tick_lower.deref(),
+        tick_upper.deref(),
+        pool_state.tick,
+        pool_state.reward_growth_global_x32,
+    );
     position_state.load_mut()?.update(
         liquidity_delta,
         fee_growth_inside_0_x32,
         fee_growth_inside_1_x32,
+        reward_growth_inside_x32,
     )?;
 
     // Deallocate the tick accounts if they get un-initialized
@@ -2362,6 +4177,11 @@ pub fn _update_position<'info>(
             tick_upper.clear();
         }
     }
+
+    if liquidity_delta != 0 {
+        tick_lower.check_bitmap(bitmap_lower.load()?.deref(), pool_state.tick_spacing)?;
+        tick_upper.check_bitmap(bitmap_upper.load()?.deref(), pool_state.tick_spacing)?;
+    }
     Ok(())
 }
 
@@ -2387,17 +4207,22 @@ pub fn add_liquidity<'info>(
     tick_lower: i32,
     tick_upper: i32,
 ) -> Result<(u64, u64, u64)> {
-    let sqrt_price_x32 = accounts.pool_state.load()?.sqrt_price_x32;
+    let pool = *accounts.pool_state.load()?;
 
     let sqrt_ratio_a_x32 = tick_math::get_sqrt_ratio_at_tick(tick_lower)?;
     let sqrt_ratio_b_x32 = tick_math::get_sqrt_ratio_at_tick(tick_upper)?;
+    // wide ranges at extreme prices, e.g. full range positions, can back more liquidity than a
+    // tick holds
     let liquidity = liquidity_amounts::get_liquidity_for_amounts(
-        sqrt_price_x32,
+        pool.sqrt_price_x32,
         sqrt_ratio_a_x32,
         sqrt_ratio_b_x32,
         amount_0_desired,
         amount_1_desired,
-    );
+    )
+    .min(tick_spacing_to_max_liquidity_per_tick(
+        pool.tick_spacing as i32,
+    ));
 
     let balance_0_before = accounts.vault_0.amount;
     let balance_1_before = accounts.vault_1.amount;
@@ -2405,6 +4230,7 @@ pub fn add_liquidity<'info>(
     mint(
         Context::new(&ID, accounts, remaining_accounts, BTreeMap::default()),
         liquidity,
+        0,
     )?;
 
     accounts.vault_0.reload()?;
@@ -3,3 +3,78 @@
 
 pub const Q32: u64 = (u32::MAX as u64) + 1; // 2^32
 pub const RESOLUTION: u8 = 32;
+
+/// Q32 as a float, to convert Q32.32 numbers off-chain
+#[cfg(not(target_arch = "bpf"))]
+pub const Q32_F64: f64 = Q32 as f64;
+
+/// Converts a Q32.32 number to a float, for logs, tests and off-chain clients.
+///
+/// Exact below 2^53, beyond which the result has a relative error of at most 2^-53.
+///
+#[cfg(not(target_arch = "bpf"))]
+pub fn x32_to_f64(x: u64) -> f64 {
+    x as f64 / Q32_F64
+}
+
+/// Converts a float to the nearest Q32.32 number, for tests and off-chain clients.
+///
+/// Returns `None` for negative, NaN or too large values, i.e. if `v >= 2^32`.
+///
+#[cfg(not(target_arch = "bpf"))]
+pub fn f64_to_x32(v: f64) -> Option<u64> {
+    let x = (v * Q32_F64).round();
+    if !(0.0..u64::MAX as f64).contains(&x) {
+        return None;
+    }
+    Some(x as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_integers_and_fractions() {
+        assert_eq!(x32_to_f64(Q32), 1.0);
+        assert_eq!(x32_to_f64(Q32 / 4), 0.25);
+        assert_eq!(x32_to_f64(3 * Q32 + Q32 / 2), 3.5);
+        assert_eq!(f64_to_x32(1.0), Some(Q32));
+        assert_eq!(f64_to_x32(0.25), Some(Q32 / 4));
+        assert_eq!(f64_to_x32(0.0), Some(0));
+    }
+
+    #[test]
+    fn rounds_to_nearest() {
+        // 1 / 3 = 1431655765.33 / 2^32
+        assert_eq!(f64_to_x32(1.0 / 3.0), Some(1431655765));
+        // 2 / 3 = 2863311530.67 / 2^32
+        assert_eq!(f64_to_x32(2.0 / 3.0), Some(2863311531));
+    }
+
+    #[test]
+    fn round_trips_within_tolerance() {
+        // exact below 2^53
+        for x in [0, 1, Q32 - 1, Q32, 4295128739, (1 << 53) - 1] {
+            assert_eq!(f64_to_x32(x32_to_f64(x)), Some(x));
+        }
+        // relative error of 2^-53 beyond
+        for x in [(1 << 53) + 1, 1 << 60, u64::MAX / 2] {
+            let round_trip = f64_to_x32(x32_to_f64(x)).unwrap();
+            assert!(round_trip.abs_diff(x) <= x >> 53);
+        }
+        for v in [0.0001, 1.0001, 1234.5678, 1e9] {
+            assert!((x32_to_f64(f64_to_x32(v).unwrap()) - v).abs() <= 0.5 / Q32_F64);
+        }
+    }
+
+    #[test]
+    fn rejects_out_of_range_values() {
+        assert_eq!(f64_to_x32(-1.0 / Q32_F64), None);
+        assert_eq!(f64_to_x32(-1.0), None);
+        assert_eq!(f64_to_x32(f64::NAN), None);
+        assert_eq!(f64_to_x32(f64::INFINITY), None);
+        assert_eq!(f64_to_x32(Q32 as f64), None);
+        assert_eq!(f64_to_x32(u32::MAX as f64), Some(u32::MAX as u64 * Q32));
+    }
+}
@@ -148,6 +148,57 @@ impl MulDiv for U128 {
     }
 }
 
+/// Variant of [`MulDiv`] which returns `None` for a zero denominator instead of panicking.
+///
+/// `None` is returned if the denominator is zero, or if the overall result does not fit into
+/// the type.
+pub trait CheckedMulDiv<RHS = Self> {
+    /// Output type for the methods of this trait.
+    type Output;
+
+    /// Calculates `floor(val * num / denom)`. Returns `None` for a zero denominator.
+    fn checked_mul_div_floor(self, num: RHS, denom: RHS) -> Option<Self::Output>;
+
+    /// Calculates `ceil(val * num / denom)`. Returns `None` for a zero denominator.
+    fn checked_mul_div_ceil(self, num: RHS, denom: RHS) -> Option<Self::Output>;
+}
+
+impl CheckedMulDiv for u64 {
+    type Output = u64;
+
+    fn checked_mul_div_floor(self, num: Self, denom: Self) -> Option<Self::Output> {
+        if denom == 0 {
+            return None;
+        }
+        self.mul_div_floor(num, denom)
+    }
+
+    fn checked_mul_div_ceil(self, num: Self, denom: Self) -> Option<Self::Output> {
+        if denom == 0 {
+            return None;
+        }
+        self.mul_div_ceil(num, denom)
+    }
+}
+
+impl CheckedMulDiv for U128 {
+    type Output = U128;
+
+    fn checked_mul_div_floor(self, num: Self, denom: Self) -> Option<Self::Output> {
+        if denom == U128::default() {
+            return None;
+        }
+        self.mul_div_floor(num, denom)
+    }
+
+    fn checked_mul_div_ceil(self, num: Self, denom: Self) -> Option<Self::Output> {
+        if denom == U128::default() {
+            return None;
+        }
+        self.mul_div_ceil(num, denom)
+    }
+}
+
 #[cfg(test)]
 mod muldiv_u64_tests {
     use super::*;
@@ -433,3 +484,68 @@ mod uniswap_tests {
         }
     }
 }
+
+#[cfg(test)]
+mod checked_muldiv_tests {
+    use super::*;
+    use crate::libraries::fixed_point_32;
+
+    use quickcheck::quickcheck;
+
+    quickcheck! {
+        fn matches_mul_div_for_non_zero_denominator(val: u64, num: u64, den: u64) -> bool {
+            den == 0 || (
+                val.checked_mul_div_floor(num, den) == val.mul_div_floor(num, den)
+                    && val.checked_mul_div_ceil(num, den) == val.mul_div_ceil(num, den)
+            )
+        }
+    }
+
+    #[test]
+    fn returns_none_if_denominator_is_zero() {
+        assert_eq!(fixed_point_32::Q32.checked_mul_div_floor(5, 0), None);
+        assert_eq!(fixed_point_32::Q32.checked_mul_div_ceil(5, 0), None);
+    }
+
+    #[test]
+    fn returns_none_if_denominator_is_zero_and_numerator_overflows() {
+        assert_eq!(
+            fixed_point_32::Q32.checked_mul_div_floor(fixed_point_32::Q32, 0),
+            None
+        );
+        assert_eq!(
+            fixed_point_32::Q32.checked_mul_div_ceil(fixed_point_32::Q32, 0),
+            None
+        );
+    }
+
+    #[test]
+    fn returns_none_if_output_overflows_u64() {
+        assert_eq!(
+            fixed_point_32::Q32.checked_mul_div_floor(fixed_point_32::Q32, 1),
+            None
+        );
+        assert_eq!(
+            fixed_point_32::Q32.checked_mul_div_ceil(fixed_point_32::Q32, 1),
+            None
+        );
+    }
+
+    #[test]
+    fn returns_none_for_u128_zero_denominator() {
+        assert_eq!(
+            U128::from(5).checked_mul_div_floor(U128::from(5), U128::default()),
+            None
+        );
+        assert_eq!(
+            U128::from(5).checked_mul_div_ceil(U128::from(5), U128::default()),
+            None
+        );
+    }
+
+    #[test]
+    fn zero_numerator() {
+        assert_eq!(0_u64.checked_mul_div_floor(5, 3), Some(0));
+        assert_eq!(0_u64.checked_mul_div_ceil(5, 3), Some(0));
+    }
+}
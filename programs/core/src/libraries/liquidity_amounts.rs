@@ -4,11 +4,17 @@
 ///
 use super::big_num::U128;
 use super::fixed_point_32;
-use super::full_math::MulDiv;
+use super::full_math::{CheckedMulDiv, MulDiv};
+use super::sqrt_price_math;
+use crate::error::ErrorCode;
 
 /// Computes the amount of liquidity received for a given amount of token_0 and price range
 /// Calculates ΔL = Δx (√P_upper x √P_lower)/(√P_upper - √P_lower)
 ///
+/// Returns 0 if the price range has zero width. Saturates at u64::MAX for narrow ranges at
+/// high prices, where a small amount backs more liquidity than a u64 holds. Intermediates are
+/// kept in u128, so that large deposits never overflow into a wrong, small liquidity.
+///
 /// # Arguments
 ///
 /// * `sqrt_ratio_a_x32` - A sqrt price representing the first tick boundary
@@ -24,18 +30,25 @@ pub fn get_liquidity_for_amount_0(
     if sqrt_ratio_a_x32 > sqrt_ratio_b_x32 {
         std::mem::swap(&mut sqrt_ratio_a_x32, &mut sqrt_ratio_b_x32);
     };
-    let intermediate = sqrt_ratio_a_x32
-        .mul_div_floor(sqrt_ratio_b_x32, fixed_point_32::Q32)
-        .unwrap();
+    let intermediate =
+        (U128::from(sqrt_ratio_a_x32) * U128::from(sqrt_ratio_b_x32)) >> fixed_point_32::RESOLUTION;
 
-    amount_0
-        .mul_div_floor(intermediate, sqrt_ratio_b_x32 - sqrt_ratio_a_x32)
-        .unwrap()
+    // the quotient of u64 operands always fits in a u128, so the only failure is the zero
+    // denominator of a zero width range, which holds no liquidity
+    U128::from(amount_0)
+        .checked_mul_div_floor(
+            intermediate,
+            U128::from(sqrt_ratio_b_x32 - sqrt_ratio_a_x32),
+        )
+        .map_or(0, |liquidity| liquidity.min(U128::from(u64::MAX)).as_u64())
 }
 
 /// Computes the amount of liquidity received for a given amount of token_1 and price range
 /// Calculates ΔL = Δy / (√P_upper - √P_lower)
 ///
+/// Returns 0 if the price range has zero width. Saturates at u64::MAX for narrow ranges, where
+/// a small amount backs more liquidity than a u64 holds.
+///
 /// # Arguments
 ///
 /// * `sqrt_ratio_a_x32` - A sqrt price representing the first tick boundary
@@ -52,9 +65,13 @@ pub fn get_liquidity_for_amount_1(
         std::mem::swap(&mut sqrt_ratio_a_x32, &mut sqrt_ratio_b_x32);
     };
 
-    amount_1
-        .mul_div_floor(fixed_point_32::Q32, sqrt_ratio_b_x32 - sqrt_ratio_a_x32)
-        .unwrap()
+    // as for token_0, the only failure in u128 is the zero denominator of a zero width range
+    U128::from(amount_1)
+        .checked_mul_div_floor(
+            U128::from(fixed_point_32::Q32),
+            U128::from(sqrt_ratio_b_x32 - sqrt_ratio_a_x32),
+        )
+        .map_or(0, |liquidity| liquidity.min(U128::from(u64::MAX)).as_u64())
 }
 
 /// Computes the maximum amount of liquidity received for a given amount of token_0, token_1, the current
@@ -100,6 +117,9 @@ pub fn get_liquidity_for_amounts(
 /// Calculates Δx = ΔL (√P_upper - √P_lower) / (√P_upper x √P_lower)
 ///     = ΔL (1 / √P_lower -1 / √P_upper)
 ///
+/// Saturates at u64::MAX, e.g. for a full range position near the minimum price, since no
+/// larger amount can be held. A lower sqrt price of zero, below any tick, also saturates.
+///
 /// # Arguments
 ///
 /// * `sqrt_ratio_a_x32` - A sqrt price representing the first tick boundary
@@ -116,20 +136,24 @@ pub fn get_amount_0_for_liquidity(
         std::mem::swap(&mut sqrt_ratio_a_x32, &mut sqrt_ratio_b_x32);
     };
 
-    // Token amount can't exceed u64
-    ((U128::from(liquidity) << fixed_point_32::RESOLUTION)
-        .mul_div_floor(
+    // the first quotient is at most liquidity << 32, so a division fails only on a zero sqrt price
+    (U128::from(liquidity) << fixed_point_32::RESOLUTION)
+        .checked_mul_div_floor(
             U128::from(sqrt_ratio_b_x32 - sqrt_ratio_a_x32),
             U128::from(sqrt_ratio_b_x32),
         )
-        .unwrap()
-        / U128::from(sqrt_ratio_a_x32))
-    .as_u64()
+        .and_then(|amount_0| amount_0.checked_div(U128::from(sqrt_ratio_a_x32)))
+        .map_or(u64::MAX, |amount_0| {
+            amount_0.min(U128::from(u64::MAX)).as_u64()
+        })
 }
 
 /// Computes the amount of token_1 for a given amount of liquidity and a price range
 /// Calculates Δy = ΔL * (√P_upper - √P_lower)
 ///
+/// Saturates at u64::MAX, e.g. for a full range position near the maximum price, since no
+/// larger amount can be held.
+///
 /// # Arguments
 ///
 /// * `sqrt_ratio_a_x32` - A sqrt price representing the first tick boundary
@@ -146,14 +170,19 @@ pub fn get_amount_1_for_liquidity(
         std::mem::swap(&mut sqrt_ratio_a_x32, &mut sqrt_ratio_b_x32);
     };
 
+    // the denominator is the constant Q32, so the only failure is the overflow of a u64
     liquidity
         .mul_div_floor(sqrt_ratio_b_x32 - sqrt_ratio_a_x32, fixed_point_32::Q32)
-        .unwrap()
+        .unwrap_or(u64::MAX)
 }
 
 /// Computes the token_0 and token_1 value for a given amount of liquidity, the current
 /// pool prices and the prices at the tick boundaries
 ///
+/// The active range follows `P_lower ≤ P < P_upper`, as for ticks in mint and burn.
+/// At P = P_lower the position is in range on its token_0 side, i.e. entirely in token_0
+/// with no token_1. At P = P_upper the position is entirely in token_1.
+///
 /// # Arguments
 ///
 /// * `sqrt_ratio_x32` - A sqrt price representing the current pool prices
@@ -195,6 +224,175 @@ pub fn get_amounts_for_liquidity(
     }
 }
 
+/// Computes the token_0 and token_1 that a given amount of liquidity holds across a price
+/// interval, for any two sqrt prices, not necessarily at tick boundaries
+///
+/// The token_0 amount is held while the price is below the interval, and converted into the
+/// token_1 amount as the price moves across it. Splitting an interval at the current price
+/// gives the amounts of #get_amounts_for_liquidity, which makes this the building block for
+/// depth charts and locked value across price intervals.
+///
+/// # Arguments
+///
+/// * `liquidity` - The liquidity being valued
+/// * `sqrt_ratio_a_x32` - A sqrt price bounding the interval
+/// * `sqrt_ratio_b_x32` - Another sqrt price bounding the interval
+///
+pub fn amount_locked_between(
+    liquidity: u64,
+    sqrt_ratio_a_x32: u64,
+    sqrt_ratio_b_x32: u64,
+) -> (u64, u64) {
+    (
+        get_amount_0_for_liquidity(sqrt_ratio_a_x32, sqrt_ratio_b_x32, liquidity),
+        get_amount_1_for_liquidity(sqrt_ratio_a_x32, sqrt_ratio_b_x32, liquidity),
+    )
+}
+
+/// Computes the amount of token_1 needed alongside a given amount of token_0 to mint a position
+/// at the current price. Rounds up, so that the amount of token_1 backs at least the liquidity
+/// of amount_0 in `get_liquidity_for_amount_1`.
+///
+/// Returns 0 if the current price lies outside the range, since the position is then single sided.
+///
+/// # Arguments
+///
+/// * `amount_0` - The amount of token_0 being sent in
+/// * `sqrt_ratio_x32` - A sqrt price representing the current pool prices
+/// * `sqrt_ratio_a_x32` - A sqrt price representing the first tick boundary
+/// * `sqrt_ratio_b_x32` - A sqrt price representing the second tick boundary
+///
+pub fn amount_1_for_amount_0(
+    amount_0: u64,
+    sqrt_ratio_x32: u64,
+    mut sqrt_ratio_a_x32: u64,
+    mut sqrt_ratio_b_x32: u64,
+) -> u64 {
+    // sqrt_ratio_a_x32 should hold the smaller value
+    if sqrt_ratio_a_x32 > sqrt_ratio_b_x32 {
+        std::mem::swap(&mut sqrt_ratio_a_x32, &mut sqrt_ratio_b_x32);
+    };
+    if sqrt_ratio_x32 <= sqrt_ratio_a_x32 || sqrt_ratio_x32 >= sqrt_ratio_b_x32 {
+        return 0;
+    }
+
+    let liquidity = get_liquidity_for_amount_0(sqrt_ratio_x32, sqrt_ratio_b_x32, amount_0);
+    sqrt_price_math::get_amount_1_delta_unsigned(sqrt_ratio_a_x32, sqrt_ratio_x32, liquidity, true)
+}
+
+/// Computes the amount of token_0 needed alongside a given amount of token_1 to mint a position
+/// at the current price. Rounds up, so that the amount of token_0 backs at least the liquidity
+/// of amount_1 in `get_liquidity_for_amount_0`.
+///
+/// Returns 0 if the current price lies outside the range, since the position is then single sided.
+/// Saturates at u64::MAX if the amount does not fit in a u64, or for sqrt prices so far below the
+/// minimum that their product rounds down to zero.
+///
+/// # Arguments
+///
+/// * `amount_1` - The amount of token_1 being sent in
+/// * `sqrt_ratio_x32` - A sqrt price representing the current pool prices
+/// * `sqrt_ratio_a_x32` - A sqrt price representing the first tick boundary
+/// * `sqrt_ratio_b_x32` - A sqrt price representing the second tick boundary
+///
+pub fn amount_0_for_amount_1(
+    amount_1: u64,
+    sqrt_ratio_x32: u64,
+    mut sqrt_ratio_a_x32: u64,
+    mut sqrt_ratio_b_x32: u64,
+) -> u64 {
+    // sqrt_ratio_a_x32 should hold the smaller value
+    if sqrt_ratio_a_x32 > sqrt_ratio_b_x32 {
+        std::mem::swap(&mut sqrt_ratio_a_x32, &mut sqrt_ratio_b_x32);
+    };
+    if sqrt_ratio_x32 <= sqrt_ratio_a_x32 || sqrt_ratio_x32 >= sqrt_ratio_b_x32 {
+        return 0;
+    }
+
+    // invert ΔL = Δx (√P_upper x √P)/(√P_upper - √P), with the same rounded down intermediate
+    let liquidity = get_liquidity_for_amount_1(sqrt_ratio_a_x32, sqrt_ratio_x32, amount_1);
+    sqrt_ratio_x32
+        .mul_div_floor(sqrt_ratio_b_x32, fixed_point_32::Q32)
+        .and_then(|intermediate| {
+            liquidity.checked_mul_div_ceil(sqrt_ratio_b_x32 - sqrt_ratio_x32, intermediate)
+        })
+        .unwrap_or(u64::MAX)
+}
+
+/// Computes the swap needed to convert token balances into the token ratio of a position at
+/// the current price. Returns whether token_0 should be swapped for token_1, and the amount
+/// to swap in.
+///
+/// The estimate ignores fees and price impact, so a small remainder of one token may be left
+/// over when the balances are minted.
+///
+/// If the current price lies outside the range, the position is single sided and the entire
+/// balance of the other token is swapped. The amount is zero if no swap is needed.
+///
+/// Fails, rather than panics, if an intermediate does not fit in its type.
+///
+/// # Arguments
+///
+/// * `sqrt_ratio_x32` - A sqrt price representing the current pool prices
+/// * `sqrt_ratio_a_x32` - A sqrt price representing the first tick boundary
+/// * `sqrt_ratio_b_x32` - A sqrt price representing the second tick boundary
+/// * `amount_0` - The available amount of token_0
+/// * `amount_1` - The available amount of token_1
+///
+pub fn get_rebalance_swap_amount(
+    sqrt_ratio_x32: u64,
+    mut sqrt_ratio_a_x32: u64,
+    mut sqrt_ratio_b_x32: u64,
+    amount_0: u64,
+    amount_1: u64,
+) -> Result<(bool, u64), anchor_lang::error::Error> {
+    // sqrt_ratio_a_x32 should hold the smaller value
+    if sqrt_ratio_a_x32 > sqrt_ratio_b_x32 {
+        std::mem::swap(&mut sqrt_ratio_a_x32, &mut sqrt_ratio_b_x32);
+    };
+
+    if sqrt_ratio_x32 <= sqrt_ratio_a_x32 {
+        // If P ≤ P_lower, only token_0 is needed
+        return Ok((false, amount_1));
+    } else if sqrt_ratio_x32 >= sqrt_ratio_b_x32 {
+        // If P ≥ P_upper, only token_1 is needed
+        return Ok((true, amount_0));
+    }
+
+    // Value of token_0 and token_1 held by a unit of liquidity, in terms of token_1.
+    // V_0 = P Δx = √P (√P_upper - √P) / √P_upper, V_1 = Δy = √P - √P_lower
+    let value_0_per_liquidity = sqrt_ratio_x32
+        .checked_mul_div_floor(sqrt_ratio_b_x32 - sqrt_ratio_x32, sqrt_ratio_b_x32)
+        .ok_or(ErrorCode::RebalanceAmountOverflow)?;
+    let value_1_per_liquidity = sqrt_ratio_x32 - sqrt_ratio_a_x32;
+
+    // P = (√P)^2 / 2^64
+    let price_x64 = U128::from(sqrt_ratio_x32) * U128::from(sqrt_ratio_x32);
+    let q64 = U128::from(1) << 64;
+
+    // Total value of the balances in terms of token_1, and the share which should be held in token_1
+    let value_total = U128::from(amount_0)
+        .checked_mul_div_floor(price_x64, q64)
+        .and_then(|value_0| value_0.checked_add(U128::from(amount_1)))
+        .ok_or(ErrorCode::RebalanceAmountOverflow)?;
+    let target_1 = value_total
+        .checked_mul_div_floor(
+            U128::from(value_1_per_liquidity),
+            U128::from(value_0_per_liquidity) + U128::from(value_1_per_liquidity),
+        )
+        .ok_or(ErrorCode::RebalanceAmountOverflow)?;
+
+    if target_1 > U128::from(amount_1) {
+        // token_0 is in excess. Convert the token_1 shortfall into token_0
+        let amount_0_in = (target_1 - U128::from(amount_1))
+            .checked_mul_div_floor(q64, price_x64)
+            .ok_or(ErrorCode::RebalanceAmountOverflow)?;
+        Ok((true, amount_0_in.min(U128::from(amount_0)).as_u64()))
+    } else {
+        Ok((false, amount_1 - target_1.as_u64()))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -294,8 +492,77 @@ mod tests {
         }
     }
 
+    mod zero_width_range {
+        use super::*;
+        use crate::libraries::test_utils::encode_price_sqrt_x32;
+
+        #[test]
+        fn liquidity_for_amount_0_is_zero() {
+            let sqrt_price_x32 = encode_price_sqrt_x32(1, 1);
+            assert_eq!(
+                get_liquidity_for_amount_0(sqrt_price_x32, sqrt_price_x32, 100),
+                0
+            );
+        }
+
+        #[test]
+        fn liquidity_for_amount_1_is_zero() {
+            let sqrt_price_x32 = encode_price_sqrt_x32(1, 1);
+            assert_eq!(
+                get_liquidity_for_amount_1(sqrt_price_x32, sqrt_price_x32, 200),
+                0
+            );
+        }
+
+        #[test]
+        fn liquidity_for_amounts_is_zero() {
+            let sqrt_price_a_x32 = encode_price_sqrt_x32(100, 110);
+            for sqrt_price_x32 in [
+                encode_price_sqrt_x32(99, 110),
+                sqrt_price_a_x32,
+                encode_price_sqrt_x32(111, 100),
+            ] {
+                assert_eq!(
+                    get_liquidity_for_amounts(
+                        sqrt_price_x32,
+                        sqrt_price_a_x32,
+                        sqrt_price_a_x32,
+                        100,
+                        200
+                    ),
+                    0
+                );
+            }
+        }
+
+        #[test]
+        fn liquidity_for_amount_1_saturates_for_the_narrowest_range() {
+            let sqrt_price_x32 = encode_price_sqrt_x32(1, 1);
+            assert_eq!(
+                get_liquidity_for_amount_1(sqrt_price_x32, sqrt_price_x32 + 1, u64::MAX),
+                u64::MAX
+            );
+        }
+
+        #[test]
+        fn amount_0_for_liquidity_saturates_at_a_zero_sqrt_price() {
+            assert_eq!(
+                get_amount_0_for_liquidity(0, encode_price_sqrt_x32(1, 1), 100),
+                u64::MAX
+            );
+            assert_eq!(get_amount_0_for_liquidity(0, 0, 100), u64::MAX);
+        }
+
+        #[test]
+        fn amount_0_for_amount_1_saturates_for_a_zero_intermediate() {
+            // √P x √P_upper rounds down to zero in a Q32.32
+            assert_eq!(amount_0_for_amount_1(100, 2, 1, 3), u64::MAX);
+        }
+    }
+
     mod get_amount_0_for_liquidity {
         use super::*;
+        use crate::libraries::sqrt_price_math;
         use crate::libraries::test_utils::encode_price_sqrt_x32;
 
         #[test]
@@ -357,5 +624,511 @@ mod tests {
                 (0, 199)
             );
         }
+
+        #[test]
+        fn lower_boundary_matches_in_range_amounts() {
+            let sqrt_price_a_x32 = encode_price_sqrt_x32(100, 110);
+            let sqrt_price_b_x32 = encode_price_sqrt_x32(110, 100);
+            let liquidity = 1048;
+
+            assert_eq!(
+                get_amounts_for_liquidity(
+                    sqrt_price_a_x32,
+                    sqrt_price_a_x32,
+                    sqrt_price_b_x32,
+                    liquidity
+                ),
+                (
+                    get_amount_0_for_liquidity(sqrt_price_a_x32, sqrt_price_b_x32, liquidity),
+                    get_amount_1_for_liquidity(sqrt_price_a_x32, sqrt_price_a_x32, liquidity),
+                )
+            );
+        }
+
+        #[test]
+        fn boundaries_match_position_deltas() {
+            // mint and burn price a position with the same deltas, rounded up and down respectively
+            let sqrt_price_a_x32 = encode_price_sqrt_x32(100, 110);
+            let sqrt_price_b_x32 = encode_price_sqrt_x32(110, 100);
+            let liquidity = 1_000_000;
+
+            let (amount_0, amount_1) = get_amounts_for_liquidity(
+                sqrt_price_a_x32,
+                sqrt_price_a_x32,
+                sqrt_price_b_x32,
+                liquidity,
+            );
+            assert_eq!(
+                amount_0,
+                sqrt_price_math::get_amount_0_delta_unsigned(
+                    sqrt_price_a_x32,
+                    sqrt_price_b_x32,
+                    liquidity,
+                    false
+                )
+            );
+            assert_eq!(amount_1, 0);
+
+            let (amount_0, amount_1) = get_amounts_for_liquidity(
+                sqrt_price_b_x32,
+                sqrt_price_a_x32,
+                sqrt_price_b_x32,
+                liquidity,
+            );
+            assert_eq!(amount_0, 0);
+            assert_eq!(
+                amount_1,
+                sqrt_price_math::get_amount_1_delta_unsigned(
+                    sqrt_price_a_x32,
+                    sqrt_price_b_x32,
+                    liquidity,
+                    false
+                )
+            );
+        }
+    }
+
+    mod full_range {
+        use super::*;
+        use crate::libraries::tick_math::{
+            get_sqrt_ratio_at_tick, MAX_SQRT_RATIO, MAX_TICK, MIN_SQRT_RATIO, MIN_TICK,
+        };
+        use crate::states::tick::tick_spacing_to_max_liquidity_per_tick;
+
+        #[test]
+        fn values_a_full_range_position_at_every_price() {
+            let liquidity = tick_spacing_to_max_liquidity_per_tick(60);
+            let (sqrt_price_a_x32, sqrt_price_b_x32) = (
+                get_sqrt_ratio_at_tick(MIN_TICK).unwrap(),
+                get_sqrt_ratio_at_tick(MAX_TICK).unwrap(),
+            );
+            for tick in [MIN_TICK, MIN_TICK + 1, -100_000, 0, 100_000, MAX_TICK - 1] {
+                let sqrt_price_x32 = get_sqrt_ratio_at_tick(tick).unwrap();
+                let (amount_0, amount_1) = get_amounts_for_liquidity(
+                    sqrt_price_x32,
+                    sqrt_price_a_x32,
+                    sqrt_price_b_x32,
+                    liquidity,
+                );
+                assert!(amount_0 > 0 || amount_1 > 0);
+            }
+
+            // just below the upper boundary the position is almost entirely in token_1
+            let (amount_0, amount_1) = get_amounts_for_liquidity(
+                MAX_SQRT_RATIO - 1,
+                sqrt_price_a_x32,
+                sqrt_price_b_x32,
+                liquidity,
+            );
+            assert!(amount_0 <= 1);
+            assert!(amount_1 > 0);
+        }
+
+        #[test]
+        fn saturates_amounts_that_exceed_u64() {
+            // Δx ≈ L / √P_min = 65536 L
+            assert_eq!(
+                get_amount_0_for_liquidity(MIN_SQRT_RATIO, MAX_SQRT_RATIO, u64::MAX >> 8),
+                u64::MAX
+            );
+            // Δy ≈ L √P_max = 65536 L
+            assert_eq!(
+                get_amount_1_for_liquidity(MIN_SQRT_RATIO, MAX_SQRT_RATIO, u64::MAX >> 8),
+                u64::MAX
+            );
+        }
+
+        #[test]
+        fn matches_deposit_at_a_central_price() {
+            // at price 1, a full range position holds about L of each token
+            let liquidity = 1_000_000_000;
+            let (amount_0, amount_1) =
+                get_amounts_for_liquidity(1 << 32, MIN_SQRT_RATIO, MAX_SQRT_RATIO, liquidity);
+            assert!(amount_0 <= liquidity && amount_0 > liquidity - liquidity / 10_000);
+            assert!(amount_1 <= liquidity && amount_1 > liquidity - liquidity / 10_000);
+
+            // the liquidity for those amounts round trips
+            let liquidity_for_amounts = get_liquidity_for_amounts(
+                1 << 32,
+                MIN_SQRT_RATIO,
+                MAX_SQRT_RATIO,
+                amount_0,
+                amount_1,
+            );
+            assert!(liquidity_for_amounts <= liquidity);
+            assert!(liquidity_for_amounts > liquidity - 2);
+        }
+
+        #[test]
+        fn saturates_liquidity_for_narrow_ranges_at_extreme_prices() {
+            // one tick wide range at the maximum price backs over u64::MAX liquidity
+            let (sqrt_price_a_x32, sqrt_price_b_x32) = (
+                get_sqrt_ratio_at_tick(MAX_TICK - 1).unwrap(),
+                get_sqrt_ratio_at_tick(MAX_TICK).unwrap(),
+            );
+            assert_eq!(
+                get_liquidity_for_amounts(
+                    MIN_SQRT_RATIO,
+                    sqrt_price_a_x32,
+                    sqrt_price_b_x32,
+                    u64::MAX,
+                    0
+                ),
+                u64::MAX
+            );
+        }
+
+        #[test]
+        fn large_deposits_over_wide_ranges_do_not_overflow() {
+            // a range reaching the top of the price space, where the product of the sqrt
+            // prices leaves no headroom in a u64
+            let sqrt_price_a_x32 = get_sqrt_ratio_at_tick(0).unwrap();
+            let sqrt_price_b_x32 = get_sqrt_ratio_at_tick(MAX_TICK).unwrap();
+            for amount in [u64::MAX / 2, u64::MAX] {
+                let liquidity_0 =
+                    get_liquidity_for_amount_0(sqrt_price_a_x32, sqrt_price_b_x32, amount);
+                let liquidity_1 =
+                    get_liquidity_for_amount_1(sqrt_price_a_x32, sqrt_price_b_x32, amount);
+                // the liquidity is about the deposit, and not wrapped to a small value
+                assert!(liquidity_0 >= amount / 2, "liquidity {}", liquidity_0);
+                assert!(liquidity_1 > 0 && liquidity_1 < amount);
+
+                // the liquidity is backed by the deposit
+                assert!(
+                    get_amount_0_for_liquidity(sqrt_price_a_x32, sqrt_price_b_x32, liquidity_0)
+                        <= amount
+                );
+                assert!(
+                    get_amount_1_for_liquidity(sqrt_price_a_x32, sqrt_price_b_x32, liquidity_1)
+                        <= amount
+                );
+            }
+        }
+
+        #[test]
+        fn sqrt_price_products_beyond_u64_are_handled() {
+            let (sqrt_price_a_x32, sqrt_price_b_x32) = (
+                get_sqrt_ratio_at_tick(MAX_TICK - 1).unwrap(),
+                get_sqrt_ratio_at_tick(MAX_TICK).unwrap(),
+            );
+            assert_eq!(
+                get_liquidity_for_amount_0(sqrt_price_a_x32, sqrt_price_b_x32, 1),
+                ((U128::from(sqrt_price_a_x32) * U128::from(sqrt_price_b_x32)
+                    >> fixed_point_32::RESOLUTION)
+                    / U128::from(sqrt_price_b_x32 - sqrt_price_a_x32))
+                .as_u64()
+            );
+        }
+    }
+
+    mod get_rebalance_swap_amount {
+        use super::*;
+        use crate::libraries::fixed_point_32;
+
+        // range [√P / 2, 2√P] holds an equal value of token_0 and token_1 at P = 1
+        const SQRT_PRICE_X32: u64 = fixed_point_32::Q32;
+        const SQRT_PRICE_A_X32: u64 = fixed_point_32::Q32 / 2;
+        const SQRT_PRICE_B_X32: u64 = fixed_point_32::Q32 * 2;
+
+        #[test]
+        fn symmetric_recenter_swaps_half_of_token_0() {
+            assert_eq!(
+                get_rebalance_swap_amount(
+                    SQRT_PRICE_X32,
+                    SQRT_PRICE_A_X32,
+                    SQRT_PRICE_B_X32,
+                    1_000_000,
+                    0
+                )
+                .unwrap(),
+                (true, 500_000)
+            );
+        }
+
+        #[test]
+        fn symmetric_recenter_swaps_half_of_token_1() {
+            assert_eq!(
+                get_rebalance_swap_amount(
+                    SQRT_PRICE_X32,
+                    SQRT_PRICE_B_X32,
+                    SQRT_PRICE_A_X32,
+                    0,
+                    1_000_000
+                )
+                .unwrap(),
+                (false, 500_000)
+            );
+        }
+
+        #[test]
+        fn no_swap_if_balances_match_the_range() {
+            assert_eq!(
+                get_rebalance_swap_amount(
+                    SQRT_PRICE_X32,
+                    SQRT_PRICE_A_X32,
+                    SQRT_PRICE_B_X32,
+                    500_000,
+                    500_000
+                )
+                .unwrap(),
+                (false, 0)
+            );
+        }
+
+        #[test]
+        fn single_sided_below_range_swaps_all_token_1() {
+            assert_eq!(
+                get_rebalance_swap_amount(
+                    SQRT_PRICE_A_X32,
+                    SQRT_PRICE_X32,
+                    SQRT_PRICE_B_X32,
+                    100,
+                    200
+                )
+                .unwrap(),
+                (false, 200)
+            );
+        }
+
+        #[test]
+        fn single_sided_above_range_swaps_all_token_0() {
+            assert_eq!(
+                get_rebalance_swap_amount(
+                    SQRT_PRICE_B_X32,
+                    SQRT_PRICE_A_X32,
+                    SQRT_PRICE_X32,
+                    100,
+                    200
+                )
+                .unwrap(),
+                (true, 100)
+            );
+        }
+
+        #[test]
+        fn single_sided_with_required_token_only_needs_no_swap() {
+            assert_eq!(
+                get_rebalance_swap_amount(
+                    SQRT_PRICE_A_X32,
+                    SQRT_PRICE_X32,
+                    SQRT_PRICE_B_X32,
+                    100,
+                    0
+                )
+                .unwrap(),
+                (false, 0)
+            );
+        }
+    }
+
+    mod amount_for_other_token {
+        use super::*;
+        use crate::libraries::test_utils::encode_price_sqrt_x32;
+
+        const AMOUNTS: [u64; 4] = [1, 1_000, 1_000_000, 1_000_000_000_000];
+
+        fn range() -> (u64, u64) {
+            (
+                encode_price_sqrt_x32(100, 110),
+                encode_price_sqrt_x32(110, 100),
+            )
+        }
+
+        fn prices_in_range() -> [u64; 3] {
+            [
+                encode_price_sqrt_x32(95, 100),
+                encode_price_sqrt_x32(1, 1),
+                encode_price_sqrt_x32(105, 100),
+            ]
+        }
+
+        #[test]
+        fn amount_1_is_consistent_with_liquidity_for_amounts() {
+            let (sqrt_price_a_x32, sqrt_price_b_x32) = range();
+            for sqrt_price_x32 in prices_in_range() {
+                for amount_0 in AMOUNTS {
+                    let amount_1 = amount_1_for_amount_0(
+                        amount_0,
+                        sqrt_price_x32,
+                        sqrt_price_a_x32,
+                        sqrt_price_b_x32,
+                    );
+                    // amount_1 suffices to back the liquidity of amount_0
+                    assert_eq!(
+                        get_liquidity_for_amounts(
+                            sqrt_price_x32,
+                            sqrt_price_a_x32,
+                            sqrt_price_b_x32,
+                            amount_0,
+                            amount_1
+                        ),
+                        get_liquidity_for_amount_0(sqrt_price_x32, sqrt_price_b_x32, amount_0)
+                    );
+                }
+            }
+        }
+
+        #[test]
+        fn amount_0_is_consistent_with_liquidity_for_amounts() {
+            let (sqrt_price_a_x32, sqrt_price_b_x32) = range();
+            for sqrt_price_x32 in prices_in_range() {
+                for amount_1 in AMOUNTS {
+                    let amount_0 = amount_0_for_amount_1(
+                        amount_1,
+                        sqrt_price_x32,
+                        sqrt_price_a_x32,
+                        sqrt_price_b_x32,
+                    );
+                    // amount_0 suffices to back the liquidity of amount_1
+                    assert_eq!(
+                        get_liquidity_for_amounts(
+                            sqrt_price_x32,
+                            sqrt_price_a_x32,
+                            sqrt_price_b_x32,
+                            amount_0,
+                            amount_1
+                        ),
+                        get_liquidity_for_amount_1(sqrt_price_a_x32, sqrt_price_x32, amount_1)
+                    );
+                }
+            }
+        }
+
+        #[test]
+        fn amounts_link_in_both_directions() {
+            let (sqrt_price_a_x32, sqrt_price_b_x32) = range();
+            let sqrt_price_x32 = encode_price_sqrt_x32(1, 1);
+            let amount_1 = amount_1_for_amount_0(
+                1_000_000,
+                sqrt_price_x32,
+                sqrt_price_a_x32,
+                sqrt_price_b_x32,
+            );
+            let amount_0 =
+                amount_0_for_amount_1(amount_1, sqrt_price_x32, sqrt_price_a_x32, sqrt_price_b_x32);
+            assert!(amount_0.abs_diff(1_000_000) <= 1);
+        }
+
+        #[test]
+        fn reversed_bounds_give_the_same_amount() {
+            let (sqrt_price_a_x32, sqrt_price_b_x32) = range();
+            let sqrt_price_x32 = encode_price_sqrt_x32(1, 1);
+            assert_eq!(
+                amount_1_for_amount_0(1_000, sqrt_price_x32, sqrt_price_a_x32, sqrt_price_b_x32),
+                amount_1_for_amount_0(1_000, sqrt_price_x32, sqrt_price_b_x32, sqrt_price_a_x32)
+            );
+            assert_eq!(
+                amount_0_for_amount_1(1_000, sqrt_price_x32, sqrt_price_a_x32, sqrt_price_b_x32),
+                amount_0_for_amount_1(1_000, sqrt_price_x32, sqrt_price_b_x32, sqrt_price_a_x32)
+            );
+        }
+
+        #[test]
+        fn out_of_range_needs_no_other_token() {
+            let (sqrt_price_a_x32, sqrt_price_b_x32) = range();
+            for sqrt_price_x32 in [sqrt_price_a_x32, sqrt_price_b_x32] {
+                assert_eq!(
+                    amount_1_for_amount_0(
+                        1_000,
+                        sqrt_price_x32,
+                        sqrt_price_a_x32,
+                        sqrt_price_b_x32
+                    ),
+                    0
+                );
+                assert_eq!(
+                    amount_0_for_amount_1(
+                        1_000,
+                        sqrt_price_x32,
+                        sqrt_price_a_x32,
+                        sqrt_price_b_x32
+                    ),
+                    0
+                );
+            }
+        }
+    }
+
+    mod amount_locked_between {
+        use super::*;
+        use crate::libraries::{test_utils::encode_price_sqrt_x32, tick_math};
+
+        const LIQUIDITY: [u64; 3] = [1, 1_000_000, 1 << 40];
+
+        #[test]
+        fn matches_single_sided_amounts_outside_a_tick_range() {
+            let sqrt_price_lower_x32 = tick_math::get_sqrt_ratio_at_tick(-600).unwrap();
+            let sqrt_price_upper_x32 = tick_math::get_sqrt_ratio_at_tick(600).unwrap();
+            for liquidity in LIQUIDITY {
+                let (amount_0, amount_1) =
+                    amount_locked_between(liquidity, sqrt_price_lower_x32, sqrt_price_upper_x32);
+                assert_eq!(
+                    get_amounts_for_liquidity(
+                        sqrt_price_lower_x32,
+                        sqrt_price_lower_x32,
+                        sqrt_price_upper_x32,
+                        liquidity
+                    ),
+                    (amount_0, 0)
+                );
+                assert_eq!(
+                    get_amounts_for_liquidity(
+                        sqrt_price_upper_x32,
+                        sqrt_price_lower_x32,
+                        sqrt_price_upper_x32,
+                        liquidity
+                    ),
+                    (0, amount_1)
+                );
+            }
+        }
+
+        #[test]
+        fn splits_a_tick_range_at_the_current_price() {
+            let sqrt_price_lower_x32 = tick_math::get_sqrt_ratio_at_tick(-600).unwrap();
+            let sqrt_price_upper_x32 = tick_math::get_sqrt_ratio_at_tick(600).unwrap();
+            // prices off the tick grid
+            for sqrt_price_x32 in [
+                encode_price_sqrt_x32(95, 100),
+                encode_price_sqrt_x32(1, 1),
+                encode_price_sqrt_x32(103, 100),
+            ] {
+                for liquidity in LIQUIDITY {
+                    assert_eq!(
+                        get_amounts_for_liquidity(
+                            sqrt_price_x32,
+                            sqrt_price_lower_x32,
+                            sqrt_price_upper_x32,
+                            liquidity
+                        ),
+                        (
+                            amount_locked_between(liquidity, sqrt_price_x32, sqrt_price_upper_x32)
+                                .0,
+                            amount_locked_between(liquidity, sqrt_price_lower_x32, sqrt_price_x32)
+                                .1,
+                        )
+                    );
+                }
+            }
+        }
+
+        #[test]
+        fn is_symmetric_in_its_prices() {
+            let sqrt_price_a_x32 = encode_price_sqrt_x32(90, 100);
+            let sqrt_price_b_x32 = encode_price_sqrt_x32(121, 100);
+            assert_eq!(
+                amount_locked_between(1_000_000, sqrt_price_a_x32, sqrt_price_b_x32),
+                amount_locked_between(1_000_000, sqrt_price_b_x32, sqrt_price_a_x32)
+            );
+        }
+
+        #[test]
+        fn holds_nothing_across_an_empty_interval() {
+            let sqrt_price_x32 = encode_price_sqrt_x32(1, 1);
+            assert_eq!(
+                amount_locked_between(1_000_000, sqrt_price_x32, sqrt_price_x32),
+                (0, 0)
+            );
+        }
     }
 }
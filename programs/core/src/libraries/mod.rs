@@ -4,8 +4,13 @@ pub mod fixed_point_32;
 pub mod full_math;
 pub mod liquidity_amounts;
 pub mod liquidity_math;
+pub mod position_math;
+pub mod sqrt_price;
 pub mod sqrt_price_math;
+#[cfg(any(test, feature = "testing"))]
+pub mod swap_fuzz;
 pub mod swap_math;
+pub mod swap_quote;
 #[cfg(test)]
 pub mod test_utils;
 pub mod tick_math;
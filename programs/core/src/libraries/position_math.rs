@@ -0,0 +1,672 @@
+//! Helper functions to estimate the performance of a liquidity position
+//! Values are denominated in token_1
+//!
+use super::big_num::U128;
+use super::fixed_point_32;
+use super::full_math::MulDiv;
+use super::liquidity_amounts;
+use super::tick_math;
+
+/// The number of slots in a year, at the target slot time of 400ms
+pub const SLOTS_PER_YEAR: u64 = 78_840_000;
+
+/// Computes the impermanent loss of a position against holding its tokens, while the price
+/// lies inside the position's range. Rounds down.
+///
+/// Since V_hold - V_position = L (√P - √P_entry)^2 / √P_entry, the loss does not depend on the
+/// range bounds. The result is in terms of token_1.
+///
+/// # Arguments
+///
+/// * `liquidity` - The liquidity of the position
+/// * `sqrt_price_entry_x32` - The sqrt price at which the position was opened
+/// * `sqrt_price_x32` - The current sqrt price
+///
+pub fn get_impermanent_loss(liquidity: u64, sqrt_price_entry_x32: u64, sqrt_price_x32: u64) -> u64 {
+    let delta = sqrt_price_x32.abs_diff(sqrt_price_entry_x32);
+
+    (U128::from(liquidity) * U128::from(delta))
+        .mul_div_floor(
+            U128::from(delta),
+            U128::from(sqrt_price_entry_x32) << fixed_point_32::RESOLUTION,
+        )
+        .unwrap()
+        .as_u64()
+}
+
+/// Estimates the tick band around the center of a position, within which the projected fees
+/// exceed the impermanent loss.
+///
+/// The position is assumed to be opened at the center tick of its range. Solving
+/// L (√P - √P_entry)^2 / √P_entry = fees gives the band √P_entry ± √(fees √P_entry / L).
+/// The band is rounded inwards to ticks and capped at the position's range.
+///
+/// A position without liquidity earns no fees, and has an empty band at the center tick.
+///
+/// # Arguments
+///
+/// * `liquidity` - The liquidity of the position
+/// * `tick_lower` - The lower tick of the position
+/// * `tick_upper` - The upper tick of the position
+/// * `expected_fee_growth_x32` - The projected fee growth inside the range per unit of
+///   liquidity, in terms of token_1 as a Q32.32
+///
+pub fn breakeven_band(
+    liquidity: u64,
+    tick_lower: i32,
+    tick_upper: i32,
+    expected_fee_growth_x32: u64,
+) -> Result<(i32, i32), anchor_lang::error::Error> {
+    let tick_entry = (tick_lower + tick_upper) / 2;
+    if liquidity == 0 {
+        return Ok((tick_entry, tick_entry));
+    }
+
+    let sqrt_price_lower_x32 = tick_math::get_sqrt_ratio_at_tick(tick_lower)?;
+    let sqrt_price_upper_x32 = tick_math::get_sqrt_ratio_at_tick(tick_upper)?;
+    let sqrt_price_entry_x32 = tick_math::get_sqrt_ratio_at_tick(tick_entry)?;
+
+    let fees = expected_fee_growth_x32
+        .mul_div_floor(liquidity, fixed_point_32::Q32)
+        .unwrap();
+
+    // Δ√P^2 = fees * √P_entry / L, saturating since the band is capped at the range anyway
+    let delta_squared = U128::from(fees)
+        .mul_div_floor(
+            U128::from(sqrt_price_entry_x32) << fixed_point_32::RESOLUTION,
+            U128::from(liquidity),
+        )
+        .unwrap_or(U128::MAX);
+    let delta = delta_squared.integer_sqrt().as_u64();
+
+    let sqrt_price_a_x32 = sqrt_price_entry_x32
+        .saturating_sub(delta)
+        .max(sqrt_price_lower_x32);
+    let mut tick_a = tick_math::get_tick_at_sqrt_ratio(sqrt_price_a_x32)?;
+    if tick_math::get_sqrt_ratio_at_tick(tick_a)? < sqrt_price_a_x32 {
+        tick_a += 1;
+    }
+
+    let sqrt_price_b_x32 = sqrt_price_entry_x32.saturating_add(delta);
+    let tick_b = if sqrt_price_b_x32 >= sqrt_price_upper_x32 {
+        tick_upper
+    } else {
+        tick_math::get_tick_at_sqrt_ratio(sqrt_price_b_x32)?
+    };
+
+    Ok((tick_a, tick_b))
+}
+
+/// Estimates the annualized fee yield of a hypothetical position, from the fee growth inside its
+/// range over a recent interval.
+///
+/// The fees the position would have earned are valued against its principal at the current
+/// price, both in terms of token_1, and scaled from the interval to a year. The yield is
+/// simple, without compounding. The fee growth deltas are the wrapping differences of fee
+/// growth inside the range between the start and the end of the interval.
+///
+/// Returns 0 for an empty interval or a position without liquidity.
+///
+/// # Arguments
+///
+/// * `fee_growth_delta_0_x32` - The token_0 fee growth inside the range per unit of liquidity
+///   over the interval, as a Q32.32
+/// * `fee_growth_delta_1_x32` - The token_1 fee growth inside the range per unit of liquidity
+///   over the interval, as a Q32.32
+/// * `interval_slots` - The length of the interval in slots
+/// * `tick_lower` - The lower tick of the position
+/// * `tick_upper` - The upper tick of the position
+/// * `liquidity` - The liquidity of the position
+/// * `sqrt_price_x32` - The current sqrt price, used to value token_0 in terms of token_1
+///
+#[cfg(not(target_arch = "bpf"))]
+pub fn fee_apr_estimate(
+    fee_growth_delta_0_x32: u64,
+    fee_growth_delta_1_x32: u64,
+    interval_slots: u64,
+    tick_lower: i32,
+    tick_upper: i32,
+    liquidity: u64,
+    sqrt_price_x32: u64,
+) -> Result<f64, anchor_lang::error::Error> {
+    if interval_slots == 0 || liquidity == 0 {
+        return Ok(0.0);
+    }
+
+    let (amount_0, amount_1) = liquidity_amounts::get_amounts_for_liquidity(
+        sqrt_price_x32,
+        tick_math::get_sqrt_ratio_at_tick(tick_lower)?,
+        tick_math::get_sqrt_ratio_at_tick(tick_upper)?,
+        liquidity,
+    );
+    let fees_0 = fee_growth_delta_0_x32
+        .mul_div_floor(liquidity, fixed_point_32::Q32)
+        .unwrap();
+    let fees_1 = fee_growth_delta_1_x32
+        .mul_div_floor(liquidity, fixed_point_32::Q32)
+        .unwrap();
+
+    let sqrt_price = fixed_point_32::x32_to_f64(sqrt_price_x32);
+    let price = sqrt_price * sqrt_price;
+    let principal = amount_0 as f64 * price + amount_1 as f64;
+    if principal == 0.0 {
+        return Ok(0.0);
+    }
+    let fees = fees_0 as f64 * price + fees_1 as f64;
+
+    Ok(fees / principal * (SLOTS_PER_YEAR as f64 / interval_slots as f64))
+}
+
+/// Computes the liquidity weighted average of the center ticks of a set of positions, such as
+/// the positions of a vault choosing its next range. Rounds towards zero, like the center tick
+/// (tick_lower + tick_upper) / 2 of a single range.
+///
+/// Returns 0 if the positions hold no liquidity.
+///
+/// # Arguments
+///
+/// * `positions` - The lower tick, upper tick and liquidity of each position
+///
+pub fn weighted_center_tick(positions: &[(i32, i32, u64)]) -> i32 {
+    let mut weighted_sum = 0_i128;
+    let mut total_liquidity = 0_i128;
+    for &(tick_lower, tick_upper, liquidity) in positions {
+        // each term fits in 86 bits, leaving room for any practical number of positions
+        weighted_sum += i128::from(liquidity) * i128::from(tick_lower + tick_upper);
+        total_liquidity += i128::from(liquidity);
+    }
+    if total_liquidity == 0 {
+        return 0;
+    }
+    // the average lies between the lowest and highest center tick
+    (weighted_sum / (2 * total_liquidity)) as i32
+}
+
+/// Returns the tick range around the current tick whose liquidity is `efficiency_multiple`
+/// times as capital efficient as the same liquidity over the whole price range.
+///
+/// A range [P_c / r, P_c r] around its center price P_c needs a share 1 - 1 / √r of the
+/// capital of an unbounded range for the same liquidity at P_c, so its efficiency is
+/// √r / (√r - 1). With √r = 1.0001^(w / 4) for a range w ticks wide, the target is met by
+/// w = 4 ln(E / (E - 1)) / ln(1.0001).
+///
+/// The range is symmetric around the tick spacing containing the current tick, with ends on
+/// multiples of the tick spacing, and its width is the closest such width to the target.
+/// Targets of 1 or less give the whole usable tick range, and targets above the efficiency
+/// of a single tick spacing give that spacing.
+///
+/// # Arguments
+///
+/// * `current_tick` - The current tick of the pool
+/// * `efficiency_multiple` - The target capital efficiency, relative to a full range position
+/// * `tick_spacing` - The tick spacing of the pool
+///
+#[cfg(not(target_arch = "bpf"))]
+pub fn range_for_efficiency(
+    current_tick: i32,
+    efficiency_multiple: f64,
+    tick_spacing: u16,
+) -> (i32, i32) {
+    let spacing = tick_spacing as i32;
+    let compressed = current_tick.div_euclid(spacing);
+    let (min_compressed, max_compressed) =
+        (tick_math::MIN_TICK / spacing, tick_math::MAX_TICK / spacing);
+    let range = |k: i32| {
+        (
+            (compressed - k).max(min_compressed) * spacing,
+            (compressed + 1 + k).min(max_compressed) * spacing,
+        )
+    };
+
+    let max_k = (compressed - min_compressed).max(max_compressed - compressed);
+    if efficiency_multiple <= 1.0 {
+        return range(max_k);
+    }
+    let width = 4.0 * (efficiency_multiple / (efficiency_multiple - 1.0)).ln() / 1.0001_f64.ln();
+    // a range of (2k + 1) tick spacings
+    let k = ((width / spacing as f64 - 1.0) / 2.0).round();
+    range(k.clamp(0.0, max_k as f64) as i32)
+}
+
+/// Computes a conservative value of a position for use as collateral, in terms of token_1.
+/// Rounds down, and saturates at u64::MAX.
+///
+/// The value of a position in terms of token_1, L (2√P - √P_lower - P / √P_upper) in range,
+/// grows with the price. The position is therefore valued at the worst of the oracle price and
+/// the prices in its range, i.e. the lower of the oracle price and the price at tick_lower,
+/// where it holds only token_0. An in-range or above-range position is valued below its spot
+/// value, and a below-range position at its spot value.
+///
+/// # Arguments
+///
+/// * `tick_lower` - The lower tick of the position
+/// * `tick_upper` - The upper tick of the position
+/// * `liquidity` - The liquidity of the position
+/// * `oracle_sqrt_price_x32` - The oracle sqrt price of token_0 in terms of token_1, as a Q32.32
+///
+pub fn conservative_collateral_value(
+    tick_lower: i32,
+    tick_upper: i32,
+    liquidity: u64,
+    oracle_sqrt_price_x32: u64,
+) -> Result<u64, anchor_lang::error::Error> {
+    let sqrt_price_lower_x32 = tick_math::get_sqrt_ratio_at_tick(tick_lower)?;
+    let sqrt_price_x32 = oracle_sqrt_price_x32.min(sqrt_price_lower_x32);
+
+    let (amount_0, amount_1) = liquidity_amounts::get_amounts_for_liquidity(
+        sqrt_price_x32,
+        sqrt_price_lower_x32,
+        tick_math::get_sqrt_ratio_at_tick(tick_upper)?,
+        liquidity,
+    );
+    let q32 = U128::from(fixed_point_32::Q32);
+    let value_0 = U128::from(amount_0)
+        .mul_div_floor(U128::from(sqrt_price_x32), q32)
+        .unwrap()
+        .mul_div_floor(U128::from(sqrt_price_x32), q32)
+        .unwrap();
+
+    Ok((value_0 + U128::from(amount_1))
+        .min(U128::from(u64::MAX))
+        .as_u64())
+}
+
+#[cfg(test)]
+mod get_impermanent_loss {
+    use super::*;
+
+    #[test]
+    fn zero_at_entry_price() {
+        assert_eq!(get_impermanent_loss(1000, 1 << 32, 1 << 32), 0);
+    }
+
+    #[test]
+    fn symmetric_in_sqrt_price() {
+        let q32 = fixed_point_32::Q32;
+        // L (0.1)^2 / 1 = 0.01 L
+        assert_eq!(
+            get_impermanent_loss(100_000, q32, q32 + q32 / 10),
+            get_impermanent_loss(100_000, q32, q32 - q32 / 10),
+        );
+        assert_eq!(get_impermanent_loss(100_000, q32, q32 + q32 / 10), 999);
+    }
+}
+
+#[cfg(test)]
+mod breakeven_band {
+    use super::*;
+
+    const LIQUIDITY: u64 = 1 << 32;
+
+    #[test]
+    fn band_for_projected_fee_growth() {
+        // fee growth of 0.01 per unit liquidity gives √P = 1 ± √0.01 = [0.9, 1.1]
+        // tick_lower = ceil(ln(0.81) / ln(1.0001)) = -2107
+        // tick_upper = floor(ln(1.21) / ln(1.0001)) = 1906
+        let fee_growth_x32 = fixed_point_32::Q32 / 100;
+        assert_eq!(
+            breakeven_band(LIQUIDITY, -4000, 4000, fee_growth_x32).unwrap(),
+            (-2107, 1906)
+        );
+    }
+
+    #[test]
+    fn band_is_centered_on_the_range() {
+        // entry at tick 1000, √P_entry = 1.0001^500 = 1.05127
+        // √P = 1.05127 ± √(0.0001 * 1.05127) = [1.04102, 1.06152]
+        // tick_lower = ceil(ln(1.04102^2) / ln(1.0001)) = 804
+        // tick_upper = floor(ln(1.06152^2) / ln(1.0001)) = 1194
+        let fee_growth_x32 = fixed_point_32::Q32 / 10_000;
+        assert_eq!(
+            breakeven_band(LIQUIDITY, 0, 2000, fee_growth_x32).unwrap(),
+            (804, 1194)
+        );
+    }
+
+    #[test]
+    fn fees_exceed_loss_inside_the_band() {
+        let fee_growth_x32 = fixed_point_32::Q32 / 100;
+        let (tick_a, tick_b) = breakeven_band(LIQUIDITY, -4000, 4000, fee_growth_x32).unwrap();
+        let fees = fee_growth_x32
+            .mul_div_floor(LIQUIDITY, fixed_point_32::Q32)
+            .unwrap();
+        let sqrt_price_entry_x32 = tick_math::get_sqrt_ratio_at_tick(0).unwrap();
+
+        for tick in [tick_a, tick_b] {
+            let sqrt_price_x32 = tick_math::get_sqrt_ratio_at_tick(tick).unwrap();
+            assert!(get_impermanent_loss(LIQUIDITY, sqrt_price_entry_x32, sqrt_price_x32) <= fees);
+        }
+        for tick in [tick_a - 1, tick_b + 1] {
+            let sqrt_price_x32 = tick_math::get_sqrt_ratio_at_tick(tick).unwrap();
+            assert!(get_impermanent_loss(LIQUIDITY, sqrt_price_entry_x32, sqrt_price_x32) > fees);
+        }
+    }
+
+    #[test]
+    fn no_fee_growth_gives_empty_band() {
+        assert_eq!(breakeven_band(LIQUIDITY, -4000, 4000, 0).unwrap(), (0, 0));
+    }
+
+    #[test]
+    fn zero_liquidity_gives_empty_band() {
+        assert_eq!(
+            breakeven_band(0, -4000, 4000, fixed_point_32::Q32).unwrap(),
+            (0, 0)
+        );
+    }
+
+    #[test]
+    fn band_is_capped_at_the_range() {
+        assert_eq!(
+            breakeven_band(LIQUIDITY, -4000, 4000, fixed_point_32::Q32).unwrap(),
+            (-4000, 4000)
+        );
+        assert_eq!(
+            breakeven_band(
+                LIQUIDITY,
+                tick_math::MIN_TICK,
+                tick_math::MAX_TICK,
+                u64::MAX
+            )
+            .unwrap(),
+            (tick_math::MIN_TICK, tick_math::MAX_TICK)
+        );
+    }
+}
+
+#[cfg(test)]
+mod fee_apr_estimate {
+    use super::*;
+
+    const LIQUIDITY: u64 = 1 << 32;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() <= expected * 1e-6,
+            "{} is not close to {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn one_percent_of_principal_over_a_hundredth_of_a_year() {
+        let sqrt_price_x32 = tick_math::get_sqrt_ratio_at_tick(0).unwrap();
+        let (amount_0, amount_1) = liquidity_amounts::get_amounts_for_liquidity(
+            sqrt_price_x32,
+            tick_math::get_sqrt_ratio_at_tick(-4000).unwrap(),
+            tick_math::get_sqrt_ratio_at_tick(4000).unwrap(),
+            LIQUIDITY,
+        );
+        // with L = 2^32, the fees earned equal the Q32.32 fee growth
+        let apr = fee_apr_estimate(
+            amount_0 / 100,
+            amount_1 / 100,
+            SLOTS_PER_YEAR / 100,
+            -4000,
+            4000,
+            LIQUIDITY,
+            sqrt_price_x32,
+        )
+        .unwrap();
+        assert_close(apr, 1.0);
+    }
+
+    #[test]
+    fn token_0_fees_are_valued_at_the_current_price() {
+        // at P = 4, just below the range, the position holds token_0 only
+        // principal = 4 amount_0 and fees = 4 amount_0 / 50 over a day, i.e. 2% a day
+        let sqrt_price_x32 = 2 * fixed_point_32::Q32;
+        let tick_lower = tick_math::get_tick_at_sqrt_ratio(sqrt_price_x32).unwrap() + 1;
+        let (amount_0, amount_1) = liquidity_amounts::get_amounts_for_liquidity(
+            sqrt_price_x32,
+            tick_math::get_sqrt_ratio_at_tick(tick_lower).unwrap(),
+            tick_math::get_sqrt_ratio_at_tick(tick_lower + 1000).unwrap(),
+            LIQUIDITY,
+        );
+        assert_eq!(amount_1, 0);
+        let apr = fee_apr_estimate(
+            amount_0 / 50,
+            0,
+            SLOTS_PER_YEAR / 365,
+            tick_lower,
+            tick_lower + 1000,
+            LIQUIDITY,
+            sqrt_price_x32,
+        )
+        .unwrap();
+        assert_close(apr, 0.02 * 365.0);
+    }
+
+    #[test]
+    fn scales_inversely_with_the_interval() {
+        let sqrt_price_x32 = tick_math::get_sqrt_ratio_at_tick(0).unwrap();
+        let apr = |interval_slots| {
+            fee_apr_estimate(
+                fixed_point_32::Q32 / 1000,
+                fixed_point_32::Q32 / 1000,
+                interval_slots,
+                -4000,
+                4000,
+                LIQUIDITY,
+                sqrt_price_x32,
+            )
+            .unwrap()
+        };
+        assert_close(apr(1000), 2.0 * apr(2000));
+    }
+
+    #[test]
+    fn does_not_depend_on_the_liquidity() {
+        let sqrt_price_x32 = tick_math::get_sqrt_ratio_at_tick(0).unwrap();
+        let apr = |liquidity| {
+            fee_apr_estimate(
+                fixed_point_32::Q32 / 1000,
+                fixed_point_32::Q32 / 1000,
+                SLOTS_PER_YEAR / 365,
+                -4000,
+                4000,
+                liquidity,
+                sqrt_price_x32,
+            )
+            .unwrap()
+        };
+        assert_close(apr(LIQUIDITY), apr(1000 * LIQUIDITY));
+    }
+
+    #[test]
+    fn zero_without_liquidity_or_interval() {
+        let sqrt_price_x32 = tick_math::get_sqrt_ratio_at_tick(0).unwrap();
+        let q32 = fixed_point_32::Q32;
+        assert_eq!(
+            fee_apr_estimate(q32, q32, 1000, -4000, 4000, 0, sqrt_price_x32).unwrap(),
+            0.0
+        );
+        assert_eq!(
+            fee_apr_estimate(q32, q32, 0, -4000, 4000, LIQUIDITY, sqrt_price_x32).unwrap(),
+            0.0
+        );
+    }
+}
+
+#[cfg(test)]
+mod weighted_center_tick {
+    use super::*;
+
+    #[test]
+    fn center_of_a_single_position_is_its_midpoint() {
+        assert_eq!(weighted_center_tick(&[(-600, 1200, 1_000)]), 300);
+        assert_eq!(weighted_center_tick(&[(-15, 10, 1_000)]), (-15 + 10) / 2);
+        assert_eq!(weighted_center_tick(&[(10, 20, u64::MAX)]), 15);
+    }
+
+    #[test]
+    fn center_is_weighted_by_liquidity() {
+        // centers 0 and 1000, with three times the liquidity at 1000
+        assert_eq!(
+            weighted_center_tick(&[(-100, 100, 1_000), (900, 1100, 3_000)]),
+            750
+        );
+        // centers -600, 0 and 300 weighted 1 : 2 : 1
+        assert_eq!(
+            weighted_center_tick(&[(-700, -500, 500), (-10, 10, 1_000), (200, 400, 500)]),
+            -75
+        );
+    }
+
+    #[test]
+    fn positions_without_liquidity_are_not_counted() {
+        assert_eq!(
+            weighted_center_tick(&[(-100, 100, 1_000), (900, 1100, 0)]),
+            0
+        );
+        assert_eq!(weighted_center_tick(&[(900, 1100, 0)]), 0);
+        assert_eq!(weighted_center_tick(&[]), 0);
+    }
+
+    #[test]
+    fn extreme_ticks_and_liquidity_do_not_overflow() {
+        let positions = [
+            (tick_math::MAX_TICK - 1, tick_math::MAX_TICK, u64::MAX),
+            (tick_math::MAX_TICK - 1, tick_math::MAX_TICK, u64::MAX),
+        ];
+        assert_eq!(weighted_center_tick(&positions), tick_math::MAX_TICK - 1);
+    }
+}
+
+#[cfg(test)]
+mod conservative_collateral_value {
+    use super::*;
+
+    const LIQUIDITY: u64 = 1 << 40;
+
+    /// The value in token_1 of the tokens held by a position at the oracle price, rounding the
+    /// price rather than the value of token_0
+    fn spot_value(tick_lower: i32, tick_upper: i32, sqrt_price_x32: u64) -> u64 {
+        let (amount_0, amount_1) = liquidity_amounts::get_amounts_for_liquidity(
+            sqrt_price_x32,
+            tick_math::get_sqrt_ratio_at_tick(tick_lower).unwrap(),
+            tick_math::get_sqrt_ratio_at_tick(tick_upper).unwrap(),
+            LIQUIDITY,
+        );
+        let price_x32 = sqrt_price_x32
+            .mul_div_floor(sqrt_price_x32, fixed_point_32::Q32)
+            .unwrap();
+        amount_0
+            .mul_div_floor(price_x32, fixed_point_32::Q32)
+            .unwrap()
+            + amount_1
+    }
+
+    #[test]
+    fn in_range_position_is_valued_below_spot() {
+        let sqrt_price_x32 = tick_math::get_sqrt_ratio_at_tick(0).unwrap();
+        let value = conservative_collateral_value(-1000, 1000, LIQUIDITY, sqrt_price_x32).unwrap();
+        let spot = spot_value(-1000, 1000, sqrt_price_x32);
+        assert!(value > 0);
+        assert!(value < spot);
+
+        // L (√P_lower - P_lower / √P_upper), with √P_lower = 1.0001^-500 and
+        // √P_upper = 1.0001^500
+        let expected = LIQUIDITY as f64 * (1.0001_f64.powi(-500) - 1.0001_f64.powi(-1500));
+        assert!((value as f64 - expected).abs() / expected < 1e-6);
+    }
+
+    #[test]
+    fn value_is_at_the_lower_tick_for_any_price_above_it() {
+        let value_at_lower = spot_value(
+            -1000,
+            1000,
+            tick_math::get_sqrt_ratio_at_tick(-1000).unwrap(),
+        );
+        for tick in [-1000, -10, 0, 999, 1000, 5000] {
+            let sqrt_price_x32 = tick_math::get_sqrt_ratio_at_tick(tick).unwrap();
+            let value =
+                conservative_collateral_value(-1000, 1000, LIQUIDITY, sqrt_price_x32).unwrap();
+            assert!(value.abs_diff(value_at_lower) <= value_at_lower / 1_000_000_000);
+            let spot = spot_value(-1000, 1000, sqrt_price_x32);
+            assert!(value <= spot + spot / 1_000_000_000);
+        }
+    }
+
+    #[test]
+    fn below_range_position_is_valued_at_spot() {
+        let sqrt_price_x32 = tick_math::get_sqrt_ratio_at_tick(-3000).unwrap();
+        let value = conservative_collateral_value(-1000, 1000, LIQUIDITY, sqrt_price_x32).unwrap();
+        let spot = spot_value(-1000, 1000, sqrt_price_x32);
+        assert!(value.abs_diff(spot) <= spot / 1_000_000_000);
+    }
+
+    #[test]
+    fn position_without_liquidity_has_no_value() {
+        let sqrt_price_x32 = tick_math::get_sqrt_ratio_at_tick(0).unwrap();
+        assert_eq!(
+            conservative_collateral_value(-1000, 1000, 0, sqrt_price_x32).unwrap(),
+            0
+        );
+    }
+}
+
+#[cfg(test)]
+mod range_for_efficiency {
+    use super::*;
+
+    /// The capital efficiency of a range, relative to an unbounded range, about its center
+    fn efficiency(width: i32) -> f64 {
+        let sqrt_ratio = 1.0001_f64.powf(width as f64 / 4.0);
+        sqrt_ratio / (sqrt_ratio - 1.0)
+    }
+
+    #[test]
+    fn efficiency_matches_the_target_within_the_tick_spacing() {
+        for tick_spacing in [1_u16, 10, 60, 200] {
+            for current_tick in [0, -12_345, 55_555] {
+                for target in [1.5, 2.0, 5.0, 10.0, 50.0, 200.0] {
+                    let (tick_lower, tick_upper) =
+                        range_for_efficiency(current_tick, target, tick_spacing);
+                    let spacing = tick_spacing as i32;
+                    assert_eq!(tick_lower % spacing, 0);
+                    assert_eq!(tick_upper % spacing, 0);
+                    assert!(is_symmetric(tick_lower, tick_upper, current_tick, spacing));
+
+                    // one spacing wider on each side is less efficient than the target, and
+                    // one narrower more efficient, unless the range is a single spacing
+                    let width = tick_upper - tick_lower;
+                    assert!(efficiency(width + 2 * spacing) <= target);
+                    assert!(width == spacing || efficiency(width - 2 * spacing) >= target);
+                }
+            }
+        }
+    }
+
+    /// Whether the range is centered on the tick spacing containing the current tick
+    fn is_symmetric(tick_lower: i32, tick_upper: i32, current_tick: i32, spacing: i32) -> bool {
+        let spacing_lower = current_tick.div_euclid(spacing) * spacing;
+        spacing_lower - tick_lower == tick_upper - (spacing_lower + spacing)
+            && crate::states::position::is_in_range(tick_lower, tick_upper, current_tick)
+    }
+
+    #[test]
+    fn efficiency_of_two_spans_a_quarter_to_four_times_the_price() {
+        // √r = 2 for an efficiency of 2, i.e. r = 4 and a width of 4 ln(2) / ln(1.0001) ticks
+        let (tick_lower, tick_upper) = range_for_efficiency(0, 2.0, 1);
+        let width = 4.0 * 2.0_f64.ln() / 1.0001_f64.ln();
+        assert!(((tick_upper - tick_lower) as f64 - width).abs() <= 2.0);
+    }
+
+    #[test]
+    fn low_targets_give_the_whole_usable_range() {
+        for target in [1.0, 0.5, 0.0, -3.0] {
+            assert_eq!(
+                range_for_efficiency(120, target, 60),
+                (tick_math::MIN_TICK / 60 * 60, tick_math::MAX_TICK / 60 * 60)
+            );
+        }
+    }
+
+    #[test]
+    fn high_targets_give_a_single_tick_spacing() {
+        assert_eq!(range_for_efficiency(125, 1e9, 60), (120, 180));
+        assert_eq!(range_for_efficiency(-1, 1e9, 10), (-10, 0));
+    }
+}
@@ -0,0 +1,242 @@
+//! A typed Q32.32 sqrt price, keeping sqrt prices apart from prices, ticks and other Q32.32
+//! values across the math boundary
+//!
+//! Instruction arguments stay raw Q32.32 numbers, as in the IDL, and are wrapped on entry.
+//!
+use super::big_num::U128;
+use super::fixed_point_32;
+use super::tick_math;
+use crate::error::ErrorCode;
+use anchor_lang::require;
+use std::ops::Deref;
+
+/// The square root of the price of token_0 in terms of token_1, as a Q32.32
+///
+/// Values made with #new or #from_tick lie in the range supported by tick math. The raw
+/// value is read through `Deref` or `u64::from`.
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SqrtPriceX32(u64);
+
+impl SqrtPriceX32 {
+    /// The sqrt price at MIN_TICK
+    pub const MIN: SqrtPriceX32 = SqrtPriceX32(tick_math::MIN_SQRT_RATIO);
+
+    /// The sqrt price at MAX_TICK. Pools never reach it, so it has no tick of its own
+    pub const MAX: SqrtPriceX32 = SqrtPriceX32(tick_math::MAX_SQRT_RATIO);
+
+    /// Wraps a Q32.32 sqrt price
+    ///
+    /// Throws if sqrt_price_x32 < MIN_SQRT_RATIO or sqrt_price_x32 > MAX_SQRT_RATIO
+    ///
+    /// # Arguments
+    ///
+    /// * `sqrt_price_x32` - The sqrt price as a Q32.32
+    ///
+    pub fn new(sqrt_price_x32: u64) -> Result<SqrtPriceX32, anchor_lang::error::Error> {
+        require!(
+            (tick_math::MIN_SQRT_RATIO..=tick_math::MAX_SQRT_RATIO).contains(&sqrt_price_x32),
+            ErrorCode::R
+        );
+        Ok(SqrtPriceX32(sqrt_price_x32))
+    }
+
+    /// Wraps a Q32.32 value known to be a sqrt price in range, such as the price of a pool,
+    /// without checking it
+    ///
+    /// # Arguments
+    ///
+    /// * `sqrt_price_x32` - The sqrt price as a Q32.32
+    ///
+    pub const fn new_unchecked(sqrt_price_x32: u64) -> SqrtPriceX32 {
+        SqrtPriceX32(sqrt_price_x32)
+    }
+
+    /// Returns the sqrt price at a tick, i.e. sqrt(1.0001^tick)
+    ///
+    /// Throws if |tick| > MAX_TICK
+    ///
+    /// # Arguments
+    ///
+    /// * `tick` - Price tick
+    ///
+    pub fn from_tick(tick: i32) -> Result<SqrtPriceX32, anchor_lang::error::Error> {
+        Ok(SqrtPriceX32(tick_math::get_sqrt_ratio_at_tick(tick)?))
+    }
+
+    /// Returns the greatest tick whose sqrt price is at or below this one
+    ///
+    /// Throws for MAX, or for values made with #new_unchecked outside the range
+    ///
+    pub fn to_tick(self) -> Result<i32, anchor_lang::error::Error> {
+        tick_math::get_tick_at_sqrt_ratio(self.0)
+    }
+}
+
+impl Deref for SqrtPriceX32 {
+    type Target = u64;
+
+    fn deref(&self) -> &u64 {
+        &self.0
+    }
+}
+
+impl From<SqrtPriceX32> for u64 {
+    fn from(sqrt_price: SqrtPriceX32) -> u64 {
+        sqrt_price.0
+    }
+}
+
+/// Returns the Q32.32 sqrt price for a price of amount_1 token_1 per amount_0 token_0, i.e.
+/// sqrt(amount_1 / amount_0). Rounds down.
+///
+/// Returns None if amount_0 is zero. The result is not checked against the tick math range,
+/// see SqrtPriceX32#new.
+///
+/// # Arguments
+///
+/// * `amount_1` - The amount of token_1 in the ratio
+/// * `amount_0` - The amount of token_0 in the ratio
+///
+pub fn sqrt_price_from_ratio(amount_1: u64, amount_0: u64) -> Option<u64> {
+    if amount_0 == 0 {
+        return None;
+    }
+    // sqrt(amount_1 / amount_0) * 2^32 = sqrt(amount_1 * 2^64 / amount_0), below 2^64
+    let ratio_x64 = (U128::from(amount_1) << (2 * fixed_point_32::RESOLUTION)) / amount_0;
+    Some(ratio_x64.integer_sqrt().as_u64())
+}
+
+/// Returns the price of token_0 in terms of token_1 for a Q32.32 sqrt price, i.e. the
+/// amount_1 / amount_0 ratio, as a Q32.32. Rounds down.
+///
+/// The inverse of #sqrt_price_from_ratio.
+///
+/// # Arguments
+///
+/// * `sqrt_price_x32` - The sqrt price as a Q32.32
+///
+pub fn ratio_from_sqrt_price(sqrt_price_x32: u64) -> u128 {
+    let sqrt_price_x32 = u128::from(sqrt_price_x32);
+    (sqrt_price_x32 * sqrt_price_x32) >> fixed_point_32::RESOLUTION
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_sqrt_prices_in_range() {
+        for sqrt_price_x32 in [
+            tick_math::MIN_SQRT_RATIO,
+            1 << 32,
+            tick_math::MAX_SQRT_RATIO,
+        ] {
+            let sqrt_price = SqrtPriceX32::new(sqrt_price_x32).unwrap();
+            assert_eq!(*sqrt_price, sqrt_price_x32);
+            assert_eq!(u64::from(sqrt_price), sqrt_price_x32);
+        }
+        assert_eq!(
+            SqrtPriceX32::new(tick_math::MIN_SQRT_RATIO).unwrap(),
+            SqrtPriceX32::MIN
+        );
+    }
+
+    #[test]
+    fn rejects_sqrt_prices_out_of_range() {
+        for sqrt_price_x32 in [
+            0,
+            tick_math::MIN_SQRT_RATIO - 1,
+            tick_math::MAX_SQRT_RATIO + 1,
+            u64::MAX,
+        ] {
+            match SqrtPriceX32::new(sqrt_price_x32).err().unwrap() {
+                anchor_lang::error::Error::AnchorError(error) => {
+                    assert_eq!(error.error_code_number, u32::from(ErrorCode::R))
+                }
+                error => panic!("unexpected error {}", error),
+            }
+        }
+    }
+
+    #[test]
+    fn converts_to_and_from_ticks() {
+        for tick in [
+            tick_math::MIN_TICK,
+            -600,
+            -1,
+            0,
+            1,
+            600,
+            tick_math::MAX_TICK - 1,
+        ] {
+            let sqrt_price = SqrtPriceX32::from_tick(tick).unwrap();
+            assert_eq!(
+                *sqrt_price,
+                tick_math::get_sqrt_ratio_at_tick(tick).unwrap()
+            );
+            assert_eq!(sqrt_price.to_tick().unwrap(), tick);
+        }
+        assert_eq!(
+            SqrtPriceX32::from_tick(tick_math::MAX_TICK).unwrap(),
+            SqrtPriceX32::MAX
+        );
+        assert!(SqrtPriceX32::MAX.to_tick().is_err());
+        assert!(SqrtPriceX32::from_tick(tick_math::MAX_TICK + 1).is_err());
+    }
+
+    #[test]
+    fn orders_like_the_price() {
+        let lower = SqrtPriceX32::from_tick(-10).unwrap();
+        let upper = SqrtPriceX32::from_tick(10).unwrap();
+        assert!(SqrtPriceX32::MIN < lower && lower < upper && upper < SqrtPriceX32::MAX);
+    }
+
+    #[test]
+    fn ratios_round_trip_through_the_sqrt_price() {
+        for (amount_1, amount_0) in [
+            (1, 1),
+            (4, 1),
+            (1, 4),
+            (3, 2),
+            (2, 3),
+            (1_000_000_000, 1),
+            (1, 1_000_000),
+            (123_456_789, 987_654_321),
+        ] {
+            let sqrt_price_x32 = sqrt_price_from_ratio(amount_1, amount_0).unwrap();
+            let ratio_x32 = ratio_from_sqrt_price(sqrt_price_x32);
+            let expected_x32 =
+                (u128::from(amount_1) << fixed_point_32::RESOLUTION) / u128::from(amount_0);
+
+            // the sqrt is floored to a Q32.32, so the squared ratio falls short by at most
+            // 2 / sqrt_price in relative terms
+            assert!(ratio_x32 <= expected_x32);
+            assert!((expected_x32 - ratio_x32) * 1_000_000 <= expected_x32);
+        }
+    }
+
+    #[test]
+    fn equal_amounts_give_a_sqrt_price_of_one() {
+        assert_eq!(sqrt_price_from_ratio(1, 1), Some(1 << 32));
+        assert_eq!(sqrt_price_from_ratio(u64::MAX, u64::MAX), Some(1 << 32));
+        assert_eq!(ratio_from_sqrt_price(1 << 32), 1 << 32);
+    }
+
+    #[test]
+    fn sqrt_price_matches_tick_math() {
+        // 1.0001^600 is not a ratio of integers, so compare against the floored sqrt of the
+        // price at the tick
+        let sqrt_price_x32 = tick_math::get_sqrt_ratio_at_tick(600).unwrap();
+        let ratio_x32 = ratio_from_sqrt_price(sqrt_price_x32) as u64;
+        let round_trip_x32 = sqrt_price_from_ratio(ratio_x32, 1 << 32).unwrap();
+        assert!(sqrt_price_x32 - round_trip_x32 <= 1);
+    }
+
+    #[test]
+    fn zero_amount_0_has_no_sqrt_price() {
+        assert_eq!(sqrt_price_from_ratio(1, 0), None);
+        assert_eq!(sqrt_price_from_ratio(0, 0), None);
+        assert_eq!(sqrt_price_from_ratio(0, 1), Some(0));
+    }
+}
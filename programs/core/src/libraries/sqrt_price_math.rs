@@ -3,6 +3,8 @@ use super::big_num::U128;
 use super::fixed_point_32;
 use super::full_math::MulDiv;
 use super::unsafe_math::UnsafeMathTrait;
+use crate::error::ErrorCode;
+use anchor_lang::prelude::*;
 
 /// Gets the next sqrt price √P' given a delta of token_0
 ///
@@ -210,11 +212,24 @@ pub fn get_next_sqrt_price_from_output(
 /// * `round_up`- Whether to round the amount up or down
 ///
 pub fn get_amount_0_delta_unsigned(
+    sqrt_ratio_a_x32: u64,
+    sqrt_ratio_b_x32: u64,
+    liquidity: u64,
+    round_up: bool,
+) -> u64 {
+    checked_amount_0_delta(sqrt_ratio_a_x32, sqrt_ratio_b_x32, liquidity, round_up).unwrap()
+}
+
+/// Gets the amount_0 delta between two prices like #get_amount_0_delta_unsigned, or None if
+/// the amount does not fit in a u64. Wide ranges near the minimum price, e.g. full range
+/// positions, need more token_0 than a u64 holds for large liquidity.
+///
+fn checked_amount_0_delta(
     mut sqrt_ratio_a_x32: u64,
     mut sqrt_ratio_b_x32: u64,
     liquidity: u64,
     round_up: bool,
-) -> u64 {
+) -> Option<u64> {
     // sqrt_ratio_a_x32 should hold the smaller value
     if sqrt_ratio_a_x32 > sqrt_ratio_b_x32 {
         std::mem::swap(&mut sqrt_ratio_a_x32, &mut sqrt_ratio_b_x32);
@@ -225,20 +240,19 @@ pub fn get_amount_0_delta_unsigned(
 
     assert!(sqrt_ratio_a_x32 > 0);
 
-    if round_up {
+    let amount_0 = if round_up {
         U128::div_rounding_up(
-            numerator_1
-                .mul_div_ceil(numerator_2, U128::from(sqrt_ratio_b_x32))
-                .unwrap(),
+            numerator_1.mul_div_ceil(numerator_2, U128::from(sqrt_ratio_b_x32))?,
             U128::from(sqrt_ratio_a_x32),
         )
-        .as_u64()
     } else {
-        (numerator_1
-            .mul_div_floor(numerator_2, U128::from(sqrt_ratio_b_x32))
-            .unwrap()
-            / U128::from(sqrt_ratio_a_x32))
-        .as_u64()
+        numerator_1.mul_div_floor(numerator_2, U128::from(sqrt_ratio_b_x32))?
+            / U128::from(sqrt_ratio_a_x32)
+    };
+    if amount_0 > U128::from(u64::MAX) {
+        None
+    } else {
+        Some(amount_0.as_u64())
     }
 }
 
@@ -256,11 +270,24 @@ pub fn get_amount_0_delta_unsigned(
 /// * `round_up`- Whether to round the amount up or down
 ///
 pub fn get_amount_1_delta_unsigned(
+    sqrt_ratio_a_x32: u64,
+    sqrt_ratio_b_x32: u64,
+    liquidity: u64,
+    round_up: bool,
+) -> u64 {
+    checked_amount_1_delta(sqrt_ratio_a_x32, sqrt_ratio_b_x32, liquidity, round_up).unwrap()
+}
+
+/// Gets the amount_1 delta between two prices like #get_amount_1_delta_unsigned, or None if
+/// the amount does not fit in a u64. Wide ranges near the maximum price, e.g. full range
+/// positions, need more token_1 than a u64 holds for large liquidity.
+///
+fn checked_amount_1_delta(
     mut sqrt_ratio_a_x32: u64,
     mut sqrt_ratio_b_x32: u64,
     liquidity: u64,
     round_up: bool,
-) -> u64 {
+) -> Option<u64> {
     // sqrt_ratio_a_x32 should hold the smaller value
     if sqrt_ratio_a_x32 > sqrt_ratio_b_x32 {
         std::mem::swap(&mut sqrt_ratio_a_x32, &mut sqrt_ratio_b_x32);
@@ -271,7 +298,15 @@ pub fn get_amount_1_delta_unsigned(
     } else {
         liquidity.mul_div_floor(sqrt_ratio_b_x32 - sqrt_ratio_a_x32, fixed_point_32::Q32)
     }
-    .unwrap()
+}
+
+/// Applies the sign of a liquidity delta to the token amount it moves, failing if the amount
+/// does not fit in an i64
+fn signed_amount(amount: Option<u64>, liquidity: i64) -> Result<i64> {
+    let amount = amount
+        .and_then(|amount| i64::try_from(amount).ok())
+        .ok_or(ErrorCode::AmountOverflow)?;
+    Ok(if liquidity < 0 { -amount } else { amount })
 }
 
 /// Helper function to get signed token_0 delta between two prices,
@@ -287,15 +322,15 @@ pub fn get_amount_0_delta_signed(
     sqrt_ratio_a_x32: u64,
     sqrt_ratio_b_x32: u64,
     liquidity: i64,
-) -> i64 {
-    if liquidity < 0 {
-        -(get_amount_0_delta_unsigned(sqrt_ratio_a_x32, sqrt_ratio_b_x32, -liquidity as u64, false)
-            as i64)
-    } else {
-        // TODO check overflow, since i64::MAX < u64::MAX
-        get_amount_0_delta_unsigned(sqrt_ratio_a_x32, sqrt_ratio_b_x32, liquidity as u64, true)
-            as i64
-    }
+) -> Result<i64> {
+    // round up amounts paid in, and down amounts paid out
+    let amount_0 = checked_amount_0_delta(
+        sqrt_ratio_a_x32,
+        sqrt_ratio_b_x32,
+        liquidity.unsigned_abs(),
+        liquidity >= 0,
+    );
+    signed_amount(amount_0, liquidity)
 }
 
 /// Helper function to get signed token_1 delta between two prices,
@@ -311,15 +346,15 @@ pub fn get_amount_1_delta_signed(
     sqrt_ratio_a_x32: u64,
     sqrt_ratio_b_x32: u64,
     liquidity: i64,
-) -> i64 {
-    if liquidity < 0 {
-        -(get_amount_1_delta_unsigned(sqrt_ratio_a_x32, sqrt_ratio_b_x32, -liquidity as u64, false)
-            as i64)
-    } else {
-        // TODO check overflow, since i64::MAX < u64::MAX
-        get_amount_1_delta_unsigned(sqrt_ratio_a_x32, sqrt_ratio_b_x32, liquidity as u64, true)
-            as i64
-    }
+) -> Result<i64> {
+    // round up amounts paid in, and down amounts paid out
+    let amount_1 = checked_amount_1_delta(
+        sqrt_ratio_a_x32,
+        sqrt_ratio_b_x32,
+        liquidity.unsigned_abs(),
+        liquidity >= 0,
+    );
+    signed_amount(amount_1, liquidity)
 }
 
 #[cfg(test)]
@@ -776,4 +811,93 @@ mod sqrt_math {
             assert_eq!(amount_0_down, 0); // floor
         }
     }
+
+    mod get_amount_delta_signed {
+        use super::*;
+        use crate::libraries::tick_math::{MAX_SQRT_RATIO, MIN_SQRT_RATIO};
+
+        fn assert_overflow(result: Result<i64>) {
+            match result.err().unwrap() {
+                anchor_lang::error::Error::AnchorError(error) => assert_eq!(
+                    error.error_code_number,
+                    u32::from(ErrorCode::AmountOverflow)
+                ),
+                error => panic!("unexpected error {}", error),
+            }
+        }
+
+        #[test]
+        fn rounds_up_amounts_paid_in_and_down_amounts_paid_out() {
+            let (sqrt_price_a_x32, sqrt_price_b_x32) =
+                (encode_price_sqrt_x32(1, 1), encode_price_sqrt_x32(121, 100));
+            let liquidity = i64::pow(10, 8);
+            let amount_0_up = get_amount_0_delta_unsigned(
+                sqrt_price_a_x32,
+                sqrt_price_b_x32,
+                u64::pow(10, 8),
+                true,
+            );
+            let amount_1_down = get_amount_1_delta_unsigned(
+                sqrt_price_a_x32,
+                sqrt_price_b_x32,
+                u64::pow(10, 8),
+                false,
+            );
+
+            assert_eq!(
+                get_amount_0_delta_signed(sqrt_price_a_x32, sqrt_price_b_x32, liquidity).unwrap(),
+                amount_0_up as i64
+            );
+            assert_eq!(
+                get_amount_1_delta_signed(sqrt_price_a_x32, sqrt_price_b_x32, -liquidity).unwrap(),
+                -(amount_1_down as i64)
+            );
+        }
+
+        #[test]
+        fn handles_full_range_positions() {
+            // Δx ≈ L / √P_min = 65536 L
+            let amount_0 =
+                get_amount_0_delta_signed(MIN_SQRT_RATIO, MAX_SQRT_RATIO, 1_000_000).unwrap();
+            assert!(amount_0 > 65_000_000_000 && amount_0 < 65_536_000_000);
+            // Δy ≈ L √P_max ≈ 65536 L
+            let amount_1 =
+                get_amount_1_delta_signed(MIN_SQRT_RATIO, MAX_SQRT_RATIO, -1_000_000).unwrap();
+            assert!(amount_1 < -65_000_000_000 && amount_1 > -65_536_000_000);
+        }
+
+        #[test]
+        fn fails_if_the_amount_overflows_u64() {
+            assert_overflow(get_amount_0_delta_signed(
+                MIN_SQRT_RATIO,
+                MAX_SQRT_RATIO,
+                i64::MAX,
+            ));
+            assert_overflow(get_amount_1_delta_signed(
+                MIN_SQRT_RATIO,
+                MAX_SQRT_RATIO,
+                i64::MIN,
+            ));
+        }
+
+        #[test]
+        fn fails_if_the_amount_overflows_i64() {
+            // Δy = 2L lies between i64::MAX and u64::MAX
+            let (sqrt_price_a_x32, sqrt_price_b_x32) = (1 << 32, 3 << 32);
+            assert_eq!(
+                get_amount_1_delta_unsigned(sqrt_price_a_x32, sqrt_price_b_x32, 1 << 62, true),
+                1 << 63
+            );
+            assert_overflow(get_amount_1_delta_signed(
+                sqrt_price_a_x32,
+                sqrt_price_b_x32,
+                1 << 62,
+            ));
+            assert_overflow(get_amount_1_delta_signed(
+                sqrt_price_a_x32,
+                sqrt_price_b_x32,
+                -(1 << 62),
+            ));
+        }
+    }
 }
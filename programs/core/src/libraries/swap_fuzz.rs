@@ -0,0 +1,379 @@
+//! Fixtures for fuzzing the swap loop against a reference model
+//! Builds random pools from a deterministic PRNG, and swaps over them with a plain model
+//! that finds ticks by scanning a sorted list instead of bitmap words.
+//!
+//! Enabled with the `testing` feature.
+//!
+use super::sqrt_price::SqrtPriceX32;
+use super::swap_math;
+use super::swap_quote::SwapQuote;
+use super::tick_math;
+use crate::states::pool::PoolState;
+use crate::states::position::is_in_range;
+use crate::states::tick::TickState;
+
+/// A deterministic SplitMix64 generator. The same seed always gives the same sequence.
+pub struct Prng(u64);
+
+impl Prng {
+    /// Creates a generator from a seed
+    pub fn new(seed: u64) -> Self {
+        Prng(seed)
+    }
+
+    /// Returns the next pseudo random u64
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a pseudo random value in `[low, high)`
+    pub fn gen_range(&mut self, low: i64, high: i64) -> i64 {
+        assert!(low < high);
+        low + (self.next_u64() % (high - low) as u64) as i64
+    }
+
+    /// Returns a pseudo random value between 1 and 2^max_bits, spread evenly over magnitudes
+    pub fn gen_magnitude(&mut self, max_bits: u32) -> u64 {
+        let bits = self.gen_range(1, max_bits as i64 + 1) as u32;
+        1 + self.next_u64() % (1 << bits)
+    }
+}
+
+/// The fee tiers and tick spacings a random pool is drawn from
+const FEE_TIERS: [(u32, u16); 4] = [(100, 1), (500, 10), (3000, 60), (10_000, 200)];
+
+/// Builds a random pool and its ticks from a seed.
+///
+/// The pool holds a few random positions around its current tick. Ticks are returned in
+/// ascending order, and may include ticks whose liquidity was entirely removed. The in range
+/// liquidity of the pool matches the positions.
+///
+/// # Arguments
+///
+/// * `seed` - Seed for the PRNG
+///
+pub fn random_pool_state(seed: u64) -> (PoolState, Vec<TickState>) {
+    let mut rng = Prng::new(seed);
+    let (fee, tick_spacing) = FEE_TIERS[rng.gen_range(0, FEE_TIERS.len() as i64) as usize];
+    let spacing = tick_spacing as i64;
+
+    let tick = rng.gen_range(-100_000, 100_000) as i32;
+    let sqrt_price_lower_x32 = tick_math::get_sqrt_ratio_at_tick(tick).unwrap();
+    let sqrt_price_upper_x32 = tick_math::get_sqrt_ratio_at_tick(tick + 1).unwrap();
+    let sqrt_price_x32 =
+        sqrt_price_lower_x32 + rng.next_u64() % (sqrt_price_upper_x32 - sqrt_price_lower_x32);
+
+    let mut ticks: Vec<TickState> = Vec::new();
+    let mut liquidity = 0;
+    for _ in 0..rng.gen_range(1, 9) {
+        // positions span up to two bitmap words either side of the current tick
+        let compressed = tick as i64 / spacing;
+        let compressed_lower = compressed + rng.gen_range(-512, 256);
+        let compressed_upper = compressed_lower + rng.gen_range(1, 512);
+        let tick_lower = (compressed_lower * spacing) as i32;
+        let tick_upper = (compressed_upper * spacing) as i32;
+        let position_liquidity = rng.gen_magnitude(40) as i64;
+        // some positions are burned again, leaving their ticks without liquidity
+        let burned = rng.gen_range(0, 4) == 0;
+
+        for (tick_index, upper) in [(tick_lower, false), (tick_upper, true)] {
+            let index = match ticks.iter().position(|t| t.tick == tick_index) {
+                Some(index) => index,
+                None => {
+                    ticks.push(TickState {
+                        tick: tick_index,
+                        ..Default::default()
+                    });
+                    ticks.len() - 1
+                }
+            };
+            ticks[index]
+                .update(tick, position_liquidity, 0, 0, 0, 0, 0, 0, upper, u64::MAX)
+                .unwrap();
+            if burned {
+                ticks[index]
+                    .update(tick, -position_liquidity, 0, 0, 0, 0, 0, 0, upper, u64::MAX)
+                    .unwrap();
+            }
+        }
+        if !burned && is_in_range(tick_lower, tick_upper, tick) {
+            liquidity += position_liquidity as u64;
+        }
+    }
+    ticks.sort_by_key(|t| t.tick);
+
+    let pool = PoolState {
+        fee,
+        tick_spacing,
+        sqrt_price_x32,
+        tick,
+        liquidity,
+        ..Default::default()
+    };
+    (pool, ticks)
+}
+
+/// Reference model of an exact input swap without a price limit, as done by the swap loop.
+///
+/// The next tick is the nearest initialized tick in the swap direction, found by scanning the
+/// ticks. Like the swap instruction, a step stops early at the boundary of the bitmap word of
+/// the current tick, since step boundaries affect rounding.
+///
+/// # Arguments
+///
+/// * `pool` - The pool before the swap
+/// * `ticks` - Ticks of the pool in ascending order
+/// * `amount_in` - The amount to swap in, including fees
+/// * `zero_for_one` - The direction of the swap, true for token_0 to token_1
+///
+pub fn reference_swap(
+    pool: &PoolState,
+    ticks: &[TickState],
+    amount_in: u64,
+    zero_for_one: bool,
+) -> SwapQuote {
+    let initialized: Vec<&TickState> = ticks.iter().filter(|t| t.liquidity_gross > 0).collect();
+    let spacing = pool.tick_spacing as i32;
+    let sqrt_price_limit_x32 = if zero_for_one {
+        tick_math::MIN_SQRT_RATIO + 1
+    } else {
+        tick_math::MAX_SQRT_RATIO - 1
+    };
+
+    let mut amount_remaining = amount_in;
+    let mut sqrt_price_x32 = pool.sqrt_price_x32;
+    let mut quote = SwapQuote {
+        tick: pool.tick,
+        ..Default::default()
+    };
+    let mut liquidity = i128::from(pool.liquidity);
+
+    while amount_remaining != 0 && sqrt_price_x32 != sqrt_price_limit_x32 {
+        // a one for zero swap starts searching from the tick after the current one
+        let compressed = quote.tick.div_euclid(spacing) + if zero_for_one { 0 } else { 1 };
+        let word_start = compressed.div_euclid(256) * 256;
+
+        let (tick_next, crossed) = if zero_for_one {
+            let word_boundary = word_start * spacing;
+            match initialized
+                .iter()
+                .rev()
+                .find(|t| t.tick <= compressed * spacing)
+            {
+                Some(t) if t.tick >= word_boundary => (t.tick, Some(t.liquidity_net)),
+                _ => (word_boundary, None),
+            }
+        } else {
+            let word_boundary = (word_start + 255) * spacing;
+            match initialized.iter().find(|t| t.tick >= compressed * spacing) {
+                Some(t) if t.tick <= word_boundary => (t.tick, Some(t.liquidity_net)),
+                _ => (word_boundary, None),
+            }
+        };
+        let tick_next = tick_next.clamp(tick_math::MIN_TICK, tick_math::MAX_TICK);
+        let sqrt_price_next_x32 = tick_math::get_sqrt_ratio_at_tick(tick_next).unwrap();
+        let sqrt_price_target_x32 = if zero_for_one {
+            sqrt_price_next_x32.max(sqrt_price_limit_x32)
+        } else {
+            sqrt_price_next_x32.min(sqrt_price_limit_x32)
+        };
+
+        let sqrt_price_start_x32 = sqrt_price_x32;
+        let step = swap_math::compute_swap_step(
+            sqrt_price_x32,
+            sqrt_price_target_x32,
+            u64::try_from(liquidity).unwrap(),
+            i64::try_from(amount_remaining).unwrap(),
+            pool.fee,
+        );
+        sqrt_price_x32 = step.sqrt_ratio_next_x32;
+        amount_remaining -= step.amount_in + step.fee_amount;
+        quote.amount_out += step.amount_out;
+        quote.fee_amount += step.fee_amount;
+        if liquidity > 0 {
+            let fee_growth_x32 = (u128::from(step.fee_amount) << 32) / liquidity as u128;
+            quote.fee_growth_x32 = quote.fee_growth_x32.wrapping_add(fee_growth_x32 as u64);
+        }
+
+        if sqrt_price_x32 == sqrt_price_next_x32 {
+            if let Some(liquidity_net) = crossed {
+                // liquidity_net is added moving right, and removed moving left
+                if zero_for_one {
+                    liquidity -= i128::from(liquidity_net);
+                } else {
+                    liquidity += i128::from(liquidity_net);
+                }
+                quote.crossed_ticks.push(tick_next);
+            }
+            quote.tick = if zero_for_one {
+                tick_next - 1
+            } else {
+                tick_next
+            };
+        } else if sqrt_price_x32 != sqrt_price_start_x32 {
+            quote.tick = tick_math::get_tick_at_sqrt_ratio(sqrt_price_x32).unwrap();
+        }
+    }
+
+    quote.sqrt_price_x32 = SqrtPriceX32::new_unchecked(sqrt_price_x32);
+    quote.amount_in = amount_in - amount_remaining;
+    quote.liquidity = u64::try_from(liquidity).unwrap();
+    quote
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorCode;
+    use crate::libraries::swap_quote::{apply_swaps, quote_swap, PoolSnapshot};
+    use crate::states::tick::liquidity_at_tick;
+    use quickcheck::quickcheck;
+
+    /// Swaps over a random pool with both the swap loop and the reference model
+    fn swap_both(seed: u64, amount_in: u64, zero_for_one: bool) -> (SwapQuote, SwapQuote) {
+        let (pool, ticks) = random_pool_state(seed);
+        let snapshot = PoolSnapshot::new(&pool, &ticks, 0);
+        let reference = reference_swap(&pool, &ticks, amount_in, zero_for_one);
+        match quote_swap(&snapshot, amount_in, zero_for_one) {
+            Ok(quote) => (quote, reference),
+            // the swap loop fails where the reference model finds no liquidity to fill against
+            Err(anchor_lang::error::Error::AnchorError(error))
+                if error.error_code_number == u32::from(ErrorCode::InsufficientLiquidity)
+                    && reference.amount_in == 0 =>
+            {
+                (
+                    reference_swap(&pool, &ticks, amount_in, zero_for_one),
+                    reference,
+                )
+            }
+            Err(error) => panic!("unexpected error {}", error),
+        }
+    }
+
+    #[test]
+    fn prng_is_deterministic() {
+        let mut a = Prng::new(7);
+        let mut b = Prng::new(7);
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+        assert_ne!(Prng::new(7).next_u64(), Prng::new(8).next_u64());
+    }
+
+    #[test]
+    fn random_pools_are_consistent() {
+        for seed in 0..200 {
+            let (pool, ticks) = random_pool_state(seed);
+            assert_eq!(
+                tick_math::get_tick_at_sqrt_ratio(pool.sqrt_price_x32).unwrap(),
+                { pool.tick }
+            );
+            assert!(ticks.windows(2).all(|pair| pair[0].tick < pair[1].tick));
+            assert!(ticks.iter().all(|t| t.tick % pool.tick_spacing as i32 == 0));
+            // the net liquidity of all ticks cancels out
+            assert_eq!(
+                ticks
+                    .iter()
+                    .map(|t| i128::from(t.liquidity_net))
+                    .sum::<i128>(),
+                0
+            );
+        }
+    }
+
+    #[test]
+    fn swaps_match_reference_for_random_pools() {
+        let mut rng = Prng::new(0);
+        for seed in 0..500 {
+            let amount_in = rng.gen_magnitude(40);
+            let zero_for_one = rng.gen_range(0, 2) == 0;
+            let (quote, reference) = swap_both(seed, amount_in, zero_for_one);
+            assert_eq!(quote, reference, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn swaps_through_all_liquidity_match_reference() {
+        // large swaps cross every tick and end at the price limit
+        for seed in 0..50 {
+            for zero_for_one in [true, false] {
+                let (quote, reference) = swap_both(seed, 1 << 62, zero_for_one);
+                assert_eq!(quote, reference, "seed {}", seed);
+                assert_eq!(reference.liquidity, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn folded_swaps_match_sequential_reference_swaps() {
+        let mut rng = Prng::new(2);
+        for seed in 0..200 {
+            let (mut pool, ticks) = random_pool_state(seed);
+            let swaps: Vec<(u64, bool)> = (0..rng.gen_range(1, 8))
+                .map(|_| (rng.gen_magnitude(36), rng.gen_range(0, 2) == 0))
+                .collect();
+            let folded = apply_swaps(&PoolSnapshot::new(&pool, &ticks, 0), &swaps);
+
+            // each swap lands on the pool the swap before it left behind
+            let mut filled = true;
+            for &(amount_in, zero_for_one) in &swaps {
+                let reference = reference_swap(&pool, &ticks, amount_in, zero_for_one);
+                filled &= reference.amount_in > 0;
+                pool.sqrt_price_x32 = *reference.sqrt_price_x32;
+                pool.tick = reference.tick;
+                pool.liquidity = reference.liquidity;
+            }
+            match folded {
+                Ok(snapshot) => {
+                    assert!(filled, "seed {}", seed);
+                    assert_eq!(
+                        (*snapshot.sqrt_price_x32, snapshot.tick, snapshot.liquidity),
+                        (pool.sqrt_price_x32, pool.tick, pool.liquidity),
+                        "seed {}",
+                        seed
+                    );
+                }
+                // a swap finding no liquidity fails the sequence, like it fails on chain
+                Err(_) => assert!(!filled, "seed {}", seed),
+            }
+        }
+    }
+
+    #[test]
+    fn reference_liquidity_matches_crossed_ticks() {
+        let mut rng = Prng::new(1);
+        for seed in 0..200 {
+            let (pool, ticks) = random_pool_state(seed);
+            let zero_for_one = rng.gen_range(0, 2) == 0;
+            let reference = reference_swap(&pool, &ticks, rng.gen_magnitude(40), zero_for_one);
+            assert_eq!(
+                reference.liquidity,
+                liquidity_at_tick(&ticks, pool.liquidity, pool.tick, reference.tick),
+                "seed {}",
+                seed
+            );
+        }
+    }
+
+    quickcheck! {
+        fn swap_matches_reference(seed: u64, amount_in: u64, zero_for_one: bool) -> bool {
+            let (quote, reference) = swap_both(seed, amount_in >> 2, zero_for_one);
+            quote == reference
+        }
+
+        fn swap_conserves_amount_in(seed: u64, amount_in: u64, zero_for_one: bool) -> bool {
+            let (pool, ticks) = random_pool_state(seed);
+            let amount_in = amount_in >> 24;
+            let reference = reference_swap(&pool, &ticks, amount_in, zero_for_one);
+            // the whole input is used unless the swap ran into the price limit
+            reference.fee_amount <= reference.amount_in
+                && (reference.amount_in == amount_in
+                    || *reference.sqrt_price_x32 == tick_math::MIN_SQRT_RATIO + 1
+                    || *reference.sqrt_price_x32 == tick_math::MAX_SQRT_RATIO - 1)
+        }
+    }
+}
@@ -19,10 +19,23 @@ pub struct SwapStep {
     pub fee_amount: u64,
 }
 
+/// How a swap step rounds the amounts it computes. The amount out is always rounded down.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoundingMode {
+    /// Rounds the amount in and the fee up, so that rounding always favors the pool.
+    /// Used by every swap
+    PoolFavored,
+
+    /// Rounds the amount in and the fee down, to the truncated exact values. Only for internal
+    /// computations which must not overstate the input, never to settle a swap
+    Floor,
+}
+
 /// Computes the result of swapping some amount in, or amount out, given the parameters of the swap
 ///
 /// The fee, plus amount in, will never exceed the amount remaining if the swap's
-/// `amount_specified` is positive, i.e. in an exact input swap
+/// `amount_specified` is positive, i.e. in an exact input swap. Rounds in favor of the pool,
+/// i.e. with RoundingMode::PoolFavored.
 ///
 /// # Arguments
 ///
@@ -40,11 +53,46 @@ pub fn compute_swap_step(
     amount_remaining: i64,
     fee_pips: u32,
 ) -> SwapStep {
+    compute_swap_step_with_rounding(
+        sqrt_ratio_current_x32,
+        sqrt_ratio_target_x32,
+        liquidity,
+        amount_remaining,
+        fee_pips,
+        RoundingMode::PoolFavored,
+    )
+}
+
+/// Computes the result of swapping some amount in, or amount out, like #compute_swap_step,
+/// with the given rounding of the input
+///
+/// The fee, plus amount in, will never exceed the amount remaining if the swap's
+/// `amount_specified` is positive, i.e. in an exact input swap
+///
+/// # Arguments
+///
+/// * `sqrt_ratio_current_x32` - The current sqrt price of the pool
+/// * `sqrt_ratio_target_x32` - The price that cannot be exceeded, from which the direction of
+///   the swap is determined
+/// * `liquidity` The usable liquidity
+/// * `amount_remaining` - How much input or output amount is remaining to be swapped in/out
+/// * `fee_pips` - The fee taken from the input amount, expressed in hundredths of a bip (1/100 x 0.01% = 10^6)
+/// * `rounding` - How the amount in and fee are rounded
+///
+pub fn compute_swap_step_with_rounding(
+    sqrt_ratio_current_x32: u64,
+    sqrt_ratio_target_x32: u64,
+    liquidity: u64,
+    amount_remaining: i64,
+    fee_pips: u32,
+    rounding: RoundingMode,
+) -> SwapStep {
+    let round_up_input = rounding == RoundingMode::PoolFavored;
     let zero_for_one = sqrt_ratio_current_x32 >= sqrt_ratio_target_x32;
     let exact_in = amount_remaining >= 0;
     let mut swap_step = SwapStep::default();
     if exact_in {
-        // round up amount_in
+        // round up amount_in, unless rounding down
         // In exact input case, amount_remaining is positive
         let amount_remaining_less_fee = (amount_remaining as u64)
             .mul_div_floor((1_000_000 - fee_pips).into(), 1_000_000)
@@ -55,14 +103,14 @@ pub fn compute_swap_step(
                 sqrt_ratio_target_x32,
                 sqrt_ratio_current_x32,
                 liquidity,
-                true,
+                round_up_input,
             )
         } else {
             sqrt_price_math::get_amount_1_delta_unsigned(
                 sqrt_ratio_current_x32,
                 sqrt_ratio_target_x32,
                 liquidity,
-                true,
+                round_up_input,
             )
         };
         swap_step.sqrt_ratio_next_x32 = if amount_remaining_less_fee >= swap_step.amount_in {
@@ -116,7 +164,7 @@ pub fn compute_swap_step(
                 swap_step.sqrt_ratio_next_x32,
                 sqrt_ratio_current_x32,
                 liquidity,
-                true,
+                round_up_input,
             )
         };
         // if max is reached for exact output case, entire amount_out is needed
@@ -134,7 +182,7 @@ pub fn compute_swap_step(
                 sqrt_ratio_current_x32,
                 swap_step.sqrt_ratio_next_x32,
                 liquidity,
-                true,
+                round_up_input,
             )
         };
         if !(max && !exact_in) {
@@ -152,21 +200,48 @@ pub fn compute_swap_step(
         swap_step.amount_out = -amount_remaining as u64;
     }
 
-    swap_step.fee_amount = if exact_in && swap_step.sqrt_ratio_next_x32 != sqrt_ratio_target_x32 {
+    swap_step.fee_amount = if fee_pips == 0 {
+        // zero fee pools never accrue fees. Swap dust is taken as input instead
+        if exact_in && swap_step.sqrt_ratio_next_x32 != sqrt_ratio_target_x32 {
+            swap_step.amount_in = amount_remaining as u64;
+        }
+        0
+    } else if exact_in && swap_step.sqrt_ratio_next_x32 != sqrt_ratio_target_x32 {
         // we didn't reach the target, so take the remainder of the maximum input as fee
         // swap dust is granted as fee
         amount_remaining as u64 - swap_step.amount_in
     } else {
         // take pip percentage as fee
-        swap_step
-            .amount_in
-            .mul_div_ceil(fee_pips.into(), (1_000_000 - fee_pips).into())
-            .unwrap()
+        match rounding {
+            RoundingMode::PoolFavored => swap_step
+                .amount_in
+                .mul_div_ceil(fee_pips.into(), (1_000_000 - fee_pips).into()),
+            RoundingMode::Floor => swap_step
+                .amount_in
+                .mul_div_floor(fee_pips.into(), (1_000_000 - fee_pips).into()),
+        }
+        .unwrap()
     };
 
     swap_step
 }
 
+/// Splits an exact input amount between two pools of the same pair. Rounds the first
+/// leg down, so the remainder is routed through the second pool.
+///
+/// # Arguments
+///
+/// * `amount_in` - The total amount to be swapped in
+/// * `share_pips` - The share of `amount_in` routed through the first pool, expressed in
+///   hundredths of a bip. Must not exceed 10^6
+///
+pub fn split_amount_in(amount_in: u64, share_pips: u32) -> (u64, u64) {
+    let amount_in_first = amount_in
+        .mul_div_floor(share_pips.into(), 1_000_000)
+        .unwrap();
+    (amount_in_first, amount_in - amount_in_first)
+}
+
 /// Derive expected values from math formulae
 ///
 #[cfg(test)]
@@ -174,6 +249,64 @@ mod swap_math {
     use super::*;
     use crate::libraries::test_utils::*;
 
+    #[test]
+    fn pool_favored_rounding_rounds_the_input_of_a_step_up() {
+        let sqrt_p_x32 = encode_price_sqrt_x32(1, 1);
+        let sqrt_p_x32_target = encode_price_sqrt_x32(101, 100);
+        let liquidity = 2 * u64::pow(10, 8);
+        let amount = i64::pow(10, 8);
+
+        let step = compute_swap_step_with_rounding(
+            sqrt_p_x32,
+            sqrt_p_x32_target,
+            liquidity,
+            amount,
+            600,
+            RoundingMode::PoolFavored,
+        );
+        // ceil(2 * 10^8 (4316388712 - 4294967296) / 2^32), and ceil(997513 * 600 / (10^6 - 600))
+        assert_eq!((step.amount_in, step.fee_amount), (997513, 599));
+        assert_eq!(
+            step.amount_out,
+            compute_swap_step(sqrt_p_x32, sqrt_p_x32_target, liquidity, amount, 600).amount_out
+        );
+    }
+
+    #[test]
+    fn floor_rounding_rounds_the_input_of_a_step_down() {
+        let sqrt_p_x32 = encode_price_sqrt_x32(1, 1);
+        let sqrt_p_x32_target = encode_price_sqrt_x32(101, 100);
+        let liquidity = 2 * u64::pow(10, 8);
+
+        let floor = compute_swap_step_with_rounding(
+            sqrt_p_x32,
+            sqrt_p_x32_target,
+            liquidity,
+            i64::pow(10, 8),
+            600,
+            RoundingMode::Floor,
+        );
+        assert_eq!(floor.sqrt_ratio_next_x32, sqrt_p_x32_target);
+        assert_eq!((floor.amount_in, floor.fee_amount), (997512, 598));
+        // the output is rounded down in either mode
+        assert_eq!(floor.amount_out, 992561);
+
+        // an exact output step pays one less unit of input for the same output
+        let amount_out = -(floor.amount_out as i64);
+        let [pool_favored, floor] = [RoundingMode::PoolFavored, RoundingMode::Floor].map(|mode| {
+            compute_swap_step_with_rounding(
+                sqrt_p_x32,
+                sqrt_p_x32_target,
+                liquidity,
+                amount_out,
+                600,
+                mode,
+            )
+        });
+        assert_eq!(pool_favored.amount_out, floor.amount_out);
+        assert_eq!(pool_favored.amount_in, floor.amount_in + 1);
+    }
+
     #[test]
     fn exact_amount_in_that_gets_capped_at_price_target_in_one_for_zero() {
         // exact amount in for token_1 -> token_0 swap
@@ -452,6 +585,59 @@ mod swap_math {
         assert_eq!(fee_amount, 1); // entire input must convert into fees
     }
 
+    #[test]
+    fn zero_fee_takes_no_fee_when_capped_at_price_target() {
+        let sqrt_p_x32 = encode_price_sqrt_x32(1, 1);
+        let sqrt_p_x32_target = encode_price_sqrt_x32(101, 100);
+        let liquidity = 2 * u64::pow(10, 8);
+        let amount = i64::pow(10, 8);
+
+        let SwapStep {
+            sqrt_ratio_next_x32,
+            amount_in,
+            fee_amount,
+            ..
+        } = compute_swap_step(sqrt_p_x32, sqrt_p_x32_target, liquidity, amount, 0);
+
+        assert_eq!(sqrt_ratio_next_x32, sqrt_p_x32_target);
+        assert!(amount_in < amount as u64);
+        assert_eq!(fee_amount, 0);
+    }
+
+    #[test]
+    fn zero_fee_takes_swap_dust_as_input() {
+        // Δy is too small to move the price. floor(100 + 1 * 2^32 / 2^33) = 100
+        let SwapStep {
+            sqrt_ratio_next_x32,
+            amount_in,
+            amount_out,
+            fee_amount,
+        } = compute_swap_step(100, 100_000, u64::pow(2, 33), 1, 0);
+
+        assert_eq!(sqrt_ratio_next_x32, 100);
+        assert_eq!(amount_out, 0);
+        assert_eq!(amount_in, 1);
+        assert_eq!(fee_amount, 0);
+    }
+
+    #[test]
+    fn zero_fee_exact_output_takes_no_fee() {
+        let sqrt_p_x32 = encode_price_sqrt_x32(1, 1);
+        let sqrt_p_x32_target = encode_price_sqrt_x32(100, 101);
+        let liquidity = 2 * u64::pow(10, 8);
+
+        let SwapStep {
+            amount_in,
+            amount_out,
+            fee_amount,
+            ..
+        } = compute_swap_step(sqrt_p_x32, sqrt_p_x32_target, liquidity, -1000, 0);
+
+        assert_eq!(amount_out, 1000);
+        assert!(amount_in > 0);
+        assert_eq!(fee_amount, 0);
+    }
+
     /// Exact output amount can remain unmet if liquidity is low and
     /// available price range is small. Due to rounding down, output amount
     /// can become zero.
@@ -517,3 +703,60 @@ mod swap_math {
         assert_eq!(fee_amount, 1); // ceil(0.003 / (1- 0.003) * 1)
     }
 }
+
+#[cfg(test)]
+mod split_amount_in {
+    use super::*;
+    use crate::libraries::test_utils::*;
+    use crate::libraries::tick_math;
+
+    /// Output of an exact input zero for one swap within a single tick range
+    fn amount_out(liquidity: u64, amount_in: u64, fee_pips: u32) -> u64 {
+        if amount_in == 0 {
+            return 0;
+        }
+        compute_swap_step(
+            encode_price_sqrt_x32(1, 1),
+            tick_math::MIN_SQRT_RATIO + 1,
+            liquidity,
+            amount_in as i64,
+            fee_pips,
+        )
+        .amount_out
+    }
+
+    #[test]
+    fn remainder_goes_to_the_second_leg() {
+        assert_eq!(split_amount_in(10, 333_333), (3, 7));
+        assert_eq!(split_amount_in(1_000, 500_000), (500, 500));
+        assert_eq!(split_amount_in(1_000, 0), (0, 1_000));
+        assert_eq!(split_amount_in(1_000, 1_000_000), (1_000, 0));
+    }
+
+    #[test]
+    fn split_across_fee_tiers_beats_single_pool_for_large_order() {
+        let liquidity = 2 * u64::pow(10, 9);
+        let amount_in = u64::pow(10, 9);
+
+        let single = amount_out(liquidity, amount_in, 3000);
+
+        let (amount_in_low_fee, amount_in_high_fee) = split_amount_in(amount_in, 500_000);
+        let split = amount_out(liquidity, amount_in_low_fee, 500)
+            + amount_out(liquidity, amount_in_high_fee, 3000);
+
+        assert!(split > single);
+    }
+
+    #[test]
+    fn full_share_matches_single_pool() {
+        let liquidity = 2 * u64::pow(10, 9);
+        let amount_in = u64::pow(10, 9);
+
+        let (amount_in_first, amount_in_second) = split_amount_in(amount_in, 1_000_000);
+        assert_eq!(
+            amount_out(liquidity, amount_in_first, 3000)
+                + amount_out(liquidity, amount_in_second, 500),
+            amount_out(liquidity, amount_in, 3000)
+        );
+    }
+}
@@ -0,0 +1,2463 @@
+//! Off-chain swap quotes from a snapshot of pool state
+//! Steps through ticks exactly like the swap instruction, so that quotes match execution
+//!
+use super::big_num::U256;
+use super::full_math::MulDiv;
+use super::sqrt_price::SqrtPriceX32;
+use super::{fixed_point_32, liquidity_math, swap_math, tick_math};
+use crate::error::ErrorCode;
+use crate::states::pool::PoolState;
+use crate::states::position::is_in_range;
+use crate::states::tick::TickState;
+use crate::states::tick_bitmap;
+use anchor_lang::require;
+
+/// A read only copy of the pool state needed to simulate swaps
+#[derive(Clone, Copy)]
+pub struct PoolSnapshot<'a> {
+    /// The current sqrt price of the pool
+    pub sqrt_price_x32: SqrtPriceX32,
+
+    /// The current tick of the pool
+    pub tick: i32,
+
+    /// The currently in range liquidity of the pool
+    pub liquidity: u64,
+
+    /// The pool fee in hundredths of a bip, i.e. 1e-6
+    pub fee: u32,
+
+    /// The minimum number of ticks between initialized ticks
+    pub tick_spacing: u16,
+
+    /// Ticks of the pool in ascending order. Must hold every initialized tick that
+    /// quoted swaps can cross. Uninitialized ticks are skipped.
+    pub ticks: &'a [TickState],
+
+    /// The slot at which the pool state was read
+    pub slot: u64,
+}
+
+impl<'a> PoolSnapshot<'a> {
+    /// Takes a snapshot of a pool and its ticks
+    ///
+    /// # Arguments
+    ///
+    /// * `pool` - The pool state
+    /// * `ticks` - Ticks of the pool in ascending order
+    /// * `slot` - The slot at which the pool and ticks were read
+    ///
+    pub fn new(pool: &PoolState, ticks: &'a [TickState], slot: u64) -> Self {
+        PoolSnapshot {
+            sqrt_price_x32: SqrtPriceX32::new_unchecked(pool.sqrt_price_x32),
+            tick: pool.tick,
+            liquidity: pool.liquidity,
+            fee: pool.fee,
+            tick_spacing: pool.tick_spacing,
+            ticks,
+            slot,
+        }
+    }
+
+    /// Quotes an exact input swap like #quote_swap, and moves the snapshot to the pool state
+    /// after it, so that the next quote starts where this swap ends
+    ///
+    /// Crossing a tick leaves its net liquidity unchanged, so the ticks stay valid. The
+    /// snapshot is unchanged if the swap fails.
+    ///
+    /// # Arguments
+    ///
+    /// * `amount_in` - The amount to swap in, including fees
+    /// * `zero_for_one` - The direction of the swap, true for token_0 to token_1
+    ///
+    pub fn apply_swap(
+        &mut self,
+        amount_in: u64,
+        zero_for_one: bool,
+    ) -> Result<SwapQuote, anchor_lang::error::Error> {
+        let quote = quote_swap(self, amount_in, zero_for_one)?;
+        self.sqrt_price_x32 = quote.sqrt_price_x32;
+        self.tick = quote.tick;
+        self.liquidity = quote.liquidity;
+        Ok(quote)
+    }
+}
+
+/// The result of a quoted exact input swap
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SwapQuote {
+    /// The sqrt price of the pool after the swap
+    pub sqrt_price_x32: SqrtPriceX32,
+
+    /// The tick of the pool after the swap
+    pub tick: i32,
+
+    /// The in range liquidity after the swap
+    pub liquidity: u64,
+
+    /// The amount swapped in, including fees. See #principal_in for the part that moved the price
+    pub amount_in: u64,
+
+    /// The amount swapped out
+    pub amount_out: u64,
+
+    /// The portion of `amount_in` taken as fees, skimmed to liquidity providers
+    pub fee_amount: u64,
+
+    /// The fee growth per unit of liquidity added to the global fee growth of the input token,
+    /// as a Q32.32. Accrued step by step like the swap instruction, and wraps like it.
+    pub fee_growth_x32: u64,
+
+    /// The initialized ticks crossed by the swap, in the order they were crossed
+    pub crossed_ticks: Vec<i32>,
+}
+
+impl SwapQuote {
+    /// Returns the marginal price after the swap, i.e. the spot price of token_0 in terms of
+    /// token_1 the next swap starts from, as a Q32.32. Rounds down.
+    ///
+    /// The price is derived from the sqrt price rather than the tick, so that a swap ending
+    /// exactly on a tick in the zero for one direction, which leaves the tick one below, gives
+    /// the price at that tick.
+    ///
+    pub fn marginal_price_x32(&self) -> u64 {
+        let sqrt_price_x32 = u128::from(*self.sqrt_price_x32);
+        // the square of MAX_SQRT_RATIO as a Q32.32 fits in 64 bits
+        ((sqrt_price_x32 * sqrt_price_x32) >> fixed_point_32::RESOLUTION) as u64
+    }
+
+    /// Returns the principal of the swap, the portion of `amount_in` that traversed the curve
+    /// and moved the price. Together with `fee_amount` it makes up `amount_in`.
+    ///
+    /// The effective price of the principal, `amount_out / principal_in`, excludes the fee.
+    ///
+    pub fn principal_in(&self) -> u64 {
+        self.amount_in - self.fee_amount
+    }
+}
+
+/// State of a swap as it steps through ticks
+struct QuoteState {
+    amount_remaining: i64,
+    amount_in: u64,
+    amount_out: u64,
+    fee_amount: u64,
+    fee_growth_x32: u64,
+    sqrt_price_x32: u64,
+    tick: i32,
+    liquidity: u64,
+}
+
+/// Quotes an exact input swap against a pool snapshot, without a price limit
+///
+/// Fails with InsufficientLiquidity if the pool has no liquidity to fill any of the swap,
+/// like the swap instruction. A swap running out of liquidity part way is partially filled.
+///
+/// # Arguments
+///
+/// * `snapshot` - The state of the pool before the swap
+/// * `amount_in` - The amount to swap in, including fees
+/// * `zero_for_one` - The direction of the swap, true for token_0 to token_1
+///
+pub fn quote_swap(
+    snapshot: &PoolSnapshot,
+    amount_in: u64,
+    zero_for_one: bool,
+) -> Result<SwapQuote, anchor_lang::error::Error> {
+    let mut crossed_ticks = Vec::new();
+    let state = simulate_swap(
+        snapshot,
+        i64::try_from(amount_in).unwrap(),
+        no_price_limit(zero_for_one),
+        zero_for_one,
+        |tick| crossed_ticks.push(tick),
+    )?;
+    // like the swap instruction, a swap that finds no liquidity at all fails
+    require!(
+        amount_in == 0 || state.amount_in > 0,
+        ErrorCode::InsufficientLiquidity
+    );
+
+    Ok(SwapQuote {
+        sqrt_price_x32: SqrtPriceX32::new_unchecked(state.sqrt_price_x32),
+        tick: state.tick,
+        liquidity: state.liquidity,
+        amount_in: state.amount_in,
+        amount_out: state.amount_out,
+        fee_amount: state.fee_amount,
+        fee_growth_x32: state.fee_growth_x32,
+        crossed_ticks,
+    })
+}
+
+/// Quotes an exact input swap like #quote_swap, failing with StaleSnapshot if the snapshot
+/// was taken more than `max_staleness_slots` before `current_slot`.
+///
+/// A stale snapshot can miss swaps and position updates since, and give quotes that no longer
+/// execute. A snapshot from a slot after `current_slot` is fresh.
+///
+/// # Arguments
+///
+/// * `snapshot` - The state of the pool before the swap
+/// * `amount_in` - The amount to swap in, including fees
+/// * `zero_for_one` - The direction of the swap, true for token_0 to token_1
+/// * `max_staleness_slots` - The largest tolerated age of the snapshot, in slots
+/// * `current_slot` - The latest slot seen on chain
+///
+pub fn quote_swap_checked(
+    snapshot: &PoolSnapshot,
+    amount_in: u64,
+    zero_for_one: bool,
+    max_staleness_slots: u64,
+    current_slot: u64,
+) -> Result<SwapQuote, anchor_lang::error::Error> {
+    require!(
+        current_slot.saturating_sub(snapshot.slot) <= max_staleness_slots,
+        ErrorCode::StaleSnapshot
+    );
+    quote_swap(snapshot, amount_in, zero_for_one)
+}
+
+/// Returns the pool snapshot after a sequence of exact input swaps, such as the swaps of a
+/// block, each applied by #PoolSnapshot::apply_swap to the state left by the swaps before it
+///
+/// Fails like #quote_swap if any swap finds no liquidity to fill against.
+///
+/// # Arguments
+///
+/// * `snapshot` - The state of the pool before the swaps
+/// * `swaps` - The amount in, including fees, and the direction of each swap, in order
+///
+pub fn apply_swaps<'a>(
+    snapshot: &PoolSnapshot<'a>,
+    swaps: &[(u64, bool)],
+) -> Result<PoolSnapshot<'a>, anchor_lang::error::Error> {
+    let mut snapshot = *snapshot;
+    for &(amount_in, zero_for_one) in swaps {
+        snapshot.apply_swap(amount_in, zero_for_one)?;
+    }
+    Ok(snapshot)
+}
+
+/// Quotes the sqrt price and output amount of an exact input swap, like #quote_swap.
+///
+/// Performs no heap allocation, for simulating large batches of quotes.
+///
+/// # Arguments
+///
+/// * `snapshot` - The state of the pool before the swap
+/// * `amount_in` - The amount to swap in, including fees
+/// * `zero_for_one` - The direction of the swap, true for token_0 to token_1
+///
+pub fn quote_swap_price_only(
+    snapshot: &PoolSnapshot,
+    amount_in: u64,
+    zero_for_one: bool,
+) -> Result<(SqrtPriceX32, u64), anchor_lang::error::Error> {
+    let state = simulate_swap(
+        snapshot,
+        i64::try_from(amount_in).unwrap(),
+        no_price_limit(zero_for_one),
+        zero_for_one,
+        |_| {},
+    )?;
+    Ok((
+        SqrtPriceX32::new_unchecked(state.sqrt_price_x32),
+        state.amount_out,
+    ))
+}
+
+/// Returns the price impact of an exact input swap in basis points, i.e. how much worse the
+/// execution price amount_out / amount_in is than the spot price before the swap.
+///
+/// The impact includes the pool fee, since the output is what the trader receives. It is
+/// rounded up, and is 10_000 when a swap receives no output. A swap of 0 has no impact.
+/// Swaps moving the Q32.32 sqrt price by only a few units also show the rounding of the price.
+///
+/// # Arguments
+///
+/// * `snapshot` - The state of the pool before the swap
+/// * `amount_in` - The amount to swap in, including fees
+/// * `zero_for_one` - The direction of the swap, true for token_0 to token_1
+///
+pub fn price_impact_bps(
+    snapshot: &PoolSnapshot,
+    amount_in: u64,
+    zero_for_one: bool,
+) -> Result<u32, anchor_lang::error::Error> {
+    if amount_in == 0 {
+        return Ok(0);
+    }
+    let (_, amount_out) = quote_swap_price_only(snapshot, amount_in, zero_for_one)?;
+
+    // The spot price of token_0 in token_1 is sqrt_price^2 / 2^64. The output at spot price is
+    // amount_in * price for a zero for one swap, and amount_in / price otherwise.
+    let price_x64 = U256::from(*snapshot.sqrt_price_x32) * U256::from(*snapshot.sqrt_price_x32);
+    let (received, spot_output) = if zero_for_one {
+        (
+            U256::from(amount_out) << 64,
+            U256::from(amount_in) * price_x64,
+        )
+    } else {
+        (
+            U256::from(amount_out) * price_x64,
+            U256::from(amount_in) << 64,
+        )
+    };
+    let received_bps = (received * U256::from(10_000) / spot_output).min(U256::from(10_000));
+    Ok(10_000 - received_bps.as_u32())
+}
+
+/// Returns the price impact in basis points of a reference exact input swap, as by
+/// #price_impact_bps, after hypothetically minting a position into the pool.
+///
+/// Lets liquidity providers compare how much a deposit in different tick ranges would deepen
+/// the pool for a typical trade size.
+///
+/// # Arguments
+///
+/// * `snapshot` - The state of the pool before the mint
+/// * `tick_lower` - The lower tick of the minted position
+/// * `tick_upper` - The upper tick of the minted position
+/// * `liquidity` - The liquidity minted to the position
+/// * `reference_amount_in` - The amount to swap in, including fees
+/// * `zero_for_one` - The direction of the swap, true for token_0 to token_1
+///
+pub fn impact_after_mint(
+    snapshot: &PoolSnapshot,
+    tick_lower: i32,
+    tick_upper: i32,
+    liquidity: u64,
+    reference_amount_in: u64,
+    zero_for_one: bool,
+) -> Result<u32, anchor_lang::error::Error> {
+    crate::check_ticks(tick_lower, tick_upper)?;
+    let liquidity_delta = i64::try_from(liquidity).unwrap();
+
+    let mut ticks = snapshot.ticks.to_vec();
+    for (tick, upper) in [(tick_lower, false), (tick_upper, true)] {
+        let index = match ticks.binary_search_by_key(&tick, |t| t.tick) {
+            Ok(index) => index,
+            Err(index) => {
+                ticks.insert(
+                    index,
+                    TickState {
+                        tick,
+                        ..Default::default()
+                    },
+                );
+                index
+            }
+        };
+        ticks[index].update(
+            snapshot.tick,
+            liquidity_delta,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            upper,
+            u64::MAX,
+        )?;
+    }
+
+    let liquidity_after = if is_in_range(tick_lower, tick_upper, snapshot.tick) {
+        liquidity_math::add_delta(snapshot.liquidity, liquidity_delta)?
+    } else {
+        snapshot.liquidity
+    };
+    price_impact_bps(
+        &PoolSnapshot {
+            liquidity: liquidity_after,
+            ticks: &ticks,
+            ..*snapshot
+        },
+        reference_amount_in,
+        zero_for_one,
+    )
+}
+
+/// Returns the additional liquidity which, minted over the full tick range, keeps the price
+/// impact of a reference exact input swap, as by #price_impact_bps, within `max_impact_bps`.
+///
+/// The impact falls as liquidity is added, until the pool is so deep that the rounding of the
+/// Q32.32 sqrt price in each swap step outweighs the price movement, and impact grows again.
+/// The liquidity is doubled until the impact is within the threshold, or stops falling, and
+/// the smallest sufficient liquidity then found by bisection over #impact_after_mint.
+///
+/// It is 0 if the pool already keeps the impact within the threshold, and u64::MAX if no
+/// liquidity can. As the impact includes the pool fee and rounding, a threshold at or just
+/// above the fee may not be met.
+///
+/// # Arguments
+///
+/// * `snapshot` - The state of the pool before the mint
+/// * `reference_amount_in` - The amount to swap in, including fees
+/// * `zero_for_one` - The direction of the swap, true for token_0 to token_1
+/// * `max_impact_bps` - The largest tolerated price impact, in basis points
+///
+pub fn liquidity_to_limit_impact(
+    snapshot: &PoolSnapshot,
+    reference_amount_in: u64,
+    zero_for_one: bool,
+    max_impact_bps: u32,
+) -> Result<u64, anchor_lang::error::Error> {
+    let tick_spacing = i32::from(snapshot.tick_spacing);
+    let tick_lower = tick_math::MIN_TICK / tick_spacing * tick_spacing;
+    let tick_upper = tick_math::MAX_TICK / tick_spacing * tick_spacing;
+    let impact = |liquidity: u64| {
+        impact_after_mint(
+            snapshot,
+            tick_lower,
+            tick_upper,
+            liquidity,
+            reference_amount_in,
+            zero_for_one,
+        )
+    };
+
+    let mut impact_lo = impact(0)?;
+    if impact_lo <= max_impact_bps {
+        return Ok(0);
+    }
+    // the in range liquidity must stay within an i64
+    let max_liquidity = i64::MAX as u64 - snapshot.liquidity;
+    let (mut lo, mut hi) = (0, 1);
+    loop {
+        let impact_hi = impact(hi)?;
+        if impact_hi <= max_impact_bps {
+            break;
+        }
+        if impact_hi > impact_lo || hi == max_liquidity {
+            return Ok(u64::MAX);
+        }
+        lo = hi;
+        impact_lo = impact_hi;
+        hi = hi.saturating_mul(2).min(max_liquidity);
+    }
+    while hi - lo > 1 {
+        let mid = lo + (hi - lo) / 2;
+        if impact(mid)? <= max_impact_bps {
+            hi = mid;
+        } else {
+            lo = mid;
+        }
+    }
+    Ok(hi)
+}
+
+/// Returns the tightest tick range around the current tick that holds at least `coverage_bps`
+/// basis points of the pool's liquidity, weighing the liquidity of each range by the ticks it
+/// spans.
+///
+/// The range is symmetric around the tick spacing containing the current tick, with ends on
+/// multiples of the tick spacing so that positions can be minted over it. The liquidity of
+/// each range between initialized ticks is found by walking their `liquidity_net` from the
+/// in range liquidity. Coverages above 10_000 take the whole pool. A pool without liquidity
+/// gives the tick spacing containing the current tick.
+///
+/// # Arguments
+///
+/// * `snapshot` - The state of the pool
+/// * `coverage_bps` - The share of the liquidity the range must hold, in basis points
+///
+pub fn range_for_liquidity_coverage(snapshot: &PoolSnapshot, coverage_bps: u16) -> (i32, i32) {
+    // the liquidity below the lowest initialized tick, zero for a consistent snapshot
+    let mut liquidity = i128::from(snapshot.liquidity)
+        - snapshot
+            .ticks
+            .iter()
+            .filter(|t| t.tick <= snapshot.tick)
+            .map(|t| i128::from(t.liquidity_net))
+            .sum::<i128>();
+
+    // (start tick, end tick, liquidity) of the ranges between initialized ticks
+    let mut ranges = Vec::with_capacity(snapshot.ticks.len() + 1);
+    let mut start = tick_math::MIN_TICK;
+    for tick in snapshot.ticks {
+        ranges.push((start, tick.tick, u128::try_from(liquidity).unwrap_or(0)));
+        liquidity += i128::from(tick.liquidity_net);
+        start = tick.tick;
+    }
+    ranges.push((
+        start,
+        tick_math::MAX_TICK,
+        u128::try_from(liquidity).unwrap_or(0),
+    ));
+
+    let covered = |(tick_lower, tick_upper): (i32, i32)| -> u128 {
+        ranges
+            .iter()
+            .map(|&(start, end, liquidity)| {
+                let width = (end.min(tick_upper) - start.max(tick_lower)).max(0);
+                liquidity * width as u128
+            })
+            .sum()
+    };
+
+    let spacing = snapshot.tick_spacing as i32;
+    let compressed = snapshot.tick.div_euclid(spacing);
+    let (min_compressed, max_compressed) =
+        (tick_math::MIN_TICK / spacing, tick_math::MAX_TICK / spacing);
+    let range = |k: i32| {
+        (
+            (compressed - k).max(min_compressed) * spacing,
+            (compressed + 1 + k).min(max_compressed) * spacing,
+        )
+    };
+
+    // the smallest widening holding the coverage, by bisection since coverage grows with width
+    let required = covered(range(i32::MAX / 2)) * u128::from(coverage_bps.min(10_000));
+    let (mut low, mut high) = (
+        0,
+        (compressed - min_compressed).max(max_compressed - compressed),
+    );
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if covered(range(mid)) * 10_000 >= required {
+            high = mid;
+        } else {
+            low = mid + 1;
+        }
+    }
+    range(low)
+}
+
+/// Returns the active liquidity across a tick range as a step function, for charting the
+/// liquidity distribution of a pool.
+///
+/// Each point `(tick, liquidity)` gives the in range liquidity from its tick up to the tick of
+/// the next point. The curve starts at `tick_lower`, and steps at every initialized tick up to
+/// `tick_upper`. If that takes more than `max_points` points, the range is instead sampled at
+/// `max_points` evenly spaced ticks, on multiples of the tick spacing past `tick_lower`. Each
+/// sample then gives the liquidity at its tick, and bands narrower than the sample spacing
+/// may be missed.
+///
+/// # Arguments
+///
+/// * `snapshot` - The state of the pool
+/// * `tick_lower` - The first tick of the curve
+/// * `tick_upper` - The last tick of the curve, no smaller than `tick_lower`
+/// * `max_points` - The largest number of points to return, at least 1
+///
+pub fn liquidity_curve(
+    snapshot: &PoolSnapshot,
+    tick_lower: i32,
+    tick_upper: i32,
+    max_points: usize,
+) -> Vec<(i32, u64)> {
+    assert!(tick_lower <= tick_upper && max_points > 0);
+    let initialized = |t: &&TickState| t.liquidity_gross > 0;
+    let first_above = snapshot.ticks.partition_point(|t| t.tick <= tick_lower);
+    let steps = snapshot.ticks[first_above..]
+        .iter()
+        .take_while(|t| t.tick <= tick_upper)
+        .filter(initialized)
+        .count();
+
+    let points: Vec<i32> = if steps < max_points {
+        std::iter::once(tick_lower)
+            .chain(
+                snapshot.ticks[first_above..]
+                    .iter()
+                    .take_while(|t| t.tick <= tick_upper)
+                    .filter(initialized)
+                    .map(|t| t.tick),
+            )
+            .collect()
+    } else {
+        let tick_spacing = i64::from(snapshot.tick_spacing);
+        let width = i64::from(tick_upper) - i64::from(tick_lower);
+        let spacing = ((width + max_points as i64 - 1) / max_points as i64 + tick_spacing - 1)
+            / tick_spacing
+            * tick_spacing;
+        (0..max_points as i64)
+            .map(|i| i64::from(tick_lower) + i * spacing.max(1))
+            .take_while(|&tick| tick <= i64::from(tick_upper))
+            .map(|tick| tick as i32)
+            .collect()
+    };
+
+    // the liquidity from tick_lower, found by crossing the ticks between it and the current tick
+    let mut liquidity = i128::from(snapshot.liquidity);
+    for tick in snapshot.ticks.iter().filter(initialized) {
+        if tick.tick > snapshot.tick && tick.tick <= tick_lower {
+            liquidity += i128::from(tick.liquidity_net);
+        } else if tick.tick > tick_lower && tick.tick <= snapshot.tick {
+            liquidity -= i128::from(tick.liquidity_net);
+        }
+    }
+
+    let mut next = first_above;
+    points
+        .into_iter()
+        .map(|point| {
+            while next < snapshot.ticks.len() && snapshot.ticks[next].tick <= point {
+                if snapshot.ticks[next].liquidity_gross > 0 {
+                    liquidity += i128::from(snapshot.ticks[next].liquidity_net);
+                }
+                next += 1;
+            }
+            (point, liquidity.clamp(0, i128::from(u64::MAX)) as u64)
+        })
+        .collect()
+}
+
+/// Returns the `amount_out_minimum` to pass to an exact input swap, accepting up to
+/// `slippage_bps` basis points less than the expected output.
+///
+/// The minimum is rounded down, and a tolerance of 10_000 or more accepts any output.
+///
+/// # Arguments
+///
+/// * `expected_out` - The quoted output of the swap
+/// * `slippage_bps` - The tolerated shortfall of the output, in basis points
+///
+pub fn amount_out_minimum(expected_out: u64, slippage_bps: u16) -> u64 {
+    expected_out
+        .mul_div_floor(u64::from(10_000u16.saturating_sub(slippage_bps)), 10_000)
+        .unwrap()
+}
+
+/// Estimates an exact input swap that crosses at most `max_ticks_crossed` initialized ticks.
+///
+/// The estimate is off-chain only, since the swap instruction does not limit the ticks it
+/// crosses. To execute it, pass the estimated sqrt price as the swap's sqrt price limit, which
+/// stops the swap there and refunds the unswapped input.
+///
+/// If the swap would cross more ticks, it is partially filled and stops exactly at the last
+/// initialized tick it may cross, after crossing it. The price is then the price at that tick,
+/// and the tick and liquidity are those of a pool stopped on the tick boundary, instead of a
+/// point in the middle of the next interval. With a limit of 0 such a swap is not filled.
+///
+/// # Arguments
+///
+/// * `snapshot` - The state of the pool before the swap
+/// * `amount_in` - The amount to swap in, including fees
+/// * `zero_for_one` - The direction of the swap, true for token_0 to token_1
+/// * `max_ticks_crossed` - The largest number of initialized ticks the swap may cross
+///
+pub fn estimate_swap_with_max_ticks_crossed(
+    snapshot: &PoolSnapshot,
+    amount_in: u64,
+    zero_for_one: bool,
+    max_ticks_crossed: usize,
+) -> Result<SwapQuote, anchor_lang::error::Error> {
+    let quote = quote_swap(snapshot, amount_in, zero_for_one)?;
+    if quote.crossed_ticks.len() <= max_ticks_crossed {
+        return Ok(quote);
+    }
+    if max_ticks_crossed == 0 {
+        return Ok(SwapQuote {
+            sqrt_price_x32: snapshot.sqrt_price_x32,
+            tick: snapshot.tick,
+            liquidity: snapshot.liquidity,
+            ..Default::default()
+        });
+    }
+
+    // Swapping up to the price of the last tick crosses it, and ends the swap on its boundary
+    let mut crossed_ticks = Vec::new();
+    let state = simulate_swap(
+        snapshot,
+        i64::try_from(amount_in).unwrap(),
+        tick_math::get_sqrt_ratio_at_tick(quote.crossed_ticks[max_ticks_crossed - 1])?,
+        zero_for_one,
+        |tick| crossed_ticks.push(tick),
+    )?;
+
+    Ok(SwapQuote {
+        sqrt_price_x32: SqrtPriceX32::new_unchecked(state.sqrt_price_x32),
+        tick: state.tick,
+        liquidity: state.liquidity,
+        amount_in: state.amount_in,
+        amount_out: state.amount_out,
+        fee_amount: state.fee_amount,
+        fee_growth_x32: state.fee_growth_x32,
+        crossed_ticks,
+    })
+}
+
+/// Returns the largest input of an exact input swap that moves the price up to, but not past,
+/// the price limit. Bounds the price impact of a swap.
+///
+/// Quotes an exact output swap for an unbounded output amount, so that every step runs to the
+/// next tick or the price limit, and sums the input and fees each step needs.
+///
+/// # Arguments
+///
+/// * `snapshot` - The state of the pool before the swap
+/// * `sqrt_price_limit` - The sqrt price the swap must not go past
+/// * `zero_for_one` - The direction of the swap, true for token_0 to token_1
+///
+pub fn max_input_to_price_limit(
+    snapshot: &PoolSnapshot,
+    sqrt_price_limit: SqrtPriceX32,
+    zero_for_one: bool,
+) -> Result<u64, anchor_lang::error::Error> {
+    require!(
+        if zero_for_one {
+            sqrt_price_limit <= snapshot.sqrt_price_x32 && sqrt_price_limit > SqrtPriceX32::MIN
+        } else {
+            sqrt_price_limit >= snapshot.sqrt_price_x32 && sqrt_price_limit < SqrtPriceX32::MAX
+        },
+        ErrorCode::SPL
+    );
+    let state = simulate_swap(snapshot, -i64::MAX, *sqrt_price_limit, zero_for_one, |_| {})?;
+    Ok(state.amount_in)
+}
+
+/// Returns the spread in basis points between the spot prices of two pools of the same pair,
+/// net of both pool fees.
+///
+/// Buying token_0 in one pool and selling it in the other returns
+/// `price_sell / price_buy * (1 - fee_buy) * (1 - fee_sell)` per unit of token_1 at spot
+/// prices. The spread is that return less one, rounded towards zero. It is positive if buying
+/// in `pool_a` and selling in `pool_b` is profitable, negative if buying in `pool_b` and
+/// selling in `pool_a` is, and 0 if the price gap does not cover the fees either way.
+/// Price impact is not accounted for, so a nonzero spread only signals that a small trade
+/// is profitable.
+///
+/// # Arguments
+///
+/// * `pool_a` - A pool of the pair
+/// * `pool_b` - Another pool of the same pair, with the same token order
+///
+pub fn cross_pool_spread_bps(pool_a: &PoolSnapshot, pool_b: &PoolSnapshot) -> i32 {
+    let price_a_x64 = U256::from(*pool_a.sqrt_price_x32) * U256::from(*pool_a.sqrt_price_x32);
+    let price_b_x64 = U256::from(*pool_b.sqrt_price_x32) * U256::from(*pool_b.sqrt_price_x32);
+    let fees_kept = U256::from(1_000_000 - pool_a.fee) * U256::from(1_000_000 - pool_b.fee);
+
+    // the return of buying at price_buy and selling at price_sell, as a fraction
+    let spread = |price_buy_x64: U256, price_sell_x64: U256| {
+        let returned = price_sell_x64 * fees_kept;
+        let paid = price_buy_x64 * U256::from(1_000_000_000_000u64);
+        if returned > paid {
+            ((returned - paid) * U256::from(10_000) / paid)
+                .min(U256::from(i32::MAX))
+                .as_u32() as i32
+        } else {
+            0
+        }
+    };
+    let a_to_b = spread(price_a_x64, price_b_x64);
+    if a_to_b > 0 {
+        a_to_b
+    } else {
+        -spread(price_b_x64, price_a_x64)
+    }
+}
+
+/// The input size of a two pool arbitrage and its profit, as found by #optimal_arb_amount
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ArbAmount {
+    /// The token_1 amount to swap into the buy pool
+    pub amount_in: u64,
+
+    /// The token_1 received from the sell pool, less `amount_in`
+    pub profit: u64,
+}
+
+/// Finds the token_1 input of an arbitrage buying token_0 in one pool and selling it in
+/// another, at which the profit is largest.
+///
+/// Several inputs can make about the largest profit, such as on the plateau after the buy
+/// pool runs out of liquidity, or from rounding near the optimum. For a reproducible choice
+/// that ties up the least capital, the smallest input whose profit is within `epsilon` of the
+/// largest is returned, along with its profit. Without a profitable input, the result has no
+/// input and no profit.
+///
+/// The profit is taken to increase up to the optimum and decrease after it, so that the
+/// largest profit is found by ternary search and the smallest input by bisection. This holds
+/// up to rounding: the token_0 bought only grows with a whole step of the Q32.32 sqrt price,
+/// so the profit falls by a unit for every unit of input within a step. `epsilon` should
+/// exceed such steps, or a slightly smaller profit may be returned. The returned input is
+/// always the first of a step, making more profit than one unit less.
+///
+/// Only the input consumed by a partially filled buy is paid, and token_0 left over by a
+/// partially filled sell is not counted. Pick the buy and sell pools from the sign of
+/// #cross_pool_spread_bps.
+///
+/// # Arguments
+///
+/// * `buy_pool` - The pool where token_0 is bought with token_1
+/// * `sell_pool` - The pool of the same pair where the bought token_0 is sold for token_1
+/// * `max_amount_in` - The largest token_1 input to consider
+/// * `epsilon` - The profit which may be given up for a smaller input
+///
+pub fn optimal_arb_amount(
+    buy_pool: &PoolSnapshot,
+    sell_pool: &PoolSnapshot,
+    max_amount_in: u64,
+    epsilon: u64,
+) -> Result<ArbAmount, anchor_lang::error::Error> {
+    let profit = |amount_in: u64| -> Result<i128, anchor_lang::error::Error> {
+        if amount_in == 0 {
+            return Ok(0);
+        }
+        let buy = simulate_swap(
+            buy_pool,
+            i64::try_from(amount_in).unwrap(),
+            no_price_limit(false),
+            false,
+            |_| {},
+        )?;
+        let amount_out = if buy.amount_out == 0 {
+            0
+        } else {
+            simulate_swap(
+                sell_pool,
+                i64::try_from(buy.amount_out).unwrap(),
+                no_price_limit(true),
+                true,
+                |_| {},
+            )?
+            .amount_out
+        };
+        Ok(i128::from(amount_out) - i128::from(buy.amount_in))
+    };
+
+    // ternary search for the largest profit, keeping to the left on ties
+    let (mut lo, mut hi) = (0, max_amount_in.min(i64::MAX as u64));
+    while hi - lo > 2 {
+        let m1 = lo + (hi - lo) / 3;
+        let m2 = hi - (hi - lo) / 3;
+        if profit(m1)? < profit(m2)? {
+            lo = m1 + 1;
+        } else {
+            hi = m2;
+        }
+    }
+    let mut best = (lo, profit(lo)?);
+    for amount_in in lo + 1..=hi {
+        let p = profit(amount_in)?;
+        if p > best.1 {
+            best = (amount_in, p);
+        }
+    }
+    if best.1 <= 0 {
+        return Ok(ArbAmount::default());
+    }
+
+    // bisect for the smallest input within epsilon of the largest profit
+    let threshold = (best.1 - i128::from(epsilon)).max(1);
+    let (mut lo, mut hi) = (0, best.0);
+    while hi - lo > 1 {
+        let mid = lo + (hi - lo) / 2;
+        if profit(mid)? >= threshold {
+            hi = mid;
+        } else {
+            lo = mid;
+        }
+    }
+    Ok(ArbAmount {
+        amount_in: hi,
+        profit: u64::try_from(profit(hi)?).unwrap(),
+    })
+}
+
+/// Returns the largest total fee in basis points, over both legs, at which an arbitrage of
+/// `amount_in` buying token_0 in `pool_a` and selling it in `pool_b` is still profitable.
+///
+/// The two legs are quoted without fees, so that the price gap less the price impact of the
+/// trade returns `amount_out` for the consumed `amount_in`. A total fee `f` keeps the trade
+/// profitable while `amount_out * (1 - f) > amount_in`, so the result is
+/// `(amount_out - amount_in) / amount_out`, rounded down. The result is 0 if the trade does
+/// not profit even without fees.
+///
+/// Comparing the sum of the pool fees against the result is conservative: both fees together
+/// keep `(1 - fee_a) * (1 - fee_b)`, a little more than `1 - fee_a - fee_b`, and the buy fee
+/// leaves less to swap, with less price impact.
+///
+/// # Arguments
+///
+/// * `pool_a` - The pool where token_0 is bought with token_1
+/// * `pool_b` - The pool of the same pair where the bought token_0 is sold for token_1
+/// * `amount_in` - The token_1 input of the arbitrage
+///
+pub fn breakeven_total_fee_bps(
+    pool_a: &PoolSnapshot,
+    pool_b: &PoolSnapshot,
+    amount_in: u64,
+) -> Result<u32, anchor_lang::error::Error> {
+    let buy = simulate_swap(
+        &PoolSnapshot { fee: 0, ..*pool_a },
+        i64::try_from(amount_in).unwrap(),
+        no_price_limit(false),
+        false,
+        |_| {},
+    )?;
+    if buy.amount_out == 0 {
+        return Ok(0);
+    }
+    let sell = simulate_swap(
+        &PoolSnapshot { fee: 0, ..*pool_b },
+        i64::try_from(buy.amount_out).unwrap(),
+        no_price_limit(true),
+        true,
+        |_| {},
+    )?;
+    if sell.amount_out <= buy.amount_in {
+        return Ok(0);
+    }
+    Ok(((sell.amount_out - buy.amount_in) as u128 * 10_000 / sell.amount_out as u128) as u32)
+}
+
+/// Compute units used by a swap that crosses no initialized tick
+pub const SWAP_BASE_COMPUTE_UNITS: u32 = 70_000;
+
+/// Additional compute units used for every initialized tick crossed by a swap
+pub const TICK_CROSS_COMPUTE_UNITS: u32 = 25_000;
+
+/// Estimates the compute units used by an exact input swap, to set the compute budget of
+/// a transaction.
+///
+/// Crossing an initialized tick loads and writes a tick account and dominates the cost, so the
+/// estimate is a base cost plus a cost per crossed tick. The `#swap compute units` test in
+/// tests/cyclos-core.ts logs the units consumed by swaps crossing 0, 1, 2... ticks and fails
+/// if the estimate falls below them. The constants are copied into that test, and should be
+/// set again from its logged measurements when the swap loop changes.
+///
+/// # Arguments
+///
+/// * `snapshot` - The state of the pool before the swap
+/// * `amount_in` - The amount to swap in, including fees
+/// * `zero_for_one` - The direction of the swap, true for token_0 to token_1
+///
+pub fn estimate_compute_units(
+    snapshot: &PoolSnapshot,
+    amount_in: u64,
+    zero_for_one: bool,
+) -> Result<u32, anchor_lang::error::Error> {
+    let mut crossed_ticks = 0u32;
+    simulate_swap(
+        snapshot,
+        i64::try_from(amount_in).unwrap(),
+        no_price_limit(zero_for_one),
+        zero_for_one,
+        |_| crossed_ticks += 1,
+    )?;
+    Ok(SWAP_BASE_COMPUTE_UNITS
+        .saturating_add(crossed_ticks.saturating_mul(TICK_CROSS_COMPUTE_UNITS)))
+}
+
+/// The most extreme price limit a swap in the given direction accepts
+fn no_price_limit(zero_for_one: bool) -> u64 {
+    if zero_for_one {
+        tick_math::MIN_SQRT_RATIO + 1
+    } else {
+        tick_math::MAX_SQRT_RATIO - 1
+    }
+}
+
+/// Runs the swap loop over the snapshot, calling `on_cross` for every initialized tick crossed.
+///
+/// A positive `amount_specified` is an exact input, and a negative one an exact output.
+fn simulate_swap<F: FnMut(i32)>(
+    snapshot: &PoolSnapshot,
+    amount_specified: i64,
+    sqrt_price_limit_x32: u64,
+    zero_for_one: bool,
+    mut on_cross: F,
+) -> Result<QuoteState, anchor_lang::error::Error> {
+    let exact_input = amount_specified > 0;
+    let mut state = QuoteState {
+        amount_remaining: amount_specified,
+        amount_in: 0,
+        amount_out: 0,
+        fee_amount: 0,
+        fee_growth_x32: 0,
+        sqrt_price_x32: *snapshot.sqrt_price_x32,
+        tick: snapshot.tick,
+        liquidity: snapshot.liquidity,
+    };
+
+    while state.amount_remaining != 0 && state.sqrt_price_x32 != sqrt_price_limit_x32 {
+        let sqrt_price_start_x32 = state.sqrt_price_x32;
+
+        let (tick_next, tick_state) = next_tick_within_one_word(
+            snapshot.ticks,
+            state.tick,
+            snapshot.tick_spacing as i32,
+            zero_for_one,
+        );
+        let tick_next = tick_next.clamp(tick_math::MIN_TICK, tick_math::MAX_TICK);
+        let sqrt_price_next_x32 = tick_math::get_sqrt_ratio_at_tick(tick_next)?;
+
+        let target_price = if (zero_for_one && sqrt_price_next_x32 < sqrt_price_limit_x32)
+            || (!zero_for_one && sqrt_price_next_x32 > sqrt_price_limit_x32)
+        {
+            sqrt_price_limit_x32
+        } else {
+            sqrt_price_next_x32
+        };
+        let step = swap_math::compute_swap_step(
+            state.sqrt_price_x32,
+            target_price,
+            state.liquidity,
+            state.amount_remaining,
+            snapshot.fee,
+        );
+        state.sqrt_price_x32 = step.sqrt_ratio_next_x32;
+        if exact_input {
+            state.amount_remaining -= i64::try_from(step.amount_in + step.fee_amount).unwrap();
+        } else {
+            state.amount_remaining += i64::try_from(step.amount_out).unwrap();
+        }
+        state.amount_in = state
+            .amount_in
+            .saturating_add(step.amount_in + step.fee_amount);
+        state.amount_out += step.amount_out;
+        state.fee_amount += step.fee_amount;
+        if state.liquidity > 0 && step.fee_amount > 0 {
+            state.fee_growth_x32 = state.fee_growth_x32.wrapping_add(
+                step.fee_amount
+                    .mul_div_floor(fixed_point_32::Q32, state.liquidity)
+                    .unwrap(),
+            );
+        }
+
+        // shift tick if we reached the next price
+        if state.sqrt_price_x32 == sqrt_price_next_x32 {
+            if let Some(tick_state) = tick_state {
+                let liquidity_net = if zero_for_one {
+                    -tick_state.liquidity_net
+                } else {
+                    tick_state.liquidity_net
+                };
+                state.liquidity = liquidity_math::add_delta(state.liquidity, liquidity_net)?;
+                on_cross(tick_next);
+            }
+            state.tick = if zero_for_one {
+                tick_next - 1
+            } else {
+                tick_next
+            };
+        } else if state.sqrt_price_x32 != sqrt_price_start_x32 {
+            state.tick = tick_math::get_tick_at_sqrt_ratio(state.sqrt_price_x32)?;
+        }
+    }
+
+    Ok(state)
+}
+
+/// Finds the next tick the swap steps to, searching the bitmap word of the current tick as
+/// the swap instruction does. Returns the next initialized tick if there is one in the word,
+/// else the boundary of the word.
+fn next_tick_within_one_word(
+    ticks: &[TickState],
+    tick: i32,
+    tick_spacing: i32,
+    zero_for_one: bool,
+) -> (i32, Option<&TickState>) {
+    let mut compressed = tick / tick_spacing;
+    if tick < 0 && tick % tick_spacing != 0 {
+        compressed -= 1; // round towards negative infinity
+    }
+    // The current tick is not considered in the one for zero case
+    if !zero_for_one {
+        compressed += 1;
+    }
+    let word_start = (tick_bitmap::position(compressed).word_pos as i32) << 8;
+
+    if zero_for_one {
+        let end = ticks.partition_point(|t| t.tick <= compressed * tick_spacing);
+        let next = ticks[..end]
+            .iter()
+            .rev()
+            .take_while(|t| t.tick >= word_start * tick_spacing)
+            .find(|t| t.liquidity_gross > 0);
+        match next {
+            Some(tick_state) => (tick_state.tick, Some(tick_state)),
+            None => (word_start * tick_spacing, None),
+        }
+    } else {
+        let start = ticks.partition_point(|t| t.tick < compressed * tick_spacing);
+        let next = ticks[start..]
+            .iter()
+            .take_while(|t| t.tick <= (word_start + 255) * tick_spacing)
+            .find(|t| t.liquidity_gross > 0);
+        match next {
+            Some(tick_state) => (tick_state.tick, Some(tick_state)),
+            None => ((word_start + 255) * tick_spacing, None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libraries::sqrt_price_math;
+    use crate::libraries::test_utils::{assert_fee_conservation, encode_price_sqrt_x32};
+    use crate::states::position::PositionState;
+    use crate::states::tick::get_fee_growth_inside;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    /// Counts heap allocations made by the current thread
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn allocations() -> usize {
+        ALLOCATIONS.with(|count| count.get())
+    }
+
+    const TICK_SPACING: u16 = 10;
+
+    // (tick_lower, tick_upper, liquidity), spanning more than one bitmap word either side
+    const POSITIONS: [(i32, i32, i64); 4] = [
+        (-3000, 3000, 10_000_000_000),
+        (-200, 500, 5_000_000_000),
+        (2700, 6000, 20_000_000_000),
+        (-8000, -2600, 1_000_000_000),
+    ];
+
+    fn ticks() -> Vec<TickState> {
+        let mut ticks: Vec<TickState> = Vec::new();
+        for (tick_lower, tick_upper, liquidity) in POSITIONS {
+            for (tick, upper) in [(tick_lower, false), (tick_upper, true)] {
+                let index = match ticks.iter().position(|t| t.tick == tick) {
+                    Some(index) => index,
+                    None => {
+                        ticks.push(TickState {
+                            tick,
+                            ..Default::default()
+                        });
+                        ticks.len() - 1
+                    }
+                };
+                ticks[index]
+                    .update(0, liquidity, 0, 0, 0, 0, 0, 0, upper, u64::MAX)
+                    .unwrap();
+            }
+        }
+        ticks.sort_by_key(|t| t.tick);
+        ticks
+    }
+
+    fn snapshot(ticks: &[TickState]) -> PoolSnapshot<'_> {
+        PoolSnapshot {
+            sqrt_price_x32: SqrtPriceX32::new(encode_price_sqrt_x32(1, 1)).unwrap(),
+            tick: 0,
+            liquidity: 15_000_000_000,
+            fee: 3000,
+            tick_spacing: TICK_SPACING,
+            ticks,
+            slot: 0,
+        }
+    }
+
+    /// The pool after applying a quoted swap, crediting its fee growth to the input token
+    fn pool_after_swap(pool: &PoolState, quote: &SwapQuote, zero_for_one: bool) -> PoolState {
+        let mut pool_after = PoolState {
+            sqrt_price_x32: *quote.sqrt_price_x32,
+            tick: quote.tick,
+            liquidity: quote.liquidity,
+            ..*pool
+        };
+        if zero_for_one {
+            pool_after.fee_growth_global_0_x32 = pool
+                .fee_growth_global_0_x32
+                .wrapping_add(quote.fee_growth_x32);
+        } else {
+            pool_after.fee_growth_global_1_x32 = pool
+                .fee_growth_global_1_x32
+                .wrapping_add(quote.fee_growth_x32);
+        }
+        pool_after
+    }
+
+    /// Quotes a swap within the only range of a pool, and asserts that its fee is conserved
+    fn assert_swap_conserves_fees(liquidity: u64, fee: u32, amount_in: u64, zero_for_one: bool) {
+        let pool = PoolState {
+            sqrt_price_x32: encode_price_sqrt_x32(1, 1),
+            tick: 0,
+            liquidity,
+            fee,
+            tick_spacing: TICK_SPACING,
+            fee_growth_global_0_x32: u64::MAX - 5,
+            fee_growth_global_1_x32: 7 << 32,
+            ..Default::default()
+        };
+        let quote = quote_swap(&PoolSnapshot::new(&pool, &[], 0), amount_in, zero_for_one).unwrap();
+        assert_eq!(quote.liquidity, liquidity);
+        assert_fee_conservation(
+            &pool,
+            &pool_after_swap(&pool, &quote, zero_for_one),
+            quote.fee_amount,
+            liquidity,
+        );
+    }
+
+    #[test]
+    fn swaps_within_a_range_conserve_fees() {
+        for liquidity in [1_000, 65_537, 15_000_000, 1 << 31, 1 << 32] {
+            for fee in [100, 500, 3000, 10_000] {
+                // small enough inputs to swap in one step, within the bitmap word
+                for amount_in in [1, liquidity / 1000 + 1, liquidity / 100, liquidity / 10] {
+                    for zero_for_one in [true, false] {
+                        assert_swap_conserves_fees(liquidity, fee, amount_in, zero_for_one);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "to rounding")]
+    fn swaps_over_liquidity_above_q32_lose_fees_to_rounding() {
+        // fee growth below one Q32.32 unit per unit of liquidity is floored away
+        assert_swap_conserves_fees(1 << 40, 3000, 1 << 30, true);
+    }
+
+    #[test]
+    fn zero_fee_swaps_accrue_no_fees() {
+        let ticks = ticks();
+        let pool = PoolState {
+            sqrt_price_x32: encode_price_sqrt_x32(1, 1),
+            tick: 0,
+            liquidity: 15_000_000_000,
+            fee: 0,
+            tick_spacing: TICK_SPACING,
+            fee_growth_global_0_x32: 3 << 32,
+            fee_growth_global_1_x32: 5 << 32,
+            ..Default::default()
+        };
+        let tick_state = |tick: i32| *ticks.iter().find(|t| t.tick == tick).unwrap();
+        let (tick_lower, tick_upper) = (tick_state(-200), tick_state(500));
+        let (fee_growth_inside_0_x32, fee_growth_inside_1_x32) = get_fee_growth_inside(
+            &tick_lower,
+            &tick_upper,
+            pool.tick,
+            pool.fee_growth_global_0_x32,
+            pool.fee_growth_global_1_x32,
+        );
+        let mut position = PositionState {
+            liquidity: 5_000_000_000,
+            fee_growth_inside_0_last_x32: fee_growth_inside_0_x32,
+            fee_growth_inside_1_last_x32: fee_growth_inside_1_x32,
+            ..Default::default()
+        };
+
+        for zero_for_one in [true, false] {
+            // within the range of the position, so that it earns any fees of the swap
+            let quote = quote_swap(
+                &PoolSnapshot::new(&pool, &ticks, 0),
+                1_000_000,
+                zero_for_one,
+            )
+            .unwrap();
+            assert!(quote.crossed_ticks.is_empty());
+            assert_eq!((quote.fee_amount, quote.fee_growth_x32), (0, 0));
+
+            let pool_after = pool_after_swap(&pool, &quote, zero_for_one);
+            assert_eq!(
+                ({ pool_after.fee_growth_global_0_x32 }, {
+                    pool_after.fee_growth_global_1_x32
+                }),
+                (3 << 32, 5 << 32)
+            );
+
+            let (fee_growth_inside_0_x32, fee_growth_inside_1_x32) = get_fee_growth_inside(
+                &tick_lower,
+                &tick_upper,
+                pool_after.tick,
+                pool_after.fee_growth_global_0_x32,
+                pool_after.fee_growth_global_1_x32,
+            );
+            position
+                .poke(fee_growth_inside_0_x32, fee_growth_inside_1_x32, 0)
+                .unwrap();
+            assert_eq!(
+                ({ position.tokens_owed_0 }, { position.tokens_owed_1 }),
+                (0, 0)
+            );
+        }
+    }
+
+    #[test]
+    fn matches_single_step_within_a_range() {
+        let ticks = ticks();
+        let snapshot = snapshot(&ticks);
+        let quote = quote_swap(&snapshot, 1_000_000, true).unwrap();
+
+        let step = swap_math::compute_swap_step(
+            *snapshot.sqrt_price_x32,
+            tick_math::get_sqrt_ratio_at_tick(-200).unwrap(),
+            snapshot.liquidity,
+            1_000_000,
+            3000,
+        );
+        assert_eq!(*quote.sqrt_price_x32, step.sqrt_ratio_next_x32);
+        assert_eq!(quote.amount_out, step.amount_out);
+        assert_eq!(quote.amount_in, 1_000_000);
+        assert!(quote.crossed_ticks.is_empty());
+    }
+
+    #[test]
+    fn crosses_initialized_ticks_across_words() {
+        let ticks = ticks();
+        let snapshot = snapshot(&ticks);
+
+        let quote = quote_swap(&snapshot, 4_000_000_000, false).unwrap();
+        assert_eq!(quote.crossed_ticks, vec![500, 2700, 3000]);
+        assert_eq!(quote.liquidity, 20_000_000_000);
+        assert!(quote.tick >= 3000 && quote.tick < 6000);
+
+        let quote = quote_swap(&snapshot, 4_000_000_000, true).unwrap();
+        // runs out of liquidity below the last position
+        assert_eq!(quote.crossed_ticks, vec![-200, -2600, -3000, -8000]);
+        assert_eq!(quote.liquidity, 0);
+        assert_eq!(*quote.sqrt_price_x32, tick_math::MIN_SQRT_RATIO + 1);
+        assert!(quote.amount_in < 4_000_000_000);
+    }
+
+    /// A pool at price 1 with a single range covering every tick
+    fn full_range_snapshot(liquidity: u64, fee: u32) -> PoolSnapshot<'static> {
+        PoolSnapshot {
+            sqrt_price_x32: SqrtPriceX32::new(encode_price_sqrt_x32(1, 1)).unwrap(),
+            tick: 0,
+            liquidity,
+            fee,
+            tick_spacing: TICK_SPACING,
+            ticks: &[],
+            slot: 0,
+        }
+    }
+
+    #[test]
+    fn price_impact_in_a_deep_pool_is_the_fee() {
+        let snapshot = full_range_snapshot(1 << 48, 100);
+        for zero_for_one in [true, false] {
+            // the 1 bip fee, and rounding of the sqrt price
+            let impact = price_impact_bps(&snapshot, 1_000_000_000, zero_for_one).unwrap();
+            assert!((1..=2).contains(&impact), "impact {}", impact);
+        }
+    }
+
+    #[test]
+    fn price_impact_in_a_shallow_pool_is_large() {
+        // swapping in the liquidity halves the sqrt price, for an output of half the input
+        let snapshot = full_range_snapshot(1_000_000, 3000);
+        for zero_for_one in [true, false] {
+            let impact = price_impact_bps(&snapshot, 1_000_000, zero_for_one).unwrap();
+            assert!((5000..5100).contains(&impact), "impact {}", impact);
+        }
+    }
+
+    #[test]
+    fn price_impact_grows_with_the_amount() {
+        let ticks = ticks();
+        let snapshot = snapshot(&ticks);
+        for zero_for_one in [true, false] {
+            let impacts: Vec<u32> = [1_000_000, 10_000_000, 100_000_000, 4_000_000_000]
+                .iter()
+                .map(|amount_in| price_impact_bps(&snapshot, *amount_in, zero_for_one).unwrap())
+                .collect();
+            assert!(
+                impacts.windows(2).all(|pair| pair[0] <= pair[1]),
+                "impacts {:?}",
+                impacts
+            );
+            assert!(impacts[0] >= 30 && impacts[3] < 10_000);
+        }
+    }
+
+    #[test]
+    fn price_impact_without_output() {
+        // all input is swapped without liquidity to trade against
+        let snapshot = full_range_snapshot(0, 3000);
+        assert_eq!(price_impact_bps(&snapshot, 1_000, true).unwrap(), 10_000);
+        assert_eq!(price_impact_bps(&snapshot, 1_000, false).unwrap(), 10_000);
+        // a swap of nothing has no impact
+        assert_eq!(
+            price_impact_bps(&full_range_snapshot(1 << 50, 3000), 0, true).unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn partial_fill_stops_at_the_last_crossed_tick() {
+        let ticks = ticks();
+        let snapshot = snapshot(&ticks);
+
+        // (direction, last crossed tick, tick after the fill, liquidity after the fill)
+        for (zero_for_one, last_tick, tick, liquidity) in [
+            (false, 2700, 2700, 30_000_000_000),
+            (true, -2600, -2601, 11_000_000_000),
+        ] {
+            let quote =
+                estimate_swap_with_max_ticks_crossed(&snapshot, 4_000_000_000, zero_for_one, 2)
+                    .unwrap();
+            assert_eq!(quote.crossed_ticks.len(), 2);
+            assert_eq!(*quote.crossed_ticks.last().unwrap(), last_tick);
+            assert!(ticks
+                .iter()
+                .any(|t| t.tick == last_tick && t.liquidity_gross > 0));
+
+            let sqrt_price_at_tick = SqrtPriceX32::from_tick(last_tick).unwrap();
+            assert_eq!(quote.sqrt_price_x32, sqrt_price_at_tick);
+            assert_eq!(quote.tick, tick);
+            assert_eq!(quote.liquidity, liquidity);
+            assert!(quote.amount_in < 4_000_000_000);
+            assert_eq!(
+                quote.amount_in,
+                max_input_to_price_limit(&snapshot, sqrt_price_at_tick, zero_for_one).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn partial_fill_within_the_limit_is_a_full_quote() {
+        let ticks = ticks();
+        let snapshot = snapshot(&ticks);
+        for max_ticks_crossed in [3, 4, 10] {
+            assert_eq!(
+                estimate_swap_with_max_ticks_crossed(
+                    &snapshot,
+                    4_000_000_000,
+                    false,
+                    max_ticks_crossed
+                )
+                .unwrap(),
+                quote_swap(&snapshot, 4_000_000_000, false).unwrap()
+            );
+        }
+        // no tick is crossed
+        assert_eq!(
+            estimate_swap_with_max_ticks_crossed(&snapshot, 1_000_000, true, 0).unwrap(),
+            quote_swap(&snapshot, 1_000_000, true).unwrap()
+        );
+    }
+
+    #[test]
+    fn partial_fill_without_crossing_is_not_filled() {
+        let ticks = ticks();
+        let snapshot = snapshot(&ticks);
+        let quote =
+            estimate_swap_with_max_ticks_crossed(&snapshot, 4_000_000_000, true, 0).unwrap();
+        assert_eq!(quote.sqrt_price_x32, snapshot.sqrt_price_x32);
+        assert_eq!(quote.tick, snapshot.tick);
+        assert_eq!(quote.liquidity, snapshot.liquidity);
+        assert_eq!((quote.amount_in, quote.amount_out), (0, 0));
+        assert!(quote.crossed_ticks.is_empty());
+    }
+
+    #[test]
+    fn price_only_quote_matches_full_quote() {
+        let ticks = ticks();
+        let snapshot = snapshot(&ticks);
+        for zero_for_one in [true, false] {
+            for amount_in in [
+                1,
+                1_000,
+                1_000_000,
+                500_000_000,
+                2_000_000_000,
+                4_000_000_000,
+            ] {
+                let quote = quote_swap(&snapshot, amount_in, zero_for_one).unwrap();
+                assert_eq!(
+                    quote_swap_price_only(&snapshot, amount_in, zero_for_one).unwrap(),
+                    (quote.sqrt_price_x32, quote.amount_out)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn price_only_quote_does_not_allocate() {
+        let ticks = ticks();
+        let snapshot = snapshot(&ticks);
+
+        let before = allocations();
+        let mut amount_out_total = 0;
+        for i in 1..=1_000u64 {
+            let (_, amount_out) =
+                quote_swap_price_only(&snapshot, i * 4_000_000, i % 2 == 0).unwrap();
+            amount_out_total += amount_out;
+        }
+        assert_eq!(allocations(), before);
+        assert!(amount_out_total > 0);
+
+        // the full quote collects crossed ticks
+        quote_swap(&snapshot, 4_000_000_000, false).unwrap();
+        assert!(allocations() > before);
+    }
+
+    #[test]
+    fn max_input_swaps_exactly_to_the_price_limit() {
+        let ticks = ticks();
+        let snapshot = snapshot(&ticks);
+        for (limit_tick, zero_for_one) in [
+            // within the current range
+            (-100, true),
+            (100, false),
+            // across initialized ticks, and on an initialized tick
+            (-2600, true),
+            (-5000, true),
+            (2700, false),
+            (4321, false),
+        ] {
+            let sqrt_price_limit = SqrtPriceX32::from_tick(limit_tick).unwrap();
+            let max_input =
+                max_input_to_price_limit(&snapshot, sqrt_price_limit, zero_for_one).unwrap();
+
+            let quote = quote_swap(&snapshot, max_input, zero_for_one).unwrap();
+            assert_eq!(quote.sqrt_price_x32, sqrt_price_limit);
+            assert_eq!(quote.amount_in, max_input);
+
+            // any less input stops short of the limit, and more input goes past it once it
+            // exceeds the rounding dust taken as fee
+            let (short_sqrt_price_x32, _) =
+                quote_swap_price_only(&snapshot, max_input - 1, zero_for_one).unwrap();
+            let (past_sqrt_price_x32, _) =
+                quote_swap_price_only(&snapshot, max_input + 1_000, zero_for_one).unwrap();
+            if zero_for_one {
+                assert!(short_sqrt_price_x32 > sqrt_price_limit);
+                assert!(past_sqrt_price_x32 < sqrt_price_limit);
+            } else {
+                assert!(short_sqrt_price_x32 < sqrt_price_limit);
+                assert!(past_sqrt_price_x32 > sqrt_price_limit);
+            }
+        }
+    }
+
+    #[test]
+    fn applied_swaps_start_where_the_previous_swap_ended() {
+        let ticks = ticks();
+        let snapshot = snapshot(&ticks);
+        let swaps = [
+            (2_000_000_000, true),
+            (500_000_000, false),
+            (1_000_000, true),
+        ];
+
+        let mut expected = snapshot;
+        for (amount_in, zero_for_one) in swaps {
+            let quote = quote_swap(&expected, amount_in, zero_for_one).unwrap();
+            expected = PoolSnapshot {
+                sqrt_price_x32: quote.sqrt_price_x32,
+                tick: quote.tick,
+                liquidity: quote.liquidity,
+                ..expected
+            };
+        }
+        let folded = apply_swaps(&snapshot, &swaps).unwrap();
+        assert_eq!(
+            (folded.sqrt_price_x32, folded.tick, folded.liquidity),
+            (expected.sqrt_price_x32, expected.tick, expected.liquidity)
+        );
+        // the first swap crosses into the range below -200
+        assert!(folded.tick < -200);
+
+        let unchanged = apply_swaps(&snapshot, &[]).unwrap();
+        assert_eq!(unchanged.sqrt_price_x32, snapshot.sqrt_price_x32);
+    }
+
+    #[test]
+    fn principal_and_fee_make_up_the_input_in_a_high_fee_pool() {
+        // a 5% fee, within one bitmap word of liquidity so that the swap takes a single step
+        let snapshot = full_range_snapshot(1 << 40, 50_000);
+        for (amount_in, zero_for_one) in [(1_000_000, true), (1_000_000, false), (999, true)] {
+            let quote = quote_swap(&snapshot, amount_in, zero_for_one).unwrap();
+            assert_eq!(quote.amount_in, amount_in);
+            assert_eq!(quote.principal_in() + quote.fee_amount, quote.amount_in);
+            assert!(quote.fee_amount >= amount_in / 20);
+
+            // the principal alone is what the curve takes to move to the final price
+            let (sqrt_price_start_x32, sqrt_price_end_x32) =
+                (*snapshot.sqrt_price_x32, *quote.sqrt_price_x32);
+            let curve_amount_in = if zero_for_one {
+                sqrt_price_math::get_amount_0_delta_unsigned(
+                    sqrt_price_end_x32,
+                    sqrt_price_start_x32,
+                    snapshot.liquidity,
+                    true,
+                )
+            } else {
+                sqrt_price_math::get_amount_1_delta_unsigned(
+                    sqrt_price_start_x32,
+                    sqrt_price_end_x32,
+                    snapshot.liquidity,
+                    true,
+                )
+            };
+            assert_eq!(quote.principal_in(), curve_amount_in);
+        }
+    }
+
+    #[test]
+    fn marginal_price_is_the_spot_price_after_the_swap() {
+        let ticks = ticks();
+        let snapshot = snapshot(&ticks);
+        for (amount_in, zero_for_one) in [
+            (1_000, true),
+            (1_000, false),
+            (1_000_000_000, true),
+            (1_000_000_000, false),
+        ] {
+            let quote = quote_swap(&snapshot, amount_in, zero_for_one).unwrap();
+            let sqrt_price = fixed_point_32::x32_to_f64(*quote.sqrt_price_x32);
+            let price = fixed_point_32::x32_to_f64(quote.marginal_price_x32());
+            assert!((price - sqrt_price * sqrt_price).abs() <= 1.0 / fixed_point_32::Q32_F64);
+            assert_eq!(
+                u128::from(quote.marginal_price_x32()),
+                u128::from(*quote.sqrt_price_x32).pow(2) >> 32
+            );
+        }
+    }
+
+    #[test]
+    fn marginal_price_at_an_exhausted_tick_is_the_tick_price() {
+        let ticks = ticks();
+        let snapshot = snapshot(&ticks);
+        for (limit_tick, zero_for_one) in [(-2600, true), (2700, false)] {
+            let sqrt_price_limit = SqrtPriceX32::from_tick(limit_tick).unwrap();
+            let max_input =
+                max_input_to_price_limit(&snapshot, sqrt_price_limit, zero_for_one).unwrap();
+            let quote = quote_swap(&snapshot, max_input, zero_for_one).unwrap();
+            assert_eq!(quote.sqrt_price_x32, sqrt_price_limit);
+
+            let price_at_tick = u128::from(*sqrt_price_limit).pow(2) >> 32;
+            assert_eq!(u128::from(quote.marginal_price_x32()), price_at_tick);
+            // the tick of a zero for one swap ending on a tick is one below it
+            assert_eq!(
+                quote.tick,
+                if zero_for_one {
+                    limit_tick - 1
+                } else {
+                    limit_tick
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn marginal_price_at_the_max_sqrt_price_does_not_overflow() {
+        let quote = SwapQuote {
+            sqrt_price_x32: SqrtPriceX32::MAX,
+            ..Default::default()
+        };
+        let max_price_x32 = u128::from(tick_math::MAX_SQRT_RATIO).pow(2) >> 32;
+        assert!(max_price_x32 <= u128::from(u64::MAX));
+        assert_eq!(u128::from(quote.marginal_price_x32()), max_price_x32);
+    }
+
+    #[test]
+    fn max_input_is_zero_at_the_current_price() {
+        let ticks = ticks();
+        let snapshot = snapshot(&ticks);
+        for zero_for_one in [true, false] {
+            assert_eq!(
+                max_input_to_price_limit(&snapshot, snapshot.sqrt_price_x32, zero_for_one).unwrap(),
+                0
+            );
+        }
+    }
+
+    #[test]
+    fn max_input_rejects_a_limit_in_the_wrong_direction() {
+        let ticks = ticks();
+        let snapshot = snapshot(&ticks);
+        let above = SqrtPriceX32::from_tick(100).unwrap();
+        let below = SqrtPriceX32::from_tick(-100).unwrap();
+        assert!(max_input_to_price_limit(&snapshot, above, true).is_err());
+        assert!(max_input_to_price_limit(&snapshot, below, false).is_err());
+        assert!(max_input_to_price_limit(&snapshot, SqrtPriceX32::MIN, true).is_err());
+    }
+
+    #[test]
+    fn swaps_through_a_full_range_position() {
+        let max_liquidity = crate::states::tick::tick_spacing_to_max_liquidity_per_tick(10);
+        // leaving the range from price 1 takes about 65536 L of input
+        for (liquidity, leaves_range) in [(1_000_000_000, true), (max_liquidity, false)] {
+            swap_through_full_range(liquidity, leaves_range);
+        }
+    }
+
+    fn swap_through_full_range(liquidity: u64, leaves_range: bool) {
+        let full_range_lower = tick_math::MIN_TICK / TICK_SPACING as i32 * TICK_SPACING as i32;
+        let (sqrt_price_lower_x32, sqrt_price_upper_x32) = (
+            tick_math::get_sqrt_ratio_at_tick(full_range_lower).unwrap(),
+            tick_math::get_sqrt_ratio_at_tick(-full_range_lower).unwrap(),
+        );
+        let mut ticks = [
+            TickState {
+                tick: full_range_lower,
+                ..Default::default()
+            },
+            TickState {
+                tick: -full_range_lower,
+                ..Default::default()
+            },
+        ];
+        ticks[0]
+            .update(0, liquidity as i64, 0, 0, 0, 0, 0, 0, false, u64::MAX)
+            .unwrap();
+        ticks[1]
+            .update(0, liquidity as i64, 0, 0, 0, 0, 0, 0, true, u64::MAX)
+            .unwrap();
+        let snapshot = PoolSnapshot {
+            liquidity,
+            ..snapshot(&ticks)
+        };
+
+        for zero_for_one in [true, false] {
+            // swaps within the range keep the liquidity
+            let quote = quote_swap(&snapshot, 1_000_000_000_000, zero_for_one).unwrap();
+            assert_eq!(quote.liquidity, liquidity);
+            assert!(quote.crossed_ticks.is_empty());
+            assert!(quote.amount_out > 0);
+
+            // the largest swap leaves the range if it can pay for the range's liquidity
+            let quote = quote_swap(&snapshot, i64::MAX as u64, zero_for_one).unwrap();
+            if leaves_range {
+                assert_eq!(quote.crossed_ticks.len(), 1);
+                assert_eq!(quote.liquidity, 0);
+            } else {
+                assert!(quote.crossed_ticks.is_empty());
+                assert_eq!(quote.amount_in, i64::MAX as u64);
+                assert!(if zero_for_one {
+                    *quote.sqrt_price_x32 > sqrt_price_lower_x32
+                } else {
+                    *quote.sqrt_price_x32 < sqrt_price_upper_x32
+                });
+            }
+        }
+    }
+
+    #[test]
+    fn swapping_more_than_the_pool_holds_fills_to_the_price_limit() {
+        let liquidity = 1_000_000_000;
+        let mut ticks = [
+            TickState {
+                tick: -600,
+                ..Default::default()
+            },
+            TickState {
+                tick: 600,
+                ..Default::default()
+            },
+        ];
+        ticks[0]
+            .update(0, liquidity as i64, 0, 0, 0, 0, 0, 0, false, u64::MAX)
+            .unwrap();
+        ticks[1]
+            .update(0, liquidity as i64, 0, 0, 0, 0, 0, 0, true, u64::MAX)
+            .unwrap();
+        let snapshot = PoolSnapshot {
+            liquidity,
+            ..snapshot(&ticks)
+        };
+        let sqrt_price_x32 = *snapshot.sqrt_price_x32;
+
+        for (zero_for_one, boundary) in [(true, -600), (false, 600)] {
+            let quote = quote_swap(&snapshot, 1 << 50, zero_for_one).unwrap();
+            let sqrt_price_boundary_x32 = tick_math::get_sqrt_ratio_at_tick(boundary).unwrap();
+
+            // the whole position is swapped out, and the price runs through the empty range
+            // beyond it to the limit
+            assert_eq!(quote.crossed_ticks, vec![boundary]);
+            assert_eq!(quote.liquidity, 0);
+            assert_eq!(*quote.sqrt_price_x32, no_price_limit(zero_for_one));
+            let (amount_in, amount_out) = if zero_for_one {
+                (
+                    sqrt_price_math::get_amount_0_delta_unsigned(
+                        sqrt_price_boundary_x32,
+                        sqrt_price_x32,
+                        liquidity,
+                        true,
+                    ),
+                    sqrt_price_math::get_amount_1_delta_unsigned(
+                        sqrt_price_boundary_x32,
+                        sqrt_price_x32,
+                        liquidity,
+                        false,
+                    ),
+                )
+            } else {
+                (
+                    sqrt_price_math::get_amount_1_delta_unsigned(
+                        sqrt_price_x32,
+                        sqrt_price_boundary_x32,
+                        liquidity,
+                        true,
+                    ),
+                    sqrt_price_math::get_amount_0_delta_unsigned(
+                        sqrt_price_x32,
+                        sqrt_price_boundary_x32,
+                        liquidity,
+                        false,
+                    ),
+                )
+            };
+            assert_eq!(quote.amount_out, amount_out);
+
+            // only the input filled by the position is consumed
+            assert_eq!(quote.amount_in - quote.fee_amount, amount_in);
+            assert!(quote.amount_in < 1 << 50);
+        }
+    }
+
+    #[test]
+    fn impact_decreases_after_minting_in_range_liquidity() {
+        let ticks = ticks();
+        let snapshot = snapshot(&ticks);
+        for zero_for_one in [true, false] {
+            let amount_in = 1_000_000_000;
+            let impact = price_impact_bps(&snapshot, amount_in, zero_for_one).unwrap();
+            let impact_after = impact_after_mint(
+                &snapshot,
+                -600,
+                600,
+                30_000_000_000,
+                amount_in,
+                zero_for_one,
+            )
+            .unwrap();
+            assert!(
+                impact_after < impact,
+                "impact {} after {}",
+                impact,
+                impact_after
+            );
+            // the fee is paid regardless of depth
+            assert!(impact_after >= 30);
+        }
+    }
+
+    #[test]
+    fn impact_is_unchanged_by_liquidity_out_of_the_swap_path() {
+        let ticks = ticks();
+        let snapshot = snapshot(&ticks);
+        // the small swap stays within the current range, and never reaches the minted position
+        let amount_in = 1_000;
+        assert_eq!(
+            impact_after_mint(&snapshot, 6000, 12000, 1 << 40, amount_in, false).unwrap(),
+            price_impact_bps(&snapshot, amount_in, false).unwrap()
+        );
+    }
+
+    #[test]
+    fn impact_after_mint_rejects_an_inverted_range() {
+        let ticks = ticks();
+        assert!(impact_after_mint(&snapshot(&ticks), 600, -600, 1, 1_000, true).is_err());
+    }
+
+    mod range_for_liquidity_coverage {
+        use super::*;
+
+        // positions over [-100, 100) and [100, 300), each of 1000 liquidity
+        fn ticks() -> Vec<TickState> {
+            [(-100, 1000), (100, 0), (300, -1000)]
+                .iter()
+                .map(|&(tick, liquidity_net)| TickState {
+                    tick,
+                    liquidity_net,
+                    liquidity_gross: 1000,
+                    ..Default::default()
+                })
+                .collect()
+        }
+
+        fn snapshot(ticks: &[TickState]) -> PoolSnapshot<'_> {
+            PoolSnapshot {
+                tick: 5,
+                liquidity: 1000,
+                ..super::snapshot(ticks)
+            }
+        }
+
+        #[test]
+        fn widens_symmetrically_until_the_coverage_is_held() {
+            let ticks = ticks();
+            let snapshot = snapshot(&ticks);
+            // half the liquidity lies in [-100, 100). [-90, 100) holds only 190_000 of 400_000
+            assert_eq!(range_for_liquidity_coverage(&snapshot, 5_000), (-100, 110));
+            assert_eq!(range_for_liquidity_coverage(&snapshot, 7_500), (-190, 200));
+            assert_eq!(range_for_liquidity_coverage(&snapshot, 10_000), (-290, 300));
+        }
+
+        #[test]
+        fn takes_the_current_spacing_without_coverage() {
+            let ticks = ticks();
+            assert_eq!(range_for_liquidity_coverage(&snapshot(&ticks), 0), (0, 10));
+
+            // a pool without liquidity
+            let mut snapshot = snapshot(&[]);
+            snapshot.liquidity = 0;
+            snapshot.tick = -15;
+            assert_eq!(range_for_liquidity_coverage(&snapshot, 10_000), (-20, -10));
+        }
+
+        #[test]
+        fn coverage_above_the_whole_pool_is_capped() {
+            let ticks = ticks();
+            let snapshot = snapshot(&ticks);
+            assert_eq!(
+                range_for_liquidity_coverage(&snapshot, u16::MAX),
+                range_for_liquidity_coverage(&snapshot, 10_000)
+            );
+        }
+
+        #[test]
+        fn holds_the_coverage_of_an_uneven_pool() {
+            let ticks = super::ticks();
+            let snapshot = super::snapshot(&ticks);
+            for coverage_bps in [1_000, 5_000, 9_000, 10_000] {
+                let (tick_lower, tick_upper) =
+                    range_for_liquidity_coverage(&snapshot, coverage_bps);
+                assert_eq!(tick_lower % TICK_SPACING as i32, 0);
+                assert_eq!(tick_upper % TICK_SPACING as i32, 0);
+                // symmetric around the current spacing [0, 10)
+                assert_eq!(tick_lower, -(tick_upper - 10));
+            }
+            // every position lies within [-8000, 6000)
+            assert_eq!(
+                range_for_liquidity_coverage(&snapshot, 10_000),
+                (-8000, 8010)
+            );
+        }
+    }
+
+    fn assert_insufficient_liquidity(result: Result<SwapQuote, anchor_lang::error::Error>) {
+        match result.err().unwrap() {
+            anchor_lang::error::Error::AnchorError(error) => assert_eq!(
+                error.error_code_number,
+                u32::from(ErrorCode::InsufficientLiquidity)
+            ),
+            error => panic!("unexpected error {}", error),
+        }
+    }
+
+    #[test]
+    fn swapping_an_empty_pool_fails() {
+        let snapshot = full_range_snapshot(0, 3000);
+        for zero_for_one in [true, false] {
+            assert_insufficient_liquidity(quote_swap(&snapshot, 1_000, zero_for_one));
+        }
+    }
+
+    #[test]
+    fn swapping_away_from_all_liquidity_fails() {
+        let liquidity = 1_000_000_000;
+        let mut ticks = [
+            TickState {
+                tick: 600,
+                ..Default::default()
+            },
+            TickState {
+                tick: 1200,
+                ..Default::default()
+            },
+        ];
+        ticks[0]
+            .update(0, liquidity as i64, 0, 0, 0, 0, 0, 0, false, u64::MAX)
+            .unwrap();
+        ticks[1]
+            .update(0, liquidity as i64, 0, 0, 0, 0, 0, 0, true, u64::MAX)
+            .unwrap();
+        let snapshot = PoolSnapshot {
+            liquidity: 0,
+            ..snapshot(&ticks)
+        };
+
+        // the liquidity above the price is filled after crossing into it
+        let quote = quote_swap(&snapshot, 1_000, false).unwrap();
+        assert_eq!(quote.crossed_ticks, vec![600]);
+        assert_eq!(quote.amount_in, 1_000);
+        assert!(quote.amount_out > 0);
+
+        // there is nothing to fill below the price
+        assert_insufficient_liquidity(quote_swap(&snapshot, 1_000, true));
+    }
+
+    #[test]
+    fn amount_out_minimum_applies_the_slippage_tolerance() {
+        assert_eq!(amount_out_minimum(1_000_000, 0), 1_000_000);
+        assert_eq!(amount_out_minimum(1_000_000, 1), 999_900);
+        assert_eq!(amount_out_minimum(1_000_000, 50), 995_000);
+        assert_eq!(amount_out_minimum(1_000_000, 9_999), 100);
+        assert_eq!(amount_out_minimum(1_000_000, 10_000), 0);
+        assert_eq!(amount_out_minimum(1_000_000, u16::MAX), 0);
+    }
+
+    #[test]
+    fn amount_out_minimum_rounds_down() {
+        // 0.3% of 999 is 2.997
+        assert_eq!(amount_out_minimum(999, 30), 996);
+        assert_eq!(amount_out_minimum(1, 1), 0);
+        assert_eq!(amount_out_minimum(0, 0), 0);
+        assert_eq!(amount_out_minimum(u64::MAX, 0), u64::MAX);
+        assert_eq!(amount_out_minimum(u64::MAX, 5_000), u64::MAX / 2);
+    }
+
+    #[test]
+    fn compute_units_grow_with_crossed_ticks() {
+        let ticks = ticks();
+        let snapshot = snapshot(&ticks);
+
+        assert_eq!(
+            estimate_compute_units(&snapshot, 1_000_000, true).unwrap(),
+            SWAP_BASE_COMPUTE_UNITS
+        );
+        for (amount_in, zero_for_one) in [(4_000_000_000, false), (4_000_000_000, true)] {
+            let quote = quote_swap(&snapshot, amount_in, zero_for_one).unwrap();
+            assert_eq!(
+                estimate_compute_units(&snapshot, amount_in, zero_for_one).unwrap(),
+                SWAP_BASE_COMPUTE_UNITS
+                    + quote.crossed_ticks.len() as u32 * TICK_CROSS_COMPUTE_UNITS
+            );
+        }
+
+        let mut previous = 0;
+        for amount_in in [1, 1_000_000, 500_000_000, 2_000_000_000, 4_000_000_000] {
+            let estimate = estimate_compute_units(&snapshot, amount_in, false).unwrap();
+            assert!(estimate >= previous);
+            previous = estimate;
+        }
+    }
+
+    /// A pool at a tick over the given initialized ticks
+    fn pool(ticks: &[TickState], tick: i32, liquidity: u64, fee: u32) -> PoolSnapshot<'_> {
+        PoolSnapshot {
+            sqrt_price_x32: SqrtPriceX32::from_tick(tick).unwrap(),
+            tick,
+            liquidity,
+            fee,
+            ..snapshot(ticks)
+        }
+    }
+
+    /// The profit of an arbitrage of `amount_in` after the fees of both pools, like
+    /// #optimal_arb_amount reckons it
+    fn profit(buy_pool: &PoolSnapshot, sell_pool: &PoolSnapshot, amount_in: u64) -> i128 {
+        let buy = quote_swap(buy_pool, amount_in, false).unwrap();
+        let sell = quote_swap(sell_pool, buy.amount_out, true).unwrap();
+        i128::from(sell.amount_out) - i128::from(buy.amount_in)
+    }
+
+    mod cross_pool_spread_bps {
+        use super::*;
+
+        #[test]
+        fn is_the_price_gap_between_fee_free_pools() {
+            // prices of 1 and 1.0001^13863 = 3.9997
+            let cheap = pool(&[], 0, 0, 0);
+            let dear = pool(&[], 13_863, 0, 0);
+            assert_eq!(cross_pool_spread_bps(&cheap, &dear), 29_997);
+            assert_eq!(cross_pool_spread_bps(&dear, &cheap), -29_997);
+            assert_eq!(cross_pool_spread_bps(&cheap, &cheap), 0);
+        }
+
+        #[test]
+        fn is_net_of_both_fees() {
+            let cheap = pool(&[], 0, 0, 3000);
+            let dear = pool(&[], 100, 0, 3000);
+            // 1.01005 * 0.997 * 0.997 = 1.0039...
+            assert_eq!(cross_pool_spread_bps(&cheap, &dear), 39);
+            assert_eq!(cross_pool_spread_bps(&dear, &cheap), -39);
+
+            let dear = pool(&[], 100, 0, 10_000);
+            // 1.01005 * 0.997 * 0.99 = 0.9969...
+            assert_eq!(cross_pool_spread_bps(&cheap, &dear), 0);
+            assert_eq!(cross_pool_spread_bps(&dear, &cheap), 0);
+        }
+
+        #[test]
+        fn is_zero_if_the_gap_does_not_cover_the_fees() {
+            let cheap = pool(&[], 0, 0, 500);
+            let dear = pool(&[], 10, 0, 500);
+            // 1.0010005 * 0.9995 * 0.9995 = 0.99999...
+            assert_eq!(cross_pool_spread_bps(&cheap, &dear), 0);
+
+            let dear = pool(&[], 30, 0, 500);
+            // 1.0030044 * 0.9995 * 0.9995 = 1.0020...
+            assert_eq!(cross_pool_spread_bps(&cheap, &dear), 20);
+            assert_eq!(cross_pool_spread_bps(&dear, &cheap), -20);
+        }
+
+        #[test]
+        fn saturates_for_extreme_gaps() {
+            let cheap = pool(&[], tick_math::MIN_TICK, 0, 0);
+            let dear = pool(&[], tick_math::MAX_TICK, 0, 0);
+            assert_eq!(cross_pool_spread_bps(&cheap, &dear), i32::MAX);
+            assert_eq!(cross_pool_spread_bps(&dear, &cheap), -i32::MAX);
+        }
+    }
+
+    mod optimal_arb_amount {
+        use super::*;
+
+        fn range(tick_lower: i32, tick_upper: i32, liquidity: u64) -> Vec<TickState> {
+            [
+                (tick_lower, liquidity as i64),
+                (tick_upper, -(liquidity as i64)),
+            ]
+            .iter()
+            .map(|&(tick, liquidity_net)| TickState {
+                tick,
+                liquidity_net,
+                liquidity_gross: liquidity,
+                ..Default::default()
+            })
+            .collect()
+        }
+
+        #[test]
+        fn takes_the_smallest_input_on_a_profit_plateau() {
+            // buying up to a price of 1.01 in a narrow range, to sell at about 1.22
+            let buy_ticks = range(0, 100, 1_000_000_000_000);
+            let sell_ticks = range(-10_000, 10_000, 1_000_000_000_000);
+            let buy = pool(&buy_ticks, 0, 1_000_000_000_000, 0);
+            let sell = pool(&sell_ticks, 2000, 1_000_000_000_000, 0);
+
+            // the buy pool runs dry at its capacity, and larger inputs make the same profit
+            let max_amount_in = 100_000_000_000;
+            let capacity = quote_swap(&buy, max_amount_in, false).unwrap().amount_in;
+            let plateau_profit = profit(&buy, &sell, max_amount_in);
+            assert_eq!(profit(&buy, &sell, 2 * capacity), plateau_profit);
+
+            for epsilon in [1_000, 1_000_000] {
+                let arb = optimal_arb_amount(&buy, &sell, max_amount_in, epsilon).unwrap();
+                assert!(arb.amount_in <= capacity);
+                assert_eq!(i128::from(arb.profit), profit(&buy, &sell, arb.amount_in));
+                assert!(i128::from(arb.profit + epsilon) >= plateau_profit);
+                assert!(profit(&buy, &sell, arb.amount_in - 1) < i128::from(arb.profit));
+                // reproducible
+                assert_eq!(
+                    optimal_arb_amount(&buy, &sell, max_amount_in, epsilon).unwrap(),
+                    arb
+                );
+            }
+
+            // a larger tolerance settles for a smaller input
+            let tight = optimal_arb_amount(&buy, &sell, max_amount_in, 1_000).unwrap();
+            let loose = optimal_arb_amount(&buy, &sell, max_amount_in, 1_000_000).unwrap();
+            assert!(loose.amount_in < tight.amount_in);
+            assert!(loose.profit < tight.profit);
+        }
+
+        #[test]
+        fn stays_within_the_max_input() {
+            let buy_ticks = range(0, 100, 1_000_000_000_000);
+            let sell_ticks = range(-10_000, 10_000, 1_000_000_000_000);
+            let buy = pool(&buy_ticks, 0, 1_000_000_000_000, 0);
+            let sell = pool(&sell_ticks, 2000, 1_000_000_000_000, 0);
+
+            let arb = optimal_arb_amount(&buy, &sell, 1_000_000_000, 1_000).unwrap();
+            assert!(arb.amount_in <= 1_000_000_000);
+            assert!(i128::from(arb.profit + 1_000) >= profit(&buy, &sell, 1_000_000_000));
+        }
+
+        #[test]
+        fn is_empty_without_a_profitable_input() {
+            let ticks = range(-10_000, 10_000, 1_000_000_000_000);
+            let a = pool(&ticks, 0, 1_000_000_000_000, 3000);
+            let b = pool(&ticks, 50, 1_000_000_000_000, 3000);
+            // a gap of 0.5% does not cover fees of 0.3% twice, and buying dear loses outright
+            assert_eq!(cross_pool_spread_bps(&a, &b), 0);
+            assert_eq!(
+                optimal_arb_amount(&a, &b, 1_000_000_000, 0).unwrap(),
+                ArbAmount::default()
+            );
+            assert_eq!(
+                optimal_arb_amount(&b, &a, 1_000_000_000, 0).unwrap(),
+                ArbAmount::default()
+            );
+        }
+    }
+
+    mod breakeven_total_fee_bps {
+        use super::*;
+
+        const AMOUNT_IN: u64 = 1_000_000_000;
+        const LIQUIDITY: u64 = 100_000_000_000_000;
+
+        #[test]
+        fn is_the_price_gap_less_price_impact() {
+            // a gap of 1.0001^100 = 1.01005 returns 1 - 1 / 1.01005 = 99.5 bps, less about
+            // 0.4 bps of price impact over both legs and 0.2 bps from rounding to Q32.32
+            assert_eq!(
+                breakeven_total_fee_bps(
+                    &pool(&[], 0, LIQUIDITY, 0),
+                    &pool(&[], 100, LIQUIDITY, 0),
+                    AMOUNT_IN
+                )
+                .unwrap(),
+                98
+            );
+            // the pool fees are ignored
+            assert_eq!(
+                breakeven_total_fee_bps(
+                    &pool(&[], 0, LIQUIDITY, 3000),
+                    &pool(&[], 100, LIQUIDITY, 10_000),
+                    AMOUNT_IN
+                )
+                .unwrap(),
+                98
+            );
+        }
+
+        #[test]
+        fn is_profitable_when_the_spread_covers_the_fees() {
+            // 30 + 30 bps of fees are covered by the spread
+            let (buy, sell) = (
+                pool(&[], 0, LIQUIDITY, 3000),
+                pool(&[], 100, LIQUIDITY, 3000),
+            );
+            let breakeven = breakeven_total_fee_bps(&buy, &sell, AMOUNT_IN).unwrap();
+            assert!((buy.fee + sell.fee) / 100 <= breakeven);
+            assert!(profit(&buy, &sell, AMOUNT_IN) > 0);
+        }
+
+        #[test]
+        fn is_unprofitable_when_the_fees_exceed_the_spread() {
+            // 50 + 50 bps of fees exceed the spread
+            let (buy, sell) = (
+                pool(&[], 0, LIQUIDITY, 5000),
+                pool(&[], 100, LIQUIDITY, 5000),
+            );
+            let breakeven = breakeven_total_fee_bps(&buy, &sell, AMOUNT_IN).unwrap();
+            assert!((buy.fee + sell.fee) / 100 > breakeven);
+            assert!(profit(&buy, &sell, AMOUNT_IN) <= 0);
+        }
+
+        #[test]
+        fn shrinks_with_price_impact() {
+            let (buy, sell) = (
+                pool(&[], 0, 1_000_000_000_000, 0),
+                pool(&[], 100, 1_000_000_000_000, 0),
+            );
+            let small = breakeven_total_fee_bps(&buy, &sell, AMOUNT_IN).unwrap();
+            let large = breakeven_total_fee_bps(&buy, &sell, 100 * AMOUNT_IN).unwrap();
+            assert!(large < small);
+        }
+
+        #[test]
+        fn is_zero_without_a_spread() {
+            assert_eq!(
+                breakeven_total_fee_bps(
+                    &pool(&[], 0, LIQUIDITY, 0),
+                    &pool(&[], 0, LIQUIDITY, 0),
+                    AMOUNT_IN
+                )
+                .unwrap(),
+                0
+            );
+            // buying in the dearer pool loses even without fees
+            assert_eq!(
+                breakeven_total_fee_bps(
+                    &pool(&[], 100, LIQUIDITY, 0),
+                    &pool(&[], 0, LIQUIDITY, 0),
+                    AMOUNT_IN
+                )
+                .unwrap(),
+                0
+            );
+        }
+    }
+
+    mod quote_swap_checked {
+        use super::*;
+
+        #[test]
+        fn quotes_fresh_snapshots_like_quote_swap() {
+            let ticks = ticks();
+            let snapshot = PoolSnapshot {
+                slot: 1_000,
+                ..snapshot(&ticks)
+            };
+            let quote = quote_swap(&snapshot, 1_000_000, true).unwrap();
+            // taken at the current slot, at the oldest tolerated slot, and after the current slot
+            for current_slot in [1_000, 1_010, 990] {
+                assert_eq!(
+                    quote_swap_checked(&snapshot, 1_000_000, true, 10, current_slot).unwrap(),
+                    quote
+                );
+            }
+        }
+
+        #[test]
+        fn rejects_stale_snapshots() {
+            let ticks = ticks();
+            let snapshot = PoolSnapshot {
+                slot: 1_000,
+                ..snapshot(&ticks)
+            };
+            for (max_staleness_slots, current_slot) in [(10, 1_011), (0, 1_001), (10, u64::MAX)] {
+                match quote_swap_checked(
+                    &snapshot,
+                    1_000_000,
+                    true,
+                    max_staleness_slots,
+                    current_slot,
+                )
+                .err()
+                .unwrap()
+                {
+                    anchor_lang::error::Error::AnchorError(error) => {
+                        assert_eq!(error.error_code_number, u32::from(ErrorCode::StaleSnapshot))
+                    }
+                    error => panic!("unexpected error {:?}", error),
+                }
+            }
+        }
+    }
+
+    mod liquidity_to_limit_impact {
+        use super::*;
+
+        const AMOUNT_IN: u64 = 300_000_000;
+
+        fn impact(snapshot: &PoolSnapshot, liquidity: u64, zero_for_one: bool) -> u32 {
+            let tick_lower =
+                tick_math::MIN_TICK / i32::from(TICK_SPACING) * i32::from(TICK_SPACING);
+            impact_after_mint(
+                snapshot,
+                tick_lower,
+                -tick_lower,
+                liquidity,
+                AMOUNT_IN,
+                zero_for_one,
+            )
+            .unwrap()
+        }
+
+        #[test]
+        fn adds_the_least_liquidity_within_the_impact() {
+            let ticks = ticks();
+            let snapshot = snapshot(&ticks);
+            for zero_for_one in [true, false] {
+                let impact_before = impact(&snapshot, 0, zero_for_one);
+                assert!(impact_before > 200);
+                for max_impact_bps in [31, 50, 100, 200] {
+                    let liquidity = liquidity_to_limit_impact(
+                        &snapshot,
+                        AMOUNT_IN,
+                        zero_for_one,
+                        max_impact_bps,
+                    )
+                    .unwrap();
+                    assert!(impact(&snapshot, liquidity, zero_for_one) <= max_impact_bps);
+                    assert!(impact(&snapshot, liquidity - 1, zero_for_one) > max_impact_bps);
+                }
+            }
+        }
+
+        #[test]
+        fn is_zero_if_the_impact_is_already_within_the_limit() {
+            let ticks = ticks();
+            let snapshot = snapshot(&ticks);
+            let impact_before = impact(&snapshot, 0, true);
+            assert_eq!(
+                liquidity_to_limit_impact(&snapshot, AMOUNT_IN, true, impact_before).unwrap(),
+                0
+            );
+        }
+
+        #[test]
+        fn is_max_if_the_limit_is_below_the_fee() {
+            let ticks = ticks();
+            let snapshot = snapshot(&ticks);
+            // the pool fee is 30 bps
+            assert_eq!(
+                liquidity_to_limit_impact(&snapshot, AMOUNT_IN, true, 29).unwrap(),
+                u64::MAX
+            );
+        }
+    }
+
+    mod liquidity_curve {
+        use super::*;
+
+        /// The liquidity of the positions covering a tick
+        fn liquidity_at(tick: i32) -> u64 {
+            POSITIONS
+                .iter()
+                .filter(|(tick_lower, tick_upper, _)| *tick_lower <= tick && tick < *tick_upper)
+                .map(|(_, _, liquidity)| *liquidity as u64)
+                .sum()
+        }
+
+        #[test]
+        fn steps_at_every_initialized_tick() {
+            let ticks = ticks();
+            let snapshot = snapshot(&ticks);
+            let curve = liquidity_curve(&snapshot, -9000, 7000, 100);
+            assert_eq!(
+                curve,
+                vec![
+                    (-9000, 0),
+                    (-8000, 1_000_000_000),
+                    (-3000, 11_000_000_000),
+                    (-2600, 10_000_000_000),
+                    (-200, 15_000_000_000),
+                    (500, 10_000_000_000),
+                    (2700, 30_000_000_000),
+                    (3000, 20_000_000_000),
+                    (6000, 0),
+                ]
+            );
+
+            // every tick of a band has the liquidity of its step
+            for window in curve.windows(2) {
+                for tick in (window[0].0..window[1].0).step_by(50) {
+                    assert_eq!(liquidity_at(tick), window[0].1);
+                }
+            }
+        }
+
+        #[test]
+        fn starts_from_the_liquidity_at_the_lower_tick() {
+            let ticks = ticks();
+            let snapshot = snapshot(&ticks);
+            // both sides of the current tick, and within a band
+            for tick_lower in [-5000, -100, 0, 100, 2800] {
+                let curve = liquidity_curve(&snapshot, tick_lower, tick_lower + 50, 10);
+                assert_eq!(curve, vec![(tick_lower, liquidity_at(tick_lower))]);
+            }
+        }
+
+        #[test]
+        fn samples_large_ranges() {
+            let ticks = ticks();
+            let snapshot = snapshot(&ticks);
+            let curve = liquidity_curve(&snapshot, -9000, 7000, 4);
+            assert_eq!(curve.len(), 4);
+            for (index, (tick, liquidity)) in curve.into_iter().enumerate() {
+                assert_eq!(tick, -9000 + 4000 * index as i32);
+                assert_eq!(liquidity, liquidity_at(tick));
+            }
+
+            // sample ticks are on the tick spacing
+            let curve = liquidity_curve(&snapshot, -9000, 7000, 7);
+            assert!(curve
+                .windows(2)
+                .all(|window| (window[1].0 - window[0].0) % i32::from(TICK_SPACING) == 0));
+            assert!(curve
+                .iter()
+                .all(|&(tick, liquidity)| liquidity == liquidity_at(tick)));
+        }
+    }
+}
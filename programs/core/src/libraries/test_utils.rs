@@ -1,3 +1,5 @@
+use crate::states::pool::PoolState;
+
 /// Helper functions for unit tests
 
 #[cfg(test)]
@@ -18,3 +20,52 @@ pub fn encode_price_sqrt_x32(reserve_1: u64, reserve_0: u64) -> u64 {
 pub fn encode_liquidity(reserve_1: u64, reserve_0: u64) -> u64 {
     (reserve_1 as f64 * reserve_0 as f64).sqrt().round() as u64
 }
+
+/// Asserts that a swap credits its fee to liquidity providers and the protocol, losing at
+/// most one unit to rounding
+///
+/// The global fee growth is floored to a Q32.32 per unit of liquidity, so accruing it over
+/// `liquidity` gives back the LP fee less a rounding loss. The loss stays below one unit for
+/// a single step over at most 2^32 liquidity. Swaps taking several steps, or over more
+/// liquidity, can lose more and fail the assertion.
+///
+/// # Arguments
+///
+/// * `pool_before` - The pool before the swap
+/// * `pool_after` - The pool after the swap
+/// * `swap_fee` - The fee paid by the swap, including the protocol fee
+/// * `liquidity` - The in range liquidity the fee accrued to
+///
+pub fn assert_fee_conservation(
+    pool_before: &PoolState,
+    pool_after: &PoolState,
+    swap_fee: u64,
+    liquidity: u64,
+) {
+    let fee_growth_x32 = pool_after
+        .fee_growth_global_0_x32
+        .wrapping_sub(pool_before.fee_growth_global_0_x32)
+        .wrapping_add(
+            pool_after
+                .fee_growth_global_1_x32
+                .wrapping_sub(pool_before.fee_growth_global_1_x32),
+        );
+    let lp_fee = (u128::from(fee_growth_x32) * u128::from(liquidity)) >> 32;
+    let protocol_fee =
+        u128::from(pool_after.protocol_fees_token_0 - pool_before.protocol_fees_token_0)
+            + u128::from(pool_after.protocol_fees_token_1 - pool_before.protocol_fees_token_1);
+    let credited = lp_fee + protocol_fee;
+
+    assert!(
+        credited <= u128::from(swap_fee),
+        "credited {} is more than the swap fee {}",
+        credited,
+        swap_fee
+    );
+    assert!(
+        u128::from(swap_fee) - credited <= 1,
+        "lost {} of the swap fee {} to rounding",
+        u128::from(swap_fee) - credited,
+        swap_fee
+    );
+}
@@ -281,10 +281,103 @@ pub fn get_tick_at_sqrt_ratio(sqrt_price_x32: u64) -> Result<i32, anchor_lang::e
     })
 }
 
+/// Computes the price at a tick for display, off-chain.
+///
+/// The price is token_1 per token_0, or token_0 per token_1 if inverted. It is derived from the
+/// Q32.32 sqrt price at the tick, so it matches the price the pool trades at rather than the
+/// exact value of 1.0001^tick. If token decimals are given, the price is between whole tokens
+/// instead of base units.
+///
+/// # Arguments
+///
+/// * `tick` - Price tick
+/// * `invert` - Whether to return the price of token_1 in terms of token_0
+/// * `decimals` - The decimals of token_0 and token_1, or None for a price between base units
+///
+#[cfg(not(target_arch = "bpf"))]
+pub fn price_at_tick(
+    tick: i32,
+    invert: bool,
+    decimals: Option<(u8, u8)>,
+) -> Result<f64, anchor_lang::error::Error> {
+    let sqrt_price = super::fixed_point_32::x32_to_f64(get_sqrt_ratio_at_tick(tick)?);
+    let mut price = sqrt_price * sqrt_price;
+    if let Some((decimals_0, decimals_1)) = decimals {
+        price *= 10f64.powi(decimals_0 as i32 - decimals_1 as i32);
+    }
+    Ok(if invert { 1.0 / price } else { price })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    mod price_at_tick {
+        use super::*;
+
+        fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+            assert!(
+                ((actual - expected) / expected).abs() < tolerance,
+                "{} is not close to {}",
+                actual,
+                expected
+            );
+        }
+
+        #[test]
+        fn price_is_one_at_tick_zero() {
+            assert_eq!(price_at_tick(0, false, None).unwrap(), 1.0);
+            assert_eq!(price_at_tick(0, true, None).unwrap(), 1.0);
+        }
+
+        #[test]
+        fn adjusts_tick_zero_for_decimals() {
+            // a base unit of token_0 with 9 decimals is worth a base unit of token_1 with 6
+            assert_close(
+                price_at_tick(0, false, Some((9, 6))).unwrap(),
+                1_000.0,
+                1e-12,
+            );
+            assert_close(price_at_tick(0, true, Some((9, 6))).unwrap(), 0.001, 1e-12);
+            assert_close(price_at_tick(0, false, Some((6, 9))).unwrap(), 0.001, 1e-12);
+            assert_eq!(price_at_tick(0, false, Some((6, 6))).unwrap(), 1.0);
+        }
+
+        #[test]
+        fn matches_powers_of_the_tick_base() {
+            for tick in [1, 10, 1_000, -1_000, 50_000, -50_000, 200_000, -200_000] {
+                let expected = 1.0001f64.powi(tick);
+                // relative precision of the Q32.32 sqrt price drops for low prices
+                let tolerance = if tick < -100_000 { 1e-4 } else { 1e-8 };
+                assert_close(
+                    price_at_tick(tick, false, None).unwrap(),
+                    expected,
+                    tolerance,
+                );
+                assert_close(
+                    price_at_tick(tick, true, None).unwrap(),
+                    1.0 / expected,
+                    tolerance,
+                );
+            }
+        }
+
+        #[test]
+        fn inverted_price_is_the_reciprocal() {
+            for tick in [-30_000, -7, 7, 30_000] {
+                let price = price_at_tick(tick, false, Some((8, 6))).unwrap();
+                let inverted = price_at_tick(tick, true, Some((8, 6))).unwrap();
+                assert_close(price * inverted, 1.0, 1e-12);
+            }
+        }
+
+        #[test]
+        #[should_panic]
+        fn throws_outside_the_tick_range() {
+            price_at_tick(MAX_TICK + 1, false, None).unwrap();
+        }
+    }
+
     mod get_sqrt_ratio_at_tick {
         use crate::libraries::test_utils::encode_price_sqrt_x32;
 
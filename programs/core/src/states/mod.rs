@@ -1,6 +1,7 @@
 pub mod factory;
 pub mod fee;
 pub mod oracle;
+pub mod owner_liquidity;
 pub mod pool;
 pub mod position;
 pub mod tick;
@@ -12,6 +12,7 @@
 /// The most recent observation is available, independent of the length of the oracle array,
 /// by passing 0 as the index seed.
 ///
+use crate::error::ErrorCode;
 use anchor_lang::prelude::*;
 
 /// Seed to derive account address and signature
@@ -34,7 +35,9 @@ pub struct ObservationState {
     /// The block timestamp of the observation
     pub block_timestamp: u32,
 
-    /// The tick multiplied by seconds elapsed for the life of the pool as of the observation timestamp
+    /// The tick multiplied by seconds elapsed for the life of the pool as of the observation timestamp.
+    /// Wraps on overflow, i.e. is stored mod 2**64. Only differences between two cumulatives
+    /// are meaningful, and they are correct across a wrap if computed with wrapping arithmetic.
     pub tick_cumulative: i64,
 
     /// The seconds per in range liquidity for the life of the pool as of the observation timestamp
@@ -62,7 +65,10 @@ impl ObservationState {
             bump: self.bump,
             index: self.index,
             block_timestamp,
-            tick_cumulative: self.tick_cumulative + tick as i64 * delta as i64,
+            // overflow is desired
+            tick_cumulative: self
+                .tick_cumulative
+                .wrapping_add(tick as i64 * delta as i64),
             seconds_per_liquidity_cumulative_x32: self.seconds_per_liquidity_cumulative_x32
                 + ((delta as u64) << 32) / if liquidity > 0 { liquidity } else { 1 },
             initialized: true,
@@ -124,6 +130,61 @@ impl ObservationState {
             last.seconds_per_liquidity_cumulative_x32,
         )
     }
+
+    /// Returns the time weighted average tick from this observation up to the given time,
+    /// rounded towards negative infinity
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - A past observation, marking the start of the averaging window
+    /// * `tick_cumulative` - The tick cumulative at the end of the window
+    /// * `time` - The timestamp at the end of the window
+    ///
+    pub fn twap_tick_since(self, tick_cumulative: i64, time: u32) -> Result<i32> {
+        require!(
+            self.initialized && time > self.block_timestamp,
+            ErrorCode::OLD
+        );
+        let seconds_ago = (time - self.block_timestamp) as i64;
+        // the window delta is correct even if the cumulative wrapped in between
+        let tick_cumulative_delta = tick_cumulative.wrapping_sub(self.tick_cumulative);
+
+        let mut twap_tick = tick_cumulative_delta / seconds_ago;
+        if tick_cumulative_delta < 0 && tick_cumulative_delta % seconds_ago != 0 {
+            twap_tick -= 1;
+        }
+        Ok(twap_tick as i32)
+    }
+}
+
+/// Returns the index written after `current` in an oracle array of `cardinality` elements,
+/// wrapping from the last element, `cardinality - 1`, back to 0
+///
+/// # Arguments
+///
+/// * `current` - The index of the most recently written observation, below `cardinality`
+/// * `cardinality` - The length of the oracle array, at least 1
+///
+pub fn next_observation_index(current: u16, cardinality: u16) -> u16 {
+    // widened, since incrementing the index of a full length array overflows u16
+    ((u32::from(current) + 1) % u32::from(cardinality)) as u16
+}
+
+/// Checks that the spot tick lies within `max_tick_deviation` ticks of the time weighted
+/// average tick, protecting value sensitive swaps against manipulated spot prices
+///
+/// # Arguments
+///
+/// * `tick` - The current tick of the pool
+/// * `twap_tick` - The time weighted average tick read from the oracle
+/// * `max_tick_deviation` - The permitted distance between the two ticks
+///
+pub fn check_price_deviation(tick: i32, twap_tick: i32, max_tick_deviation: u32) -> Result<()> {
+    require!(
+        tick.abs_diff(twap_tick) <= max_tick_deviation,
+        ErrorCode::PriceDeviation
+    );
+    Ok(())
 }
 
 /// Returns the block timestamp truncated to 32 bits, i.e. mod 2**32
@@ -145,3 +206,157 @@ pub struct IncreaseObservationCardinalityNext {
     /// The updated value of the next observation cardinality
     pub observation_cardinality_next_new: u16,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn observation(block_timestamp: u32, tick_cumulative: i64) -> ObservationState {
+        ObservationState {
+            block_timestamp,
+            tick_cumulative,
+            initialized: true,
+            ..Default::default()
+        }
+    }
+
+    mod next_observation_index {
+        use super::*;
+
+        #[test]
+        fn advances_within_the_array() {
+            assert_eq!(next_observation_index(0, 4), 1);
+            assert_eq!(next_observation_index(2, 4), 3);
+        }
+
+        #[test]
+        fn wraps_from_the_last_index_to_zero() {
+            assert_eq!(next_observation_index(0, 1), 0);
+            assert_eq!(next_observation_index(3, 4), 0);
+            assert_eq!(next_observation_index(u16::MAX - 1, u16::MAX), 0);
+        }
+
+        #[test]
+        fn never_reaches_cardinality() {
+            for cardinality in [1, 2, 3, 7, 256, u16::MAX] {
+                let mut index = 0;
+                for _ in 0..u32::from(cardinality) * 2 {
+                    index = next_observation_index(index, cardinality);
+                    assert!(index < cardinality);
+                }
+                assert_eq!(index, 0);
+            }
+        }
+
+        #[test]
+        fn keeps_the_ring_consistent_across_a_wrap() {
+            const CARDINALITY: u16 = 4;
+            let mut ring: Vec<ObservationState> = (0..CARDINALITY)
+                .map(|index| ObservationState {
+                    index,
+                    ..Default::default()
+                })
+                .collect();
+            ring[0].initialized = true;
+
+            let mut index = 0;
+            let writes = u32::from(CARDINALITY) + 2;
+            for time in 1..=writes {
+                index = next_observation_index(index, CARDINALITY);
+                let observation = &mut ring[index as usize];
+                assert_eq!(
+                    observation.update(time, 0, 1, CARDINALITY, CARDINALITY),
+                    CARDINALITY
+                );
+            }
+            assert_eq!(index, 2);
+
+            // every slot kept its own index, the newest write is at the index and the
+            // oldest right after it
+            for (slot, observation) in ring.iter().enumerate() {
+                assert_eq!({ observation.index } as usize, slot);
+                assert!(observation.initialized);
+            }
+            let timestamps: Vec<u32> = (1..=CARDINALITY)
+                .map(|offset| {
+                    let slot = (index + offset) % CARDINALITY;
+                    ring[slot as usize].block_timestamp
+                })
+                .collect();
+            assert_eq!(timestamps, vec![3, 4, 5, 6]);
+        }
+    }
+
+    mod twap_tick_since {
+        use super::*;
+
+        #[test]
+        fn averages_over_the_window() {
+            // tick 100 for 10 seconds
+            assert_eq!(
+                observation(1000, 5000).twap_tick_since(6000, 1010).unwrap(),
+                100
+            );
+        }
+
+        #[test]
+        fn rounds_negative_ticks_down() {
+            // -25 / 10 = -2.5
+            assert_eq!(observation(0, 0).twap_tick_since(-25, 10).unwrap(), -3);
+            assert_eq!(observation(0, 0).twap_tick_since(-20, 10).unwrap(), -2);
+        }
+
+        #[test]
+        fn averages_across_a_wrap_of_the_cumulative() {
+            // tick 221_800 for 60 seconds, starting 1_000_000 below the i64 boundary
+            let start = observation(1000, i64::MAX - 1_000_000);
+            let end = start.transform(1060, 221_800, 1);
+            assert!({ end.tick_cumulative } < 0);
+            assert_eq!(
+                start.twap_tick_since(end.tick_cumulative, 1060).unwrap(),
+                221_800
+            );
+
+            // and for a negative tick wrapping below i64::MIN
+            let start = observation(1000, i64::MIN + 1_000_000);
+            let end = start.transform(1060, -221_800, 1);
+            assert!({ end.tick_cumulative } > 0);
+            assert_eq!(
+                start.twap_tick_since(end.tick_cumulative, 1060).unwrap(),
+                -221_800
+            );
+        }
+
+        #[test]
+        fn fails_for_an_empty_window() {
+            assert!(observation(1000, 0).twap_tick_since(0, 1000).is_err());
+        }
+
+        #[test]
+        fn fails_for_an_uninitialized_observation() {
+            assert!(ObservationState::default().twap_tick_since(0, 10).is_err());
+        }
+    }
+
+    mod check_price_deviation {
+        use super::*;
+
+        #[test]
+        fn passes_if_spot_equals_twap() {
+            let twap_tick = observation(0, 0).twap_tick_since(-6000, 60).unwrap();
+            assert!(check_price_deviation(-100, twap_tick, 0).is_ok());
+        }
+
+        #[test]
+        fn passes_within_the_deviation() {
+            assert!(check_price_deviation(150, 100, 50).is_ok());
+            assert!(check_price_deviation(50, 100, 50).is_ok());
+        }
+
+        #[test]
+        fn reverts_if_spot_is_far_from_twap() {
+            assert!(check_price_deviation(151, 100, 50).is_err());
+            assert!(check_price_deviation(-10_000, 100, 50).is_err());
+        }
+    }
+}
@@ -0,0 +1,110 @@
+///! The liquidity an owner holds across their positions in a pool, tracked on chain so that
+///! the pool's limit on liquidity per owner is enforced without enumerating positions
+///!
+use crate::error::ErrorCode;
+use anchor_lang::prelude::*;
+
+/// Seed to derive account address and signature
+pub const OWNER_LIQUIDITY_SEED: &str = "ol";
+
+/// Total liquidity of an owner's positions in a pool
+///
+/// Updated by every mint, burn and transfer of the owner's positions. Tokenized positions are
+/// held by the factory, so their liquidity is counted towards the factory.
+///
+/// PDA of `[OWNER_LIQUIDITY_SEED, token_0, token_1, fee, owner]`
+///
+#[account(zero_copy)]
+#[derive(Default, Debug)]
+#[repr(packed)]
+pub struct OwnerLiquidityState {
+    /// Bump to identify PDA
+    pub bump: u8,
+
+    /// The summed liquidity of the owner's positions in the pool
+    pub liquidity: u64,
+}
+
+impl OwnerLiquidityState {
+    /// Adds liquidity minted to or removes liquidity burned from one of the owner's positions
+    ///
+    /// Liquidity minted before the account was created is not counted, so removing more
+    /// than is counted leaves zero.
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The owner's liquidity in the pool
+    /// * `liquidity_delta` - The change in liquidity of the owner's position
+    ///
+    pub fn update(&mut self, liquidity_delta: i64) -> Result<()> {
+        self.liquidity = if liquidity_delta < 0 {
+            self.liquidity
+                .saturating_sub(liquidity_delta.unsigned_abs())
+        } else {
+            self.liquidity
+                .checked_add(liquidity_delta as u64)
+                .ok_or(ErrorCode::OwnerLimitExceeded)?
+        };
+        Ok(())
+    }
+}
+
+/// Derives the address and canonical bump of an owner's liquidity account, as created by
+/// #init_owner_liquidity_account
+///
+/// # Arguments
+///
+/// * `token_0`, `token_1`, `fee` - Identify the pool
+/// * `owner` - The owner of the positions
+///
+pub fn derive_owner_liquidity_address(
+    token_0: Pubkey,
+    token_1: Pubkey,
+    fee: u32,
+    owner: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            OWNER_LIQUIDITY_SEED.as_bytes(),
+            token_0.as_ref(),
+            token_1.as_ref(),
+            &fee.to_be_bytes(),
+            owner.as_ref(),
+        ],
+        &crate::ID,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn counts_minted_and_burned_liquidity() {
+        let mut owner_liquidity = OwnerLiquidityState::default();
+        owner_liquidity.update(1000).unwrap();
+        owner_liquidity.update(500).unwrap();
+        owner_liquidity.update(-300).unwrap();
+        assert_eq!({ owner_liquidity.liquidity }, 1200);
+    }
+
+    #[test]
+    fn burning_uncounted_liquidity_leaves_zero() {
+        let mut owner_liquidity = OwnerLiquidityState {
+            liquidity: 100,
+            ..Default::default()
+        };
+        owner_liquidity.update(-300).unwrap();
+        assert_eq!({ owner_liquidity.liquidity }, 0);
+    }
+
+    #[test]
+    fn rejects_overflow() {
+        let mut owner_liquidity = OwnerLiquidityState {
+            liquidity: u64::MAX,
+            ..Default::default()
+        };
+        assert!(owner_liquidity.update(1).is_err());
+        assert!(owner_liquidity.update(0).is_ok());
+    }
+}
@@ -1,11 +1,14 @@
 use anchor_lang::prelude::*;
 
 use crate::{
+    error::ErrorCode,
+    libraries::{fixed_point_32, full_math::MulDiv},
     program::CyclosCore,
     states::{
         oracle::{self, OBSERVATION_SEED},
-        position::POSITION_SEED,
-        tick::TICK_SEED,
+        owner_liquidity::derive_owner_liquidity_address,
+        position::derive_position_address,
+        tick::{tick_seed, TICK_SEED},
         tick_bitmap::BITMAP_SEED,
     },
 };
@@ -15,6 +18,9 @@ use super::{oracle::ObservationState, tick::TickState};
 /// Seed to derive account address and signature
 pub const POOL_SEED: &str = "p";
 
+/// The highest referral fee a pool may allow, in basis points of the swap output
+pub const MAX_REFERRAL_FEE_BPS: u16 = 1_000;
+
 /// The pool state
 ///
 /// PDA of `[POOL_SEED, token_0, token_1, fee]`
@@ -58,6 +64,10 @@ pub struct PoolState {
     /// The next maximum number of observations to store, triggered on a swap or position update
     pub observation_cardinality_next: u16,
 
+    /// The denominator of the protocol's % share of the fees, or 0 if the protocol fee is off.
+    /// Copied from the factory on pool creation
+    pub fee_protocol: u8,
+
     /// The fee growth as a Q32.32 number, i.e. fees of token_0 and token_1 collected per
     /// unit of liquidity for the entire life of the pool.
     /// These values can overflow u64
@@ -71,6 +81,38 @@ pub struct PoolState {
 
     /// Whether the pool is currently locked to reentrancy
     pub unlocked: bool,
+
+    /// The maximum liquidity an owner may hold across positions in the pool.
+    /// Zero if there is no limit
+    pub max_liquidity_per_owner: u64,
+
+    /// The minimum width of a position's tick range in multiples of tick_spacing.
+    /// Zero if there is no minimum
+    pub min_tick_width: u32,
+
+    /// The bonus reward token distributed to in range liquidity, in addition to swap fees.
+    /// The default pubkey if the pool has no rewards
+    pub reward_mint: Pubkey,
+
+    /// The all-time reward growth as a Q32.32, i.e. the rewards distributed per unit of
+    /// in range liquidity
+    pub reward_growth_global_x32: u64,
+
+    /// The number of slots liquidity must be held after a mint before the position keeps
+    /// the fees it earns. Zero if there is no holding period
+    pub min_holding_slots: u64,
+
+    /// The fees paid by swaps in token_0 and token_1 over the lifetime of the pool,
+    /// including the protocol fee
+    pub total_fees_token_0: u128,
+    pub total_fees_token_1: u128,
+
+    /// The highest referral fee a swap may skim from its output, in basis points.
+    /// Zero if referrals are disabled
+    pub max_referral_fee_bps: u16,
+
+    /// Whether swap values in events are denominated in token_1, else in token_0
+    pub quote_is_token_1: bool,
 }
 
 impl PoolState {
@@ -80,7 +122,7 @@ impl PoolState {
     /// * `self` - A pool account
     ///
     pub fn next_observation_index(self) -> u16 {
-        (self.observation_index + 1) % self.observation_cardinality_next
+        oracle::next_observation_index(self.observation_index, self.observation_cardinality_next)
     }
 
     /// Validates the public key of an observation account
@@ -110,6 +152,28 @@ impl PoolState {
         Ok(())
     }
 
+    /// Validates the public key of an observation account at any index
+    ///
+    /// # Arguments
+    ///
+    /// * `self`- The pool to which the account belongs
+    /// * `key` - The address to validated
+    /// * `bump` - The PDA bump for the address
+    /// * `index` - The index of the observation in the oracle array
+    ///
+    pub fn validate_observation_address_at(self, key: &Pubkey, bump: u8, index: u16) -> Result<()> {
+        let seeds = [
+            OBSERVATION_SEED.as_bytes(),
+            self.token_0.as_ref(),
+            self.token_1.as_ref(),
+            &self.fee.to_be_bytes(),
+            &index.to_be_bytes(),
+            &[bump],
+        ];
+        assert!(*key == Pubkey::create_program_address(&seeds, &CyclosCore::id()).unwrap());
+        Ok(())
+    }
+
     /// Validates the public key of a tick account
     ///
     /// # Arguments
@@ -127,7 +191,7 @@ impl PoolState {
                     self.token_0.as_ref(),
                     self.token_1.as_ref(),
                     &self.fee.to_be_bytes(),
-                    &tick.to_be_bytes(),
+                    &tick_seed(tick),
                     &[bump],
                 ],
                 &CyclosCore::id(),
@@ -137,6 +201,45 @@ impl PoolState {
         Ok(())
     }
 
+    /// Loads the tick account for the next initialized tick crossed by a swap
+    ///
+    /// Tick accounts are read from remaining accounts in the order the swap crosses them. The
+    /// next account must be the tick account of this pool for `tick`.
+    ///
+    /// # Arguments
+    ///
+    /// * `self`- The pool being swapped against
+    /// * `remaining_accounts` - Remaining accounts, advanced past the tick account
+    /// * `tick` - The initialized tick being crossed
+    ///
+    pub fn load_next_tick_account<'info>(
+        self,
+        remaining_accounts: &mut std::slice::Iter<AccountInfo<'info>>,
+        tick: i32,
+    ) -> Result<AccountLoader<'info, TickState>> {
+        let tick_loader = remaining_accounts
+            .next()
+            .and_then(|account| AccountLoader::<TickState>::try_from(account).ok())
+            .ok_or(ErrorCode::MissingTickAccount)?;
+        let bump = tick_loader.load()?.bump;
+        let expected_key = Pubkey::create_program_address(
+            &[
+                TICK_SEED.as_bytes(),
+                self.token_0.as_ref(),
+                self.token_1.as_ref(),
+                &self.fee.to_be_bytes(),
+                &tick_seed(tick),
+                &[bump],
+            ],
+            &CyclosCore::id(),
+        );
+        require!(
+            expected_key == Ok(tick_loader.key()),
+            ErrorCode::MissingTickAccount
+        );
+        Ok(tick_loader)
+    }
+
     /// Validates the public key of a bitmap account
     ///
     /// # Arguments
@@ -164,14 +267,17 @@ impl PoolState {
         Ok(())
     }
 
-    /// Validates the public key of a bitmap account
+    /// Validates the public key of a position account, and that the position stores the
+    /// canonical bump of its address
     ///
     /// # Arguments
     ///
     /// * `self`- The pool to which the account belongs
     /// * `key` - The address to validated
-    /// * `bump` - The PDA bump for the address
-    /// * `tick` - The tick from which the address should be derived
+    /// * `bump` - The PDA bump stored in the position
+    /// * `position_owner` - The owner of the position
+    /// * `tick_lower` - The lower tick of the position
+    /// * `tick_upper` - The upper tick of the position
     ///
     pub fn validate_position_address(
         self,
@@ -181,25 +287,190 @@ impl PoolState {
         tick_lower: i32,
         tick_upper: i32,
     ) -> Result<()> {
-        assert!(
-            *key == Pubkey::create_program_address(
-                &[
-                    &POSITION_SEED.as_bytes(),
-                    self.token_0.as_ref(),
-                    self.token_1.as_ref(),
-                    &self.fee.to_be_bytes(),
-                    position_owner.as_ref(),
-                    &tick_lower.to_be_bytes(),
-                    &tick_upper.to_be_bytes(),
-                    &[bump],
-                ],
-                &CyclosCore::id(),
-            )
-            .unwrap(),
+        let (expected_key, canonical_bump) = derive_position_address(
+            self.token_0,
+            self.token_1,
+            self.fee,
+            position_owner,
+            tick_lower,
+            tick_upper,
         );
+        assert!(*key == expected_key);
+        require!(bump == canonical_bump, ErrorCode::InvalidBump);
         Ok(())
     }
 
+    /// Validates the address of an owner's liquidity account in the pool, and that its bump
+    /// is canonical
+    ///
+    /// # Arguments
+    ///
+    /// * `self`- The pool the account belongs to
+    /// * `key` - Address of the owner's liquidity account
+    /// * `bump` - The bump stored in the account
+    /// * `owner` - The owner of the positions
+    ///
+    pub fn validate_owner_liquidity_address(
+        self,
+        key: &Pubkey,
+        bump: u8,
+        owner: &Pubkey,
+    ) -> Result<()> {
+        let (expected_key, canonical_bump) =
+            derive_owner_liquidity_address(self.token_0, self.token_1, self.fee, owner);
+        assert!(*key == expected_key);
+        require!(bump == canonical_bump, ErrorCode::InvalidBump);
+        Ok(())
+    }
+
+    /// Sets the denominator of the protocol's % share of the fees, returning the previous value.
+    /// Protocol fees accrued so far are unaffected.
+    ///
+    /// # Arguments
+    ///
+    /// * `self`- The pool whose protocol fee is set
+    /// * `fee_protocol` - The new denominator, in [4, 10], or 0 to turn off the protocol fee
+    ///
+    pub fn set_fee_protocol(&mut self, fee_protocol: u8) -> Result<u8> {
+        require!(
+            fee_protocol == 0 || (4..=10).contains(&fee_protocol),
+            ErrorCode::InvalidProtocolFee
+        );
+        let fee_protocol_old = self.fee_protocol;
+        self.fee_protocol = fee_protocol;
+        Ok(fee_protocol_old)
+    }
+
+    /// Checks the liquidity held by an owner across positions against the per owner limit
+    ///
+    /// # Arguments
+    ///
+    /// * `self`- The pool in which liquidity is held
+    /// * `liquidity` - The total liquidity of the owner
+    ///
+    pub fn check_liquidity_per_owner(self, liquidity: u64) -> Result<()> {
+        require!(
+            self.max_liquidity_per_owner == 0 || liquidity <= self.max_liquidity_per_owner,
+            ErrorCode::OwnerLimitExceeded
+        );
+        Ok(())
+    }
+
+    /// Checks the width of a position's tick range against the pool's minimum width
+    ///
+    /// # Arguments
+    ///
+    /// * `self`- The pool in which the position is minted
+    /// * `tick_lower` - The lower tick of the position
+    /// * `tick_upper` - The upper tick of the position
+    ///
+    pub fn check_tick_width(self, tick_lower: i32, tick_upper: i32) -> Result<()> {
+        let width = tick_upper.abs_diff(tick_lower) / self.tick_spacing as u32;
+        require!(width >= self.min_tick_width, ErrorCode::RangeTooNarrow);
+        Ok(())
+    }
+
+    /// Distributes rewards to the liquidity currently in range, by growing the global reward
+    /// growth. Rewards rounded away per unit of liquidity stay in the reward vault.
+    ///
+    /// Fails if the rewards per unit of liquidity do not fit in a Q32.32, e.g. for a large
+    /// amount over dust liquidity.
+    ///
+    /// # Arguments
+    ///
+    /// * `self`- The pool receiving the rewards
+    /// * `amount` - The amount of the reward token distributed
+    ///
+    pub fn add_rewards(&mut self, amount: u64) -> Result<()> {
+        require!(self.reward_mint != Pubkey::default(), ErrorCode::RewardMint);
+        require!(self.liquidity > 0, ErrorCode::NoRewardLiquidity);
+        self.reward_growth_global_x32 = self.reward_growth_global_x32.wrapping_add(
+            amount
+                .mul_div_floor(fixed_point_32::Q32, self.liquidity)
+                .ok_or(ErrorCode::RewardGrowthOverflow)?,
+        );
+        Ok(())
+    }
+
+    /// Adds the fees paid by a swap to the pool's lifetime fee totals
+    ///
+    /// # Arguments
+    ///
+    /// * `self`- The pool in which the swap executed
+    /// * `zero_for_one` - The direction of the swap, true for token_0 to token_1
+    /// * `fee_amount` - The fee paid in the input token, including the protocol fee
+    ///
+    pub fn record_swap_fees(&mut self, zero_for_one: bool, fee_amount: u64) {
+        if zero_for_one {
+            self.total_fees_token_0 = self.total_fees_token_0.saturating_add(fee_amount.into());
+        } else {
+            self.total_fees_token_1 = self.total_fees_token_1.saturating_add(fee_amount.into());
+        }
+    }
+
+    /// Returns the value of a swap in the pool's quote token. At the execution price
+    /// |amount_1| / |amount_0|, the leg in the other token is worth exactly the quote token
+    /// leg, which is returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `self`- The pool in which the swap executed
+    /// * `amount_0` - The delta of the token_0 balance of the pool
+    /// * `amount_1` - The delta of the token_1 balance of the pool
+    ///
+    pub fn value_in_quote(&self, amount_0: i64, amount_1: i64) -> u64 {
+        if self.quote_is_token_1 {
+            amount_1.unsigned_abs()
+        } else {
+            amount_0.unsigned_abs()
+        }
+    }
+
+    /// Returns the referral fee skimmed from the output of a swap, rounded down
+    ///
+    /// # Arguments
+    ///
+    /// * `self`- The pool in which the swap executed
+    /// * `amount_out` - The output of the swap paid by the pool
+    /// * `fee_bps` - The referral fee requested by the swap, at most the pool's maximum
+    ///
+    pub fn referral_fee(&self, amount_out: u64, fee_bps: u16) -> Result<u64> {
+        require!(
+            fee_bps <= self.max_referral_fee_bps,
+            ErrorCode::ReferralFeeTooHigh
+        );
+        // the share is capped at MAX_REFERRAL_FEE_BPS of the output, so it cannot overflow
+        Ok(amount_out.mul_div_floor(fee_bps.into(), 10_000).unwrap())
+    }
+
+    /// Redistributes fees forfeited by a position to the liquidity currently in range, by growing
+    /// the global fee growth. Fees rounded away per unit of liquidity stay in the vaults.
+    ///
+    /// Without in range liquidity to receive them, the fees are owed to the protocol instead.
+    ///
+    /// # Arguments
+    ///
+    /// * `self`- The pool receiving the fees
+    /// * `amount_0` - The forfeited fees in token_0
+    /// * `amount_1` - The forfeited fees in token_1
+    ///
+    pub fn redistribute_fees(&mut self, amount_0: u64, amount_1: u64) {
+        let growth = |amount: u64| match self.liquidity {
+            0 => None,
+            liquidity => amount.mul_div_floor(fixed_point_32::Q32, liquidity),
+        };
+        match (growth(amount_0), growth(amount_1)) {
+            (Some(growth_0), Some(growth_1)) => {
+                self.fee_growth_global_0_x32 = self.fee_growth_global_0_x32.wrapping_add(growth_0);
+                self.fee_growth_global_1_x32 = self.fee_growth_global_1_x32.wrapping_add(growth_1);
+            }
+            _ => {
+                self.protocol_fees_token_0 = self.protocol_fees_token_0.saturating_add(amount_0);
+                self.protocol_fees_token_1 = self.protocol_fees_token_1.saturating_add(amount_1);
+            }
+        }
+    }
+
     /// Returns a snapshot of the tick cumulative, seconds per liquidity and seconds inside a tick range
     ///
     /// Snapshots must only be compared to other snapshots, taken over a period for which a position existed.
@@ -220,8 +491,9 @@ impl PoolState {
     ) -> SnapshotCumulative {
         if self.tick < lower.tick {
             SnapshotCumulative {
-                tick_cumulative_inside: lower.tick_cumulative_outside
-                    - upper.tick_cumulative_outside,
+                tick_cumulative_inside: lower
+                    .tick_cumulative_outside
+                    .wrapping_sub(upper.tick_cumulative_outside),
                 seconds_per_liquidity_inside_x32: lower.seconds_per_liquidity_outside_x32
                     - upper.seconds_per_liquidity_outside_x32,
                 seconds_inside: lower.seconds_outside - upper.seconds_outside,
@@ -240,8 +512,8 @@ impl PoolState {
 
             SnapshotCumulative {
                 tick_cumulative_inside: tick_cumulative
-                    - lower.tick_cumulative_outside
-                    - upper.tick_cumulative_outside,
+                    .wrapping_sub(lower.tick_cumulative_outside)
+                    .wrapping_sub(upper.tick_cumulative_outside),
                 seconds_per_liquidity_inside_x32: seconds_per_liquidity_cumulative_x32
                     - lower.seconds_per_liquidity_outside_x32
                     - upper.seconds_per_liquidity_outside_x32,
@@ -249,8 +521,9 @@ impl PoolState {
             }
         } else {
             SnapshotCumulative {
-                tick_cumulative_inside: upper.tick_cumulative_outside
-                    - lower.tick_cumulative_outside,
+                tick_cumulative_inside: upper
+                    .tick_cumulative_outside
+                    .wrapping_sub(lower.tick_cumulative_outside),
                 seconds_per_liquidity_inside_x32: upper.seconds_per_liquidity_outside_x32
                     - lower.seconds_per_liquidity_outside_x32,
                 seconds_inside: upper.seconds_outside - lower.seconds_outside,
@@ -259,6 +532,46 @@ impl PoolState {
     }
 }
 
+/// Sorts a token pair into the canonical pool order, where token_0 address < token_1 address.
+/// Returns the sorted pair and whether the inputs were swapped.
+///
+/// The flag maps user order to swap direction: if `a` is the input token, the swap is
+/// zero for one when the flag is false.
+///
+/// # Arguments
+///
+/// * `a` - A token mint address
+/// * `b` - The other token mint address
+///
+pub fn sort_tokens(a: Pubkey, b: Pubkey) -> (Pubkey, Pubkey, bool) {
+    if a < b {
+        (a, b, false)
+    } else {
+        (b, a, true)
+    }
+}
+
+/// Derives the pool address and bump for a token pair and fee. Tokens can be passed in any order.
+///
+/// # Arguments
+///
+/// * `token_a` - A token mint address of the pool
+/// * `token_b` - The other token mint address of the pool
+/// * `fee` - The fee tier of the pool, denominated in hundredths of a bip
+///
+pub fn derive_pool_address(token_a: Pubkey, token_b: Pubkey, fee: u32) -> (Pubkey, u8) {
+    let (token_0, token_1, _) = sort_tokens(token_a, token_b);
+    Pubkey::find_program_address(
+        &[
+            POOL_SEED.as_bytes(),
+            token_0.as_ref(),
+            token_1.as_ref(),
+            &fee.to_be_bytes(),
+        ],
+        &CyclosCore::id(),
+    )
+}
+
 /// A snapshot of the tick cumulative, seconds per liquidity and seconds inside a tick range
 pub struct SnapshotCumulative {
     /// The snapshot of the tick accumulator for the range.
@@ -324,6 +637,164 @@ pub struct CollectProtocolEvent {
     pub amount_1: u64,
 }
 
+/// Emitted when the factory owner recovers tokens sent to a pool by mistake
+#[event]
+pub struct RecoverTokenEvent {
+    /// The pool from where the tokens are recovered
+    #[index]
+    pub pool_state: Pubkey,
+
+    /// The mint of the recovered token
+    pub mint: Pubkey,
+
+    /// The token account that receives the recovered tokens
+    pub recipient: Pubkey,
+
+    /// The amount of tokens recovered
+    pub amount: u64,
+}
+
+/// Emitted when the factory owner changes the protocol fee of a pool
+#[event]
+pub struct SetPoolFeeProtocolEvent {
+    /// The pool for which the protocol fee is changed
+    #[index]
+    pub pool_state: Pubkey,
+
+    /// The previous value of the protocol fee denominator
+    pub fee_protocol_old: u8,
+
+    /// The updated value of the protocol fee denominator
+    pub fee_protocol: u8,
+}
+
+/// Emitted when the factory owner changes the liquidity limit per owner of a pool
+#[event]
+pub struct SetMaxLiquidityPerOwnerEvent {
+    /// The pool for which the limit is changed
+    #[index]
+    pub pool_state: Pubkey,
+
+    /// The previous limit, zero if there was no limit
+    pub max_liquidity_per_owner_old: u64,
+
+    /// The updated limit, zero for no limit
+    pub max_liquidity_per_owner: u64,
+}
+
+/// Emitted when the factory owner changes the minimum tick width of a pool
+#[event]
+pub struct SetMinTickWidthEvent {
+    /// The pool for which the minimum is changed
+    #[index]
+    pub pool_state: Pubkey,
+
+    /// The previous minimum width, zero if there was no minimum
+    pub min_tick_width_old: u32,
+
+    /// The updated minimum width, zero for no minimum
+    pub min_tick_width: u32,
+}
+
+/// Emitted when the factory owner changes the minimum holding period of a pool
+#[event]
+pub struct SetMinHoldingSlotsEvent {
+    /// The pool for which the holding period is changed
+    #[index]
+    pub pool_state: Pubkey,
+
+    /// The previous holding period in slots, zero if there was none
+    pub min_holding_slots_old: u64,
+
+    /// The updated holding period in slots, zero for none
+    pub min_holding_slots: u64,
+}
+
+/// Emitted when the factory owner changes the maximum referral fee of a pool
+#[event]
+pub struct SetMaxReferralFeeEvent {
+    /// The pool for which the maximum was set
+    #[index]
+    pub pool_state: Pubkey,
+
+    /// The previous maximum in basis points, zero if referrals were disabled
+    pub max_referral_fee_bps_old: u16,
+
+    /// The updated maximum in basis points, zero to disable referrals
+    pub max_referral_fee_bps: u16,
+}
+
+/// A referral fee skimmed from the output of a swap, for aggregators routing the swap
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Referral {
+    /// The token account of the output token receiving the fee. It must be passed
+    /// in remaining accounts
+    pub token_account: Pubkey,
+
+    /// The fee in basis points of the output, at most the pool's maximum referral fee
+    pub fee_bps: u16,
+}
+
+/// Emitted when the factory owner changes the quote token of a pool
+#[event]
+pub struct SetQuoteTokenEvent {
+    /// The pool for which the quote token was set
+    #[index]
+    pub pool_state: Pubkey,
+
+    /// Whether swap values are now denominated in token_1, else in token_0
+    pub quote_is_token_1: bool,
+}
+
+/// Emitted when a swap pays a referral fee out of its output
+#[event]
+pub struct ReferralFeeEvent {
+    /// The pool in which the swap executed
+    #[index]
+    pub pool_state: Pubkey,
+
+    /// The token account receiving the referral fee
+    #[index]
+    pub referral_token_account: Pubkey,
+
+    /// The referral fee in basis points of the output
+    pub fee_bps: u16,
+
+    /// The amount of the output token paid to the referral account
+    pub amount: u64,
+}
+
+/// Emitted when the reward token of a pool is set
+#[event]
+pub struct SetRewardMintEvent {
+    /// The pool distributing the rewards
+    #[index]
+    pub pool_state: Pubkey,
+
+    /// The reward token
+    pub reward_mint: Pubkey,
+}
+
+/// Emitted when rewards are distributed to the in range liquidity of a pool
+#[event]
+pub struct AddRewardsEvent {
+    /// The pool distributing the rewards
+    #[index]
+    pub pool_state: Pubkey,
+
+    /// The address that paid the rewards
+    pub funder: Pubkey,
+
+    /// The amount of the reward token distributed
+    pub amount: u64,
+
+    /// The in range liquidity receiving the rewards
+    pub liquidity: u64,
+
+    /// The global reward growth after the rewards are added
+    pub reward_growth_global_x32: u64,
+}
+
 /// Emitted by when a swap is performed for a pool
 #[event]
 pub struct SwapEvent {
@@ -359,4 +830,799 @@ pub struct SwapEvent {
 
     /// The log base 1.0001 of price of the pool after the swap
     pub tick: i32,
+
+    /// The value of the swap in the pool's quote token, as by #value_in_quote
+    pub value_in_quote: u64,
+}
+
+/// Emitted when a swap fee update wraps a global fee growth accumulator past zero
+///
+/// Fee growth is only meaningful as a difference mod 2^64. Indexers that track absolute values
+/// must account for the wrap to compute fees correctly.
+#[event]
+pub struct FeeGrowthRolloverEvent {
+    /// The pool whose fee growth wrapped
+    #[index]
+    pub pool_state: Pubkey,
+
+    /// The mint of the token whose fee growth wrapped
+    pub token: Pubkey,
+
+    /// The fee growth per unit of liquidity after the wrap, as a Q32.32
+    pub new_value: u64,
+}
+
+/// Decodes the net token deltas of the pool from a swap event, as (token_0_delta, token_1_delta)
+///
+/// A positive delta is paid into the pool, and a negative delta is paid out of the pool.
+/// The input token of a swap therefore has a positive delta, and the output token a negative one.
+///
+/// # Arguments
+///
+/// * `event` - The emitted swap event
+///
+pub fn net_deltas(event: &SwapEvent) -> (i128, i128) {
+    (i128::from(event.amount_0), i128::from(event.amount_1))
+}
+
+/// Returns the amount credited to a swap for a transfer into the input vault, measured from
+/// the vault balance. This is less than the amount sent for tokens that take a fee on transfer.
+///
+/// # Arguments
+///
+/// * `balance_before` - The vault balance before the transfer
+/// * `balance_after` - The vault balance after the transfer
+///
+pub fn received_amount_in(balance_before: u64, balance_after: u64) -> Result<i64> {
+    let received = balance_after.saturating_sub(balance_before);
+    require!(received > 0, ErrorCode::IIA);
+    Ok(i64::try_from(received).unwrap())
+}
+
+/// Adds to a global fee growth accumulator, wrapping past u64::MAX
+///
+/// Returns the new fee growth and whether it wrapped past zero.
+///
+/// # Arguments
+///
+/// * `fee_growth_global_x32` - The fee growth per unit of liquidity before the update
+/// * `fee_growth_delta_x32` - The fee growth per unit of liquidity earned by the swap step
+///
+pub fn add_fee_growth(fee_growth_global_x32: u64, fee_growth_delta_x32: u64) -> (u64, bool) {
+    let fee_growth_next_x32 = fee_growth_global_x32.wrapping_add(fee_growth_delta_x32);
+    (
+        fee_growth_next_x32,
+        fee_growth_next_x32 < fee_growth_global_x32,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    mod sort_tokens {
+        use super::*;
+
+        #[test]
+        fn keeps_sorted_inputs() {
+            let a = Pubkey::new_from_array([1; 32]);
+            let b = Pubkey::new_from_array([2; 32]);
+            assert_eq!(sort_tokens(a, b), (a, b, false));
+        }
+
+        #[test]
+        fn sorts_reversed_inputs_and_sets_flag() {
+            let a = Pubkey::new_from_array([1; 32]);
+            let b = Pubkey::new_from_array([2; 32]);
+            assert_eq!(sort_tokens(b, a), (a, b, true));
+        }
+    }
+
+    mod derive_pool_address {
+        use super::*;
+
+        #[test]
+        fn reversed_inputs_map_to_the_same_pool() {
+            let a = Pubkey::new_from_array([1; 32]);
+            let b = Pubkey::new_from_array([2; 32]);
+            assert_eq!(
+                derive_pool_address(a, b, 500),
+                derive_pool_address(b, a, 500)
+            );
+        }
+
+        #[test]
+        fn matches_pool_seeds() {
+            let a = Pubkey::new_from_array([1; 32]);
+            let b = Pubkey::new_from_array([2; 32]);
+            let expected = Pubkey::find_program_address(
+                &[
+                    POOL_SEED.as_bytes(),
+                    a.as_ref(),
+                    b.as_ref(),
+                    &500_u32.to_be_bytes(),
+                ],
+                &CyclosCore::id(),
+            );
+            assert_eq!(derive_pool_address(b, a, 500), expected);
+        }
+
+        #[test]
+        fn fee_tiers_map_to_different_pools() {
+            let a = Pubkey::new_from_array([1; 32]);
+            let b = Pubkey::new_from_array([2; 32]);
+            assert_ne!(
+                derive_pool_address(a, b, 500),
+                derive_pool_address(a, b, 3000)
+            );
+        }
+    }
+
+    mod check_liquidity_per_owner {
+        use super::*;
+
+        fn pool_with_limit(max_liquidity_per_owner: u64) -> PoolState {
+            PoolState {
+                max_liquidity_per_owner,
+                ..Default::default()
+            }
+        }
+
+        #[test]
+        fn allows_any_liquidity_without_limit() {
+            assert!(pool_with_limit(0)
+                .check_liquidity_per_owner(u64::MAX)
+                .is_ok());
+        }
+
+        #[test]
+        fn allows_liquidity_at_the_limit() {
+            assert!(pool_with_limit(1000).check_liquidity_per_owner(999).is_ok());
+            assert!(pool_with_limit(1000)
+                .check_liquidity_per_owner(1000)
+                .is_ok());
+        }
+
+        #[test]
+        fn rejects_liquidity_above_the_limit() {
+            assert!(pool_with_limit(1000)
+                .check_liquidity_per_owner(1001)
+                .is_err());
+        }
+    }
+
+    mod check_tick_width {
+        use super::*;
+
+        fn pool_with_min_width(min_tick_width: u32) -> PoolState {
+            PoolState {
+                tick_spacing: 10,
+                min_tick_width,
+                ..Default::default()
+            }
+        }
+
+        #[test]
+        fn allows_any_width_without_minimum() {
+            assert!(pool_with_min_width(0).check_tick_width(0, 10).is_ok());
+        }
+
+        #[test]
+        fn allows_ranges_at_the_minimum_width() {
+            assert!(pool_with_min_width(3).check_tick_width(0, 30).is_ok());
+            assert!(pool_with_min_width(3).check_tick_width(-20, 10).is_ok());
+            assert!(pool_with_min_width(3).check_tick_width(-100, 100).is_ok());
+        }
+
+        #[test]
+        fn rejects_ranges_below_the_minimum_width() {
+            for (tick_lower, tick_upper) in [(0, 20), (-10, 10), (-30, -10)] {
+                match pool_with_min_width(3)
+                    .check_tick_width(tick_lower, tick_upper)
+                    .err()
+                    .unwrap()
+                {
+                    anchor_lang::error::Error::AnchorError(error) => assert_eq!(
+                        error.error_code_number,
+                        u32::from(ErrorCode::RangeTooNarrow)
+                    ),
+                    error => panic!("unexpected error {}", error),
+                }
+            }
+        }
+    }
+
+    mod add_rewards {
+        use super::*;
+
+        fn assert_error(result: Result<()>, code: ErrorCode) {
+            match result.err().unwrap() {
+                anchor_lang::error::Error::AnchorError(error) => {
+                    assert_eq!(error.error_code_number, u32::from(code))
+                }
+                error => panic!("unexpected error {}", error),
+            }
+        }
+
+        fn rewarded_pool(liquidity: u64) -> PoolState {
+            PoolState {
+                reward_mint: Pubkey::new_unique(),
+                liquidity,
+                ..Default::default()
+            }
+        }
+
+        #[test]
+        fn grows_rewards_per_unit_of_in_range_liquidity() {
+            let mut pool = rewarded_pool(4 << 32);
+            pool.add_rewards(1 << 32).unwrap();
+            assert_eq!({ pool.reward_growth_global_x32 }, 1 << 30);
+            pool.add_rewards(3).unwrap();
+            assert_eq!({ pool.reward_growth_global_x32 }, 1 << 30);
+            pool.add_rewards(3 << 32).unwrap();
+            assert_eq!({ pool.reward_growth_global_x32 }, 1 << 32);
+        }
+
+        #[test]
+        fn wraps_the_global_reward_growth() {
+            let mut pool = PoolState {
+                reward_growth_global_x32: u64::MAX,
+                ..rewarded_pool(1 << 32)
+            };
+            pool.add_rewards(2).unwrap();
+            assert_eq!({ pool.reward_growth_global_x32 }, 1);
+        }
+
+        #[test]
+        fn rejects_a_pool_without_a_reward_mint() {
+            let mut pool = PoolState {
+                reward_mint: Pubkey::default(),
+                ..rewarded_pool(1 << 32)
+            };
+            assert_error(pool.add_rewards(1), ErrorCode::RewardMint);
+        }
+
+        #[test]
+        fn rejects_a_pool_without_in_range_liquidity() {
+            let mut pool = rewarded_pool(0);
+            assert_error(pool.add_rewards(1), ErrorCode::NoRewardLiquidity);
+        }
+
+        #[test]
+        fn rejects_rewards_overflowing_the_growth_per_unit_of_liquidity() {
+            let mut pool = rewarded_pool(1);
+            assert_error(pool.add_rewards(1 << 32), ErrorCode::RewardGrowthOverflow);
+            pool.add_rewards(u32::MAX as u64).unwrap();
+        }
+    }
+
+    mod record_swap_fees {
+        use super::*;
+
+        #[test]
+        fn sums_fees_across_swaps() {
+            let mut pool = PoolState::default();
+            // the swap fees in token_0 sum past u64::MAX
+            let swaps = [(true, 3u64), (false, 5), (true, u64::MAX), (true, 7)];
+            for (zero_for_one, fee_amount) in swaps {
+                pool.record_swap_fees(zero_for_one, fee_amount);
+            }
+            assert_eq!(
+                { pool.total_fees_token_0 },
+                swaps
+                    .iter()
+                    .filter(|(zero_for_one, _)| *zero_for_one)
+                    .map(|(_, fee_amount)| u128::from(*fee_amount))
+                    .sum::<u128>()
+            );
+            assert_eq!({ pool.total_fees_token_1 }, 5);
+        }
+    }
+
+    mod value_in_quote {
+        use super::*;
+
+        /// The value of the token_0 leg at the execution price, in token_1
+        fn value_of_token_0(amount_0: i64, amount_1: i64) -> u64 {
+            let price_x32 = (amount_1.unsigned_abs() << 32) / amount_0.unsigned_abs();
+            (u128::from(amount_0.unsigned_abs()) * u128::from(price_x32) >> 32) as u64
+        }
+
+        #[test]
+        fn values_swaps_in_either_token() {
+            // token_0 trades at 2 token_1
+            for (amount_0, amount_1) in [(1000, -2000), (-1000, 2000)] {
+                let mut pool = PoolState {
+                    quote_is_token_1: true,
+                    ..Default::default()
+                };
+                assert_eq!(
+                    pool.value_in_quote(amount_0, amount_1),
+                    value_of_token_0(amount_0, amount_1)
+                );
+
+                // the token_1 leg, valued in token_0
+                pool.quote_is_token_1 = false;
+                assert_eq!(
+                    pool.value_in_quote(amount_0, amount_1),
+                    value_of_token_0(amount_1, amount_0)
+                );
+                assert_eq!(pool.value_in_quote(amount_0, amount_1), 1000);
+            }
+        }
+
+        #[test]
+        fn handles_extreme_amounts() {
+            let pool = PoolState {
+                quote_is_token_1: true,
+                ..Default::default()
+            };
+            assert_eq!(pool.value_in_quote(1, i64::MIN), 1 << 63);
+        }
+    }
+
+    mod referral_fee {
+        use super::*;
+
+        #[test]
+        fn skims_the_requested_share_of_the_output() {
+            let pool = PoolState {
+                max_referral_fee_bps: 50,
+                ..Default::default()
+            };
+            assert_eq!(pool.referral_fee(1_000_000, 50).unwrap(), 5_000);
+            assert_eq!(pool.referral_fee(1_000_000, 0).unwrap(), 0);
+            // rounds down, in favor of the recipient
+            assert_eq!(pool.referral_fee(199, 50).unwrap(), 0);
+            assert_eq!(pool.referral_fee(u64::MAX, 50).unwrap(), u64::MAX / 200);
+        }
+
+        #[test]
+        fn fails_above_the_pool_maximum() {
+            let mut pool = PoolState::default();
+            // referrals are disabled by default
+            assert!(pool.referral_fee(1_000_000, 1).is_err());
+
+            pool.max_referral_fee_bps = 50;
+            match pool.referral_fee(1_000_000, 51).err().unwrap() {
+                anchor_lang::error::Error::AnchorError(e) => assert_eq!(
+                    e.error_code_number,
+                    u32::from(ErrorCode::ReferralFeeTooHigh)
+                ),
+                e => panic!("unexpected error {}", e),
+            }
+        }
+    }
+
+    mod redistribute_fees {
+        use super::*;
+
+        #[test]
+        fn grows_fees_of_in_range_liquidity() {
+            let mut pool = PoolState {
+                liquidity: 4 << 32,
+                fee_growth_global_0_x32: u64::MAX,
+                ..Default::default()
+            };
+            pool.redistribute_fees(2, 4 << 32);
+            // a half unit of token_0 per unit of liquidity rounds down to zero
+            assert_eq!(
+                ({ pool.fee_growth_global_0_x32 }, {
+                    pool.fee_growth_global_1_x32
+                }),
+                (u64::MAX, 1 << 32)
+            );
+            assert_eq!(
+                ({ pool.protocol_fees_token_0 }, {
+                    pool.protocol_fees_token_1
+                }),
+                (0, 0)
+            );
+        }
+
+        #[test]
+        fn owes_fees_to_the_protocol_without_in_range_liquidity() {
+            let mut pool = PoolState::default();
+            pool.redistribute_fees(3, 5);
+            assert_eq!(
+                ({ pool.protocol_fees_token_0 }, {
+                    pool.protocol_fees_token_1
+                }),
+                (3, 5)
+            );
+            assert_eq!(
+                ({ pool.fee_growth_global_0_x32 }, {
+                    pool.fee_growth_global_1_x32
+                }),
+                (0, 0)
+            );
+        }
+    }
+
+    mod net_deltas {
+        use super::*;
+
+        fn swap_event(amount_0: i64, amount_1: i64) -> SwapEvent {
+            SwapEvent {
+                pool_state: Pubkey::default(),
+                sender: Pubkey::default(),
+                token_account_0: Pubkey::default(),
+                token_account_1: Pubkey::default(),
+                amount_0,
+                amount_1,
+                sqrt_price_x32: 0,
+                liquidity: 0,
+                tick: 0,
+                value_in_quote: 0,
+            }
+        }
+
+        #[test]
+        fn zero_for_one_pays_token_0_in_and_token_1_out() {
+            assert_eq!(net_deltas(&swap_event(1000, -996)), (1000, -996));
+        }
+
+        #[test]
+        fn one_for_zero_pays_token_1_in_and_token_0_out() {
+            assert_eq!(net_deltas(&swap_event(-996, 1000)), (-996, 1000));
+        }
+
+        #[test]
+        fn handles_extreme_amounts() {
+            assert_eq!(
+                net_deltas(&swap_event(i64::MAX, i64::MIN)),
+                (i64::MAX as i128, i64::MIN as i128)
+            );
+        }
+    }
+
+    mod set_fee_protocol {
+        use super::*;
+
+        #[test]
+        fn sets_a_valid_value() {
+            let mut pool = PoolState {
+                fee_protocol: 3,
+                ..Default::default()
+            };
+            assert_eq!(pool.set_fee_protocol(6).unwrap(), 3);
+            assert_eq!(pool.fee_protocol, 6);
+            assert_eq!(pool.set_fee_protocol(0).unwrap(), 6);
+            assert_eq!(pool.fee_protocol, 0);
+        }
+
+        #[test]
+        fn accepts_the_bounds() {
+            let mut pool = PoolState::default();
+            assert!(pool.set_fee_protocol(4).is_ok());
+            assert!(pool.set_fee_protocol(10).is_ok());
+        }
+
+        #[test]
+        fn rejects_out_of_range_values() {
+            let mut pool = PoolState {
+                fee_protocol: 5,
+                ..Default::default()
+            };
+            for fee_protocol in [1, 3, 11, u8::MAX] {
+                assert!(pool.set_fee_protocol(fee_protocol).is_err());
+            }
+            assert_eq!(pool.fee_protocol, 5);
+        }
+
+        #[test]
+        fn keeps_accrued_protocol_fees() {
+            let mut pool = PoolState {
+                fee_protocol: 4,
+                protocol_fees_token_0: 100,
+                protocol_fees_token_1: 200,
+                ..Default::default()
+            };
+            pool.set_fee_protocol(10).unwrap();
+            assert_eq!(
+                (pool.protocol_fees_token_0, pool.protocol_fees_token_1),
+                (100, 200)
+            );
+        }
+    }
+
+    mod received_amount_in {
+        use super::*;
+        use crate::libraries::{swap_math, test_utils::encode_price_sqrt_x32, tick_math};
+
+        /// A token which burns a share of every transfer
+        struct MockTransferFeeToken {
+            fee_bps: u64,
+        }
+
+        impl MockTransferFeeToken {
+            fn transfer(&self, balance: &mut u64, amount: u64) {
+                *balance += amount - amount * self.fee_bps / 10_000;
+            }
+        }
+
+        #[test]
+        fn credits_the_received_amount_of_a_transfer_fee_token() {
+            let token = MockTransferFeeToken { fee_bps: 100 };
+            let mut vault_balance = 5_000;
+            let balance_before = vault_balance;
+            token.transfer(&mut vault_balance, 10_000);
+
+            let amount_in = received_amount_in(balance_before, vault_balance).unwrap();
+            assert_eq!(amount_in, 9_900);
+
+            // the swap consumes exactly the received input, including fees
+            let step = swap_math::compute_swap_step(
+                encode_price_sqrt_x32(1, 1),
+                tick_math::MIN_SQRT_RATIO + 1,
+                1 << 40,
+                amount_in,
+                3000,
+            );
+            assert_eq!(step.amount_in + step.fee_amount, 9_900);
+        }
+
+        #[test]
+        fn credits_the_full_amount_of_a_regular_token() {
+            let token = MockTransferFeeToken { fee_bps: 0 };
+            let mut vault_balance = 0;
+            token.transfer(&mut vault_balance, 10_000);
+            assert_eq!(received_amount_in(0, vault_balance).unwrap(), 10_000);
+        }
+
+        #[test]
+        fn fails_if_nothing_is_received() {
+            assert!(received_amount_in(5_000, 5_000).is_err());
+            assert!(received_amount_in(5_000, 4_000).is_err());
+
+            // the whole transfer is taken as fee
+            let token = MockTransferFeeToken { fee_bps: 10_000 };
+            let mut vault_balance = 5_000;
+            token.transfer(&mut vault_balance, 10_000);
+            assert!(received_amount_in(5_000, vault_balance).is_err());
+        }
+    }
+
+    mod load_next_tick_account {
+        use super::*;
+        use anchor_lang::Discriminator;
+
+        struct TickAccount {
+            key: Pubkey,
+            lamports: u64,
+            data: Vec<u8>,
+        }
+
+        fn pool_of(token_1: u8) -> PoolState {
+            PoolState {
+                token_0: Pubkey::new_from_array([1; 32]),
+                token_1: Pubkey::new_from_array([token_1; 32]),
+                fee: 500,
+                ..Default::default()
+            }
+        }
+
+        fn tick_account(pool: &PoolState, tick: i32) -> TickAccount {
+            let (key, bump) = Pubkey::find_program_address(
+                &[
+                    TICK_SEED.as_bytes(),
+                    pool.token_0.as_ref(),
+                    pool.token_1.as_ref(),
+                    &{ pool.fee }.to_be_bytes(),
+                    &tick.to_be_bytes(),
+                ],
+                &CyclosCore::id(),
+            );
+            let tick_state = TickState {
+                bump,
+                tick,
+                ..Default::default()
+            };
+            let mut data = TickState::discriminator().to_vec();
+            data.extend_from_slice(anchor_lang::__private::bytemuck::bytes_of(&tick_state));
+            TickAccount {
+                key,
+                lamports: 0,
+                data,
+            }
+        }
+
+        fn account_infos(accounts: &mut [TickAccount]) -> Vec<AccountInfo<'_>> {
+            accounts
+                .iter_mut()
+                .map(|account| {
+                    AccountInfo::new(
+                        &account.key,
+                        false,
+                        true,
+                        &mut account.lamports,
+                        &mut account.data,
+                        &crate::ID,
+                        false,
+                        0,
+                    )
+                })
+                .collect()
+        }
+
+        fn assert_missing(result: Result<AccountLoader<'_, TickState>>) {
+            match result.err().unwrap() {
+                anchor_lang::error::Error::AnchorError(error) => assert_eq!(
+                    error.error_code_number,
+                    u32::from(ErrorCode::MissingTickAccount)
+                ),
+                error => panic!("unexpected error {}", error),
+            }
+        }
+
+        #[test]
+        fn loads_ticks_in_crossing_order() {
+            let pool = pool_of(2);
+            let mut accounts = [tick_account(&pool, 20), tick_account(&pool, -40)];
+            let infos = account_infos(&mut accounts);
+            let mut remaining_accounts = infos.iter();
+
+            for tick in [20, -40] {
+                let tick_loader = pool
+                    .load_next_tick_account(&mut remaining_accounts, tick)
+                    .unwrap();
+                assert_eq!({ tick_loader.load().unwrap().tick }, tick);
+            }
+            assert!(remaining_accounts.next().is_none());
+        }
+
+        #[test]
+        fn rejects_a_missing_tick_account() {
+            let pool = pool_of(2);
+            let mut accounts = [tick_account(&pool, 20)];
+            let infos = account_infos(&mut accounts);
+            let mut remaining_accounts = infos.iter();
+
+            pool.load_next_tick_account(&mut remaining_accounts, 20)
+                .unwrap();
+            assert_missing(pool.load_next_tick_account(&mut remaining_accounts, -40));
+        }
+
+        #[test]
+        fn rejects_out_of_order_tick_accounts() {
+            let pool = pool_of(2);
+            let mut accounts = [tick_account(&pool, -40), tick_account(&pool, 20)];
+            let infos = account_infos(&mut accounts);
+            let mut remaining_accounts = infos.iter();
+
+            assert_missing(pool.load_next_tick_account(&mut remaining_accounts, 20));
+        }
+
+        #[test]
+        fn rejects_a_tick_account_of_another_pool() {
+            let pool = pool_of(2);
+            let mut accounts = [tick_account(&pool_of(3), 20)];
+            let infos = account_infos(&mut accounts);
+            let mut remaining_accounts = infos.iter();
+
+            assert_missing(pool.load_next_tick_account(&mut remaining_accounts, 20));
+        }
+
+        #[test]
+        fn rejects_an_account_that_is_not_a_tick() {
+            let pool = pool_of(2);
+            let mut accounts = [tick_account(&pool, 20)];
+            accounts[0].data[..8].copy_from_slice(&PoolState::discriminator());
+            let infos = account_infos(&mut accounts);
+            let mut remaining_accounts = infos.iter();
+
+            assert_missing(pool.load_next_tick_account(&mut remaining_accounts, 20));
+        }
+    }
+
+    mod add_fee_growth {
+        use super::*;
+
+        #[test]
+        fn adds_without_wrapping() {
+            assert_eq!(add_fee_growth(100, 50), (150, false));
+            assert_eq!(add_fee_growth(u64::MAX - 50, 50), (u64::MAX, false));
+            assert_eq!(add_fee_growth(u64::MAX, 0), (u64::MAX, false));
+        }
+
+        #[test]
+        fn detects_a_wrap_past_zero() {
+            assert_eq!(add_fee_growth(u64::MAX, 1), (0, true));
+            assert_eq!(add_fee_growth(u64::MAX - 10, 20), (9, true));
+        }
+
+        #[test]
+        fn rollover_is_signalled_once_across_swap_steps() {
+            // fee growth of a swap crossing several ticks, starting just below the wrap
+            let mut fee_growth_global_x32 = u64::MAX - (5 << 32);
+            let mut rollovers = Vec::new();
+            for fee_growth_delta_x32 in [2 << 32, 2 << 32, 2 << 32, 2 << 32, 0, 2 << 32] {
+                let (fee_growth_next_x32, wrapped) =
+                    add_fee_growth(fee_growth_global_x32, fee_growth_delta_x32);
+                if wrapped {
+                    rollovers.push(fee_growth_next_x32);
+                }
+                fee_growth_global_x32 = fee_growth_next_x32;
+            }
+            assert_eq!(rollovers, [(1 << 32) - 1]);
+            assert_eq!(fee_growth_global_x32, (5 << 32) - 1);
+        }
+    }
+
+    mod validate_position_address {
+        use super::*;
+        use crate::states::position::POSITION_SEED;
+
+        fn position_address(pool: &PoolState, owner: &Pubkey) -> (Pubkey, u8) {
+            Pubkey::find_program_address(
+                &[
+                    POSITION_SEED.as_bytes(),
+                    pool.token_0.as_ref(),
+                    pool.token_1.as_ref(),
+                    &{ pool.fee }.to_be_bytes(),
+                    owner.as_ref(),
+                    &0_i32.to_be_bytes(),
+                    &10_i32.to_be_bytes(),
+                ],
+                &CyclosCore::id(),
+            )
+        }
+
+        fn pool() -> PoolState {
+            PoolState {
+                token_0: Pubkey::new_from_array([1; 32]),
+                token_1: Pubkey::new_from_array([2; 32]),
+                fee: 500,
+                ..Default::default()
+            }
+        }
+
+        #[test]
+        fn accepts_the_canonical_bump() {
+            let pool = pool();
+            let owner = Pubkey::new_from_array([3; 32]);
+            let (key, bump) = position_address(&pool, &owner);
+            pool.validate_position_address(&key, bump, &owner, 0, 10)
+                .unwrap();
+        }
+
+        #[test]
+        fn rejects_a_mutated_bump() {
+            let pool = pool();
+            let owner = Pubkey::new_from_array([3; 32]);
+            let (key, bump) = position_address(&pool, &owner);
+            for mutated_bump in [0, bump.wrapping_sub(1), bump.wrapping_add(1)] {
+                match pool
+                    .validate_position_address(&key, mutated_bump, &owner, 0, 10)
+                    .err()
+                    .unwrap()
+                {
+                    anchor_lang::error::Error::AnchorError(error) => {
+                        assert_eq!(error.error_code_number, u32::from(ErrorCode::InvalidBump))
+                    }
+                    error => panic!("unexpected error {}", error),
+                }
+            }
+        }
+
+        #[test]
+        #[should_panic]
+        fn rejects_the_address_of_another_owner() {
+            let pool = pool();
+            let owner = Pubkey::new_from_array([3; 32]);
+            let (key, bump) = position_address(&pool, &Pubkey::new_from_array([4; 32]));
+            pool.validate_position_address(&key, bump, &owner, 0, 10)
+                .unwrap();
+        }
+
+        #[test]
+        fn accepts_positions_below_zero() {
+            let pool = pool();
+            let owner = Pubkey::new_from_array([3; 32]);
+            let (key, bump) =
+                derive_position_address(pool.token_0, pool.token_1, 500, &owner, -20, -10);
+            pool.validate_position_address(&key, bump, &owner, -20, -10)
+                .unwrap();
+        }
+    }
 }
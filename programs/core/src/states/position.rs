@@ -1,16 +1,37 @@
 use crate::libraries::full_math::MulDiv;
 use crate::{
     error::ErrorCode,
-    libraries::{fixed_point_32, liquidity_math},
+    libraries::{big_num::U256, fixed_point_32, liquidity_amounts, liquidity_math, tick_math},
+    states::{pool::PoolState, tick::tick_seed},
 };
 ///! Positions represent an owner address' liquidity between a lower and upper tick boundary
 ///! Positions store additional state for tracking fees owed to the position
 ///!
 use anchor_lang::prelude::*;
+use std::cell::Ref;
 
 /// Seed to derive account address and signature
 pub const POSITION_SEED: &str = "ps";
 
+/// The most positions a batched instruction processes in one call, bounding the remaining
+/// accounts it iterates over
+pub const MAX_BATCH: usize = 16;
+
+/// Current layout version of position accounts
+pub const POSITION_VERSION: u8 = 3;
+
+/// Size of a position account created before the version field was added,
+/// including the 8 byte discriminator
+pub const POSITION_LEN_V0: usize = POSITION_LEN_V1 - 1;
+
+/// Size of a version 1 position account, which has no reward fields,
+/// including the 8 byte discriminator
+pub const POSITION_LEN_V1: usize = POSITION_LEN_V2 - 16;
+
+/// Size of a version 2 position account, which has no mint slot,
+/// including the 8 byte discriminator
+pub const POSITION_LEN_V2: usize = 8 + std::mem::size_of::<PositionState>() - 8;
+
 /// Info stored for each user's position
 ///
 /// PDA of `[POSITION_SEED, token_0, token_1, fee, owner, tick_lower, tick_upper]`
@@ -36,10 +57,74 @@ pub struct PositionState {
 
     /// The fees owed to the position owner in token_1
     pub tokens_owed_1: u64,
+
+    /// Layout version of the account. Accounts created before versioning
+    /// read as version 0, and are upgraded with #migrate_position
+    pub version: u8,
+
+    /// The reward growth per unit of liquidity as of the last update to liquidity or rewards owed
+    pub reward_growth_inside_last_x32: u64,
+
+    /// The bonus rewards owed to the position owner
+    pub rewards_owed: u64,
+
+    /// The slot at which liquidity was last minted to the position. Fees credited before
+    /// the pool's minimum holding period has passed are forfeited
+    pub mint_slot: u64,
 }
 
 impl PositionState {
-    /// Credits accumulated fees to a user's position
+    /// Loads a position account on the current layout version
+    ///
+    /// Accounts on an older layout are shorter than the current one and cannot be
+    /// loaded. They must be upgraded with #migrate_position first.
+    ///
+    /// # Arguments
+    ///
+    /// * `loader` - The position account
+    ///
+    pub fn load_current<'a>(
+        loader: &'a AccountLoader<PositionState>,
+    ) -> Result<Ref<'a, PositionState>> {
+        require!(
+            loader.to_account_info().data_len() == 8 + std::mem::size_of::<PositionState>(),
+            ErrorCode::PositionNotMigrated
+        );
+        let position = loader.load()?;
+        require!(
+            position.version == POSITION_VERSION,
+            ErrorCode::PositionNotMigrated
+        );
+        Ok(position)
+    }
+
+    /// Upgrades the position to the current layout version. Fields added after the
+    /// position's version are set to their defaults, existing fields are left untouched.
+    ///
+    /// The reward checkpoint of a position from before rewards is set to the current reward
+    /// growth inside, so that it earns only the rewards distributed after its migration.
+    ///
+    /// Migrating a position on the current version is a no-op.
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The position to upgrade
+    /// * `reward_growth_inside_x32` - The all-time reward growth, per unit of liquidity,
+    ///   inside the position's tick boundaries
+    ///
+    pub fn migrate(&mut self, reward_growth_inside_x32: u64) {
+        // v0 -> v1: only adds the version field
+        // v1 -> v2: adds the reward fields, checkpointed at the current reward growth
+        // v2 -> v3: adds the mint slot, zero so that the holding period has passed
+        if self.version < 2 {
+            self.reward_growth_inside_last_x32 = reward_growth_inside_x32;
+        }
+        if self.version < POSITION_VERSION {
+            self.version = POSITION_VERSION;
+        }
+    }
+
+    /// Credits accumulated fees and rewards to a user's position
     ///
     /// # Arguments
     ///
@@ -49,26 +134,51 @@ impl PositionState {
     /// inside the position's tick boundaries
     /// * `fee_growth_inside_1_x32` - The all-time fee growth in token_1, per unit of liquidity,
     /// inside the position's tick boundaries
+    /// * `reward_growth_inside_x32` - The all-time reward growth, per unit of liquidity,
+    /// inside the position's tick boundaries
+    ///
+    /// In debug builds, the fee growth inside of a position with liquidity must not decrease,
+    /// modulo 2^64, since its tick boundaries stay initialized. A step forward of half the
+    /// range or more is taken as a decrease.
     ///
     pub fn update(
         &mut self,
         liquidity_delta: i64,
         fee_growth_inside_0_x32: u64,
         fee_growth_inside_1_x32: u64,
+        reward_growth_inside_x32: u64,
     ) -> Result<()> {
+        // fees and rewards since the last update were earned by the liquidity held over that
+        // time, before the delta is applied. They are credited on this value, never on
+        // liquidity_next
+        let liquidity_before = self.liquidity;
         let liquidity_next = if liquidity_delta == 0 {
-            require!(self.liquidity > 0, ErrorCode::NP); // disallow pokes for 0 liquidity positions
-            self.liquidity
+            require!(liquidity_before > 0, ErrorCode::CannotPokeEmptyPosition); // disallow pokes for 0 liquidity positions
+            liquidity_before
         } else {
-            liquidity_math::add_delta(self.liquidity, liquidity_delta)?
+            liquidity_math::add_delta(liquidity_before, liquidity_delta)?
         };
 
-        // calculate accumulated Fees
-        let tokens_owed_0 = (fee_growth_inside_0_x32 - self.fee_growth_inside_0_last_x32)
-            .mul_div_floor(self.liquidity as u64, fixed_point_32::Q32)
-            .unwrap();
-        let tokens_owed_1 = (fee_growth_inside_1_x32 - self.fee_growth_inside_1_last_x32)
-            .mul_div_floor(self.liquidity as u64, fixed_point_32::Q32)
+        // fee growth inside restarts from any value once the ticks of an empty position are
+        // cleared, so the check only applies to positions with liquidity
+        if cfg!(debug_assertions) && liquidity_before > 0 {
+            require!(
+                fee_growth_inside_0_x32.wrapping_sub(self.fee_growth_inside_0_last_x32)
+                    <= i64::MAX as u64
+                    && fee_growth_inside_1_x32.wrapping_sub(self.fee_growth_inside_1_last_x32)
+                        <= i64::MAX as u64,
+                ErrorCode::FeeGrowthInconsistent
+            );
+        }
+
+        let (tokens_owed_0, tokens_owed_1) = self.fees_earned(
+            liquidity_before,
+            fee_growth_inside_0_x32,
+            fee_growth_inside_1_x32,
+        );
+        let rewards_owed = reward_growth_inside_x32
+            .wrapping_sub(self.reward_growth_inside_last_x32)
+            .mul_div_floor(liquidity_before, fixed_point_32::Q32)
             .unwrap();
 
         // Update the position
@@ -82,9 +192,361 @@ impl PositionState {
             self.tokens_owed_0 += tokens_owed_0;
             self.tokens_owed_1 += tokens_owed_1;
         }
+        self.reward_growth_inside_last_x32 = reward_growth_inside_x32;
+        self.rewards_owed += rewards_owed;
 
         Ok(())
     }
+
+    /// Credits fees and rewards earned since the last update to the position, without changing
+    /// its liquidity
+    ///
+    /// Unlike an update with zero liquidity delta, a position without liquidity is left unchanged,
+    /// since it earns no fees.
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The individual position to poke
+    /// * `fee_growth_inside_0_x32` - The all-time fee growth in token_0, per unit of liquidity,
+    ///   inside the position's tick boundaries
+    /// * `fee_growth_inside_1_x32` - The all-time fee growth in token_1, per unit of liquidity,
+    ///   inside the position's tick boundaries
+    /// * `reward_growth_inside_x32` - The all-time reward growth, per unit of liquidity,
+    ///   inside the position's tick boundaries
+    ///
+    pub fn poke(
+        &mut self,
+        fee_growth_inside_0_x32: u64,
+        fee_growth_inside_1_x32: u64,
+        reward_growth_inside_x32: u64,
+    ) -> Result<()> {
+        if self.liquidity == 0 {
+            return Ok(());
+        }
+        self.update(
+            0,
+            fee_growth_inside_0_x32,
+            fee_growth_inside_1_x32,
+            reward_growth_inside_x32,
+        )
+    }
+
+    /// Whether the position has been held for the minimum number of slots since its last mint
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The individual position
+    /// * `slot` - The current slot
+    /// * `min_holding_slots` - The pool's minimum holding period
+    ///
+    pub fn is_held(self, slot: u64, min_holding_slots: u64) -> bool {
+        slot >= self.mint_slot.saturating_add(min_holding_slots)
+    }
+
+    /// Forfeits the fees credited to the position since `tokens_owed_*_before` if it has not
+    /// been held for the pool's minimum holding period, redistributing them to the pool's
+    /// in range liquidity. Returns the forfeited amounts.
+    ///
+    /// Guards against just-in-time liquidity, minted before a large swap and burned after it
+    /// to drain the swap fees from long standing positions.
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The individual position, after crediting its fees
+    /// * `pool` - The pool the position belongs to, after any change in its liquidity
+    /// * `tokens_owed_0_before` - The token_0 owed to the position before crediting fees
+    /// * `tokens_owed_1_before` - The token_1 owed to the position before crediting fees
+    /// * `slot` - The current slot
+    ///
+    pub fn forfeit_unheld_fees(
+        &mut self,
+        pool: &mut PoolState,
+        tokens_owed_0_before: u64,
+        tokens_owed_1_before: u64,
+        slot: u64,
+    ) -> (u64, u64) {
+        if self.is_held(slot, pool.min_holding_slots) {
+            return (0, 0);
+        }
+        let forfeited_0 = self.tokens_owed_0.wrapping_sub(tokens_owed_0_before);
+        let forfeited_1 = self.tokens_owed_1.wrapping_sub(tokens_owed_1_before);
+        self.tokens_owed_0 = tokens_owed_0_before;
+        self.tokens_owed_1 = tokens_owed_1_before;
+        pool.redistribute_fees(forfeited_0, forfeited_1);
+        (forfeited_0, forfeited_1)
+    }
+
+    /// Pokes the position as by #poke, then forfeits the fees the poke credited as by
+    /// #forfeit_unheld_fees. Returns the forfeited amounts.
+    ///
+    /// Instructions crediting fees outside of mint and burn, which forfeit around their own
+    /// liquidity change, must poke through this. Else fees earned within the holding period
+    /// could be collected before the burn, bypassing it.
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The individual position to poke
+    /// * `pool` - The pool the position belongs to
+    /// * `fee_growth_inside_0_x32` - The all-time fee growth in token_0, per unit of liquidity,
+    ///   inside the position's tick boundaries
+    /// * `fee_growth_inside_1_x32` - The all-time fee growth in token_1, per unit of liquidity,
+    ///   inside the position's tick boundaries
+    /// * `reward_growth_inside_x32` - The all-time reward growth, per unit of liquidity,
+    ///   inside the position's tick boundaries
+    /// * `slot` - The current slot
+    ///
+    pub fn poke_and_forfeit_unheld_fees(
+        &mut self,
+        pool: &mut PoolState,
+        fee_growth_inside_0_x32: u64,
+        fee_growth_inside_1_x32: u64,
+        reward_growth_inside_x32: u64,
+        slot: u64,
+    ) -> Result<(u64, u64)> {
+        let (tokens_owed_0_before, tokens_owed_1_before) = (self.tokens_owed_0, self.tokens_owed_1);
+        self.poke(
+            fee_growth_inside_0_x32,
+            fee_growth_inside_1_x32,
+            reward_growth_inside_x32,
+        )?;
+        Ok(self.forfeit_unheld_fees(pool, tokens_owed_0_before, tokens_owed_1_before, slot))
+    }
+
+    /// Returns the fees the owner can collect from the position, i.e. fees owed plus fees
+    /// earned since the last update, without updating the position
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The individual position
+    /// * `fee_growth_inside_0_x32` - The all-time fee growth in token_0, per unit of liquidity,
+    ///   inside the position's tick boundaries
+    /// * `fee_growth_inside_1_x32` - The all-time fee growth in token_1, per unit of liquidity,
+    ///   inside the position's tick boundaries
+    ///
+    pub fn pending_fees(
+        self,
+        fee_growth_inside_0_x32: u64,
+        fee_growth_inside_1_x32: u64,
+    ) -> (u64, u64) {
+        let (fees_earned_0, fees_earned_1) = self.fees_earned(
+            self.liquidity,
+            fee_growth_inside_0_x32,
+            fee_growth_inside_1_x32,
+        );
+        (
+            self.tokens_owed_0.wrapping_add(fees_earned_0),
+            self.tokens_owed_1.wrapping_add(fees_earned_1),
+        )
+    }
+
+    /// Fees earned since the last update by the liquidity the position held over that time
+    fn fees_earned(
+        self,
+        liquidity: u64,
+        fee_growth_inside_0_x32: u64,
+        fee_growth_inside_1_x32: u64,
+    ) -> (u64, u64) {
+        // Fee growth inside is a mod 2^64 difference
+        let fees_earned_0 = fee_growth_inside_0_x32
+            .wrapping_sub(self.fee_growth_inside_0_last_x32)
+            .mul_div_floor(liquidity, fixed_point_32::Q32)
+            .unwrap();
+        let fees_earned_1 = fee_growth_inside_1_x32
+            .wrapping_sub(self.fee_growth_inside_1_last_x32)
+            .mul_div_floor(liquidity, fixed_point_32::Q32)
+            .unwrap();
+        (fees_earned_0, fees_earned_1)
+    }
+}
+
+/// Derives the address and bump of a position account. Ticks are encoded by #tick_seed, so that
+/// clients deriving addresses of negative ticks must likewise use two's complement.
+///
+/// # Arguments
+///
+/// * `token_0` - The mint address of token_0 of the pool
+/// * `token_1` - The mint address of token_1 of the pool
+/// * `fee` - The fee tier of the pool, denominated in hundredths of a bip
+/// * `owner` - The owner of the position
+/// * `tick_lower` - The lower tick of the position
+/// * `tick_upper` - The upper tick of the position
+///
+pub fn derive_position_address(
+    token_0: Pubkey,
+    token_1: Pubkey,
+    fee: u32,
+    owner: &Pubkey,
+    tick_lower: i32,
+    tick_upper: i32,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            POSITION_SEED.as_bytes(),
+            token_0.as_ref(),
+            token_1.as_ref(),
+            &fee.to_be_bytes(),
+            owner.as_ref(),
+            &tick_seed(tick_lower),
+            &tick_seed(tick_upper),
+        ],
+        &crate::ID,
+    )
+}
+
+/// Whether a position's liquidity is active at the current tick, i.e.
+/// `tick_lower <= current_tick < tick_upper`, as for mint, burn and swaps crossing ticks
+///
+/// # Arguments
+///
+/// * `tick_lower` - The lower tick of the position
+/// * `tick_upper` - The upper tick of the position
+/// * `current_tick` - The current tick of the pool
+///
+pub fn is_in_range(tick_lower: i32, tick_upper: i32, current_tick: i32) -> bool {
+    tick_lower <= current_tick && current_tick < tick_upper
+}
+
+/// Returns the fees the owner can collect across positions, without updating them.
+/// Saturates at u64::MAX, since no larger amount can be collected.
+///
+/// # Arguments
+///
+/// * `positions` - Each position, with the all-time fee growth in token_0 and token_1, per unit
+///   of liquidity, inside its tick boundaries
+///
+pub fn total_pending_fees(positions: &[(PositionState, u64, u64)]) -> (u64, u64) {
+    positions.iter().fold(
+        (0, 0),
+        |(total_0, total_1), (position, fee_growth_inside_0_x32, fee_growth_inside_1_x32)| {
+            let (fees_0, fees_1) =
+                position.pending_fees(*fee_growth_inside_0_x32, *fee_growth_inside_1_x32);
+            (
+                total_0.saturating_add(fees_0),
+                total_1.saturating_add(fees_1),
+            )
+        },
+    )
+}
+
+/// Whether the pool vaults hold enough of token_0 and of token_1 to collect the tokens owed to
+/// a position, as by #pending_fees, in full
+///
+/// An uncovered token can still be collected in part, by requesting at most the vault balance
+/// in collect. The rest stays owed.
+///
+/// # Arguments
+///
+/// * `vault_balance_0` - The token_0 balance of the pool vault
+/// * `vault_balance_1` - The token_1 balance of the pool vault
+/// * `tokens_owed_0` - The token_0 to collect
+/// * `tokens_owed_1` - The token_1 to collect
+///
+pub fn can_cover_collect(
+    vault_balance_0: u64,
+    vault_balance_1: u64,
+    tokens_owed_0: u64,
+    tokens_owed_1: u64,
+) -> (bool, bool) {
+    (
+        tokens_owed_0 <= vault_balance_0,
+        tokens_owed_1 <= vault_balance_1,
+    )
+}
+
+/// The current value of a position in both tokens
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PositionValue {
+    /// The token_0 backing the position's liquidity at the current price
+    pub amount_0: u64,
+
+    /// The token_1 backing the position's liquidity at the current price
+    pub amount_1: u64,
+
+    /// The token_0 fees the owner can collect, owed and not yet credited
+    pub fees_0: u64,
+
+    /// The token_1 fees the owner can collect, owed and not yet credited
+    pub fees_1: u64,
+}
+
+/// Returns the current value of a position, i.e. the tokens its liquidity would withdraw at the
+/// pool's price, and the uncollected fees as given by #pending_fees
+///
+/// # Arguments
+///
+/// * `position` - The position being valued
+/// * `pool` - The pool of the position
+/// * `tick_lower` - The lower tick of the position
+/// * `tick_upper` - The upper tick of the position
+/// * `fee_growth_inside_0_x32` - The all-time fee growth in token_0, per unit of liquidity,
+///   inside the position's tick boundaries
+/// * `fee_growth_inside_1_x32` - The all-time fee growth in token_1, per unit of liquidity,
+///   inside the position's tick boundaries
+///
+pub fn position_value(
+    position: &PositionState,
+    pool: &PoolState,
+    tick_lower: i32,
+    tick_upper: i32,
+    fee_growth_inside_0_x32: u64,
+    fee_growth_inside_1_x32: u64,
+) -> Result<PositionValue> {
+    let (amount_0, amount_1) = liquidity_amounts::get_amounts_for_liquidity(
+        pool.sqrt_price_x32,
+        tick_math::get_sqrt_ratio_at_tick(tick_lower)?,
+        tick_math::get_sqrt_ratio_at_tick(tick_upper)?,
+        position.liquidity,
+    );
+    let (fees_0, fees_1) = position.pending_fees(fee_growth_inside_0_x32, fee_growth_inside_1_x32);
+    Ok(PositionValue {
+        amount_0,
+        amount_1,
+        fees_0,
+        fees_1,
+    })
+}
+
+/// Estimates the number of slots a position must be held for its fees to cover a round trip
+/// cost, assuming fees keep accruing at the rate observed since the mint. Used to tune the
+/// pool's minimum holding period against just-in-time liquidity.
+///
+/// Returns u64::MAX if no fees accrued over the observed period, since the cost is never
+/// recovered, and 0 for a free round trip. Saturates at u64::MAX.
+///
+/// # Arguments
+///
+/// * `fee_growth_at_mint_x32` - The fee growth inside the position's range when it was minted
+/// * `current_fee_growth_x32` - The fee growth inside the position's range now
+/// * `liquidity` - The liquidity of the position
+/// * `gas_cost_tokens` - The round trip cost of minting and burning, in the fee token
+/// * `slots_elapsed` - The slots over which the fee growth was observed
+///
+pub fn min_holding_slots_for_profit(
+    fee_growth_at_mint_x32: u64,
+    current_fee_growth_x32: u64,
+    liquidity: u64,
+    gas_cost_tokens: u64,
+    slots_elapsed: u64,
+) -> u64 {
+    if gas_cost_tokens == 0 {
+        return 0;
+    }
+    // fee growth is a difference mod 2^64, as in #pending_fees
+    let fees_x32 = U256::from(current_fee_growth_x32.wrapping_sub(fee_growth_at_mint_x32))
+        * U256::from(liquidity);
+    if fees_x32.is_zero() || slots_elapsed == 0 {
+        return u64::MAX;
+    }
+
+    // slots = ⌈cost x slots_elapsed / fees⌉
+    let cost_x32 =
+        (U256::from(gas_cost_tokens) * U256::from(slots_elapsed)) << fixed_point_32::RESOLUTION;
+    let slots = (cost_x32 + fees_x32 - 1) / fees_x32;
+    if slots > U256::from(u64::MAX) {
+        u64::MAX
+    } else {
+        slots.as_u64()
+    }
 }
 
 /// Emitted when liquidity is minted for a given position
@@ -147,6 +609,32 @@ pub struct BurnEvent {
     pub amount_1: u64,
 }
 
+/// Emitted when a position forfeits its fees by changing liquidity before the pool's minimum
+/// holding period has passed
+#[event]
+pub struct FeesForfeitedEvent {
+    /// The pool to whose in range liquidity the fees are redistributed
+    #[index]
+    pub pool_state: Pubkey,
+
+    /// The owner of the position
+    pub owner: Pubkey,
+
+    /// The lower tick of the position
+    #[index]
+    pub tick_lower: i32,
+
+    /// The upper tick of the position
+    #[index]
+    pub tick_upper: i32,
+
+    /// The forfeited fees in token_0
+    pub amount_0: u64,
+
+    /// The forfeited fees in token_1
+    pub amount_1: u64,
+}
+
 /// Emitted when fees are collected by the owner of a position
 /// Collect events may be emitted with zero amount_0 and amount_1 when the caller chooses not to collect fees
 #[event]
@@ -172,3 +660,1036 @@ pub struct CollectEvent {
     /// The amount of token_1 fees collected
     pub amount_1: u64,
 }
+
+/// Emitted for each position poked by #poke_positions. Events are emitted in the order
+/// the positions are passed in remaining accounts.
+#[event]
+pub struct PokeEvent {
+    /// The pool of the poked position
+    #[index]
+    pub pool_state: Pubkey,
+
+    /// The owner of the position
+    pub owner: Pubkey,
+
+    /// The lower tick of the position
+    #[index]
+    pub tick_lower: i32,
+
+    /// The upper tick of the position
+    #[index]
+    pub tick_upper: i32,
+
+    /// The index of the position among the positions passed to the instruction
+    pub position_index: u16,
+
+    /// The token_0 fees owed to the position after the poke
+    pub tokens_owed_0: u64,
+
+    /// The token_1 fees owed to the position after the poke
+    pub tokens_owed_1: u64,
+}
+
+/// Emitted when bonus rewards are collected by the owner of a position
+#[event]
+pub struct CollectRewardsEvent {
+    /// The pool from which rewards are collected
+    #[index]
+    pub pool_state: Pubkey,
+
+    /// The owner of the position for which rewards are collected
+    pub owner: Pubkey,
+
+    /// The lower tick of the position
+    #[index]
+    pub tick_lower: i32,
+
+    /// The upper tick of the position
+    #[index]
+    pub tick_upper: i32,
+
+    /// The amount of the reward token collected
+    pub amount: u64,
+}
+
+/// Emitted when a position is transferred to a new owner
+#[event]
+pub struct TransferPositionEvent {
+    /// The pool of the position
+    #[index]
+    pub pool_state: Pubkey,
+
+    /// The previous owner of the position
+    pub owner: Pubkey,
+
+    /// The new owner of the position
+    pub new_owner: Pubkey,
+
+    /// The lower tick of the position
+    #[index]
+    pub tick_lower: i32,
+
+    /// The upper tick of the position
+    #[index]
+    pub tick_upper: i32,
+
+    /// The liquidity of the transferred position
+    pub liquidity: u64,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anchor_lang::Discriminator;
+
+    fn fields(position: &PositionState) -> (u8, u64, u64, u64, u64, u64, u8) {
+        (
+            position.bump,
+            position.liquidity,
+            position.fee_growth_inside_0_last_x32,
+            position.fee_growth_inside_1_last_x32,
+            position.tokens_owed_0,
+            position.tokens_owed_1,
+            position.version,
+        )
+    }
+
+    /// Serializes a position in the layout used before the version field was added
+    fn legacy_bytes(position: &PositionState) -> Vec<u8> {
+        let mut data = PositionState::discriminator().to_vec();
+        data.push(position.bump);
+        data.extend_from_slice(&{ position.liquidity }.to_le_bytes());
+        data.extend_from_slice(&{ position.fee_growth_inside_0_last_x32 }.to_le_bytes());
+        data.extend_from_slice(&{ position.fee_growth_inside_1_last_x32 }.to_le_bytes());
+        data.extend_from_slice(&{ position.tokens_owed_0 }.to_le_bytes());
+        data.extend_from_slice(&{ position.tokens_owed_1 }.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn legacy_layout_is_a_prefix() {
+        let position = PositionState {
+            bump: 255,
+            liquidity: 1000,
+            ..Default::default()
+        };
+        let data = legacy_bytes(&position);
+        assert_eq!(data.len(), POSITION_LEN_V0);
+        assert_eq!(
+            &data[8..],
+            &anchor_lang::__private::bytemuck::bytes_of(&position)[..POSITION_LEN_V0 - 8]
+        );
+    }
+
+    #[test]
+    fn migrated_position_behaves_like_fresh_position() {
+        let mut fresh = PositionState {
+            bump: 254,
+            liquidity: 1 << 32,
+            fee_growth_inside_0_last_x32: 5 << 32,
+            fee_growth_inside_1_last_x32: 7 << 32,
+            tokens_owed_0: 11,
+            tokens_owed_1: 13,
+            version: POSITION_VERSION,
+            reward_growth_inside_last_x32: 0,
+            rewards_owed: 0,
+            mint_slot: 0,
+        };
+
+        // old account bytes, grown by the realloc in #migrate_position
+        let mut data = legacy_bytes(&fresh);
+        data.resize(8 + std::mem::size_of::<PositionState>(), 0);
+
+        let key = Pubkey::default();
+        let mut lamports = 0;
+        let info = AccountInfo::new(
+            &key,
+            false,
+            true,
+            &mut lamports,
+            &mut data,
+            &crate::ID,
+            false,
+            0,
+        );
+        let loader = AccountLoader::<PositionState>::try_from(&info).unwrap();
+        assert_eq!(loader.load().unwrap().version, 0);
+
+        // no rewards were distributed yet
+        loader.load_mut().unwrap().migrate(0);
+        let mut migrated = loader.load_mut().unwrap();
+        assert_eq!(fields(&migrated), fields(&fresh));
+
+        // migrating again is a no-op, the reward checkpoint included
+        migrated.migrate(1 << 32);
+        assert_eq!(fields(&migrated), fields(&fresh));
+
+        migrated
+            .update(-(1 << 31), 6 << 32, 9 << 32, 3 << 32)
+            .unwrap();
+        fresh.update(-(1 << 31), 6 << 32, 9 << 32, 3 << 32).unwrap();
+        assert_eq!(fields(&migrated), fields(&fresh));
+        assert_eq!({ migrated.tokens_owed_0 }, 11 + (1 << 32));
+        assert_eq!({ migrated.rewards_owed }, { fresh.rewards_owed });
+    }
+
+    #[test]
+    fn migrates_a_version_1_position() {
+        let position = PositionState {
+            bump: 254,
+            liquidity: 1 << 32,
+            fee_growth_inside_0_last_x32: 5 << 32,
+            tokens_owed_1: 13,
+            version: 1,
+            ..Default::default()
+        };
+        let mut data = legacy_bytes(&position);
+        data.push(1);
+        assert_eq!(data.len(), POSITION_LEN_V1);
+        data.resize(8 + std::mem::size_of::<PositionState>(), 0);
+
+        let key = Pubkey::default();
+        let mut lamports = 0;
+        let info = AccountInfo::new(
+            &key,
+            false,
+            true,
+            &mut lamports,
+            &mut data,
+            &crate::ID,
+            false,
+            0,
+        );
+        let loader = AccountLoader::<PositionState>::try_from(&info).unwrap();
+        // rewards were distributed before the migration
+        loader.load_mut().unwrap().migrate(5 << 32);
+        let mut migrated = loader.load_mut().unwrap();
+        assert_eq!(
+            fields(&migrated),
+            fields(&PositionState {
+                version: POSITION_VERSION,
+                reward_growth_inside_last_x32: 5 << 32,
+                ..position
+            })
+        );
+        assert_eq!({ migrated.rewards_owed }, 0);
+
+        // only the rewards distributed after the migration are earned
+        migrated.poke(5 << 32, 0, 6 << 32).unwrap();
+        assert_eq!({ migrated.rewards_owed }, 1 << 32);
+    }
+
+    /// Serializes a zero copy account with its discriminator
+    fn account_bytes<T: anchor_lang::ZeroCopy>(account: &T) -> Vec<u8> {
+        let mut data = T::discriminator().to_vec();
+        data.extend_from_slice(anchor_lang::__private::bytemuck::bytes_of(account));
+        data
+    }
+
+    #[test]
+    fn migrate_position_checkpoints_rewards_distributed_before_it() {
+        use crate::states::tick::{TickState, TICK_SEED};
+
+        let pool = PoolState {
+            token_0: Pubkey::new_unique(),
+            token_1: Pubkey::new_unique(),
+            fee: 500,
+            tick: 0,
+            reward_growth_global_x32: 9 << 32,
+            ..Default::default()
+        };
+        let tick_address = |tick: i32| {
+            Pubkey::find_program_address(
+                &[
+                    TICK_SEED.as_bytes(),
+                    pool.token_0.as_ref(),
+                    pool.token_1.as_ref(),
+                    &{ pool.fee }.to_be_bytes(),
+                    &tick_seed(tick),
+                ],
+                &crate::ID,
+            )
+        };
+        let (tick_lower_key, tick_lower_bump) = tick_address(-10);
+        let (tick_upper_key, tick_upper_bump) = tick_address(10);
+        // reward growth of 2 below and 3 above the range, so 4 inside of it
+        let tick_lower = TickState {
+            bump: tick_lower_bump,
+            tick: -10,
+            reward_growth_outside_x32: 2 << 32,
+            ..Default::default()
+        };
+        let tick_upper = TickState {
+            bump: tick_upper_bump,
+            tick: 10,
+            reward_growth_outside_x32: 3 << 32,
+            ..Default::default()
+        };
+
+        let owner_key = Pubkey::new_unique();
+        let (position_key, position_bump) =
+            derive_position_address(pool.token_0, pool.token_1, pool.fee, &owner_key, -10, 10);
+        let position = PositionState {
+            bump: position_bump,
+            liquidity: 1 << 32,
+            version: 1,
+            ..Default::default()
+        };
+        // a version 1 account, already resized so that no rent is due
+        let mut position_data = legacy_bytes(&position);
+        position_data.push(1);
+        position_data.resize(8 + std::mem::size_of::<PositionState>(), 0);
+
+        let mut pool_data = account_bytes(&pool);
+        let mut tick_lower_data = account_bytes(&tick_lower);
+        let mut tick_upper_data = account_bytes(&tick_upper);
+        let pool_key = Pubkey::new_unique();
+        let payer_key = Pubkey::new_unique();
+        let system_program_key = anchor_lang::solana_program::system_program::ID;
+        let (mut payer_lamports, mut owner_lamports, mut system_program_lamports) = (0, 0, 0);
+        let (mut position_lamports, mut pool_lamports) = (0, 0);
+        let (mut tick_lower_lamports, mut tick_upper_lamports) = (0, 0);
+        let (mut payer_data, mut owner_data, mut system_program_data) = (vec![], vec![], vec![]);
+
+        let payer_info = AccountInfo::new(
+            &payer_key,
+            true,
+            true,
+            &mut payer_lamports,
+            &mut payer_data,
+            &system_program_key,
+            false,
+            0,
+        );
+        let position_info = AccountInfo::new(
+            &position_key,
+            false,
+            true,
+            &mut position_lamports,
+            &mut position_data,
+            &crate::ID,
+            false,
+            0,
+        );
+        let owner_info = AccountInfo::new(
+            &owner_key,
+            false,
+            false,
+            &mut owner_lamports,
+            &mut owner_data,
+            &system_program_key,
+            false,
+            0,
+        );
+        let pool_info = AccountInfo::new(
+            &pool_key,
+            false,
+            false,
+            &mut pool_lamports,
+            &mut pool_data,
+            &crate::ID,
+            false,
+            0,
+        );
+        let tick_lower_info = AccountInfo::new(
+            &tick_lower_key,
+            false,
+            false,
+            &mut tick_lower_lamports,
+            &mut tick_lower_data,
+            &crate::ID,
+            false,
+            0,
+        );
+        let tick_upper_info = AccountInfo::new(
+            &tick_upper_key,
+            false,
+            false,
+            &mut tick_upper_lamports,
+            &mut tick_upper_data,
+            &crate::ID,
+            false,
+            0,
+        );
+        let system_program_info = AccountInfo::new(
+            &system_program_key,
+            false,
+            false,
+            &mut system_program_lamports,
+            &mut system_program_data,
+            &system_program_key,
+            true,
+            0,
+        );
+
+        let mut accounts = crate::context::MigratePosition {
+            payer: Signer::try_from(&payer_info).unwrap(),
+            position_state: UncheckedAccount::try_from(position_info.clone()),
+            owner: UncheckedAccount::try_from(owner_info),
+            pool_state: AccountLoader::try_from(&pool_info).unwrap(),
+            tick_lower_state: AccountLoader::try_from(&tick_lower_info).unwrap(),
+            tick_upper_state: AccountLoader::try_from(&tick_upper_info).unwrap(),
+            system_program: Program::try_from(&system_program_info).unwrap(),
+        };
+        crate::cyclos_core::migrate_position(Context::new(
+            &crate::ID,
+            &mut accounts,
+            &[],
+            std::collections::BTreeMap::new(),
+        ))
+        .unwrap();
+
+        let loader = AccountLoader::<PositionState>::try_from(&position_info).unwrap();
+        let mut migrated = loader.load_mut().unwrap();
+        assert_eq!({ migrated.version }, POSITION_VERSION);
+        assert_eq!({ migrated.reward_growth_inside_last_x32 }, 4 << 32);
+
+        // the rewards inside the range before the migration are not claimable
+        migrated.poke(0, 0, 4 << 32).unwrap();
+        assert_eq!({ migrated.rewards_owed }, 0);
+        migrated.poke(0, 0, 5 << 32).unwrap();
+        assert_eq!({ migrated.rewards_owed }, 1 << 32);
+    }
+
+    #[test]
+    fn migration_keeps_the_reward_checkpoint_of_a_version_2_position() {
+        let mut position = PositionState {
+            liquidity: 1 << 32,
+            version: 2,
+            reward_growth_inside_last_x32: 3 << 32,
+            rewards_owed: 7,
+            ..Default::default()
+        };
+        position.migrate(5 << 32);
+        assert_eq!({ position.version }, POSITION_VERSION);
+        assert_eq!({ position.reward_growth_inside_last_x32 }, 3 << 32);
+        assert_eq!({ position.rewards_owed }, 7);
+    }
+
+    #[test]
+    fn update_credits_rewards_like_fees() {
+        let mut position = PositionState {
+            liquidity: 1 << 32,
+            reward_growth_inside_last_x32: u64::MAX - (1 << 32),
+            rewards_owed: 7,
+            ..Default::default()
+        };
+        // the reward growth inside wraps around, and rewards accrue to the liquidity before
+        // the update
+        position.update(1 << 32, 0, 0, 2 << 32).unwrap();
+        assert_eq!({ position.liquidity }, 2 << 32);
+        assert_eq!({ position.reward_growth_inside_last_x32 }, 2 << 32);
+        assert_eq!({ position.rewards_owed }, 7 + (3 << 32) + 1);
+
+        // a quarter of a reward per unit of liquidity
+        position.update(0, 0, 0, (2 << 32) + (1 << 30)).unwrap();
+        assert_eq!({ position.rewards_owed }, 7 + (3 << 32) + 1 + (1 << 31));
+        assert_eq!(
+            ({ position.tokens_owed_0 }, { position.tokens_owed_1 }),
+            (0, 0)
+        );
+    }
+
+    #[test]
+    fn update_credits_fees_on_the_liquidity_before_the_delta() {
+        let mut position = PositionState {
+            liquidity: 1 << 32,
+            fee_growth_inside_0_last_x32: 5 << 32,
+            fee_growth_inside_1_last_x32: 7 << 32,
+            reward_growth_inside_last_x32: 1 << 32,
+            ..Default::default()
+        };
+        // mint three times the liquidity while crediting growth of 1, 2 and 4 per unit
+        position.update(3 << 32, 6 << 32, 9 << 32, 5 << 32).unwrap();
+        assert_eq!({ position.liquidity }, 4 << 32);
+        assert_eq!(
+            ({ position.tokens_owed_0 }, { position.tokens_owed_1 }),
+            (1 << 32, 2 << 32)
+        );
+        assert_eq!({ position.rewards_owed }, 4 << 32);
+
+        // burning credits the growth since on the liquidity held before the burn as well
+        position
+            .update(-(3 << 32), 7 << 32, 9 << 32, 5 << 32)
+            .unwrap();
+        assert_eq!({ position.liquidity }, 1 << 32);
+        assert_eq!({ position.tokens_owed_0 }, (1 << 32) + (4 << 32));
+    }
+
+    #[test]
+    fn update_accepts_increasing_fee_growth() {
+        let mut position = PositionState {
+            liquidity: 1 << 32,
+            fee_growth_inside_0_last_x32: 5 << 32,
+            fee_growth_inside_1_last_x32: 7 << 32,
+            ..Default::default()
+        };
+        position.update(0, 6 << 32, 7 << 32, 0).unwrap();
+        assert_eq!(
+            ({ position.tokens_owed_0 }, { position.tokens_owed_1 }),
+            (1 << 32, 0)
+        );
+    }
+
+    #[test]
+    fn update_accepts_wrapping_fee_growth() {
+        let mut position = PositionState {
+            liquidity: 1 << 32,
+            fee_growth_inside_0_last_x32: u64::MAX - (1 << 32) + 1,
+            fee_growth_inside_1_last_x32: u64::MAX,
+            ..Default::default()
+        };
+        position.update(0, 1 << 32, 0, 0).unwrap();
+        assert_eq!(
+            ({ position.tokens_owed_0 }, { position.tokens_owed_1 }),
+            (2 << 32, 1)
+        );
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn update_rejects_decreasing_fee_growth() {
+        let mut position = PositionState {
+            liquidity: 1 << 32,
+            fee_growth_inside_0_last_x32: 5 << 32,
+            fee_growth_inside_1_last_x32: 7 << 32,
+            ..Default::default()
+        };
+        let before = fields(&position);
+        for (fee_growth_inside_0_x32, fee_growth_inside_1_x32) in
+            [((5 << 32) - 1, 7 << 32), (5 << 32, (7 << 32) - 1)]
+        {
+            match position
+                .update(0, fee_growth_inside_0_x32, fee_growth_inside_1_x32, 0)
+                .err()
+                .unwrap()
+            {
+                anchor_lang::error::Error::AnchorError(e) => assert_eq!(
+                    e.error_code_number,
+                    u32::from(ErrorCode::FeeGrowthInconsistent)
+                ),
+                e => panic!("unexpected error {:?}", e),
+            }
+        }
+        assert_eq!(fields(&position), before);
+
+        // without liquidity, fee growth inside may restart from a lower value
+        let mut position = PositionState {
+            fee_growth_inside_0_last_x32: 5 << 32,
+            ..Default::default()
+        };
+        position.update(1 << 32, 0, 0, 0).unwrap();
+    }
+
+    #[test]
+    fn poke_credits_rewards_earned_since_last_update() {
+        let mut position = PositionState {
+            liquidity: 3 << 32,
+            reward_growth_inside_last_x32: 5 << 32,
+            ..Default::default()
+        };
+        position.poke(0, 0, 6 << 32).unwrap();
+        assert_eq!({ position.rewards_owed }, 3 << 32);
+        position.poke(0, 0, 6 << 32).unwrap();
+        assert_eq!({ position.rewards_owed }, 3 << 32);
+    }
+
+    #[test]
+    fn collect_is_covered_up_to_the_vault_balances() {
+        // fully covered, including owed amounts equal to the balances
+        assert_eq!(can_cover_collect(1_000, 2_000, 999, 2_000), (true, true));
+        assert_eq!(can_cover_collect(0, 0, 0, 0), (true, true));
+        // partially covered
+        assert_eq!(can_cover_collect(1_000, 2_000, 1_001, 5), (false, true));
+        assert_eq!(can_cover_collect(1_000, 2_000, 0, 2_001), (true, false));
+        // uncovered
+        assert_eq!(can_cover_collect(1_000, 0, u64::MAX, 1), (false, false));
+    }
+
+    #[test]
+    fn in_range_includes_the_lower_tick_only() {
+        // at both boundaries
+        assert!(is_in_range(-100, 100, -100));
+        assert!(!is_in_range(-100, 100, 100));
+        // inside
+        assert!(is_in_range(-100, 100, 0));
+        assert!(is_in_range(-100, 100, 99));
+        // outside
+        assert!(!is_in_range(-100, 100, -101));
+        assert!(!is_in_range(-100, 100, 101));
+        // a range of one tick holds only its lower tick
+        assert!(is_in_range(7, 8, 7));
+        assert!(!is_in_range(7, 8, 8));
+    }
+
+    #[test]
+    fn values_a_position_with_accrued_fees() {
+        let position = PositionState {
+            liquidity: 1 << 32,
+            fee_growth_inside_0_last_x32: 3 << 32,
+            fee_growth_inside_1_last_x32: u64::MAX,
+            tokens_owed_0: 7,
+            tokens_owed_1: 11,
+            ..Default::default()
+        };
+        // the price is at tick 0, inside the range
+        let pool = PoolState {
+            sqrt_price_x32: 1 << 32,
+            tick: 0,
+            ..Default::default()
+        };
+        let value = position_value(&position, &pool, -600, 600, 5 << 32, 1 << 31).unwrap();
+
+        // Δx = L (√P_upper - √P) / (√P √P_upper), and Δy = L (√P - √P_lower), rounded down
+        let sqrt_price_lower_x32 = tick_math::get_sqrt_ratio_at_tick(-600).unwrap() as u128;
+        let sqrt_price_upper_x32 = tick_math::get_sqrt_ratio_at_tick(600).unwrap() as u128;
+        let liquidity = 1u128 << 32;
+        let amount_0 =
+            ((liquidity << 32) * (sqrt_price_upper_x32 - (1 << 32)) / sqrt_price_upper_x32) >> 32;
+        let amount_1 = (liquidity * ((1 << 32) - sqrt_price_lower_x32)) >> 32;
+        assert_eq!(
+            value,
+            PositionValue {
+                amount_0: amount_0 as u64,
+                amount_1: amount_1 as u64,
+                // owed fees, plus the fee growth since the last update, which wraps around
+                // for token_1
+                fees_0: 7 + (2 << 32),
+                fees_1: 11 + (1 << 31) + 1,
+            }
+        );
+    }
+
+    #[test]
+    fn values_a_position_out_of_range_in_a_single_token() {
+        let position = PositionState {
+            liquidity: 1 << 32,
+            ..Default::default()
+        };
+        let below = PoolState {
+            sqrt_price_x32: tick_math::get_sqrt_ratio_at_tick(-1200).unwrap(),
+            ..Default::default()
+        };
+        let value = position_value(&position, &below, -600, 600, 0, 0).unwrap();
+        assert!(value.amount_0 > 0);
+        assert_eq!((value.amount_1, value.fees_0, value.fees_1), (0, 0, 0));
+    }
+
+    fn jit_pool() -> PoolState {
+        PoolState {
+            liquidity: 1 << 32,
+            min_holding_slots: 10,
+            ..Default::default()
+        }
+    }
+
+    fn burn_half(position: &mut PositionState) {
+        position.update(-(1 << 32), 1 << 32, 2 << 32, 0).unwrap();
+    }
+
+    #[test]
+    fn early_burn_forfeits_fees_to_remaining_liquidity() {
+        let mut pool = jit_pool();
+        let mut position = PositionState {
+            liquidity: 2 << 32,
+            tokens_owed_0: 5,
+            mint_slot: 100,
+            ..Default::default()
+        };
+        burn_half(&mut position);
+        assert_eq!(
+            ({ position.tokens_owed_0 }, { position.tokens_owed_1 }),
+            (5 + (2 << 32), 4 << 32)
+        );
+
+        assert_eq!(
+            position.forfeit_unheld_fees(&mut pool, 5, 0, 109),
+            (2 << 32, 4 << 32)
+        );
+        // fees owed before the burn are kept
+        assert_eq!(
+            ({ position.tokens_owed_0 }, { position.tokens_owed_1 }),
+            (5, 0)
+        );
+        assert_eq!(
+            ({ pool.fee_growth_global_0_x32 }, {
+                pool.fee_growth_global_1_x32
+            }),
+            (2 << 32, 4 << 32)
+        );
+    }
+
+    #[test]
+    fn held_burn_keeps_fees() {
+        let mut pool = jit_pool();
+        let mut position = PositionState {
+            liquidity: 2 << 32,
+            tokens_owed_0: 5,
+            mint_slot: 100,
+            ..Default::default()
+        };
+        burn_half(&mut position);
+
+        assert_eq!(position.forfeit_unheld_fees(&mut pool, 5, 0, 110), (0, 0));
+        assert_eq!(
+            ({ position.tokens_owed_0 }, { position.tokens_owed_1 }),
+            (5 + (2 << 32), 4 << 32)
+        );
+        assert_eq!(
+            ({ pool.fee_growth_global_0_x32 }, {
+                pool.fee_growth_global_1_x32
+            }),
+            (0, 0)
+        );
+    }
+
+    #[test]
+    fn fees_are_kept_without_a_holding_period() {
+        let position = PositionState {
+            mint_slot: 100,
+            ..Default::default()
+        };
+        assert!(position.is_held(100, 0));
+        assert!(!position.is_held(100, 1));
+        assert!(position.is_held(u64::MAX, u64::MAX));
+    }
+
+    #[test]
+    fn poke_within_holding_period_forfeits_fees() {
+        let mut pool = jit_pool();
+        let mut position = PositionState {
+            liquidity: 1 << 32,
+            tokens_owed_0: 5,
+            mint_slot: 100,
+            ..Default::default()
+        };
+        assert_eq!(
+            position
+                .poke_and_forfeit_unheld_fees(&mut pool, 1 << 32, 2 << 32, 0, 109)
+                .unwrap(),
+            (1 << 32, 2 << 32)
+        );
+        // a later collect finds nothing but the fees owed before the poke
+        assert_eq!(
+            ({ position.tokens_owed_0 }, { position.tokens_owed_1 }),
+            (5, 0)
+        );
+        assert_eq!(
+            ({ pool.fee_growth_global_0_x32 }, {
+                pool.fee_growth_global_1_x32
+            }),
+            (1 << 32, 2 << 32)
+        );
+
+        // the growth is checkpointed, so the forfeited fees are not credited again once held
+        assert_eq!(
+            position
+                .poke_and_forfeit_unheld_fees(&mut pool, 1 << 32, 2 << 32, 0, 110)
+                .unwrap(),
+            (0, 0)
+        );
+        assert_eq!(
+            ({ position.tokens_owed_0 }, { position.tokens_owed_1 }),
+            (5, 0)
+        );
+    }
+
+    #[test]
+    fn realloc_cannot_shrink() {
+        let key = Pubkey::default();
+        let mut lamports = 0;
+        let mut data = vec![0u8; POSITION_LEN_V0];
+        let info = AccountInfo::new(
+            &key,
+            false,
+            true,
+            &mut lamports,
+            &mut data,
+            &crate::ID,
+            false,
+            0,
+        );
+        assert!(crate::realloc_zeroed(&info, POSITION_LEN_V0 - 1).is_err());
+    }
+
+    #[test]
+    fn poke_credits_fees_earned_since_last_update() {
+        let mut position = PositionState {
+            liquidity: 1 << 32,
+            fee_growth_inside_0_last_x32: 5 << 32,
+            fee_growth_inside_1_last_x32: 7 << 32,
+            tokens_owed_0: 11,
+            ..Default::default()
+        };
+        position.poke(6 << 32, 10 << 32, 0).unwrap();
+        assert_eq!(
+            fields(&position),
+            (0, 1 << 32, 6 << 32, 10 << 32, 11 + (1 << 32), 3 << 32, 0)
+        );
+
+        // poking again without fee growth credits nothing
+        position.poke(6 << 32, 10 << 32, 0).unwrap();
+        assert_eq!({ position.tokens_owed_0 }, 11 + (1 << 32));
+        assert_eq!({ position.tokens_owed_1 }, 3 << 32);
+    }
+
+    #[test]
+    fn poke_skips_positions_without_liquidity() {
+        let mut position = PositionState {
+            fee_growth_inside_0_last_x32: 5 << 32,
+            tokens_owed_1: 13,
+            ..Default::default()
+        };
+        let before = fields(&position);
+        position.poke(6 << 32, 10 << 32, 0).unwrap();
+        assert_eq!(fields(&position), before);
+        assert!(position.update(0, 6 << 32, 10 << 32, 0).is_err());
+    }
+
+    #[test]
+    fn update_rejects_pokes_of_positions_without_liquidity() {
+        let mut position = PositionState {
+            fee_growth_inside_0_last_x32: 5 << 32,
+            ..Default::default()
+        };
+        let before = fields(&position);
+        match position.update(0, 6 << 32, 0, 0).err().unwrap() {
+            anchor_lang::error::Error::AnchorError(e) => assert_eq!(
+                e.error_code_number,
+                u32::from(ErrorCode::CannotPokeEmptyPosition)
+            ),
+            e => panic!("unexpected error {:?}", e),
+        }
+        assert_eq!(fields(&position), before);
+    }
+
+    #[test]
+    fn pending_fees_match_fees_owed_after_a_poke() {
+        let position = PositionState {
+            liquidity: 3 << 32,
+            fee_growth_inside_0_last_x32: u64::MAX - (1 << 32),
+            fee_growth_inside_1_last_x32: 7 << 32,
+            tokens_owed_0: 11,
+            tokens_owed_1: 13,
+            ..Default::default()
+        };
+        let before = fields(&position);
+        let pending = position.pending_fees(1 << 32, 9 << 32);
+        assert_eq!(fields(&position), before);
+
+        let mut poked = position;
+        poked.poke(1 << 32, 9 << 32, 0).unwrap();
+        assert_eq!(pending, ({ poked.tokens_owed_0 }, { poked.tokens_owed_1 }));
+    }
+
+    #[test]
+    fn total_pending_fees_sums_positions() {
+        let positions = [
+            (
+                PositionState {
+                    liquidity: 1 << 32,
+                    fee_growth_inside_0_last_x32: 5 << 32,
+                    tokens_owed_0: 11,
+                    ..Default::default()
+                },
+                6 << 32,
+                2 << 32,
+            ),
+            (
+                PositionState {
+                    liquidity: 1_000,
+                    fee_growth_inside_1_last_x32: u64::MAX,
+                    tokens_owed_1: 17,
+                    ..Default::default()
+                },
+                3 << 32,
+                99 << 32,
+            ),
+            // a position without liquidity only holds fees owed
+            (
+                PositionState {
+                    tokens_owed_0: 19,
+                    tokens_owed_1: 23,
+                    ..Default::default()
+                },
+                7 << 32,
+                7 << 32,
+            ),
+        ];
+        let expected = positions
+            .iter()
+            .map(
+                |(position, fee_growth_inside_0_x32, fee_growth_inside_1_x32)| {
+                    position.pending_fees(*fee_growth_inside_0_x32, *fee_growth_inside_1_x32)
+                },
+            )
+            .fold((0, 0), |(total_0, total_1), (fees_0, fees_1)| {
+                (total_0 + fees_0, total_1 + fees_1)
+            });
+        assert_eq!(total_pending_fees(&positions), expected);
+        assert_eq!(
+            expected,
+            (11 + (1 << 32) + 3_000 + 19, (2 << 32) + 99_000 + 17 + 23)
+        );
+        assert_eq!(total_pending_fees(&[]), (0, 0));
+    }
+
+    #[test]
+    fn total_pending_fees_saturates() {
+        let position = PositionState {
+            tokens_owed_0: u64::MAX - 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            total_pending_fees(&[(position, 0, 0), (position, 0, 0)]),
+            (u64::MAX, 0)
+        );
+    }
+
+    mod min_holding_slots_for_profit {
+        use super::*;
+
+        #[test]
+        fn extrapolates_the_fee_rate_since_the_mint() {
+            // 1000 liquidity earns just under 1 token per 10 slots, since the fee growth per
+            // unit of liquidity rounds down
+            let growth_x32 = (1u64 << 32) / 1000;
+            let liquidity = 1000;
+            assert_eq!(
+                min_holding_slots_for_profit(0, growth_x32, liquidity, 5, 10),
+                51
+            );
+            // twice the liquidity earns twice the fees
+            assert_eq!(
+                min_holding_slots_for_profit(0, growth_x32, 2 * liquidity, 5, 10),
+                26
+            );
+            // the fee growth wraps past zero since the mint
+            assert_eq!(
+                min_holding_slots_for_profit(u64::MAX - growth_x32 + 1, 0, 2 * liquidity, 5, 10),
+                26
+            );
+        }
+
+        #[test]
+        fn covers_the_cost_at_the_observed_rate() {
+            let (fee_growth_at_mint_x32, current_fee_growth_x32) = (7 << 32, 9 << 32);
+            let (liquidity, gas_cost_tokens, slots_elapsed) = (3, 1000, 40);
+            let slots = min_holding_slots_for_profit(
+                fee_growth_at_mint_x32,
+                current_fee_growth_x32,
+                liquidity,
+                gas_cost_tokens,
+                slots_elapsed,
+            );
+            // 6 tokens per 40 slots
+            let fees_after = |slots: u64| slots * 6 / 40;
+            assert!(fees_after(slots) >= gas_cost_tokens);
+            assert!(fees_after(slots - 1) < gas_cost_tokens);
+        }
+
+        #[test]
+        fn handles_free_and_unrecoverable_costs() {
+            assert_eq!(min_holding_slots_for_profit(0, 1 << 32, 1, 0, 10), 0);
+            // no fees accrued, or without liquidity
+            assert_eq!(
+                min_holding_slots_for_profit(1 << 32, 1 << 32, 1000, 1, 10),
+                u64::MAX
+            );
+            assert_eq!(min_holding_slots_for_profit(0, 1 << 32, 0, 1, 10), u64::MAX);
+            // a dust rate saturates
+            assert_eq!(
+                min_holding_slots_for_profit(0, 1, 1, u64::MAX, u64::MAX),
+                u64::MAX
+            );
+        }
+    }
+
+    #[test]
+    fn negative_ticks_are_seeded_in_twos_complement() {
+        assert_eq!(tick_seed(-1), [0xff; 4]);
+        assert_eq!(tick_seed(-10), [0xff, 0xff, 0xff, 0xf6]);
+        assert_eq!(tick_seed(10), [0, 0, 0, 0x0a]);
+        assert_eq!(
+            tick_seed(tick_math::MIN_TICK),
+            tick_math::MIN_TICK.to_be_bytes()
+        );
+
+        // the address of a position below zero, from the raw bytes of its ticks
+        let (token_0, token_1, owner) = (
+            Pubkey::new_from_array([1; 32]),
+            Pubkey::new_from_array([2; 32]),
+            Pubkey::new_from_array([3; 32]),
+        );
+        let fee = 500u32;
+        let seeds: [&[u8]; 7] = [
+            POSITION_SEED.as_bytes(),
+            token_0.as_ref(),
+            token_1.as_ref(),
+            &fee.to_be_bytes(),
+            owner.as_ref(),
+            &[0xff, 0xff, 0xff, 0xec],
+            &[0xff, 0xff, 0xff, 0xf6],
+        ];
+        let (key, bump) = derive_position_address(token_0, token_1, fee, &owner, -20, -10);
+        assert_eq!(
+            (key, bump),
+            Pubkey::find_program_address(&seeds, &crate::ID)
+        );
+
+        // encoding only the magnitude of the ticks derives the address of another position
+        assert_ne!(
+            key,
+            derive_position_address(token_0, token_1, fee, &owner, 20, 10).0
+        );
+    }
+
+    #[test]
+    fn load_current_rejects_unmigrated_positions() {
+        let position = PositionState {
+            bump: 254,
+            liquidity: 1 << 32,
+            ..Default::default()
+        };
+        let key = Pubkey::default();
+
+        // an account on the legacy layout is too short to be loaded
+        let mut data = legacy_bytes(&position);
+        let mut lamports = 0;
+        let info = AccountInfo::new(
+            &key,
+            false,
+            true,
+            &mut lamports,
+            &mut data,
+            &crate::ID,
+            false,
+            0,
+        );
+        let loader = AccountLoader::<PositionState>::try_from(&info).unwrap();
+        assert!(PositionState::load_current(&loader).is_err());
+
+        // resized but not yet migrated
+        let mut data = legacy_bytes(&position);
+        data.resize(8 + std::mem::size_of::<PositionState>(), 0);
+        let mut lamports = 0;
+        let info = AccountInfo::new(
+            &key,
+            false,
+            true,
+            &mut lamports,
+            &mut data,
+            &crate::ID,
+            false,
+            0,
+        );
+        let loader = AccountLoader::<PositionState>::try_from(&info).unwrap();
+        assert!(PositionState::load_current(&loader).is_err());
+
+        loader.load_mut().unwrap().migrate(0);
+        assert_eq!(
+            { PositionState::load_current(&loader).unwrap().liquidity },
+            1 << 32
+        );
+    }
+}
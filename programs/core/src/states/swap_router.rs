@@ -1,3 +1,4 @@
+use crate::error::ErrorCode;
 use anchor_lang::prelude::*;
 
 pub const DEFAULT_AMOUNT_IN_CACHED: u64 = u64::MAX;
@@ -10,3 +11,78 @@ pub struct SwapRouterState {
     /// Cache for exact output swaps
     pub amount_in_cached: u64,
 }
+
+/// A single pool in a multi-hop swap path
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SwapHop {
+    /// The pool swapped through
+    pub pool: Pubkey,
+    /// The token paid into the pool
+    pub token_in: Pubkey,
+    /// The token paid out of the pool
+    pub token_out: Pubkey,
+}
+
+/// Checks that a swap path is connected, with each hop spending the output token of the
+/// previous hop, and that no pool is swapped through twice
+///
+/// # Arguments
+///
+/// * `hops` - The pools of the path, in swap order
+///
+pub fn validate_swap_path(hops: &[SwapHop]) -> Result<()> {
+    for (i, hop) in hops.iter().enumerate() {
+        if i > 0 {
+            require!(
+                hops[i - 1].token_out == hop.token_in,
+                ErrorCode::InvalidRoute
+            );
+        }
+        require!(
+            hops[..i].iter().all(|previous| previous.pool != hop.pool),
+            ErrorCode::InvalidRoute
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn hop(pool: u8, token_in: u8, token_out: u8) -> SwapHop {
+        SwapHop {
+            pool: Pubkey::new_from_array([pool; 32]),
+            token_in: Pubkey::new_from_array([token_in; 32]),
+            token_out: Pubkey::new_from_array([token_out; 32]),
+        }
+    }
+
+    fn assert_invalid_route(hops: &[SwapHop]) {
+        match validate_swap_path(hops).err().unwrap() {
+            anchor_lang::error::Error::AnchorError(error) => {
+                assert_eq!(error.error_code_number, u32::from(ErrorCode::InvalidRoute))
+            }
+            error => panic!("unexpected error {}", error),
+        }
+    }
+
+    #[test]
+    fn accepts_a_connected_path_of_distinct_pools() {
+        assert!(validate_swap_path(&[hop(1, 10, 11)]).is_ok());
+        assert!(validate_swap_path(&[hop(1, 10, 11), hop(2, 11, 12), hop(3, 12, 10)]).is_ok());
+    }
+
+    #[test]
+    fn rejects_a_disconnected_path() {
+        assert_invalid_route(&[hop(1, 10, 11), hop(2, 12, 13)]);
+        assert_invalid_route(&[hop(1, 10, 11), hop(2, 11, 12), hop(3, 11, 12)]);
+    }
+
+    #[test]
+    fn rejects_a_path_repeating_a_pool() {
+        // swapping back through the first pool is connected, but repeats it
+        assert_invalid_route(&[hop(1, 10, 11), hop(1, 11, 10)]);
+        assert_invalid_route(&[hop(1, 10, 11), hop(2, 11, 12), hop(1, 12, 11)]);
+    }
+}
@@ -2,11 +2,28 @@
 ///!
 use crate::error::ErrorCode;
 use crate::libraries::{liquidity_math, tick_math};
+use crate::states::tick_bitmap::TickBitmapState;
 use anchor_lang::prelude::*;
+use anchor_lang::Discriminator;
+use std::convert::TryFrom;
 
 /// Seed to derive account address and signature
 pub const TICK_SEED: &str = "t";
 
+/// Encodes a tick for PDA seeds of tick and position accounts, as the big endian bytes of its
+/// two's complement. Negative ticks are not offset, e.g. -1 is `ff ff ff ff`.
+///
+/// Clients must encode ticks identically, else the derived addresses of negative ticks differ
+/// from the program's.
+///
+/// # Arguments
+///
+/// * `tick` - The tick to encode
+///
+pub fn tick_seed(tick: i32) -> [u8; 4] {
+    tick.to_be_bytes()
+}
+
 /// Account storing info for a price tick
 ///
 /// PDA of `[TICK_SEED, token_0, token_1, fee, tick]`
@@ -42,6 +59,10 @@ pub struct TickState {
     /// The seconds spent on the other side of the tick (relative to the current tick)
     /// only has relative meaning, not absolute — the value depends on when the tick is initialized
     pub seconds_outside: u32,
+
+    /// Reward growth per unit of liquidity on the _other_ side of this tick (relative to the current tick)
+    /// only has relative meaning, not absolute — the value depends on when the tick is initialized
+    pub reward_growth_outside_x32: u64,
 }
 
 impl TickState {
@@ -55,18 +76,24 @@ impl TickState {
     /// from left to right (right to left)
     /// * `fee_growth_global_0_x32` - The all-time global fee growth, per unit of liquidity, in token_0
     /// * `fee_growth_global_1_x32` - The all-time global fee growth, per unit of liquidity, in token_1
+    /// * `reward_growth_global_x32` - The all-time global reward growth, per unit of liquidity
     /// * `seconds_per_liquidity_cumulative_x32` - The all-time seconds per max(1, liquidity) of the pool
     /// * `tick_cumulative` - The tick * time elapsed since the pool was first initialized
     /// * `time` - The current block timestamp cast to a u32
     /// * `upper` - true for updating a position's upper tick, or false for updating a position's lower tick
     /// * `max_liquidity` - The maximum liquidity allocation for a single tick
     ///
+    /// The growth outside is only set when the tick is initialized. A tick shared with other
+    /// positions keeps its growth outside on further updates, since their fee accounting is
+    /// relative to it.
+    ///
     pub fn update(
         &mut self,
         tick_current: i32,
         liquidity_delta: i64,
         fee_growth_global_0_x32: u64,
         fee_growth_global_1_x32: u64,
+        reward_growth_global_x32: u64,
         seconds_per_liquidity_cumulative_x32: u64,
         tick_cumulative: i64,
         time: u32,
@@ -90,6 +117,7 @@ impl TickState {
             if self.tick <= tick_current {
                 self.fee_growth_outside_0_x32 = fee_growth_global_0_x32;
                 self.fee_growth_outside_1_x32 = fee_growth_global_1_x32;
+                self.reward_growth_outside_x32 = reward_growth_global_x32;
                 self.seconds_per_liquidity_outside_x32 = seconds_per_liquidity_cumulative_x32;
                 self.tick_cumulative_outside = tick_cumulative;
                 self.seconds_outside = time;
@@ -98,14 +126,10 @@ impl TickState {
 
         self.liquidity_gross = liquidity_gross_after;
 
-        // when the lower (upper) tick is crossed left to right (right to left),
-        // liquidity must be added (removed)
-        self.liquidity_net = if upper {
-            self.liquidity_net.checked_sub(liquidity_delta)
-        } else {
-            self.liquidity_net.checked_add(liquidity_delta)
-        }
-        .unwrap();
+        self.liquidity_net = self
+            .liquidity_net
+            .checked_add(liquidity_net_delta(liquidity_delta, upper))
+            .unwrap();
 
         Ok(flipped)
     }
@@ -118,6 +142,7 @@ impl TickState {
     /// * `self` - The destination tick of the transition
     /// * `fee_growth_global_0_x32` - The all-time global fee growth, per unit of liquidity, in token_0
     /// * `fee_growth_global_1_x32` - The all-time global fee growth, per unit of liquidity, in token_1
+    /// * `reward_growth_global_x32` - The all-time global reward growth, per unit of liquidity
     /// * `seconds_per_liquidity_cumulative_x32` - The current seconds per liquidity
     /// * `tick_cumulative` - The tick * time elapsed since the pool was first initialized
     /// * `time` - The current block timestamp
@@ -126,15 +151,20 @@ impl TickState {
         &mut self,
         fee_growth_global_0_x32: u64,
         fee_growth_global_1_x32: u64,
+        reward_growth_global_x32: u64,
         seconds_per_liquidity_cumulative_x32: u64,
         tick_cumulative: i64,
         time: u32,
     ) -> i64 {
-        self.fee_growth_outside_0_x32 = fee_growth_global_0_x32 - self.fee_growth_outside_0_x32;
-        self.fee_growth_outside_1_x32 = fee_growth_global_1_x32 - self.fee_growth_outside_1_x32;
+        self.fee_growth_outside_0_x32 =
+            fee_growth_global_0_x32.wrapping_sub(self.fee_growth_outside_0_x32);
+        self.fee_growth_outside_1_x32 =
+            fee_growth_global_1_x32.wrapping_sub(self.fee_growth_outside_1_x32);
+        self.reward_growth_outside_x32 =
+            reward_growth_global_x32.wrapping_sub(self.reward_growth_outside_x32);
         self.seconds_per_liquidity_outside_x32 =
             seconds_per_liquidity_cumulative_x32 - self.seconds_per_liquidity_outside_x32;
-        self.tick_cumulative_outside = tick_cumulative - self.tick_cumulative_outside;
+        self.tick_cumulative_outside = tick_cumulative.wrapping_sub(self.tick_cumulative_outside);
         self.seconds_outside = time - self.seconds_outside;
 
         self.liquidity_net
@@ -151,16 +181,36 @@ impl TickState {
         self.liquidity_gross = 0;
         self.fee_growth_outside_0_x32 = 0;
         self.fee_growth_outside_1_x32 = 0;
+        self.reward_growth_outside_x32 = 0;
         self.tick_cumulative_outside = 0;
         self.seconds_per_liquidity_outside_x32 = 0;
         self.seconds_outside = 0;
     }
 
+    /// Checks that the tick is initialized, i.e. referenced by some position, if and only if
+    /// its bit is set in the bitmap
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The tick to check
+    /// * `bitmap` - The bitmap word holding the tick
+    /// * `tick_spacing` - The tick spacing of the pool
+    ///
+    pub fn check_bitmap(&self, bitmap: &TickBitmapState, tick_spacing: u16) -> Result<()> {
+        let bit_pos = ((self.tick / tick_spacing as i32) % 256) as u8; // rightmost 8 bits
+        require!(
+            bitmap.is_initialized(bit_pos) == (self.liquidity_gross > 0),
+            ErrorCode::TickBitmapDesync
+        );
+        Ok(())
+    }
+
     pub fn is_clear(self) -> bool {
         self.liquidity_net == 0
             && self.liquidity_gross == 0
             && self.fee_growth_outside_0_x32 == 0
             && self.fee_growth_outside_1_x32 == 0
+            && self.reward_growth_outside_x32 == 0
             && self.tick_cumulative_outside == 0
             && self.seconds_per_liquidity_outside_x32 == 0
             && self.seconds_outside == 0
@@ -168,58 +218,191 @@ impl TickState {
 }
 
 /// Retrieves the all time fee growth data in token_0 and token_1, per unit of liquidity,
-/// inside a position's tick boundaries.
+/// below a tick.
 ///
-/// Calculates `fr = fg - f_below(lower) - f_above(upper)`, formula 6.19
+/// Fee growth accumulators are unsigned and wrap around, so differences are taken mod 2^64.
 ///
 /// # Arguments
 ///
-/// * `tick_lower` - The lower tick boundary of the position
-/// * `tick_upper` - The upper tick boundary of the position
+/// * `tick_lower` - The tick below which fee growth is measured
 /// * `tick_current` - The current tick
 /// * `fee_growth_global_0_x32` - The all-time global fee growth, per unit of liquidity, in token_0
 /// * `fee_growth_global_1_x32` - The all-time global fee growth, per unit of liquidity, in token_1
 ///
-pub fn get_fee_growth_inside(
+pub fn get_fee_growth_below(
     tick_lower: &TickState,
-    tick_upper: &TickState,
     tick_current: i32,
     fee_growth_global_0_x32: u64,
     fee_growth_global_1_x32: u64,
 ) -> (u64, u64) {
-    // calculate fee growth below
-    let (fee_growth_below_0_x32, fee_growth_below_1_x32) = if tick_current >= tick_lower.tick {
+    if tick_current >= tick_lower.tick {
         (
             tick_lower.fee_growth_outside_0_x32,
             tick_lower.fee_growth_outside_1_x32,
         )
     } else {
         (
-            fee_growth_global_0_x32 - tick_lower.fee_growth_outside_0_x32,
-            fee_growth_global_1_x32 - tick_lower.fee_growth_outside_1_x32,
+            fee_growth_global_0_x32.wrapping_sub(tick_lower.fee_growth_outside_0_x32),
+            fee_growth_global_1_x32.wrapping_sub(tick_lower.fee_growth_outside_1_x32),
         )
-    };
+    }
+}
 
-    // Calculate fee growth above
-    let (fee_growth_above_0_x32, fee_growth_above_1_x32) = if tick_current < tick_upper.tick {
+/// Retrieves the all time fee growth data in token_0 and token_1, per unit of liquidity,
+/// above a tick.
+///
+/// Fee growth accumulators are unsigned and wrap around, so differences are taken mod 2^64.
+///
+/// # Arguments
+///
+/// * `tick_upper` - The tick above which fee growth is measured
+/// * `tick_current` - The current tick
+/// * `fee_growth_global_0_x32` - The all-time global fee growth, per unit of liquidity, in token_0
+/// * `fee_growth_global_1_x32` - The all-time global fee growth, per unit of liquidity, in token_1
+///
+pub fn get_fee_growth_above(
+    tick_upper: &TickState,
+    tick_current: i32,
+    fee_growth_global_0_x32: u64,
+    fee_growth_global_1_x32: u64,
+) -> (u64, u64) {
+    if tick_current < tick_upper.tick {
         (
             tick_upper.fee_growth_outside_0_x32,
             tick_upper.fee_growth_outside_1_x32,
         )
     } else {
         (
-            fee_growth_global_0_x32 - tick_upper.fee_growth_outside_0_x32,
-            fee_growth_global_1_x32 - tick_upper.fee_growth_outside_1_x32,
+            fee_growth_global_0_x32.wrapping_sub(tick_upper.fee_growth_outside_0_x32),
+            fee_growth_global_1_x32.wrapping_sub(tick_upper.fee_growth_outside_1_x32),
         )
-    };
-    let fee_growth_inside_0_x32 =
-        fee_growth_global_0_x32 - fee_growth_below_0_x32 - fee_growth_above_0_x32;
-    let fee_growth_inside_1_x32 =
-        fee_growth_global_1_x32 - fee_growth_below_1_x32 - fee_growth_above_1_x32;
+    }
+}
+
+/// Retrieves the all time fee growth data in token_0 and token_1, per unit of liquidity,
+/// inside a position's tick boundaries.
+///
+/// Calculates `fr = fg - f_below(lower) - f_above(upper)`, formula 6.19. The current tick
+/// selects how #get_fee_growth_below and #get_fee_growth_above read the outside accumulators
+/// of each boundary, so the same formula holds whether the price is below, inside or above
+/// the range.
+///
+/// Accumulators are unsigned and may wrap around, so the result is the difference mod 2^64.
+/// Only differences between two readings are meaningful, which #PositionState::update takes
+/// with the same wrapping arithmetic.
+///
+/// # Arguments
+///
+/// * `tick_lower` - The lower tick boundary of the position
+/// * `tick_upper` - The upper tick boundary of the position
+/// * `tick_current` - The current tick
+/// * `fee_growth_global_0_x32` - The all-time global fee growth, per unit of liquidity, in token_0
+/// * `fee_growth_global_1_x32` - The all-time global fee growth, per unit of liquidity, in token_1
+///
+pub fn get_fee_growth_inside(
+    tick_lower: &TickState,
+    tick_upper: &TickState,
+    tick_current: i32,
+    fee_growth_global_0_x32: u64,
+    fee_growth_global_1_x32: u64,
+) -> (u64, u64) {
+    let (fee_growth_below_0_x32, fee_growth_below_1_x32) = get_fee_growth_below(
+        tick_lower,
+        tick_current,
+        fee_growth_global_0_x32,
+        fee_growth_global_1_x32,
+    );
+    let (fee_growth_above_0_x32, fee_growth_above_1_x32) = get_fee_growth_above(
+        tick_upper,
+        tick_current,
+        fee_growth_global_0_x32,
+        fee_growth_global_1_x32,
+    );
+    let fee_growth_inside_0_x32 = fee_growth_global_0_x32
+        .wrapping_sub(fee_growth_below_0_x32)
+        .wrapping_sub(fee_growth_above_0_x32);
+    let fee_growth_inside_1_x32 = fee_growth_global_1_x32
+        .wrapping_sub(fee_growth_below_1_x32)
+        .wrapping_sub(fee_growth_above_1_x32);
 
     (fee_growth_inside_0_x32, fee_growth_inside_1_x32)
 }
 
+/// Retrieves the fee growth inside a position's tick boundaries as by #get_fee_growth_inside,
+/// failing with FeeGrowthInconsistent if it is implausible against the global fee growth.
+/// Catches corrupted outside accumulators of the boundary ticks early.
+///
+/// The fee growth inside a range can be no larger than the global fee growth. It legitimately
+/// wraps below zero when the lower tick is initialized after the upper tick, assuming all
+/// prior growth took place below it, but again by no more than the global fee growth. Either
+/// way its distance from zero, mod 2^64, is at most the global value.
+///
+/// The check assumes that the global accumulators have not rolled over, after which any
+/// inside value is possible. #get_fee_growth_inside stays unchecked for this reason, so that
+/// swaps and position updates never fail on a pool whose fee growth wrapped.
+///
+/// # Arguments
+///
+/// * `tick_lower` - The lower tick boundary of the position
+/// * `tick_upper` - The upper tick boundary of the position
+/// * `tick_current` - The current tick
+/// * `fee_growth_global_0_x32` - The all-time global fee growth, per unit of liquidity, in token_0
+/// * `fee_growth_global_1_x32` - The all-time global fee growth, per unit of liquidity, in token_1
+///
+pub fn checked_fee_growth_inside(
+    tick_lower: &TickState,
+    tick_upper: &TickState,
+    tick_current: i32,
+    fee_growth_global_0_x32: u64,
+    fee_growth_global_1_x32: u64,
+) -> Result<(u64, u64)> {
+    let (fee_growth_inside_0_x32, fee_growth_inside_1_x32) = get_fee_growth_inside(
+        tick_lower,
+        tick_upper,
+        tick_current,
+        fee_growth_global_0_x32,
+        fee_growth_global_1_x32,
+    );
+    let plausible = |inside: u64, global: u64| inside <= global || inside.wrapping_neg() <= global;
+    require!(
+        plausible(fee_growth_inside_0_x32, fee_growth_global_0_x32)
+            && plausible(fee_growth_inside_1_x32, fee_growth_global_1_x32),
+        ErrorCode::FeeGrowthInconsistent
+    );
+    Ok((fee_growth_inside_0_x32, fee_growth_inside_1_x32))
+}
+
+/// Retrieves the all time reward growth, per unit of liquidity, inside a position's tick
+/// boundaries. Reward growth is tracked like fee growth, see #get_fee_growth_inside.
+///
+/// # Arguments
+///
+/// * `tick_lower` - The lower tick boundary of the position
+/// * `tick_upper` - The upper tick boundary of the position
+/// * `tick_current` - The current tick
+/// * `reward_growth_global_x32` - The all-time global reward growth, per unit of liquidity
+///
+pub fn get_reward_growth_inside(
+    tick_lower: &TickState,
+    tick_upper: &TickState,
+    tick_current: i32,
+    reward_growth_global_x32: u64,
+) -> u64 {
+    let reward_growth_below_x32 = if tick_current >= tick_lower.tick {
+        tick_lower.reward_growth_outside_x32
+    } else {
+        reward_growth_global_x32.wrapping_sub(tick_lower.reward_growth_outside_x32)
+    };
+    let reward_growth_above_x32 = if tick_current < tick_upper.tick {
+        tick_upper.reward_growth_outside_x32
+    } else {
+        reward_growth_global_x32.wrapping_sub(tick_upper.reward_growth_outside_x32)
+    };
+    reward_growth_global_x32
+        .wrapping_sub(reward_growth_below_x32)
+        .wrapping_sub(reward_growth_above_x32)
+}
+
 /// Derives max liquidity per tick from given tick spacing
 ///
 /// # Arguments
@@ -237,6 +420,128 @@ pub fn tick_spacing_to_max_liquidity_per_tick(tick_spacing: i32) -> u64 {
     u64::MAX / num_ticks
 }
 
+/// Computes the active liquidity when the pool is at `target_tick`, by walking the
+/// `liquidity_net` deltas of the ticks crossed from the current tick.
+///
+/// Moving right crosses ticks in `(tick_current, target_tick]`, adding their liquidity_net.
+/// Moving left crosses ticks in `(target_tick, tick_current]`, subtracting it. Ticks outside
+/// the walk and uninitialized ticks are ignored, so `ticks` can be passed in any order.
+///
+/// Read only, used to chart the liquidity curve. Panics if the ticks are inconsistent
+/// with `liquidity`, i.e. the walk would leave negative liquidity.
+///
+/// # Arguments
+///
+/// * `ticks` - Distinct ticks of the pool, covering every initialized tick between the current
+///   and target ticks
+/// * `liquidity` - The active liquidity of the pool at `tick_current`
+/// * `tick_current` - The current tick of the pool
+/// * `target_tick` - The tick at which to compute active liquidity
+///
+pub fn liquidity_at_tick(
+    ticks: &[TickState],
+    liquidity: u64,
+    tick_current: i32,
+    target_tick: i32,
+) -> u64 {
+    let mut liquidity_at_target = liquidity as i128;
+    for tick_state in ticks.iter().filter(|t| t.liquidity_gross != 0) {
+        let tick = tick_state.tick;
+        if tick > tick_current && tick <= target_tick {
+            liquidity_at_target += tick_state.liquidity_net as i128;
+        } else if tick > target_tick && tick <= tick_current {
+            liquidity_at_target -= tick_state.liquidity_net as i128;
+        }
+    }
+
+    u64::try_from(liquidity_at_target).unwrap()
+}
+
+/// Returns the change in a tick's net liquidity as a position's liquidity changes by
+/// `liquidity_delta`
+///
+/// When the lower (upper) tick is crossed left to right (right to left), liquidity must be
+/// added (removed). A mint therefore increases the net liquidity of the lower tick and decreases
+/// that of the upper tick, and a burn does the reverse.
+///
+/// # Arguments
+///
+/// * `liquidity_delta` - The change in the position's liquidity
+/// * `upper` - true for the position's upper tick, or false for its lower tick
+///
+pub fn liquidity_net_delta(liquidity_delta: i64, upper: bool) -> i64 {
+    if upper {
+        liquidity_delta.checked_neg().unwrap()
+    } else {
+        liquidity_delta
+    }
+}
+
+/// Checks that the net liquidity of a pool's initialized ticks sums to zero, since each
+/// position nets out between its lower and upper ticks
+///
+/// # Arguments
+///
+/// * `ticks` - Every initialized tick of the pool
+///
+pub fn check_liquidity_net_sum(ticks: &[TickState]) -> Result<()> {
+    let sum: i128 = ticks
+        .iter()
+        .filter(|t| t.liquidity_gross != 0)
+        .map(|t| t.liquidity_net as i128)
+        .sum();
+    require!(sum == 0, ErrorCode::LiquidityNetImbalance);
+    Ok(())
+}
+
+/// Checks that no tick account is passed twice among the accounts of a swap, which would let
+/// the swap cross the same tick twice and corrupt the in range liquidity
+///
+/// Accounts other than tick accounts of this program are ignored. Swaps pass few accounts, so
+/// accounts are compared pairwise.
+///
+/// # Arguments
+///
+/// * `accounts` - The remaining accounts of the swap
+///
+pub fn check_unique_tick_accounts(accounts: &[AccountInfo]) -> Result<()> {
+    let is_tick_account = |account: &AccountInfo| {
+        account.owner == &crate::ID
+            && account
+                .try_borrow_data()
+                .is_ok_and(|data| data.starts_with(&TickState::discriminator()))
+    };
+    for (index, account) in accounts.iter().enumerate() {
+        if is_tick_account(account) {
+            require!(
+                accounts[..index]
+                    .iter()
+                    .all(|other| other.key != account.key),
+                ErrorCode::DuplicateTickAccount
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Emitted when a swap crosses an initialized tick
+#[event]
+pub struct TickCrossEvent {
+    /// The pool in which the tick was crossed
+    #[index]
+    pub pool_state: Pubkey,
+
+    /// The crossed tick
+    pub tick: i32,
+
+    /// The liquidity_net stored in the crossed tick, i.e. the liquidity added when crossed
+    /// from left to right
+    pub liquidity_net: i64,
+
+    /// The direction of the swap. True if the tick was crossed from right to left
+    pub zero_for_one: bool,
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -341,6 +646,7 @@ mod test {
                 tick_cumulative_outside: 0,
                 seconds_per_liquidity_outside_x32: 0,
                 seconds_outside: 0,
+                reward_growth_outside_x32: 0,
             };
             assert_eq!(
                 get_fee_growth_inside(&tick_lower, &tick_upper, 0, 15, 15),
@@ -360,6 +666,7 @@ mod test {
                 tick_cumulative_outside: 0,
                 seconds_per_liquidity_outside_x32: 0,
                 seconds_outside: 0,
+                reward_growth_outside_x32: 0,
             };
             let mut tick_upper = TickState::default();
             tick_upper.tick = 2;
@@ -381,6 +688,7 @@ mod test {
                 tick_cumulative_outside: 0,
                 seconds_per_liquidity_outside_x32: 0,
                 seconds_outside: 0,
+                reward_growth_outside_x32: 0,
             };
             let tick_upper = TickState {
                 bump: 0,
@@ -392,6 +700,7 @@ mod test {
                 tick_cumulative_outside: 0,
                 seconds_per_liquidity_outside_x32: 0,
                 seconds_outside: 0,
+                reward_growth_outside_x32: 0,
             };
             assert_eq!(
                 get_fee_growth_inside(&tick_lower, &tick_upper, 0, 15, 15),
@@ -412,6 +721,7 @@ mod test {
                 tick_cumulative_outside: 0,
                 seconds_per_liquidity_outside_x32: 0,
                 seconds_outside: 0,
+                reward_growth_outside_x32: 0,
             };
             let tick_upper = TickState {
                 bump: 0,
@@ -423,12 +733,328 @@ mod test {
                 tick_cumulative_outside: 0,
                 seconds_per_liquidity_outside_x32: 0,
                 seconds_outside: 0,
+                reward_growth_outside_x32: 0,
             };
             assert_eq!(
                 get_fee_growth_inside(&tick_lower, &tick_upper, 0, 15, 15),
                 (16, 13)
             );
         }
+
+        mod properties {
+            use super::*;
+            use quickcheck::quickcheck;
+
+            fn tick(
+                tick: i32,
+                fee_growth_outside_0_x32: u64,
+                fee_growth_outside_1_x32: u64,
+            ) -> TickState {
+                TickState {
+                    tick,
+                    fee_growth_outside_0_x32,
+                    fee_growth_outside_1_x32,
+                    ..Default::default()
+                }
+            }
+
+            quickcheck! {
+                fn below_above_and_inside_sum_to_global(
+                    tick_current: i8,
+                    outside_lower: (u64, u64),
+                    outside_upper: (u64, u64),
+                    global: (u64, u64)
+                ) -> bool {
+                    let tick_lower = tick(-10, outside_lower.0, outside_lower.1);
+                    let tick_upper = tick(10, outside_upper.0, outside_upper.1);
+                    let tick_current = tick_current as i32;
+
+                    let below = get_fee_growth_below(&tick_lower, tick_current, global.0, global.1);
+                    let above = get_fee_growth_above(&tick_upper, tick_current, global.0, global.1);
+                    let inside =
+                        get_fee_growth_inside(&tick_lower, &tick_upper, tick_current, global.0, global.1);
+
+                    below.0.wrapping_add(above.0).wrapping_add(inside.0) == global.0
+                        && below.1.wrapping_add(above.1).wrapping_add(inside.1) == global.1
+                }
+            }
+
+            quickcheck! {
+                fn inside_difference_is_independent_of_wrapping(
+                    tick_current: i8,
+                    outside_lower: (u64, u64),
+                    outside_upper: (u64, u64),
+                    global: (u64, u64),
+                    growth: (u32, u32),
+                    offset: u64
+                ) -> bool {
+                    // shifting every accumulator by the same offset, e.g. past u64::MAX, leaves
+                    // the growth inside the range between two readings unchanged
+                    let tick_current = tick_current as i32;
+                    let inside_growth = |offset: u64| {
+                        let tick_lower = tick(
+                            -10,
+                            outside_lower.0.wrapping_add(offset),
+                            outside_lower.1.wrapping_add(offset),
+                        );
+                        let tick_upper = tick(
+                            10,
+                            outside_upper.0.wrapping_add(offset),
+                            outside_upper.1.wrapping_add(offset),
+                        );
+                        let global_0 = global.0.wrapping_add(offset);
+                        let global_1 = global.1.wrapping_add(offset);
+                        let before =
+                            get_fee_growth_inside(&tick_lower, &tick_upper, tick_current, global_0, global_1);
+                        let after = get_fee_growth_inside(
+                            &tick_lower,
+                            &tick_upper,
+                            tick_current,
+                            global_0.wrapping_add(growth.0 as u64),
+                            global_1.wrapping_add(growth.1 as u64),
+                        );
+                        (after.0.wrapping_sub(before.0), after.1.wrapping_sub(before.1))
+                    };
+                    inside_growth(0) == inside_growth(offset)
+                }
+            }
+        }
+    }
+
+    mod checked_fee_growth_inside {
+        use super::*;
+
+        fn tick_with_outside(tick: i32, outside_0: u64, outside_1: u64) -> TickState {
+            TickState {
+                tick,
+                fee_growth_outside_0_x32: outside_0,
+                fee_growth_outside_1_x32: outside_1,
+                ..TickState::default()
+            }
+        }
+
+        fn assert_inconsistent(result: Result<(u64, u64)>) {
+            match result.err().unwrap() {
+                anchor_lang::error::Error::AnchorError(error) => assert_eq!(
+                    error.error_code_number,
+                    u32::from(ErrorCode::FeeGrowthInconsistent)
+                ),
+                error => panic!("unexpected error {:?}", error),
+            }
+        }
+
+        #[test]
+        fn matches_unchecked_growth_inside_below_and_above_the_range() {
+            let tick_lower = tick_with_outside(-2, 2, 3);
+            let tick_upper = tick_with_outside(2, 4, 1);
+            for tick_current in [-4, 0, 4] {
+                assert_eq!(
+                    checked_fee_growth_inside(&tick_lower, &tick_upper, tick_current, 15, 15)
+                        .unwrap(),
+                    get_fee_growth_inside(&tick_lower, &tick_upper, tick_current, 15, 15)
+                );
+            }
+        }
+
+        #[test]
+        fn accepts_underflow_of_a_lower_tick_initialized_after_the_upper() {
+            // 10 and 8 attributed below and above, of 15 in total
+            let tick_lower = tick_with_outside(-2, 10, 10);
+            let tick_upper = tick_with_outside(2, 8, 8);
+            assert_eq!(
+                checked_fee_growth_inside(&tick_lower, &tick_upper, 0, 15, 15).unwrap(),
+                (3u64.wrapping_neg(), 3u64.wrapping_neg())
+            );
+        }
+
+        #[test]
+        fn accepts_all_global_growth_inside() {
+            let tick_lower = TickState::default();
+            let tick_upper = tick_with_outside(2, 0, 0);
+            assert_eq!(
+                checked_fee_growth_inside(&tick_lower, &tick_upper, 0, u64::MAX, 0).unwrap(),
+                (u64::MAX, 0)
+            );
+        }
+
+        #[test]
+        fn rejects_a_corrupted_outside_growth_of_token_0() {
+            let tick_lower = tick_with_outside(-2, 1 << 40, 0);
+            let tick_upper = tick_with_outside(2, 0, 0);
+            assert_inconsistent(checked_fee_growth_inside(
+                &tick_lower,
+                &tick_upper,
+                0,
+                15,
+                15,
+            ));
+        }
+
+        #[test]
+        fn rejects_a_corrupted_outside_growth_of_token_1() {
+            let tick_lower = tick_with_outside(-2, 0, 0);
+            let tick_upper = tick_with_outside(2, 0, 20);
+            assert_inconsistent(checked_fee_growth_inside(
+                &tick_lower,
+                &tick_upper,
+                -4,
+                15,
+                15,
+            ));
+        }
+    }
+
+    mod get_reward_growth_inside {
+        use super::*;
+
+        fn tick(tick: i32, reward_growth_outside_x32: u64) -> TickState {
+            TickState {
+                tick,
+                reward_growth_outside_x32,
+                ..Default::default()
+            }
+        }
+
+        #[test]
+        fn returns_all_for_two_empty_ticks_if_tick_is_inside() {
+            assert_eq!(
+                get_reward_growth_inside(&tick(-2, 0), &tick(2, 0), 0, 15),
+                15
+            );
+        }
+
+        #[test]
+        fn returns_zero_for_two_empty_ticks_if_tick_is_outside() {
+            assert_eq!(
+                get_reward_growth_inside(&tick(-2, 0), &tick(2, 0), 4, 15),
+                0
+            );
+            assert_eq!(
+                get_reward_growth_inside(&tick(-2, 0), &tick(2, 0), -4, 15),
+                0
+            );
+        }
+
+        #[test]
+        fn subtracts_upper_tick_and_lower_tick_if_inside() {
+            assert_eq!(
+                get_reward_growth_inside(&tick(-2, 2), &tick(2, 4), 0, 15),
+                9
+            );
+        }
+
+        #[test]
+        fn accrues_only_while_the_price_is_inside() {
+            let mut tick_lower = tick(-2, 0);
+            let mut tick_upper = tick(2, 0);
+            // rewards of 5 accrue below the range, then the price enters it and rewards of
+            // 7 more accrue before the price leaves it above
+            let before = get_reward_growth_inside(&tick_lower, &tick_upper, -4, 5);
+            tick_lower.cross(0, 0, 5, 0, 0, 0);
+            let inside = get_reward_growth_inside(&tick_lower, &tick_upper, 0, 12);
+            tick_upper.cross(0, 0, 12, 0, 0, 0);
+            let after = get_reward_growth_inside(&tick_lower, &tick_upper, 4, 20);
+            assert_eq!(inside.wrapping_sub(before), 7);
+            assert_eq!(after, inside);
+        }
+
+        #[test]
+        fn works_correctly_with_overflow_on_inside_tick() {
+            assert_eq!(
+                get_reward_growth_inside(&tick(-2, u64::MAX - 3), &tick(2, 3), 0, 15),
+                16
+            );
+        }
+    }
+
+    mod get_fee_growth_below {
+        use super::*;
+
+        // fee growth of 15 so far, 4 and 6 of which happened below the tick when last crossed
+        fn tick_lower() -> TickState {
+            TickState {
+                tick: -2,
+                fee_growth_outside_0_x32: 4,
+                fee_growth_outside_1_x32: 6,
+                ..Default::default()
+            }
+        }
+
+        #[test]
+        fn reads_the_outside_growth_if_price_is_inside_the_range() {
+            assert_eq!(get_fee_growth_below(&tick_lower(), 0, 15, 15), (4, 6));
+        }
+
+        #[test]
+        fn reads_the_outside_growth_if_price_is_above_the_range() {
+            assert_eq!(get_fee_growth_below(&tick_lower(), 4, 15, 15), (4, 6));
+        }
+
+        #[test]
+        fn reads_the_outside_growth_if_price_is_at_the_tick() {
+            assert_eq!(get_fee_growth_below(&tick_lower(), -2, 15, 15), (4, 6));
+        }
+
+        #[test]
+        fn subtracts_the_outside_growth_if_price_is_below_the_range() {
+            assert_eq!(get_fee_growth_below(&tick_lower(), -4, 15, 15), (11, 9));
+            assert_eq!(get_fee_growth_below(&tick_lower(), -3, 15, 15), (11, 9));
+        }
+
+        #[test]
+        fn wraps_if_outside_growth_exceeds_global() {
+            let tick = TickState {
+                tick: -2,
+                fee_growth_outside_0_x32: u64::MAX - 3,
+                ..Default::default()
+            };
+            assert_eq!(get_fee_growth_below(&tick, -4, 15, 15), (19, 15));
+        }
+    }
+
+    mod get_fee_growth_above {
+        use super::*;
+
+        // fee growth of 15 so far, 2 and 3 of which happened above the tick when last crossed
+        fn tick_upper() -> TickState {
+            TickState {
+                tick: 2,
+                fee_growth_outside_0_x32: 2,
+                fee_growth_outside_1_x32: 3,
+                ..Default::default()
+            }
+        }
+
+        #[test]
+        fn reads_the_outside_growth_if_price_is_inside_the_range() {
+            assert_eq!(get_fee_growth_above(&tick_upper(), 0, 15, 15), (2, 3));
+        }
+
+        #[test]
+        fn reads_the_outside_growth_if_price_is_below_the_range() {
+            assert_eq!(get_fee_growth_above(&tick_upper(), -4, 15, 15), (2, 3));
+            assert_eq!(get_fee_growth_above(&tick_upper(), 1, 15, 15), (2, 3));
+        }
+
+        #[test]
+        fn subtracts_the_outside_growth_if_price_is_at_the_tick() {
+            assert_eq!(get_fee_growth_above(&tick_upper(), 2, 15, 15), (13, 12));
+        }
+
+        #[test]
+        fn subtracts_the_outside_growth_if_price_is_above_the_range() {
+            assert_eq!(get_fee_growth_above(&tick_upper(), 4, 15, 15), (13, 12));
+        }
+
+        #[test]
+        fn wraps_if_outside_growth_exceeds_global() {
+            let tick = TickState {
+                tick: 2,
+                fee_growth_outside_1_x32: u64::MAX - 2,
+                ..Default::default()
+            };
+            assert_eq!(get_fee_growth_above(&tick, 4, 15, 15), (15, 18));
+        }
     }
 
     mod update {
@@ -437,46 +1063,46 @@ mod test {
         #[test]
         fn flips_from_zero_to_non_zero() {
             let mut tick = TickState::default();
-            assert!(tick.update(0, 1, 0, 0, 0, 0, 0, false, 3).unwrap());
+            assert!(tick.update(0, 1, 0, 0, 0, 0, 0, 0, false, 3).unwrap());
         }
 
         #[test]
         fn does_not_flip_from_nonzero_to_greater_nonzero() {
             let mut tick = TickState::default();
-            tick.update(0, 1, 0, 0, 0, 0, 0, false, 3).unwrap();
-            assert!(!tick.update(0, 1, 0, 0, 0, 0, 0, false, 3).unwrap());
+            tick.update(0, 1, 0, 0, 0, 0, 0, 0, false, 3).unwrap();
+            assert!(!tick.update(0, 1, 0, 0, 0, 0, 0, 0, false, 3).unwrap());
         }
 
         #[test]
         fn flips_from_nonzero_to_zero() {
             let mut tick = TickState::default();
-            tick.update(0, 1, 0, 0, 0, 0, 0, false, 3).unwrap();
-            assert!(tick.update(0, -1, 0, 0, 0, 0, 0, false, 3).unwrap());
+            tick.update(0, 1, 0, 0, 0, 0, 0, 0, false, 3).unwrap();
+            assert!(tick.update(0, -1, 0, 0, 0, 0, 0, 0, false, 3).unwrap());
         }
 
         #[test]
         fn does_not_flip_from_nonzero_to_lesser_nonzero() {
             let mut tick = TickState::default();
-            tick.update(0, 2, 0, 0, 0, 0, 0, false, 3).unwrap();
-            assert!(!tick.update(0, -1, 0, 0, 0, 0, 0, false, 3).unwrap());
+            tick.update(0, 2, 0, 0, 0, 0, 0, 0, false, 3).unwrap();
+            assert!(!tick.update(0, -1, 0, 0, 0, 0, 0, 0, false, 3).unwrap());
         }
 
         #[test]
         #[should_panic(expected = "LO")]
         fn reverts_if_total_liquidity_gross_is_greater_than_max() {
             let mut tick = TickState::default();
-            tick.update(0, 2, 0, 0, 0, 0, 0, false, 3).unwrap();
-            tick.update(0, 2, 0, 0, 0, 0, 0, false, 3).unwrap();
-            tick.update(0, 1, 0, 0, 0, 0, 0, false, 3).unwrap();
+            tick.update(0, 2, 0, 0, 0, 0, 0, 0, false, 3).unwrap();
+            tick.update(0, 2, 0, 0, 0, 0, 0, 0, false, 3).unwrap();
+            tick.update(0, 1, 0, 0, 0, 0, 0, 0, false, 3).unwrap();
         }
 
         #[test]
         fn nets_the_liquidity_based_on_upper_flag() {
             let mut tick = TickState::default();
-            tick.update(0, 2, 0, 0, 0, 0, 0, false, 3).unwrap();
-            tick.update(0, 1, 0, 0, 0, 0, 0, true, 10).unwrap();
-            tick.update(0, 3, 0, 0, 0, 0, 0, true, 10).unwrap();
-            tick.update(0, 1, 0, 0, 0, 0, 0, false, 10).unwrap();
+            tick.update(0, 2, 0, 0, 0, 0, 0, 0, false, 3).unwrap();
+            tick.update(0, 1, 0, 0, 0, 0, 0, 0, true, 10).unwrap();
+            tick.update(0, 3, 0, 0, 0, 0, 0, 0, true, 10).unwrap();
+            tick.update(0, 1, 0, 0, 0, 0, 0, 0, false, 10).unwrap();
 
             assert!(tick.liquidity_gross == 2 + 1 + 3 + 1);
             assert!(tick.liquidity_net == 2 - 1 - 3 + 1);
@@ -486,33 +1112,135 @@ mod test {
         #[should_panic]
         fn reverts_on_overflow_liquidity_gross() {
             let mut tick = TickState::default();
-            tick.update(0, (u64::MAX / 2 - 1) as i64, 0, 0, 0, 0, 0, false, u64::MAX)
-                .unwrap();
-            tick.update(0, (u64::MAX / 2 - 1) as i64, 0, 0, 0, 0, 0, false, u64::MAX)
-                .unwrap();
+            tick.update(
+                0,
+                (u64::MAX / 2 - 1) as i64,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+                false,
+                u64::MAX,
+            )
+            .unwrap();
+            tick.update(
+                0,
+                (u64::MAX / 2 - 1) as i64,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+                false,
+                u64::MAX,
+            )
+            .unwrap();
         }
 
         #[test]
         fn assume_all_growth_happens_below_ticks_lte_current_tick() {
             let mut tick = TickState::default();
             tick.tick = 1;
-            tick.update(1, 1, 1, 2, 3, 4, 5, false, u64::MAX).unwrap();
+            tick.update(1, 1, 1, 2, 8, 3, 4, 5, false, u64::MAX)
+                .unwrap();
 
             assert!(tick.fee_growth_outside_0_x32 == 1);
             assert!(tick.fee_growth_outside_1_x32 == 2);
+            assert!(tick.reward_growth_outside_x32 == 8);
             assert!(tick.seconds_per_liquidity_outside_x32 == 3);
             assert!(tick.tick_cumulative_outside == 4);
             assert!(tick.seconds_outside == 5);
         }
 
+        #[test]
+        fn keeps_growth_outside_of_an_initialized_tick() {
+            let mut tick = TickState::default();
+            tick.tick = 1;
+            tick.update(1, 1, 1, 2, 8, 3, 4, 5, false, u64::MAX)
+                .unwrap();
+            // a second position with the same boundary, after growth
+            tick.update(1, 2, 10, 20, 80, 30, 40, 50, true, u64::MAX)
+                .unwrap();
+
+            assert!(tick.liquidity_gross == 3);
+            assert!(tick.liquidity_net == 1 - 2);
+            assert!(tick.fee_growth_outside_0_x32 == 1);
+            assert!(tick.fee_growth_outside_1_x32 == 2);
+            assert!(tick.reward_growth_outside_x32 == 8);
+            assert!(tick.seconds_per_liquidity_outside_x32 == 3);
+            assert!(tick.tick_cumulative_outside == 4);
+            assert!(tick.seconds_outside == 5);
+        }
+
+        #[test]
+        fn minting_on_a_shared_boundary_keeps_the_fees_of_the_first_position() {
+            use crate::states::position::PositionState;
+            let liquidity = 1 << 32;
+            let (mut tick_lower_a, mut tick_shared, mut tick_upper_b) = (
+                TickState {
+                    tick: -100,
+                    ..Default::default()
+                },
+                TickState {
+                    tick: 0,
+                    ..Default::default()
+                },
+                TickState {
+                    tick: 100,
+                    ..Default::default()
+                },
+            );
+
+            // position A over [-100, 0] is minted at tick -50 with fee growth 10
+            let mut position_a = PositionState::default();
+            tick_lower_a
+                .update(-50, liquidity, 10, 0, 0, 0, 0, 0, false, u64::MAX)
+                .unwrap();
+            tick_shared
+                .update(-50, liquidity, 10, 0, 0, 0, 0, 0, true, u64::MAX)
+                .unwrap();
+            let (fee_growth_inside_0_x32, _) =
+                get_fee_growth_inside(&tick_lower_a, &tick_shared, -50, 10, 0);
+            position_a
+                .update(liquidity, fee_growth_inside_0_x32, 0, 0)
+                .unwrap();
+
+            // A earns growth of 20, then the price crosses into [0, 100] and growth reaches 40
+            tick_shared.cross(30, 0, 0, 0, 0, 0);
+            assert_eq!(
+                get_fee_growth_inside(&tick_lower_a, &tick_shared, 50, 40, 0).0,
+                fee_growth_inside_0_x32 + 20
+            );
+
+            // position B over [0, 100] is minted at tick 50, sharing tick 0 as its lower tick
+            tick_shared
+                .update(50, liquidity, 40, 0, 0, 0, 0, 0, false, u64::MAX)
+                .unwrap();
+            tick_upper_b
+                .update(50, liquidity, 40, 0, 0, 0, 0, 0, true, u64::MAX)
+                .unwrap();
+            assert!({ tick_shared.fee_growth_outside_0_x32 } == 30);
+
+            // A is still owed the growth of 20 earned in range, and none of the growth after
+            let (fee_growth_inside_0_x32, _) =
+                get_fee_growth_inside(&tick_lower_a, &tick_shared, 50, 40, 0);
+            position_a.update(0, fee_growth_inside_0_x32, 0, 0).unwrap();
+            assert_eq!({ position_a.tokens_owed_0 }, 20);
+        }
+
         #[test]
         fn does_not_set_any_growth_fields_for_ticks_gt_current_tick() {
             let mut tick = TickState::default();
             tick.tick = 2;
-            tick.update(1, 1, 1, 2, 3, 4, 5, false, u64::MAX).unwrap();
+            tick.update(1, 1, 1, 2, 8, 3, 4, 5, false, u64::MAX)
+                .unwrap();
 
             assert!(tick.fee_growth_outside_0_x32 == 0);
             assert!(tick.fee_growth_outside_1_x32 == 0);
+            assert!(tick.reward_growth_outside_x32 == 0);
             assert!(tick.seconds_per_liquidity_outside_x32 == 0);
             assert!(tick.tick_cumulative_outside == 0);
             assert!(tick.seconds_outside == 0);
@@ -534,11 +1262,13 @@ mod test {
                 tick_cumulative_outside: 6,
                 seconds_per_liquidity_outside_x32: 5,
                 seconds_outside: 7,
+                reward_growth_outside_x32: 3,
             };
             tick.clear();
             assert!(tick.bump == 255);
             assert!(tick.fee_growth_outside_0_x32 == 0);
             assert!(tick.fee_growth_outside_1_x32 == 0);
+            assert!(tick.reward_growth_outside_x32 == 0);
             assert!(tick.seconds_per_liquidity_outside_x32 == 0);
             assert!(tick.tick_cumulative_outside == 0);
             assert!(tick.seconds_outside == 0);
@@ -562,11 +1292,13 @@ mod test {
                 tick_cumulative_outside: 6,
                 seconds_per_liquidity_outside_x32: 5,
                 seconds_outside: 7,
+                reward_growth_outside_x32: 3,
             };
-            tick.cross(7, 9, 8, 15, 10);
+            tick.cross(7, 9, 11, 8, 15, 10);
 
             assert!(tick.fee_growth_outside_0_x32 == 6);
             assert!(tick.fee_growth_outside_1_x32 == 7);
+            assert!(tick.reward_growth_outside_x32 == 8);
             assert!(tick.seconds_per_liquidity_outside_x32 == 3);
             assert!(tick.tick_cumulative_outside == 9);
             assert!(tick.seconds_outside == 3);
@@ -584,15 +1316,421 @@ mod test {
                 tick_cumulative_outside: 6,
                 seconds_per_liquidity_outside_x32: 5,
                 seconds_outside: 7,
+                reward_growth_outside_x32: 3,
             };
-            tick.cross(7, 9, 8, 15, 10);
-            tick.cross(7, 9, 8, 15, 10);
+            tick.cross(7, 9, 11, 8, 15, 10);
+            tick.cross(7, 9, 11, 8, 15, 10);
 
             assert!(tick.fee_growth_outside_0_x32 == 1);
             assert!(tick.fee_growth_outside_1_x32 == 2);
+            assert!(tick.reward_growth_outside_x32 == 3);
             assert!(tick.seconds_per_liquidity_outside_x32 == 5);
             assert!(tick.tick_cumulative_outside == 6);
             assert!(tick.seconds_outside == 7);
         }
+
+        #[test]
+        fn crosses_after_fee_growth_rolls_over() {
+            // the global fee growth wrapped past u64::MAX after the outside growth was recorded
+            let mut tick = TickState {
+                bump: 255,
+                tick: 2,
+                liquidity_net: 4,
+                liquidity_gross: 3,
+                fee_growth_outside_0_x32: u64::MAX - 1,
+                fee_growth_outside_1_x32: u64::MAX - 2,
+                tick_cumulative_outside: 6,
+                seconds_per_liquidity_outside_x32: 5,
+                seconds_outside: 7,
+                reward_growth_outside_x32: 3,
+            };
+            tick.cross(3, 4, 11, 8, 15, 10);
+
+            assert!(tick.fee_growth_outside_0_x32 == 5);
+            assert!(tick.fee_growth_outside_1_x32 == 7);
+
+            tick.cross(3, 4, 11, 8, 15, 10);
+            assert!(tick.fee_growth_outside_0_x32 == u64::MAX - 1);
+            assert!(tick.fee_growth_outside_1_x32 == u64::MAX - 2);
+        }
+    }
+
+    mod check_bitmap {
+        use super::*;
+
+        const TICK_SPACING: u16 = 2;
+
+        fn tick(tick: i32) -> TickState {
+            TickState {
+                tick,
+                ..Default::default()
+            }
+        }
+
+        fn bit_pos(tick: &TickState) -> u8 {
+            ((tick.tick / TICK_SPACING as i32) % 256) as u8
+        }
+
+        /// Updates the ticks and bitmap of a position as done by mint and burn
+        fn modify_position(
+            tick_lower: &mut TickState,
+            tick_upper: &mut TickState,
+            bitmap: &mut TickBitmapState,
+            liquidity_delta: i64,
+        ) -> Result<()> {
+            let flipped_lower =
+                tick_lower.update(0, liquidity_delta, 0, 0, 0, 0, 0, 0, false, u64::MAX)?;
+            let flipped_upper =
+                tick_upper.update(0, liquidity_delta, 0, 0, 0, 0, 0, 0, true, u64::MAX)?;
+            if flipped_lower {
+                bitmap.flip_bit(bit_pos(tick_lower));
+            }
+            if flipped_upper {
+                bitmap.flip_bit(bit_pos(tick_upper));
+            }
+            if liquidity_delta < 0 {
+                if flipped_lower {
+                    tick_lower.clear();
+                }
+                if flipped_upper {
+                    tick_upper.clear();
+                }
+            }
+            tick_lower.check_bitmap(bitmap, TICK_SPACING)?;
+            tick_upper.check_bitmap(bitmap, TICK_SPACING)
+        }
+
+        #[test]
+        fn consistent_through_mint_and_full_burn_cycles() {
+            let mut bitmap = TickBitmapState::default();
+            let mut tick_a = tick(-4);
+            let mut tick_b = tick(2);
+            let mut tick_c = tick(6);
+
+            for _ in 0..2 {
+                modify_position(&mut tick_a, &mut tick_b, &mut bitmap, 10).unwrap();
+                // shares the lower tick with the first position
+                modify_position(&mut tick_a, &mut tick_c, &mut bitmap, 5).unwrap();
+                assert!(bitmap.is_initialized(bit_pos(&tick_a)));
+                assert!(bitmap.is_initialized(bit_pos(&tick_b)));
+                assert!(bitmap.is_initialized(bit_pos(&tick_c)));
+
+                // the shared tick stays initialized
+                modify_position(&mut tick_a, &mut tick_b, &mut bitmap, -10).unwrap();
+                assert!(bitmap.is_initialized(bit_pos(&tick_a)));
+                assert!(!bitmap.is_initialized(bit_pos(&tick_b)));
+                assert!(tick_b.is_clear());
+
+                modify_position(&mut tick_a, &mut tick_c, &mut bitmap, -5).unwrap();
+                assert_eq!({ bitmap.word }, [0; 4]);
+                assert!(tick_a.is_clear() && tick_c.is_clear());
+            }
+        }
+
+        #[test]
+        fn fails_if_bit_is_not_set_for_initialized_tick() {
+            let bitmap = TickBitmapState::default();
+            let mut tick_state = tick(-4);
+            tick_state
+                .update(0, 10, 0, 0, 0, 0, 0, 0, false, u64::MAX)
+                .unwrap();
+            assert!(tick_state.check_bitmap(&bitmap, TICK_SPACING).is_err());
+        }
+
+        #[test]
+        fn fails_if_bit_is_set_for_uninitialized_tick() {
+            let mut bitmap = TickBitmapState::default();
+            let tick_state = tick(6);
+            bitmap.flip_bit(bit_pos(&tick_state));
+            assert!(tick_state.check_bitmap(&bitmap, TICK_SPACING).is_err());
+        }
+
+        #[test]
+        fn ignores_other_bits_in_the_word() {
+            let mut bitmap = TickBitmapState::default();
+            bitmap.flip_bit(bit_pos(&tick(2)));
+            assert!(tick(6).check_bitmap(&bitmap, TICK_SPACING).is_ok());
+        }
+    }
+
+    mod liquidity_at_tick {
+        use super::*;
+
+        const TICK_CURRENT: i32 = 10;
+
+        // (tick_lower, tick_upper, liquidity)
+        const POSITIONS: [(i32, i32, i64); 3] = [(-120, 120, 100), (-60, 60, 50), (0, 180, 30)];
+
+        /// Initialized ticks of the positions in ascending order, with an uninitialized tick
+        /// in between
+        fn ticks() -> Vec<TickState> {
+            let mut ticks: Vec<TickState> = [-120, -60, 0, 60, 90, 120, 180]
+                .iter()
+                .map(|&tick| TickState {
+                    tick,
+                    ..Default::default()
+                })
+                .collect();
+            for (tick_lower, tick_upper, liquidity) in POSITIONS {
+                for tick_state in ticks.iter_mut() {
+                    let tick = tick_state.tick;
+                    if tick == tick_lower || tick == tick_upper {
+                        tick_state
+                            .update(
+                                TICK_CURRENT,
+                                liquidity,
+                                0,
+                                0,
+                                0,
+                                0,
+                                0,
+                                0,
+                                tick == tick_upper,
+                                u64::MAX,
+                            )
+                            .unwrap();
+                    }
+                }
+            }
+            ticks
+        }
+
+        fn current_liquidity() -> u64 {
+            position_liquidity(TICK_CURRENT)
+        }
+
+        /// Sum of the positions active at the tick
+        fn position_liquidity(tick: i32) -> u64 {
+            POSITIONS
+                .iter()
+                .filter(|(tick_lower, tick_upper, _)| *tick_lower <= tick && tick < *tick_upper)
+                .map(|(_, _, liquidity)| *liquidity as u64)
+                .sum()
+        }
+
+        /// Crosses ticks one at a time towards the target, as the swap loop does
+        fn swap_loop_liquidity(mut ticks: Vec<TickState>, target_tick: i32) -> u64 {
+            let mut liquidity = current_liquidity();
+            let zero_for_one = target_tick < TICK_CURRENT;
+            if zero_for_one {
+                ticks.reverse();
+            }
+            for tick_state in ticks.iter_mut() {
+                let tick = tick_state.tick;
+                let crossed = if zero_for_one {
+                    tick <= TICK_CURRENT && tick > target_tick
+                } else {
+                    tick > TICK_CURRENT && tick <= target_tick
+                };
+                if crossed && tick_state.liquidity_gross != 0 {
+                    let mut liquidity_net = tick_state.cross(0, 0, 0, 0, 0, 0);
+                    if zero_for_one {
+                        liquidity_net = -liquidity_net;
+                    }
+                    liquidity = liquidity_math::add_delta(liquidity, liquidity_net).unwrap();
+                }
+            }
+            liquidity
+        }
+
+        #[test]
+        fn unchanged_at_current_tick() {
+            assert_eq!(
+                liquidity_at_tick(&ticks(), current_liquidity(), TICK_CURRENT, TICK_CURRENT),
+                180
+            );
+        }
+
+        #[test]
+        fn matches_swap_loop_in_every_band() {
+            for target_tick in [
+                -200, -120, -100, -60, -1, 0, 30, 59, 60, 90, 119, 120, 150, 180, 250,
+            ] {
+                let liquidity =
+                    liquidity_at_tick(&ticks(), current_liquidity(), TICK_CURRENT, target_tick);
+                assert_eq!(liquidity, swap_loop_liquidity(ticks(), target_tick));
+                assert_eq!(liquidity, position_liquidity(target_tick));
+            }
+        }
+
+        #[test]
+        fn independent_of_tick_order() {
+            let mut ticks = ticks();
+            ticks.reverse();
+            ticks.swap(1, 4);
+            assert_eq!(
+                liquidity_at_tick(&ticks, current_liquidity(), TICK_CURRENT, -100),
+                100
+            );
+            assert_eq!(
+                liquidity_at_tick(&ticks, current_liquidity(), TICK_CURRENT, 150),
+                30
+            );
+        }
+
+        #[test]
+        fn walks_from_outside_the_liquidity() {
+            assert_eq!(liquidity_at_tick(&ticks(), 0, -200, 30), 180);
+            assert_eq!(liquidity_at_tick(&ticks(), 0, 200, -100), 100);
+        }
+    }
+
+    mod liquidity_net_sum {
+        use super::*;
+        use std::collections::BTreeMap;
+
+        /// Applies a position update to the lower and upper ticks, clearing ticks that flip
+        /// to uninitialized
+        fn modify(ticks: &mut BTreeMap<i32, TickState>, lower: i32, upper: i32, delta: i64) {
+            for (tick, is_upper) in [(lower, false), (upper, true)] {
+                let tick_state = ticks.entry(tick).or_insert(TickState {
+                    tick,
+                    ..Default::default()
+                });
+                let flipped = tick_state
+                    .update(0, delta, 0, 0, 0, 0, 0, 0, is_upper, u64::MAX)
+                    .unwrap();
+                if flipped && delta < 0 {
+                    ticks.remove(&tick);
+                }
+            }
+        }
+
+        fn initialized(ticks: &BTreeMap<i32, TickState>) -> Vec<TickState> {
+            ticks.values().copied().collect()
+        }
+
+        #[test]
+        fn mint_adds_to_lower_and_removes_from_upper() {
+            assert_eq!(liquidity_net_delta(100, false), 100);
+            assert_eq!(liquidity_net_delta(100, true), -100);
+            assert_eq!(liquidity_net_delta(-100, false), -100);
+            assert_eq!(liquidity_net_delta(-100, true), 100);
+
+            let mut ticks = BTreeMap::new();
+            modify(&mut ticks, -10, 10, 100);
+            assert_eq!({ ticks[&-10].liquidity_net }, 100);
+            assert_eq!({ ticks[&10].liquidity_net }, -100);
+        }
+
+        #[test]
+        fn holds_while_minting_and_burning_ranges() {
+            let updates = [
+                (-60, 60, 1000),
+                (-60, 0, 250),
+                (0, 120, 400),
+                (-120, -60, 75),
+                (-60, 60, -600),
+                (0, 120, -400),
+                (-60, 0, -250),
+                (-120, -60, -75),
+                (-60, 60, -400),
+            ];
+            let mut ticks = BTreeMap::new();
+            for (lower, upper, delta) in updates {
+                modify(&mut ticks, lower, upper, delta);
+                check_liquidity_net_sum(&initialized(&ticks)).unwrap();
+            }
+            // every range was burned, and each tick cleared
+            assert!(ticks.is_empty());
+        }
+
+        #[test]
+        fn rejects_an_imbalanced_tick_set() {
+            let mut ticks = BTreeMap::new();
+            modify(&mut ticks, -60, 60, 1000);
+            modify(&mut ticks, 0, 120, 400);
+            ticks.remove(&120);
+            match check_liquidity_net_sum(&initialized(&ticks)).err().unwrap() {
+                anchor_lang::error::Error::AnchorError(error) => assert_eq!(
+                    error.error_code_number,
+                    u32::from(ErrorCode::LiquidityNetImbalance)
+                ),
+                error => panic!("unexpected error {}", error),
+            }
+        }
+    }
+
+    mod check_unique_tick_accounts {
+        use super::*;
+
+        struct TestAccount {
+            key: Pubkey,
+            owner: Pubkey,
+            lamports: u64,
+            data: Vec<u8>,
+        }
+
+        fn tick_account(key: u8) -> TestAccount {
+            let mut data = TickState::discriminator().to_vec();
+            data.extend_from_slice(anchor_lang::__private::bytemuck::bytes_of(
+                &TickState::default(),
+            ));
+            TestAccount {
+                key: Pubkey::new_from_array([key; 32]),
+                owner: crate::ID,
+                lamports: 0,
+                data,
+            }
+        }
+
+        fn account_infos(accounts: &mut [TestAccount]) -> Vec<AccountInfo<'_>> {
+            accounts
+                .iter_mut()
+                .map(|account| {
+                    AccountInfo::new(
+                        &account.key,
+                        false,
+                        true,
+                        &mut account.lamports,
+                        &mut account.data,
+                        &account.owner,
+                        false,
+                        0,
+                    )
+                })
+                .collect()
+        }
+
+        #[test]
+        fn accepts_distinct_tick_accounts() {
+            let mut accounts = [tick_account(1), tick_account(2), tick_account(3)];
+            assert!(check_unique_tick_accounts(&account_infos(&mut accounts)).is_ok());
+        }
+
+        #[test]
+        fn rejects_a_duplicated_tick_account() {
+            let mut accounts = [tick_account(1), tick_account(2), tick_account(1)];
+            match check_unique_tick_accounts(&account_infos(&mut accounts))
+                .err()
+                .unwrap()
+            {
+                anchor_lang::error::Error::AnchorError(error) => assert_eq!(
+                    error.error_code_number,
+                    u32::from(ErrorCode::DuplicateTickAccount)
+                ),
+                error => panic!("unexpected error {}", error),
+            }
+        }
+
+        #[test]
+        fn ignores_accounts_other_than_ticks() {
+            let bitmap = || TestAccount {
+                data: vec![0; 16],
+                ..tick_account(1)
+            };
+            let foreign_tick = || TestAccount {
+                owner: Pubkey::new_from_array([9; 32]),
+                ..tick_account(2)
+            };
+            let mut accounts = [
+                bitmap(),
+                bitmap(),
+                foreign_tick(),
+                foreign_tick(),
+                tick_account(3),
+            ];
+            assert!(check_unique_tick_accounts(&account_infos(&mut accounts)).is_ok());
+        }
     }
 }
@@ -66,6 +66,127 @@ pub fn position(tick_by_spacing: i32) -> Position {
     }
 }
 
+/// Returns the initialized ticks in `[tick_lower, tick_upper]` in ascending order, by scanning
+/// the bitmap words covering the range.
+///
+/// Words without a bitmap account are empty, so only the fetched bitmap accounts of the pool
+/// need to be passed, in any order. The range bounds need not be multiples of the tick spacing.
+///
+/// # Arguments
+///
+/// * `bitmaps` - Bitmap words of the pool
+/// * `tick_spacing` - The tick spacing of the pool
+/// * `tick_lower` - The lowest tick to return
+/// * `tick_upper` - The highest tick to return
+///
+pub fn initialized_ticks_in_range(
+    bitmaps: &[TickBitmapState],
+    tick_spacing: u16,
+    tick_lower: i32,
+    tick_upper: i32,
+) -> Vec<i32> {
+    let tick_spacing = tick_spacing as i32;
+    // the compressed ticks whose ticks lie in the range
+    let compressed_lower = -(-tick_lower).div_euclid(tick_spacing);
+    let compressed_upper = tick_upper.div_euclid(tick_spacing);
+
+    let mut ticks = Vec::new();
+    if compressed_lower > compressed_upper {
+        return ticks;
+    }
+    let lower = position(compressed_lower);
+    let upper = position(compressed_upper);
+    for word_pos in lower.word_pos..=upper.word_pos {
+        let bitmap = match bitmaps.iter().find(|bitmap| bitmap.word_pos == word_pos) {
+            Some(bitmap) => bitmap,
+            None => continue,
+        };
+        let first_bit = if word_pos == lower.word_pos {
+            lower.bit_pos
+        } else {
+            0
+        };
+        let last_bit = if word_pos == upper.word_pos {
+            upper.bit_pos
+        } else {
+            u8::MAX
+        };
+
+        let mut bit_pos = first_bit;
+        loop {
+            let NextBit { next, initialized } = bitmap.next_initialized_bit(bit_pos, false);
+            if !initialized || next > last_bit {
+                break;
+            }
+            ticks.push(((word_pos as i32) << 8 | next as i32) * tick_spacing);
+            if next == last_bit {
+                break;
+            }
+            bit_pos = next + 1;
+        }
+    }
+    ticks
+}
+
+/// Returns the nearest initialized tick at or below the current tick, and the nearest above
+/// it, by searching bitmap words outwards from the word of the current tick.
+///
+/// The current tick counts as below, as for a zero for one swap, so that the returned ticks
+/// bound the range the current tick lies in. A side without initialized ticks is None. Words
+/// without a bitmap account are empty, so only the fetched bitmap accounts of the pool need to
+/// be passed, in any order.
+///
+/// # Arguments
+///
+/// * `bitmaps` - Bitmap words of the pool
+/// * `tick_spacing` - The tick spacing of the pool
+/// * `current_tick` - The current tick of the pool
+///
+pub fn surrounding_ticks(
+    bitmaps: &[TickBitmapState],
+    tick_spacing: u16,
+    current_tick: i32,
+) -> (Option<i32>, Option<i32>) {
+    let tick_spacing = tick_spacing as i32;
+    let compressed = current_tick.div_euclid(tick_spacing);
+    let mut words: Vec<&TickBitmapState> = bitmaps.iter().collect();
+    words.sort_by_key(|bitmap| bitmap.word_pos);
+    let tick_at =
+        |word_pos: i16, bit_pos: u8| ((word_pos as i32) << 8 | bit_pos as i32) * tick_spacing;
+
+    let below_start = position(compressed);
+    let below = words
+        .iter()
+        .rev()
+        .filter(|bitmap| bitmap.word_pos <= below_start.word_pos)
+        .find_map(|bitmap| {
+            let bit_pos = if bitmap.word_pos == below_start.word_pos {
+                below_start.bit_pos
+            } else {
+                u8::MAX
+            };
+            let NextBit { next, initialized } = bitmap.next_initialized_bit(bit_pos, true);
+            initialized.then(|| tick_at(bitmap.word_pos, next))
+        });
+
+    // the current tick is not considered above
+    let above_start = position(compressed + 1);
+    let above = words
+        .iter()
+        .filter(|bitmap| bitmap.word_pos >= above_start.word_pos)
+        .find_map(|bitmap| {
+            let bit_pos = if bitmap.word_pos == above_start.word_pos {
+                above_start.bit_pos
+            } else {
+                0
+            };
+            let NextBit { next, initialized } = bitmap.next_initialized_bit(bit_pos, false);
+            initialized.then(|| tick_at(bitmap.word_pos, next))
+        });
+
+    (below, above)
+}
+
 impl TickBitmapState {
     ///  Flips the initialized state for a given bit from false to true, or vice versa
     ///
@@ -134,8 +255,7 @@ impl TickBitmapState {
     }
 
     /// Whether the tick at given bit position is initialized
-    #[cfg(test)]
-    fn is_initialized(self, bit_pos: u8) -> bool {
+    pub fn is_initialized(&self, bit_pos: u8) -> bool {
         let next_bit = self.next_initialized_bit(bit_pos, true);
         next_bit.next == bit_pos && next_bit.initialized
     }
@@ -303,4 +423,190 @@ mod tests {
             }
         }
     }
+
+    mod initialized_ticks_in_range {
+        use super::*;
+
+        pub(super) const TICK_SPACING: u16 = 10;
+
+        /// Bitmap words with the given ticks initialized
+        pub(super) fn bitmaps(ticks: &[i32]) -> Vec<TickBitmapState> {
+            let mut bitmaps: Vec<TickBitmapState> = Vec::new();
+            for tick in ticks {
+                let Position { word_pos, bit_pos } = position(tick / TICK_SPACING as i32);
+                let index = match bitmaps
+                    .iter()
+                    .position(|bitmap| bitmap.word_pos == word_pos)
+                {
+                    Some(index) => index,
+                    None => {
+                        bitmaps.push(TickBitmapState {
+                            word_pos,
+                            ..Default::default()
+                        });
+                        bitmaps.len() - 1
+                    }
+                };
+                bitmaps[index].flip_bit(bit_pos);
+            }
+            bitmaps
+        }
+
+        // words -2, -1, 0, 1 and 3 hold ticks, word 2 has no account
+        const TICKS: [i32; 11] = [
+            -5110, -2570, -2560, -10, 0, 630, 2550, 2560, 3000, 5110, 7690,
+        ];
+
+        #[test]
+        fn returns_the_ticks_across_words_in_order() {
+            // the bitmaps are passed out of order
+            let bitmaps = bitmaps(&[
+                5110, -10, 630, 2560, 0, -2560, 7690, 3000, -5110, 2550, -2570,
+            ]);
+            assert_eq!(
+                initialized_ticks_in_range(&bitmaps, TICK_SPACING, -5120, 7690),
+                TICKS.to_vec()
+            );
+            assert_eq!(
+                initialized_ticks_in_range(&bitmaps, TICK_SPACING, i32::MIN / 2, i32::MAX / 2),
+                TICKS.to_vec()
+            );
+        }
+
+        #[test]
+        fn includes_ticks_on_the_range_edges() {
+            let bitmaps = bitmaps(&TICKS);
+            // edges on the last bit of word -2 and the first bit of word 1
+            assert_eq!(
+                initialized_ticks_in_range(&bitmaps, TICK_SPACING, -2570, 2560),
+                vec![-2570, -2560, -10, 0, 630, 2550, 2560]
+            );
+            // edges in the middle of words 0 and 1
+            assert_eq!(
+                initialized_ticks_in_range(&bitmaps, TICK_SPACING, 630, 3000),
+                vec![630, 2550, 2560, 3000]
+            );
+            assert_eq!(
+                initialized_ticks_in_range(&bitmaps, TICK_SPACING, 0, 0),
+                vec![0]
+            );
+        }
+
+        #[test]
+        fn excludes_ticks_just_outside_the_range() {
+            let bitmaps = bitmaps(&TICKS);
+            assert_eq!(
+                initialized_ticks_in_range(&bitmaps, TICK_SPACING, -2569, 2559),
+                vec![-2560, -10, 0, 630, 2550]
+            );
+            // bounds between multiples of the tick spacing
+            assert_eq!(
+                initialized_ticks_in_range(&bitmaps, TICK_SPACING, -15, 635),
+                vec![-10, 0, 630]
+            );
+            assert_eq!(
+                initialized_ticks_in_range(&bitmaps, TICK_SPACING, -9, -1),
+                Vec::<i32>::new()
+            );
+        }
+
+        #[test]
+        fn skips_words_without_a_bitmap() {
+            let bitmaps = bitmaps(&TICKS);
+            assert_eq!(
+                initialized_ticks_in_range(&bitmaps, TICK_SPACING, 5120, 7680),
+                Vec::<i32>::new()
+            );
+            assert_eq!(
+                initialized_ticks_in_range(&bitmaps, TICK_SPACING, 3001, 7690),
+                vec![5110, 7690]
+            );
+        }
+
+        #[test]
+        fn returns_nothing_for_an_inverted_range() {
+            let bitmaps = bitmaps(&TICKS);
+            assert_eq!(
+                initialized_ticks_in_range(&bitmaps, TICK_SPACING, 630, 0),
+                Vec::<i32>::new()
+            );
+        }
+    }
+
+    mod surrounding_ticks {
+        use super::initialized_ticks_in_range::{bitmaps, TICK_SPACING};
+        use super::*;
+
+        #[test]
+        fn finds_ticks_on_both_sides() {
+            let bitmaps = bitmaps(&[-2570, -10, 630, 5110]);
+            assert_eq!(
+                surrounding_ticks(&bitmaps, TICK_SPACING, 0),
+                (Some(-10), Some(630))
+            );
+            // across words, and across a word without a bitmap account
+            assert_eq!(
+                surrounding_ticks(&bitmaps, TICK_SPACING, -20),
+                (Some(-2570), Some(-10))
+            );
+            assert_eq!(
+                surrounding_ticks(&bitmaps, TICK_SPACING, 640),
+                (Some(630), Some(5110))
+            );
+        }
+
+        #[test]
+        fn counts_the_current_tick_as_below() {
+            let bitmaps = bitmaps(&[-10, 0, 630]);
+            assert_eq!(
+                surrounding_ticks(&bitmaps, TICK_SPACING, 0),
+                (Some(0), Some(630))
+            );
+            // between multiples of the tick spacing
+            assert_eq!(
+                surrounding_ticks(&bitmaps, TICK_SPACING, 9),
+                (Some(0), Some(630))
+            );
+            assert_eq!(
+                surrounding_ticks(&bitmaps, TICK_SPACING, -1),
+                (Some(-10), Some(0))
+            );
+        }
+
+        #[test]
+        fn finds_ticks_only_below() {
+            let bitmaps = bitmaps(&[-5110, -2570]);
+            assert_eq!(
+                surrounding_ticks(&bitmaps, TICK_SPACING, 3000),
+                (Some(-2570), None)
+            );
+            assert_eq!(
+                surrounding_ticks(&bitmaps, TICK_SPACING, -2570),
+                (Some(-2570), None)
+            );
+        }
+
+        #[test]
+        fn finds_ticks_only_above() {
+            let bitmaps = bitmaps(&[2560, 7690]);
+            assert_eq!(
+                surrounding_ticks(&bitmaps, TICK_SPACING, -3000),
+                (None, Some(2560))
+            );
+            // the last bit of a word is followed by the first bit of the next
+            assert_eq!(
+                surrounding_ticks(&bitmaps, TICK_SPACING, 2550),
+                (None, Some(2560))
+            );
+        }
+
+        #[test]
+        fn finds_nothing_without_initialized_ticks() {
+            let bitmaps = bitmaps(&[630]);
+            let mut empty = bitmaps.clone();
+            empty[0].flip_bit(63);
+            assert_eq!(surrounding_ticks(&empty, TICK_SPACING, 0), (None, None));
+            assert_eq!(surrounding_ticks(&[], TICK_SPACING, 0), (None, None));
+        }
+    }
 }
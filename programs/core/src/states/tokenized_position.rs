@@ -1,3 +1,4 @@
+use super::position::POSITION_SEED;
 use anchor_lang::prelude::*;
 
 /// Position wrapped as an SPL non-fungible token
@@ -39,6 +40,88 @@ pub struct TokenizedPositionState {
     pub tokens_owed_1: u64,
 }
 
+impl TokenizedPositionState {
+    /// Returns the contents of the position for display, e.g. by marketplaces listing the NFT
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The tokenized position
+    ///
+    pub fn info(&self) -> TokenizedPositionInfo {
+        TokenizedPositionInfo {
+            pool_id: self.pool_id,
+            tick_lower: self.tick_lower,
+            tick_upper: self.tick_upper,
+            liquidity: self.liquidity,
+            tokens_owed_0: self.tokens_owed_0,
+            tokens_owed_1: self.tokens_owed_1,
+        }
+    }
+}
+
+/// The pool, range and owed fees of a tokenized position, returned by #tokenized_position_info
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TokenizedPositionInfo {
+    /// The pool of the position
+    pub pool_id: Pubkey,
+
+    /// The lower bound tick of the position
+    pub tick_lower: i32,
+
+    /// The upper bound tick of the position
+    pub tick_upper: i32,
+
+    /// The amount of liquidity owned by the position
+    pub liquidity: u64,
+
+    /// How many uncollected token_0 are owed to the position, as of the last computation
+    pub tokens_owed_0: u64,
+
+    /// How many uncollected token_1 are owed to the position, as of the last computation
+    pub tokens_owed_1: u64,
+}
+
+/// The most that is left to collect of each token, by one tokenized position or by a batch
+/// of positions sharing the vault balances
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CollectBudget {
+    /// The token_0 left to collect
+    pub amount_0: u64,
+
+    /// The token_1 left to collect
+    pub amount_1: u64,
+}
+
+impl CollectBudget {
+    /// Takes the amounts collected for the fees owed to a position, capped by the budget, and
+    /// deducts them from the budget. Whatever is owed beyond the budget stays owed
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The amounts left to collect
+    /// * `tokens_owed_0` - The token_0 owed to the position
+    /// * `tokens_owed_1` - The token_1 owed to the position
+    ///
+    pub fn take(&mut self, tokens_owed_0: u64, tokens_owed_1: u64) -> (u64, u64) {
+        let amount_0 = self.amount_0.min(tokens_owed_0);
+        let amount_1 = self.amount_1.min(tokens_owed_1);
+        self.amount_0 -= amount_0;
+        self.amount_1 -= amount_1;
+        (amount_0, amount_1)
+    }
+}
+
+/// Derives the tokenized position address and bump for a position NFT
+///
+/// # Arguments
+///
+/// * `nft_mint` - The mint address of the position NFT
+/// * `program_id` - The program owning the position
+///
+pub fn derive_tokenized_position_address(nft_mint: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[POSITION_SEED.as_bytes(), nft_mint.as_ref()], program_id)
+}
+
 /// Emitted when liquidity is increased for a position NFT.
 /// Also emitted when a token is minted
 #[event]
@@ -94,3 +177,109 @@ pub struct CollectTokenizedEvent {
     /// The amount of token_1 owed to the position that was collected
     pub amount_1: u64,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn derives_the_address_seeded_by_the_nft_mint() {
+        let nft_mint = Pubkey::new_unique();
+        let (key, bump) = derive_tokenized_position_address(&nft_mint, &crate::ID);
+        assert_eq!(
+            Pubkey::create_program_address(
+                &[POSITION_SEED.as_bytes(), nft_mint.as_ref(), &[bump]],
+                &crate::ID
+            )
+            .unwrap(),
+            key
+        );
+        assert_ne!(
+            derive_tokenized_position_address(&Pubkey::new_unique(), &crate::ID).0,
+            key
+        );
+        assert_ne!(
+            derive_tokenized_position_address(&nft_mint, &Pubkey::new_unique()).0,
+            key
+        );
+    }
+
+    #[test]
+    fn info_holds_the_range_pool_and_owed_fees() {
+        let position = TokenizedPositionState {
+            bump: 255,
+            mint: Pubkey::new_unique(),
+            pool_id: Pubkey::new_unique(),
+            tick_lower: -60,
+            tick_upper: 120,
+            liquidity: 1 << 40,
+            fee_growth_inside_0_last_x32: 7,
+            fee_growth_inside_1_last_x32: 9,
+            tokens_owed_0: 11,
+            tokens_owed_1: 13,
+        };
+        let info = position.info();
+        assert_eq!(
+            info,
+            TokenizedPositionInfo {
+                pool_id: position.pool_id,
+                tick_lower: -60,
+                tick_upper: 120,
+                liquidity: 1 << 40,
+                tokens_owed_0: 11,
+                tokens_owed_1: 13,
+            }
+        );
+        assert_eq!(
+            TokenizedPositionInfo::try_from_slice(&info.try_to_vec().unwrap()).unwrap(),
+            info
+        );
+    }
+
+    #[test]
+    fn collect_budget_caps_the_amounts_owed() {
+        let mut budget = CollectBudget {
+            amount_0: 100,
+            amount_1: 50,
+        };
+        assert_eq!(budget.take(30, 70), (30, 50));
+        assert_eq!(
+            budget,
+            CollectBudget {
+                amount_0: 70,
+                amount_1: 0
+            }
+        );
+        assert_eq!(budget.take(80, 10), (70, 0));
+        assert_eq!(budget, CollectBudget::default());
+    }
+
+    #[test]
+    fn batch_owing_more_than_u64_max_collects_at_most_the_vault_balances() {
+        // combined owed fees of the batch exceed u64::MAX in both tokens
+        let vault_balances = (u64::MAX - 5, 1_000);
+        let tokens_owed = [(u64::MAX, 400), (u64::MAX - 1, u64::MAX), (7, 900)];
+
+        let mut budget = CollectBudget {
+            amount_0: vault_balances.0,
+            amount_1: vault_balances.1,
+        };
+        let collected: Vec<(u64, u64)> = tokens_owed
+            .iter()
+            .map(|&(owed_0, owed_1)| budget.take(owed_0, owed_1))
+            .collect();
+
+        // positions are paid in order until the vaults are empty, the rest stays owed
+        assert_eq!(collected, vec![(u64::MAX - 5, 400), (0, 600), (0, 0)]);
+        let total_0: u128 = collected
+            .iter()
+            .map(|&(amount_0, _)| amount_0 as u128)
+            .sum();
+        let total_1: u128 = collected
+            .iter()
+            .map(|&(_, amount_1)| amount_1 as u128)
+            .sum();
+        assert_eq!(total_0, vault_balances.0 as u128);
+        assert_eq!(total_1, vault_balances.1 as u128);
+    }
+}
@@ -0,0 +1,5 @@
+#[test]
+fn raw_u64_is_not_a_sqrt_price() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,6 @@
+use cyclos_core::libraries::sqrt_price::SqrtPriceX32;
+
+fn main() {
+    // sqrt prices are built through checked constructors only
+    let _sqrt_price = SqrtPriceX32(1 << 32);
+}
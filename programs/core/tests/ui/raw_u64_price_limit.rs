@@ -0,0 +1,8 @@
+use cyclos_core::libraries::swap_quote::{max_input_to_price_limit, PoolSnapshot};
+
+fn max_input(snapshot: &PoolSnapshot) -> u64 {
+    // a price limit must be a SqrtPriceX32, not a bare Q32.32
+    max_input_to_price_limit(snapshot, 1 << 32, true).unwrap()
+}
+
+fn main() {}
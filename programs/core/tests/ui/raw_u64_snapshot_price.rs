@@ -0,0 +1,12 @@
+use cyclos_core::libraries::swap_quote::PoolSnapshot;
+
+fn main() {
+    let _snapshot = PoolSnapshot {
+        sqrt_price_x32: 1 << 32,
+        tick: 0,
+        liquidity: 1 << 32,
+        fee: 3000,
+        tick_spacing: 60,
+        ticks: &[],
+    };
+}
@@ -0,0 +1,69 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token;
+use anchor_spl::token::{Token, TokenAccount};
+
+declare_id!("5m9FmMadekDLSn2WNwgoKySfMfz4rXgn8oCEcReGz6Y2");
+
+/// The share of each payment withheld, in basis points
+pub const TRANSFER_FEE_BPS: u64 = 100;
+
+#[program]
+pub mod transfer_fee_callback {
+    use super::*;
+
+    /// Pays the pool the amount owed for a swap less a transfer fee, as a token which takes
+    /// a fee on transfer would. The fee is kept by the payer.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - Token accounts for payment, as passed by the core program
+    /// * `amount_0_delta`, `amount_1_delta` - The amounts of token_0 and token_1 owed to (positive)
+    ///   or sent by (negative) the pool
+    ///
+    pub fn swap_callback(
+        ctx: Context<SwapCallback>,
+        amount_0_delta: i64,
+        amount_1_delta: i64,
+    ) -> Result<()> {
+        let amount_owed = amount_0_delta.max(amount_1_delta) as u64;
+        let transfer_fee = amount_owed * TRANSFER_FEE_BPS / 10_000;
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                token::Transfer {
+                    from: ctx.accounts.input_token_account.to_account_info(),
+                    to: ctx.accounts.input_vault.to_account_info(),
+                    authority: ctx.accounts.signer.to_account_info(),
+                },
+            ),
+            amount_owed - transfer_fee,
+        )
+    }
+}
+
+#[derive(Accounts)]
+pub struct SwapCallback<'info> {
+    /// Pays for the swap
+    pub signer: Signer<'info>,
+
+    /// The user token account for input token
+    /// CHECK: Account validation is performed by the token program
+    #[account(mut)]
+    pub input_token_account: UncheckedAccount<'info>,
+
+    /// The user token account for output token
+    /// CHECK: Account validation is performed by the token program
+    #[account(mut)]
+    pub output_token_account: UncheckedAccount<'info>,
+
+    /// The vault token account for input token
+    #[account(mut)]
+    pub input_vault: Box<Account<'info, TokenAccount>>,
+
+    /// The vault token account for output token
+    #[account(mut)]
+    pub output_vault: Box<Account<'info, TokenAccount>>,
+
+    /// The SPL program to perform token transfers
+    pub token_program: Program<'info, Token>,
+}
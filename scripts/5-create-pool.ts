@@ -27,6 +27,8 @@ async function main() {
   anchor.setProvider(provider)
   const coreProgram = anchor.workspace.CyclosCore as Program<CyclosCore>
 
+  const [factoryState, factoryStateBump] = await web3.PublicKey.findProgramAddress([], coreProgram.programId)
+
   const fee = 500
   const [poolAState, poolAStateBump] = await web3.PublicKey.findProgramAddress(
     [
@@ -69,11 +71,12 @@ async function main() {
     true
   )
 
-  const tx = coreProgram.transaction.createAndInitPool(new anchor.BN(4294967296), {
+  const tx = coreProgram.transaction.createAndInitPool(new anchor.BN(4294967296), 1, {
     accounts: {
       poolCreator: owner,
       token0: usdtMint,
       token1: usdcMint,
+      factoryState,
       feeState,
       poolState: poolAState,
       initialObservationState: initialObservationStateA,
@@ -2,6 +2,7 @@ import * as anchor from '@project-serum/anchor'
 import { PublicKey } from '@solana/web3.js'
 import JSBI from 'jsbi'
 import { BN } from '@project-serum/anchor'
+import { i32ToSeed } from './utils'
 import { TickDataProvider, PoolVars, tickPosition, TickMath, generateBitmapWord, TICK_SEED, u32ToSeed, BITMAP_SEED, u16ToSeed, nextInitializedBit, buildTick } from '@cykura/sdk'
 
 interface TickBitmap {
@@ -112,7 +113,7 @@ interface Tick {
           this.pool.token0.toBuffer(),
           this.pool.token1.toBuffer(),
           u32ToSeed(this.pool.fee),
-          u32ToSeed(tick),
+          i32ToSeed(tick),
         ],
         this.program.programId
       )
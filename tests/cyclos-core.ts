@@ -4,6 +4,7 @@ import * as metaplex from '@metaplex/js'
 import { Token, TOKEN_PROGRAM_ID, ASSOCIATED_TOKEN_PROGRAM_ID } from '@solana/spl-token'
 import {
   Pool,
+  TickMath,
   BITMAP_SEED,
   FEE_SEED,
   OBSERVATION_SEED,
@@ -21,11 +22,14 @@ chai.use(chaiAsPromised)
 
 import { CyclosCore } from '../target/types/cyclos_core'
 import {
+  i16ToSeed,
+  i32ToSeed,
   MaxU64,
   MAX_SQRT_RATIO,
   MAX_TICK,
   MIN_SQRT_RATIO,
   MIN_TICK,
+  OWNER_LIQUIDITY_SEED,
 } from './utils'
 import SolanaTickDataProvider from './SolanaTickDataProvider'
 import { Transaction } from '@solana/web3.js'
@@ -122,6 +126,9 @@ describe('cyclos-core', async () => {
   let corePositionABump: number
   let corePositionBState: web3.PublicKey
   let corePositionBBump: number
+  let ownerLiquidityAState: web3.PublicKey
+  let factoryLiquidityAState: web3.PublicKey
+  let factoryLiquidityBState: web3.PublicKey
   let bitmapLowerAState: web3.PublicKey
   let bitmapLowerABump: number
   let bitmapLowerBState: web3.PublicKey
@@ -138,6 +145,8 @@ describe('cyclos-core', async () => {
   let positionBNftAccount: web3.PublicKey
   let metadataAccount: web3.PublicKey
   let lastObservationAState: web3.PublicKey
+  // core position held directly by the wallet, without an NFT
+  let ownerPositionAState: web3.PublicKey
   let nextObservationAState: web3.PublicKey
   let latestObservationBState: web3.PublicKey
   let nextObservationBState: web3.PublicKey
@@ -580,11 +589,12 @@ describe('cyclos-core', async () => {
 
     it('fails if tokens are passed in reverse', async () => {
       // Unlike Uniswap, we must pass the tokens by address sort order
-      await expect(coreProgram.rpc.createAndInitPool(initialPriceX32, {
+      await expect(coreProgram.rpc.createAndInitPool(initialPriceX32, 1, {
         accounts: {
           poolCreator: owner,
           token0: token1.publicKey,
           token1: token0.publicKey,
+          factoryState,
           feeState,
           poolState: poolAState,
           initialObservationState: initialObservationStateA,
@@ -596,11 +606,12 @@ describe('cyclos-core', async () => {
 
     it('fails if token0 == token1', async () => {
       // Unlike Uniswap, we must pass the tokens by address sort order
-      await expect(coreProgram.rpc.createAndInitPool(initialPriceX32, {
+      await expect(coreProgram.rpc.createAndInitPool(initialPriceX32, 1, {
         accounts: {
           poolCreator: owner,
           token0: token0.publicKey,
           token1: token0.publicKey,
+          factoryState,
           feeState,
           poolState: poolAState,
           initialObservationState: initialObservationStateA,
@@ -616,11 +627,12 @@ describe('cyclos-core', async () => {
         coreProgram.programId
       );
 
-      await expect(coreProgram.rpc.createAndInitPool(initialPriceX32, {
+      await expect(coreProgram.rpc.createAndInitPool(initialPriceX32, 1, {
         accounts: {
           poolCreator: owner,
           token0: token0.publicKey,
           token1: token0.publicKey,
+          factoryState,
           feeState: uninitializedFeeState,
           poolState: poolAState,
           initialObservationState: initialObservationStateA,
@@ -631,11 +643,12 @@ describe('cyclos-core', async () => {
     })
 
     it('fails if starting price is too low', async () => {
-      await expect(coreProgram.rpc.createAndInitPool(new BN(1), {
+      await expect(coreProgram.rpc.createAndInitPool(new BN(1), 1, {
         accounts: {
           poolCreator: owner,
           token0: token0.publicKey,
           token1: token1.publicKey,
+          factoryState,
           feeState,
           poolState: poolAState,
           initialObservationState: initialObservationStateA,
@@ -645,11 +658,12 @@ describe('cyclos-core', async () => {
       })).to.be.rejectedWith(Error)
 
       await expect(coreProgram.rpc.createAndInitPool(
-        MIN_SQRT_RATIO.subn(1), {
+        MIN_SQRT_RATIO.subn(1), 1, {
         accounts: {
           poolCreator: owner,
           token0: token0.publicKey,
           token1: token1.publicKey,
+          factoryState,
           feeState,
           poolState: poolAState,
           initialObservationState: initialObservationStateA,
@@ -661,11 +675,12 @@ describe('cyclos-core', async () => {
     })
 
     it('fails if starting price is too high', async () => {
-      await expect(coreProgram.rpc.createAndInitPool(MAX_SQRT_RATIO, {
+      await expect(coreProgram.rpc.createAndInitPool(MAX_SQRT_RATIO, 1, {
         accounts: {
           poolCreator: owner,
           token0: token0.publicKey,
           token1: token1.publicKey,
+          factoryState,
           feeState,
           poolState: poolAState,
           initialObservationState: initialObservationStateA,
@@ -675,11 +690,12 @@ describe('cyclos-core', async () => {
       })).to.be.rejectedWith(Error)
 
       await expect(coreProgram.rpc.createAndInitPool(
-        new BN(2).pow(new BN(64)).subn(1), { // u64::MAX
+        new BN(2).pow(new BN(64)).subn(1), 1, { // u64::MAX
         accounts: {
           poolCreator: owner,
           token0: token0.publicKey,
           token1: token1.publicKey,
+          factoryState,
           feeState,
           poolState: poolAState,
           initialObservationState: initialObservationStateA,
@@ -704,11 +720,12 @@ describe('cyclos-core', async () => {
           resolve([event, slot]);
         });
 
-        coreProgram.rpc.createAndInitPool(initialPriceX32, {
+        coreProgram.rpc.createAndInitPool(initialPriceX32, 1, {
           accounts: {
             poolCreator: owner,
             token0: token0.publicKey,
             token1: token1.publicKey,
+            factoryState,
             feeState,
             poolState: poolAState,
             initialObservationState: initialObservationStateA,
@@ -751,11 +768,12 @@ describe('cyclos-core', async () => {
     })
 
     it('fails if already initialized', async () => {
-      await expect(coreProgram.rpc.createAndInitPool(initialPriceX32, {
+      await expect(coreProgram.rpc.createAndInitPool(initialPriceX32, 1, {
         accounts: {
           poolCreator: owner,
           token0: token0.publicKey,
           token1: token1.publicKey,
+          factoryState,
           feeState,
           poolState: poolAState,
           initialObservationState: initialObservationStateA,
@@ -764,6 +782,94 @@ describe('cyclos-core', async () => {
         }
       })).to.be.rejectedWith(Error)
     })
+
+    describe('with an initial cardinality', () => {
+      const initialCardinality = 10
+      let poolCState: web3.PublicKey
+      let observationCStates: web3.PublicKey[]
+      let observationCBumps: number[]
+
+      it('derive pool and observation addresses', async () => {
+        [poolCState] = await PublicKey.findProgramAddress(
+          [
+            POOL_SEED,
+            token0.publicKey.toBuffer(),
+            token2.publicKey.toBuffer(),
+            u32ToSeed(fee)
+          ],
+          coreProgram.programId
+        )
+        observationCStates = []
+        observationCBumps = []
+        for (let i = 0; i < initialCardinality; i++) {
+          const [observationState, observationBump] = await PublicKey.findProgramAddress(
+            [
+              OBSERVATION_SEED,
+              token0.publicKey.toBuffer(),
+              token2.publicKey.toBuffer(),
+              u32ToSeed(fee),
+              u16ToSeed(i)
+            ],
+            coreProgram.programId
+          )
+          observationCStates.push(observationState)
+          observationCBumps.push(observationBump)
+        }
+      })
+
+      it('fails if observation accounts do not match the cardinality', async () => {
+        await expect(coreProgram.rpc.createAndInitPool(initialPriceX32, initialCardinality, {
+          accounts: {
+            poolCreator: owner,
+            token0: token0.publicKey,
+            token1: token2.publicKey,
+            factoryState,
+            feeState,
+            poolState: poolCState,
+            initialObservationState: observationCStates[0],
+            systemProgram: SystemProgram.programId,
+            rent: web3.SYSVAR_RENT_PUBKEY,
+          }, remainingAccounts: observationCStates.slice(1, -1).map(pubkey => ({
+            pubkey,
+            isSigner: false,
+            isWritable: true
+          }))
+        })).to.be.rejectedWith(Error)
+      })
+
+      it('allocates observation slots upfront', async () => {
+        await coreProgram.rpc.createAndInitPool(initialPriceX32, initialCardinality, {
+          accounts: {
+            poolCreator: owner,
+            token0: token0.publicKey,
+            token1: token2.publicKey,
+            factoryState,
+            feeState,
+            poolState: poolCState,
+            initialObservationState: observationCStates[0],
+            systemProgram: SystemProgram.programId,
+            rent: web3.SYSVAR_RENT_PUBKEY,
+          }, remainingAccounts: observationCStates.slice(1).map(pubkey => ({
+            pubkey,
+            isSigner: false,
+            isWritable: true
+          }))
+        })
+
+        const poolStateData = await coreProgram.account.poolState.fetch(poolCState)
+        assert.equal(poolStateData.observationIndex, 0)
+        assert.equal(poolStateData.observationCardinality, 1)
+        assert.equal(poolStateData.observationCardinalityNext, initialCardinality)
+
+        // the first slot is written, the others wait for the oracle to grow into them
+        for (let i = 0; i < initialCardinality; i++) {
+          const observationStateData = await coreProgram.account.observationState.fetch(observationCStates[i])
+          assert.equal(observationStateData.bump, observationCBumps[i])
+          assert.equal(observationStateData.index, i)
+          assert.equal(observationStateData.initialized, i == 0)
+        }
+      })
+    })
   })
 
   describe('#increase_observation_cardinality_next', () => {
@@ -1078,6 +1184,38 @@ describe('cyclos-core', async () => {
     })
   })
 
+  describe('#set_pool_fee_protocol', () => {
+    it('cannot be changed out of bounds', async () => {
+      for (const feeProtocol of [1, 3, 11]) {
+        await expect(coreProgram.rpc.setPoolFeeProtocol(feeProtocol, {
+          accounts: {
+            owner,
+            factoryState,
+            poolState: poolAState,
+          }
+        })).to.be.rejectedWith(Error)
+      }
+    })
+
+    it('can be changed by owner without touching accrued fees', async () => {
+      const poolStateDataBefore = await coreProgram.account.poolState.fetch(poolAState)
+
+      // match the factory protocol fee used by later swaps
+      await coreProgram.rpc.setPoolFeeProtocol(6, {
+        accounts: {
+          owner,
+          factoryState,
+          poolState: poolAState,
+        }
+      })
+
+      const poolStateData = await coreProgram.account.poolState.fetch(poolAState)
+      assert.equal(poolStateData.feeProtocol, 6)
+      assert(poolStateData.protocolFeesToken0.eq(poolStateDataBefore.protocolFeesToken0))
+      assert(poolStateData.protocolFeesToken1.eq(poolStateDataBefore.protocolFeesToken1))
+    })
+  })
+
   describe('#collect_protocol', () => {
     it('creates token accounts for recipient', async () => {
       feeRecipientWallet0 = await token0.createAssociatedTokenAccount(protocolFeeRecipient.publicKey)
@@ -1170,13 +1308,94 @@ describe('cyclos-core', async () => {
 
   })
 
+  describe('#recover_token', () => {
+    let strayTokenAccount: web3.PublicKey
+    let recoveryRecipient: web3.PublicKey
+
+    it('creates a pool owned account for a foreign token', async () => {
+      strayTokenAccount = await token2.createAccount(poolAState)
+      recoveryRecipient = await token2.createAccount(owner)
+      await token2.mintTo(strayTokenAccount, mintAuthority, [], 1000)
+    })
+
+    it('fails if caller is not owner', async () => {
+      await expect(coreProgram.rpc.recoverToken(token2.publicKey, {
+        accounts: {
+          owner: notOwner.publicKey,
+          factoryState,
+          poolState: poolAState,
+          tokenAccount: strayTokenAccount,
+          recipient: recoveryRecipient,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        }, signers: [notOwner]
+      })).to.be.rejectedWith(Error)
+    })
+
+    it('fails if mint does not match the token account', async () => {
+      await expect(coreProgram.rpc.recoverToken(token0.publicKey, {
+        accounts: {
+          owner,
+          factoryState,
+          poolState: poolAState,
+          tokenAccount: strayTokenAccount,
+          recipient: recoveryRecipient,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        }
+      })).to.be.rejectedWith(Error)
+    })
+
+    it('fails if mint is a pool token', async () => {
+      for (const [mint, vault] of [[token0.publicKey, vaultA0], [token1.publicKey, vaultA1]]) {
+        await expect(coreProgram.rpc.recoverToken(mint, {
+          accounts: {
+            owner,
+            factoryState,
+            poolState: poolAState,
+            tokenAccount: vault,
+            recipient: mint.equals(token0.publicKey) ? feeRecipientWallet0 : feeRecipientWallet1,
+            tokenProgram: TOKEN_PROGRAM_ID,
+          }
+        })).to.be.rejectedWith(Error)
+      }
+    })
+
+    it('transfers out the full balance of a foreign token', async () => {
+      let listener: number
+      let [_event, _slot] = await new Promise((resolve, _reject) => {
+        listener = coreProgram.addEventListener("RecoverTokenEvent", (event, slot) => {
+          assert((event.poolState as web3.PublicKey).equals(poolAState))
+          assert((event.mint as web3.PublicKey).equals(token2.publicKey))
+          assert((event.recipient as web3.PublicKey).equals(recoveryRecipient))
+          assert((event.amount as BN).eqn(1000))
+
+          resolve([event, slot]);
+        });
+
+        coreProgram.rpc.recoverToken(token2.publicKey, {
+          accounts: {
+            owner,
+            factoryState,
+            poolState: poolAState,
+            tokenAccount: strayTokenAccount,
+            recipient: recoveryRecipient,
+            tokenProgram: TOKEN_PROGRAM_ID,
+          }
+        })
+      })
+      await coreProgram.removeEventListener(listener)
+
+      assert((await token2.getAccountInfo(strayTokenAccount)).amount.eqn(0))
+      assert((await token2.getAccountInfo(recoveryRecipient)).amount.eqn(1000))
+    })
+  })
+
   it('find program accounts addresses for position creation', async () => {
     [tickLowerAState, tickLowerAStateBump] = await PublicKey.findProgramAddress([
       TICK_SEED,
       token0.publicKey.toBuffer(),
       token1.publicKey.toBuffer(),
       u32ToSeed(fee),
-      u32ToSeed(tickLower)
+      i32ToSeed(tickLower)
     ],
       coreProgram.programId
     );
@@ -1185,7 +1404,7 @@ describe('cyclos-core', async () => {
       token1.publicKey.toBuffer(),
       token2.publicKey.toBuffer(),
       u32ToSeed(fee),
-      u32ToSeed(tickLower)
+      i32ToSeed(tickLower)
     ],
       coreProgram.programId
     );
@@ -1195,7 +1414,7 @@ describe('cyclos-core', async () => {
       token0.publicKey.toBuffer(),
       token1.publicKey.toBuffer(),
       u32ToSeed(fee),
-      u32ToSeed(tickUpper)
+      i32ToSeed(tickUpper)
     ],
       coreProgram.programId
     );
@@ -1204,7 +1423,7 @@ describe('cyclos-core', async () => {
       token1.publicKey.toBuffer(),
       token2.publicKey.toBuffer(),
       u32ToSeed(fee),
-      u32ToSeed(tickUpper)
+      i32ToSeed(tickUpper)
     ],
       coreProgram.programId
     );
@@ -1253,8 +1472,8 @@ describe('cyclos-core', async () => {
       token1.publicKey.toBuffer(),
       u32ToSeed(fee),
       factoryState.toBuffer(),
-      u32ToSeed(tickLower),
-      u32ToSeed(tickUpper)
+      i32ToSeed(tickLower),
+      i32ToSeed(tickUpper)
     ],
       coreProgram.programId
     );
@@ -1264,12 +1483,40 @@ describe('cyclos-core', async () => {
       token2.publicKey.toBuffer(),
       u32ToSeed(fee),
       factoryState.toBuffer(),
-      u32ToSeed(tickLower),
-      u32ToSeed(tickUpper)
+      i32ToSeed(tickLower),
+      i32ToSeed(tickUpper)
     ],
       coreProgram.programId
     );
 
+    ownerLiquidityAState = (await PublicKey.findProgramAddress([
+      OWNER_LIQUIDITY_SEED,
+      token0.publicKey.toBuffer(),
+      token1.publicKey.toBuffer(),
+      u32ToSeed(fee),
+      owner.toBuffer(),
+    ],
+      coreProgram.programId
+    ))[0]
+    factoryLiquidityAState = (await PublicKey.findProgramAddress([
+      OWNER_LIQUIDITY_SEED,
+      token0.publicKey.toBuffer(),
+      token1.publicKey.toBuffer(),
+      u32ToSeed(fee),
+      factoryState.toBuffer(),
+    ],
+      coreProgram.programId
+    ))[0]
+    factoryLiquidityBState = (await PublicKey.findProgramAddress([
+      OWNER_LIQUIDITY_SEED,
+      token1.publicKey.toBuffer(),
+      token2.publicKey.toBuffer(),
+      u32ToSeed(fee),
+      factoryState.toBuffer(),
+    ],
+      coreProgram.programId
+    ))[0]
+
 
     positionANftAccount = await Token.getAssociatedTokenAddress(
       ASSOCIATED_TOKEN_PROGRAM_ID,
@@ -1323,7 +1570,7 @@ describe('cyclos-core', async () => {
         token0.publicKey.toBuffer(),
         token1.publicKey.toBuffer(),
         u32ToSeed(fee),
-        u32ToSeed(MIN_TICK - 1)
+        i32ToSeed(MIN_TICK - 1)
       ],
         coreProgram.programId
       );
@@ -1344,7 +1591,7 @@ describe('cyclos-core', async () => {
         token0.publicKey.toBuffer(),
         token1.publicKey.toBuffer(),
         u32ToSeed(fee),
-        u32ToSeed(MAX_TICK + 1)
+        i32ToSeed(MAX_TICK + 1)
       ],
         coreProgram.programId
       );
@@ -1366,7 +1613,7 @@ describe('cyclos-core', async () => {
         token0.publicKey.toBuffer(),
         token1.publicKey.toBuffer(),
         u32ToSeed(fee),
-        u32ToSeed(invalidTick)
+        i32ToSeed(invalidTick)
       ],
         coreProgram.programId
       );
@@ -1483,8 +1730,8 @@ describe('cyclos-core', async () => {
         u32ToSeed(fee),
         factoryState.toBuffer(),
         // posMgrState.toBuffer(),
-        u32ToSeed(tickUpper), // upper first
-        u32ToSeed(tickLower),
+        i32ToSeed(tickUpper), // upper first
+        i32ToSeed(tickLower),
       ],
         coreProgram.programId
       );
@@ -1518,6 +1765,101 @@ describe('cyclos-core', async () => {
       const corePositionData = await coreProgram.account.positionState.fetch(corePositionAState)
       assert.equal(corePositionData.bump, corePositionABump)
     })
+
+    it('creates a position account below zero at the address derived off chain', async () => {
+      const [negativeTickLower, negativeTickUpper] = [-20, -10]
+      assert(i32ToSeed(negativeTickLower).equals(Buffer.from([0xff, 0xff, 0xff, 0xec])))
+
+      const tickAccount = async (tick: number) => (await PublicKey.findProgramAddress([
+        TICK_SEED,
+        token0.publicKey.toBuffer(),
+        token1.publicKey.toBuffer(),
+        u32ToSeed(fee),
+        i32ToSeed(tick)
+      ],
+        coreProgram.programId
+      ))[0]
+      const tickLowerState = await tickAccount(negativeTickLower)
+      const tickUpperState = await tickAccount(negativeTickUpper)
+      for (const [tick, tickState] of [[negativeTickLower, tickLowerState], [negativeTickUpper, tickUpperState]] as const) {
+        await coreProgram.rpc.initTickAccount(tick, {
+          accounts: {
+            signer: owner,
+            poolState: poolAState,
+            tickState,
+            systemProgram: SystemProgram.programId,
+          }
+        })
+        assert.equal((await coreProgram.account.tickState.fetch(tickState)).tick, tick)
+      }
+
+      const [positionState, positionBump] = await PublicKey.findProgramAddress([
+        POSITION_SEED,
+        token0.publicKey.toBuffer(),
+        token1.publicKey.toBuffer(),
+        u32ToSeed(fee),
+        factoryState.toBuffer(),
+        i32ToSeed(negativeTickLower),
+        i32ToSeed(negativeTickUpper)
+      ],
+        coreProgram.programId
+      )
+      await coreProgram.rpc.initPositionAccount({
+        accounts: {
+          signer: owner,
+          recipient: factoryState,
+          poolState: poolAState,
+          tickLowerState,
+          tickUpperState,
+          positionState,
+          systemProgram: SystemProgram.programId,
+        }
+      })
+      assert.equal((await coreProgram.account.positionState.fetch(positionState)).bump, positionBump)
+    })
+  })
+
+  describe('#init_owner_liquidity_account', () => {
+    it('creates the liquidity accounts of the factory and the wallet', async () => {
+      for (const [liquidityOwner, ownerLiquidityState] of [
+        [factoryState, factoryLiquidityAState],
+        [owner, ownerLiquidityAState],
+      ]) {
+        await coreProgram.rpc.initOwnerLiquidityAccount({
+          accounts: {
+            signer: owner,
+            owner: liquidityOwner,
+            poolState: poolAState,
+            ownerLiquidityState,
+            systemProgram: SystemProgram.programId,
+          }
+        })
+        const { bump, liquidity } = await coreProgram.account.ownerLiquidityState.fetch(ownerLiquidityState)
+        const [, expectedBump] = await PublicKey.findProgramAddress([
+          OWNER_LIQUIDITY_SEED,
+          token0.publicKey.toBuffer(),
+          token1.publicKey.toBuffer(),
+          u32ToSeed(fee),
+          liquidityOwner.toBuffer(),
+        ],
+          coreProgram.programId
+        )
+        assert.equal(bump, expectedBump)
+        assert(liquidity.eqn(0))
+      }
+    })
+
+    it('fails to create an account twice', async () => {
+      await expect(coreProgram.rpc.initOwnerLiquidityAccount({
+        accounts: {
+          signer: owner,
+          owner,
+          poolState: poolAState,
+          ownerLiquidityState: ownerLiquidityAState,
+          systemProgram: SystemProgram.programId,
+        }
+      })).to.be.rejectedWith(Error)
+    })
   })
 
   describe('#mint_tokenized_position', () => {
@@ -1569,6 +1911,7 @@ describe('cyclos-core', async () => {
           nftAccount: positionANftAccount,
           poolState: poolAState,
           corePositionState: corePositionAState,
+          ownerLiquidityState: factoryLiquidityAState,
           tickLowerState: tickLowerAState,
           tickUpperState: tickUpperAState,
           bitmapLowerState: bitmapLowerAState,
@@ -1613,6 +1956,7 @@ describe('cyclos-core', async () => {
           nftAccount: positionANftAccount,
           poolState: poolAState,
           corePositionState: corePositionAState,
+          ownerLiquidityState: factoryLiquidityAState,
           tickLowerState: tickLowerAState,
           tickUpperState: tickUpperAState,
           bitmapLowerState: bitmapLowerAState,
@@ -1750,6 +2094,43 @@ describe('cyclos-core', async () => {
     })
   })
 
+  describe('#tokenized_position_info', () => {
+    it('reads the tokenized position at the address derived from the NFT mint', async () => {
+      const [positionAddress] = await PublicKey.findProgramAddress(
+        [POSITION_SEED, nftMintAKeypair.publicKey.toBuffer()],
+        coreProgram.programId
+      )
+      assert(positionAddress.equals(tokenizedPositionAState))
+
+      const { raw } = await coreProgram.simulate.tokenizedPositionInfo({
+        accounts: {
+          nftMint: nftMintAKeypair.publicKey,
+          tokenizedPositionState: positionAddress,
+        }
+      })
+      const prefix = `Program return: ${coreProgram.programId.toBase58()} `
+      const returnLog = raw.find(log => log.startsWith(prefix))
+      const returnData = Buffer.from(returnLog.slice(prefix.length), 'base64')
+
+      const positionData = await coreProgram.account.tokenizedPositionState.fetch(positionAddress)
+      assert(new PublicKey(returnData.subarray(0, 32)).equals(poolAState))
+      assert.equal(returnData.readInt32LE(32), tickLower)
+      assert.equal(returnData.readInt32LE(36), tickUpper)
+      assert(new BN(returnData.subarray(40, 48), 'le').eq(positionData.liquidity))
+      assert(new BN(returnData.subarray(48, 56), 'le').eq(positionData.tokensOwed0))
+      assert(new BN(returnData.subarray(56, 64), 'le').eq(positionData.tokensOwed1))
+    })
+
+    it('fails for an address not derived from the NFT mint', async () => {
+      await expect(coreProgram.simulate.tokenizedPositionInfo({
+        accounts: {
+          nftMint: nftMintBKeypair.publicKey,
+          tokenizedPositionState: tokenizedPositionAState,
+        }
+      })).to.be.rejectedWith(Error)
+    })
+  })
+
   const nftMint = new Token(
     connection,
     nftMintAKeypair.publicKey,
@@ -1823,6 +2204,7 @@ describe('cyclos-core', async () => {
           factoryState,
           poolState: poolAState,
           corePositionState: corePositionAState,
+          ownerLiquidityState: factoryLiquidityAState,
           tickLowerState: tickLowerAState,
           tickUpperState: tickUpperAState,
           bitmapLowerState: bitmapLowerAState,
@@ -1887,6 +2269,43 @@ describe('cyclos-core', async () => {
 
     })
 
+    it('fails if the amounts are too small to mint any liquidity', async () => {
+      // The price is at the upper tick, so the range only takes token_1
+      const deadline = new BN(Date.now() / 1000 + 10_000)
+      await expect(coreProgram.rpc.increaseLiquidity(
+        amount0Desired,
+        new BN(0),
+        amount0Minimum,
+        amount1Minimum,
+        deadline, {
+        accounts: {
+          payer: owner,
+          factoryState,
+          poolState: poolAState,
+          corePositionState: corePositionAState,
+          ownerLiquidityState: factoryLiquidityAState,
+          tickLowerState: tickLowerAState,
+          tickUpperState: tickUpperAState,
+          bitmapLowerState: bitmapLowerAState,
+          bitmapUpperState: bitmapUpperAState,
+          tokenAccount0: minterWallet0,
+          tokenAccount1: minterWallet1,
+          vault0: vaultA0,
+          vault1: vaultA1,
+          lastObservationState: lastObservationAState,
+          tokenizedPositionState: tokenizedPositionAState,
+          coreProgram: coreProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        },
+        remainingAccounts: [{
+          pubkey: nextObservationAState,
+          isSigner: false,
+          isWritable: true
+        }],
+      }
+      )).to.be.rejectedWith(Error)
+    })
+
     it('Add token 1 to the position', async () => {
       const deadline = new BN(Date.now() / 1000 + 10_000)
 
@@ -1901,6 +2320,7 @@ describe('cyclos-core', async () => {
           factoryState,
           poolState: poolAState,
           corePositionState: corePositionAState,
+          ownerLiquidityState: factoryLiquidityAState,
           tickLowerState: tickLowerAState,
           tickUpperState: tickUpperAState,
           bitmapLowerState: bitmapLowerAState,
@@ -2033,6 +2453,7 @@ describe('cyclos-core', async () => {
           factoryState,
           poolState: poolAState,
           corePositionState: corePositionAState,
+          ownerLiquidityState: factoryLiquidityAState,
           tickLowerState: tickLowerAState,
           tickUpperState: tickUpperAState,
           bitmapLowerState: bitmapLowerAState,
@@ -2063,6 +2484,7 @@ describe('cyclos-core', async () => {
           factoryState,
           poolState: poolAState,
           corePositionState: corePositionAState,
+          ownerLiquidityState: factoryLiquidityAState,
           tickLowerState: tickLowerAState,
           tickUpperState: tickUpperAState,
           bitmapLowerState: bitmapLowerAState,
@@ -2093,6 +2515,7 @@ describe('cyclos-core', async () => {
           factoryState,
           poolState: poolAState,
           corePositionState: corePositionAState,
+          ownerLiquidityState: factoryLiquidityAState,
           tickLowerState: tickLowerAState,
           tickUpperState: tickUpperAState,
           bitmapLowerState: bitmapLowerAState,
@@ -2140,6 +2563,7 @@ describe('cyclos-core', async () => {
           factoryState,
           poolState: poolAState,
           corePositionState: corePositionAState,
+          ownerLiquidityState: factoryLiquidityAState,
           tickLowerState: tickLowerAState,
           tickUpperState: tickUpperAState,
           bitmapLowerState: bitmapLowerAState,
@@ -2191,6 +2615,7 @@ describe('cyclos-core', async () => {
             factoryState,
             poolState: poolAState,
             corePositionState: corePositionAState,
+            ownerLiquidityState: factoryLiquidityAState,
             tickLowerState: tickLowerAState,
             tickUpperState: tickUpperAState,
             bitmapLowerState: bitmapLowerAState,
@@ -2238,6 +2663,7 @@ describe('cyclos-core', async () => {
           factoryState,
           poolState: poolAState,
           corePositionState: corePositionAState,
+          ownerLiquidityState: factoryLiquidityAState,
           tickLowerState: tickLowerAState,
           tickUpperState: tickUpperAState,
           bitmapLowerState: bitmapLowerAState,
@@ -2288,6 +2714,7 @@ describe('cyclos-core', async () => {
             factoryState,
             poolState: poolAState,
             corePositionState: corePositionAState,
+            ownerLiquidityState: factoryLiquidityAState,
             tickLowerState: tickLowerAState,
             tickUpperState: tickUpperAState,
             bitmapLowerState: bitmapLowerAState,
@@ -2331,6 +2758,7 @@ describe('cyclos-core', async () => {
           factoryState,
           poolState: poolAState,
           corePositionState: corePositionAState,
+          ownerLiquidityState: factoryLiquidityAState,
           tickLowerState: tickLowerAState,
           tickUpperState: tickUpperAState,
           bitmapLowerState: bitmapLowerAState,
@@ -2360,6 +2788,7 @@ describe('cyclos-core', async () => {
           factoryState,
           poolState: poolAState,
           corePositionState: corePositionAState,
+          ownerLiquidityState: factoryLiquidityAState,
           tickLowerState: tickLowerAState,
           tickUpperState: tickUpperAState,
           bitmapLowerState: bitmapLowerAState,
@@ -2389,6 +2818,7 @@ describe('cyclos-core', async () => {
           factoryState,
           poolState: poolAState,
           corePositionState: corePositionAState,
+          ownerLiquidityState: factoryLiquidityAState,
           tickLowerState: tickLowerAState,
           tickUpperState: tickUpperAState,
           bitmapLowerState: bitmapLowerAState,
@@ -2431,6 +2861,7 @@ describe('cyclos-core', async () => {
           factoryState,
           poolState: poolAState,
           corePositionState: corePositionAState,
+          ownerLiquidityState: factoryLiquidityAState,
           tickLowerState: tickLowerAState,
           tickUpperState: tickUpperAState,
           bitmapLowerState: bitmapLowerAState,
@@ -2473,6 +2904,7 @@ describe('cyclos-core', async () => {
           factoryState,
           poolState: poolAState,
           corePositionState: corePositionAState,
+          ownerLiquidityState: factoryLiquidityAState,
           tickLowerState: tickLowerAState,
           tickUpperState: tickUpperAState,
           bitmapLowerState: bitmapLowerAState,
@@ -2525,6 +2957,7 @@ describe('cyclos-core', async () => {
             factoryState,
             poolState: poolAState,
             corePositionState: corePositionAState,
+            ownerLiquidityState: factoryLiquidityAState,
             tickLowerState: tickLowerAState,
             tickUpperState: tickUpperAState,
             bitmapLowerState: bitmapLowerAState,
@@ -2600,6 +3033,7 @@ describe('cyclos-core', async () => {
             factoryState,
             poolState: poolAState,
             corePositionState: corePositionAState,
+            ownerLiquidityState: factoryLiquidityAState,
             tickLowerState: tickLowerAState,
             tickUpperState: tickUpperAState,
             bitmapLowerState: bitmapLowerAState,
@@ -2642,136 +3076,2867 @@ describe('cyclos-core', async () => {
     })
   })
 
-  describe('#exact_input_single', () => {
-    // before swapping, current tick = 10 and price = 4297115210
-    // active ticks are 0 and 10
-    // entire liquidity is in token_1
-
-    const deadline = new BN(Date.now() / 1000 + 1_000_000)
-
-    it('fails if limit price is greater than current pool price', async () => {
-      const amountIn = new BN(100_000)
-      const amountOutMinimum = new BN(0)
-      const sqrtPriceLimitX32 = new BN(4297115220)
+  describe('#slot deadline', () => {
+    const liquidity = new BN(1000)
+
+    const mintAccounts = () => ({
+      minter: owner,
+      tokenAccount0: minterWallet0,
+      tokenAccount1: minterWallet1,
+      vault0: vaultA0,
+      vault1: vaultA1,
+      recipient: owner,
+      poolState: poolAState,
+      tickLowerState: tickLowerAState,
+      tickUpperState: tickUpperAState,
+      bitmapLowerState: bitmapLowerAState,
+      bitmapUpperState: bitmapUpperAState,
+      positionState: ownerPositionAState,
+      ownerLiquidityState: ownerLiquidityAState,
+      lastObservationState: lastObservationAState,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      callbackHandler: coreProgram.programId,
+    })
+    const burnAccounts = () => ({
+      owner,
+      poolState: poolAState,
+      tickLowerState: tickLowerAState,
+      tickUpperState: tickUpperAState,
+      bitmapLowerState: bitmapLowerAState,
+      bitmapUpperState: bitmapUpperAState,
+      positionState: ownerPositionAState,
+      ownerLiquidityState: ownerLiquidityAState,
+      lastObservationState: lastObservationAState,
+    })
+    const collectAccounts = () => ({
+      owner,
+      poolState: poolAState,
+      tickLowerState: tickLowerAState,
+      tickUpperState: tickUpperAState,
+      positionState: ownerPositionAState,
+      vault0: vaultA0,
+      vault1: vaultA1,
+      recipientWallet0: minterWallet0,
+      recipientWallet1: minterWallet1,
+      tokenProgram: TOKEN_PROGRAM_ID,
+    })
+    const observationAccounts = () => [{
+      pubkey: nextObservationAState,
+      isSigner: false,
+      isWritable: true
+    }]
+
+    it('creates a position owned by the wallet', async () => {
+      ownerPositionAState = (await PublicKey.findProgramAddress([
+        POSITION_SEED,
+        token0.publicKey.toBuffer(),
+        token1.publicKey.toBuffer(),
+        u32ToSeed(fee),
+        owner.toBuffer(),
+        i32ToSeed(tickLower),
+        i32ToSeed(tickUpper)
+      ],
+        coreProgram.programId
+      ))[0]
+
+      await coreProgram.rpc.initPositionAccount({
+        accounts: {
+          signer: owner,
+          recipient: owner,
+          poolState: poolAState,
+          tickLowerState: tickLowerAState,
+          tickUpperState: tickUpperAState,
+          positionState: ownerPositionAState,
+          systemProgram: SystemProgram.programId,
+        }
+      })
+
+      const { observationIndex, observationCardinalityNext } = await coreProgram.account.poolState.fetch(poolAState)
+      lastObservationAState = (await PublicKey.findProgramAddress(
+        [
+          OBSERVATION_SEED,
+          token0.publicKey.toBuffer(),
+          token1.publicKey.toBuffer(),
+          u32ToSeed(fee),
+          u16ToSeed(observationIndex)
+        ],
+        coreProgram.programId
+      ))[0]
+      nextObservationAState = (await PublicKey.findProgramAddress(
+        [
+          OBSERVATION_SEED,
+          token0.publicKey.toBuffer(),
+          token1.publicKey.toBuffer(),
+          u32ToSeed(fee),
+          u16ToSeed((observationIndex + 1) % observationCardinalityNext)
+        ],
+        coreProgram.programId
+      ))[0]
+    })
+
+    it('mint fails if past the slot deadline', async () => {
+      await expect(coreProgram.rpc.mint(liquidity, new BN(1), {
+        accounts: mintAccounts(),
+        remainingAccounts: observationAccounts(),
+      })).to.be.rejectedWith(Error)
+    })
+
+    it('mints before the slot deadline', async () => {
+      const deadline = new BN(await connection.getSlot() + 1000)
+      await coreProgram.rpc.mint(liquidity, deadline, {
+        accounts: mintAccounts(),
+        remainingAccounts: observationAccounts(),
+      })
+
+      const positionData = await coreProgram.account.positionState.fetch(ownerPositionAState)
+      assert(positionData.liquidity.eq(liquidity))
+    })
+
+    it('burn fails if past the slot deadline', async () => {
+      await expect(coreProgram.rpc.burn(liquidity, new BN(1), {
+        accounts: burnAccounts(),
+        remainingAccounts: observationAccounts(),
+      })).to.be.rejectedWith(Error)
+    })
+
+    it('burns with no deadline', async () => {
+      await coreProgram.rpc.burn(liquidity, new BN(0), {
+        accounts: burnAccounts(),
+        remainingAccounts: observationAccounts(),
+      })
+
+      const positionData = await coreProgram.account.positionState.fetch(ownerPositionAState)
+      assert(positionData.liquidity.eqn(0))
+    })
+
+    it('collect fails if past the slot deadline', async () => {
+      await expect(coreProgram.rpc.collect(MaxU64, MaxU64, null, new BN(1), {
+        accounts: collectAccounts(),
+      })).to.be.rejectedWith(Error)
+    })
+
+    it('collects before the slot deadline', async () => {
+      const deadline = new BN(await connection.getSlot() + 1000)
+      await coreProgram.rpc.collect(MaxU64, MaxU64, null, deadline, {
+        accounts: collectAccounts(),
+      })
+
+      const positionData = await coreProgram.account.positionState.fetch(ownerPositionAState)
+      assert(positionData.tokensOwed0.eqn(0))
+      assert(positionData.tokensOwed1.eqn(0))
+    })
+  })
+
+  describe('#set_min_tick_width', () => {
+    // the wallet position spans a single tick spacing
+    const liquidity = new BN(1000)
+
+    const setMinTickWidth = (minTickWidth: number) => coreProgram.rpc.setMinTickWidth(minTickWidth, {
+      accounts: {
+        owner,
+        factoryState,
+        poolState: poolAState,
+      }
+    })
+    const mint = () => coreProgram.rpc.mint(liquidity, new BN(0), {
+      accounts: {
+        minter: owner,
+        tokenAccount0: minterWallet0,
+        tokenAccount1: minterWallet1,
+        vault0: vaultA0,
+        vault1: vaultA1,
+        recipient: owner,
+        poolState: poolAState,
+        tickLowerState: tickLowerAState,
+        tickUpperState: tickUpperAState,
+        bitmapLowerState: bitmapLowerAState,
+        bitmapUpperState: bitmapUpperAState,
+        positionState: ownerPositionAState,
+        ownerLiquidityState: ownerLiquidityAState,
+        lastObservationState: lastObservationAState,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        callbackHandler: coreProgram.programId,
+      },
+      remainingAccounts: [{
+        pubkey: nextObservationAState,
+        isSigner: false,
+        isWritable: true
+      }],
+    })
+
+    it('cannot be changed by addresses that are not owner', async () => {
+      await expect(coreProgram.rpc.setMinTickWidth(2, {
+        accounts: {
+          owner: notOwner.publicKey,
+          factoryState,
+          poolState: poolAState,
+        },
+        signers: [notOwner]
+      })).to.be.rejectedWith(Error)
+    })
+
+    it('rejects mints below the minimum width', async () => {
+      await setMinTickWidth(2)
+      const { minTickWidth } = await coreProgram.account.poolState.fetch(poolAState)
+      assert.equal(minTickWidth, 2)
+
+      await expect(mint()).to.be.rejectedWith(Error)
+    })
+
+    it('mints at the minimum width', async () => {
+      await setMinTickWidth(1)
+      await mint()
+
+      const positionData = await coreProgram.account.positionState.fetch(ownerPositionAState)
+      assert(positionData.liquidity.eq(liquidity))
+    })
+
+    it('removes the minimum and the minted liquidity', async () => {
+      await setMinTickWidth(0)
+
+      await coreProgram.rpc.burn(liquidity, new BN(0), {
+        accounts: {
+          owner,
+          poolState: poolAState,
+          tickLowerState: tickLowerAState,
+          tickUpperState: tickUpperAState,
+          bitmapLowerState: bitmapLowerAState,
+          bitmapUpperState: bitmapUpperAState,
+          positionState: ownerPositionAState,
+          ownerLiquidityState: ownerLiquidityAState,
+          lastObservationState: lastObservationAState,
+        },
+        remainingAccounts: [{
+          pubkey: nextObservationAState,
+          isSigner: false,
+          isWritable: true
+        }],
+      })
+      await coreProgram.rpc.collect(MaxU64, MaxU64, null, new BN(0), {
+        accounts: {
+          owner,
+          poolState: poolAState,
+          tickLowerState: tickLowerAState,
+          tickUpperState: tickUpperAState,
+          positionState: ownerPositionAState,
+          vault0: vaultA0,
+          vault1: vaultA1,
+          recipientWallet0: minterWallet0,
+          recipientWallet1: minterWallet1,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        }
+      })
+
+      const positionData = await coreProgram.account.positionState.fetch(ownerPositionAState)
+      assert(positionData.liquidity.eqn(0))
+    })
+  })
+
+  describe('#set_max_liquidity_per_owner', () => {
+    const liquidity = new BN(1000)
+    let ownerLiquidityBefore: BN
+
+    const setMaxLiquidityPerOwner = (maxLiquidityPerOwner: BN) => coreProgram.rpc.setMaxLiquidityPerOwner(maxLiquidityPerOwner, {
+      accounts: {
+        owner,
+        factoryState,
+        poolState: poolAState,
+      }
+    })
+    const mint = (amount: BN) => coreProgram.rpc.mint(amount, new BN(0), {
+      accounts: {
+        minter: owner,
+        tokenAccount0: minterWallet0,
+        tokenAccount1: minterWallet1,
+        vault0: vaultA0,
+        vault1: vaultA1,
+        recipient: owner,
+        poolState: poolAState,
+        tickLowerState: tickLowerAState,
+        tickUpperState: tickUpperAState,
+        bitmapLowerState: bitmapLowerAState,
+        bitmapUpperState: bitmapUpperAState,
+        positionState: ownerPositionAState,
+        ownerLiquidityState: ownerLiquidityAState,
+        lastObservationState: lastObservationAState,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        callbackHandler: coreProgram.programId,
+      },
+      remainingAccounts: [{
+        pubkey: nextObservationAState,
+        isSigner: false,
+        isWritable: true
+      }],
+    })
+
+    it('cannot be changed by addresses that are not owner', async () => {
+      await expect(coreProgram.rpc.setMaxLiquidityPerOwner(new BN(1), {
+        accounts: {
+          owner: notOwner.publicKey,
+          factoryState,
+          poolState: poolAState,
+        },
+        signers: [notOwner]
+      })).to.be.rejectedWith(Error)
+    })
+
+    it('mints up to the limit', async () => {
+      ownerLiquidityBefore = (await coreProgram.account.ownerLiquidityState.fetch(ownerLiquidityAState)).liquidity
+      await setMaxLiquidityPerOwner(ownerLiquidityBefore.add(liquidity))
+      await mint(liquidity)
+
+      const ownerLiquidityData = await coreProgram.account.ownerLiquidityState.fetch(ownerLiquidityAState)
+      assert(ownerLiquidityData.liquidity.eq(ownerLiquidityBefore.add(liquidity)))
+    })
+
+    it('rejects mints over the limit', async () => {
+      await expect(mint(new BN(1))).to.be.rejectedWith('Owner limit exceeded')
+
+      const ownerLiquidityData = await coreProgram.account.ownerLiquidityState.fetch(ownerLiquidityAState)
+      assert(ownerLiquidityData.liquidity.eq(ownerLiquidityBefore.add(liquidity)))
+    })
+
+    it('removes the limit and the minted liquidity', async () => {
+      await setMaxLiquidityPerOwner(new BN(0))
+
+      await coreProgram.rpc.burn(liquidity, new BN(0), {
+        accounts: {
+          owner,
+          poolState: poolAState,
+          tickLowerState: tickLowerAState,
+          tickUpperState: tickUpperAState,
+          bitmapLowerState: bitmapLowerAState,
+          bitmapUpperState: bitmapUpperAState,
+          positionState: ownerPositionAState,
+          ownerLiquidityState: ownerLiquidityAState,
+          lastObservationState: lastObservationAState,
+        },
+        remainingAccounts: [{
+          pubkey: nextObservationAState,
+          isSigner: false,
+          isWritable: true
+        }],
+      })
+      await coreProgram.rpc.collect(MaxU64, MaxU64, null, new BN(0), {
+        accounts: {
+          owner,
+          poolState: poolAState,
+          tickLowerState: tickLowerAState,
+          tickUpperState: tickUpperAState,
+          positionState: ownerPositionAState,
+          vault0: vaultA0,
+          vault1: vaultA1,
+          recipientWallet0: minterWallet0,
+          recipientWallet1: minterWallet1,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        }
+      })
+
+      const ownerLiquidityData = await coreProgram.account.ownerLiquidityState.fetch(ownerLiquidityAState)
+      assert(ownerLiquidityData.liquidity.eq(ownerLiquidityBefore))
+    })
+  })
+
+  describe('#set_min_holding_slots', () => {
+    const setMinHoldingSlots = (minHoldingSlots: number) => coreProgram.rpc.setMinHoldingSlots(new BN(minHoldingSlots), {
+      accounts: {
+        owner,
+        factoryState,
+        poolState: poolAState,
+      }
+    })
+
+    it('cannot be changed by addresses that are not owner', async () => {
+      await expect(coreProgram.rpc.setMinHoldingSlots(new BN(10), {
+        accounts: {
+          owner: notOwner.publicKey,
+          factoryState,
+          poolState: poolAState,
+        },
+        signers: [notOwner]
+      })).to.be.rejectedWith(Error)
+    })
+
+    it('sets and removes the holding period', async () => {
+      await setMinHoldingSlots(10)
+      let { minHoldingSlots } = await coreProgram.account.poolState.fetch(poolAState)
+      assert(minHoldingSlots.eqn(10))
+
+      await setMinHoldingSlots(0)
+      minHoldingSlots = (await coreProgram.account.poolState.fetch(poolAState)).minHoldingSlots
+      assert(minHoldingSlots.eqn(0))
+    })
+  })
+
+  describe('#set_quote_token', () => {
+    it('cannot be changed by addresses that are not owner', async () => {
+      await expect(coreProgram.rpc.setQuoteToken(true, {
+        accounts: {
+          owner: notOwner.publicKey,
+          factoryState,
+          poolState: poolAState,
+        },
+        signers: [notOwner]
+      })).to.be.rejectedWith(Error)
+    })
+
+    it('sets and resets the quote token', async () => {
+      for (const quoteIsToken1 of [true, false]) {
+        await coreProgram.rpc.setQuoteToken(quoteIsToken1, {
+          accounts: {
+            owner,
+            factoryState,
+            poolState: poolAState,
+          }
+        })
+        const poolStateData = await coreProgram.account.poolState.fetch(poolAState)
+        assert.equal(poolStateData.quoteIsToken1, quoteIsToken1)
+      }
+    })
+  })
+
+  describe('#mint via delegate', () => {
+    // notOwner mints for the wallet, paying from the wallet token accounts as their delegate
+    const liquidity = new BN(1000)
+
+    const approve = async (amount: number) => {
+      const approveTx = new web3.Transaction()
+      approveTx.recentBlockhash = (await connection.getRecentBlockhash()).blockhash
+      for (const wallet of [minterWallet0, minterWallet1]) {
+        approveTx.add(Token.createApproveInstruction(
+          TOKEN_PROGRAM_ID,
+          wallet,
+          notOwner.publicKey,
+          owner,
+          [],
+          amount
+        ))
+      }
+      await anchor.getProvider().send(approveTx)
+    }
+    const mint = () => coreProgram.rpc.mint(liquidity, new BN(0), {
+      accounts: {
+        minter: notOwner.publicKey,
+        tokenAccount0: minterWallet0,
+        tokenAccount1: minterWallet1,
+        vault0: vaultA0,
+        vault1: vaultA1,
+        recipient: owner,
+        poolState: poolAState,
+        tickLowerState: tickLowerAState,
+        tickUpperState: tickUpperAState,
+        bitmapLowerState: bitmapLowerAState,
+        bitmapUpperState: bitmapUpperAState,
+        positionState: ownerPositionAState,
+        ownerLiquidityState: ownerLiquidityAState,
+        lastObservationState: lastObservationAState,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        callbackHandler: coreProgram.programId,
+      },
+      remainingAccounts: [{
+        pubkey: nextObservationAState,
+        isSigner: false,
+        isWritable: true
+      }],
+      signers: [notOwner]
+    })
+
+    it('fails if the delegate has no allowance', async () => {
+      await approve(0)
+      await expect(mint()).to.be.rejectedWith(Error)
+    })
+
+    it('mints for the owner as a delegate with sufficient allowance', async () => {
+      const allowance = 1_000_000
+      await approve(allowance)
+      const wallet0Before = await token0.getAccountInfo(minterWallet0)
+      const wallet1Before = await token1.getAccountInfo(minterWallet1)
+
+      await mint()
+
+      const positionData = await coreProgram.account.positionState.fetch(ownerPositionAState)
+      assert(positionData.liquidity.eq(liquidity))
+
+      // the delegate paid from the wallet, within its allowance
+      const wallet0After = await token0.getAccountInfo(minterWallet0)
+      const wallet1After = await token1.getAccountInfo(minterWallet1)
+      const paid0 = wallet0Before.amount.sub(wallet0After.amount)
+      const paid1 = wallet1Before.amount.sub(wallet1After.amount)
+      assert(paid0.gtn(0) || paid1.gtn(0))
+      assert(wallet0After.delegatedAmount.eq(new BN(allowance).sub(paid0)))
+      assert(wallet1After.delegatedAmount.eq(new BN(allowance).sub(paid1)))
+    })
+
+    it('removes the minted liquidity and the delegation', async () => {
+      const revokeTx = new web3.Transaction()
+      revokeTx.recentBlockhash = (await connection.getRecentBlockhash()).blockhash
+      for (const wallet of [minterWallet0, minterWallet1]) {
+        revokeTx.add(Token.createRevokeInstruction(
+          TOKEN_PROGRAM_ID,
+          wallet,
+          owner,
+          [],
+        ))
+      }
+      await anchor.getProvider().send(revokeTx)
+
+      await coreProgram.rpc.burn(liquidity, new BN(0), {
+        accounts: {
+          owner,
+          poolState: poolAState,
+          tickLowerState: tickLowerAState,
+          tickUpperState: tickUpperAState,
+          bitmapLowerState: bitmapLowerAState,
+          bitmapUpperState: bitmapUpperAState,
+          positionState: ownerPositionAState,
+          ownerLiquidityState: ownerLiquidityAState,
+          lastObservationState: lastObservationAState,
+        },
+        remainingAccounts: [{
+          pubkey: nextObservationAState,
+          isSigner: false,
+          isWritable: true
+        }],
+      })
+      await coreProgram.rpc.collect(MaxU64, MaxU64, null, new BN(0), {
+        accounts: {
+          owner,
+          poolState: poolAState,
+          tickLowerState: tickLowerAState,
+          tickUpperState: tickUpperAState,
+          positionState: ownerPositionAState,
+          vault0: vaultA0,
+          vault1: vaultA1,
+          recipientWallet0: minterWallet0,
+          recipientWallet1: minterWallet1,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        }
+      })
+
+      const positionData = await coreProgram.account.positionState.fetch(ownerPositionAState)
+      assert(positionData.liquidity.eqn(0))
+    })
+  })
+
+  describe('#collect_rewards', () => {
+    // the wallet position earns rewards funded into pool A while in range
+    const liquidity = new BN(1_000_000)
+    const rewardAmount = 1_000_000
+    let rewardToken: Token
+    let rewardVault: web3.PublicKey
+    let rewardWallet: web3.PublicKey
+
+    const collectRewards = (amountRequested: BN) => coreProgram.rpc.collectRewards(amountRequested, {
+      accounts: {
+        owner,
+        poolState: poolAState,
+        tickLowerState: tickLowerAState,
+        tickUpperState: tickUpperAState,
+        positionState: ownerPositionAState,
+        rewardVault,
+        recipientWallet: rewardWallet,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      }
+    })
+
+    it('creates the reward mint and vault', async () => {
+      rewardToken = await Token.createMint(
+        connection,
+        mintAuthority,
+        mintAuthority.publicKey,
+        null,
+        8,
+        TOKEN_PROGRAM_ID
+      )
+      rewardWallet = await rewardToken.createAssociatedTokenAccount(owner)
+      await rewardToken.mintTo(rewardWallet, mintAuthority, [], rewardAmount)
+
+      rewardVault = await Token.getAssociatedTokenAddress(
+        ASSOCIATED_TOKEN_PROGRAM_ID,
+        TOKEN_PROGRAM_ID,
+        rewardToken.publicKey,
+        poolAState,
+        true
+      )
+      const createAtaTx = new Transaction()
+      createAtaTx.instructions = [
+        Token.createAssociatedTokenAccountInstruction(
+          ASSOCIATED_TOKEN_PROGRAM_ID,
+          TOKEN_PROGRAM_ID,
+          rewardToken.publicKey,
+          rewardVault,
+          poolAState,
+          owner
+        ),
+      ]
+      createAtaTx.recentBlockhash = (await connection.getRecentBlockhash()).blockhash
+      await anchor.getProvider().send(createAtaTx)
+    })
+
+    it('cannot set the reward mint from addresses that are not owner', async () => {
+      await expect(coreProgram.rpc.setRewardMint({
+        accounts: {
+          owner: notOwner.publicKey,
+          factoryState,
+          poolState: poolAState,
+          rewardMint: rewardToken.publicKey,
+        },
+        signers: [notOwner]
+      })).to.be.rejectedWith(Error)
+    })
+
+    it('cannot reward with a pool token', async () => {
+      await expect(coreProgram.rpc.setRewardMint({
+        accounts: {
+          owner,
+          factoryState,
+          poolState: poolAState,
+          rewardMint: token0.publicKey,
+        }
+      })).to.be.rejectedWith(Error)
+    })
+
+    it('sets the reward mint once', async () => {
+      await coreProgram.rpc.setRewardMint({
+        accounts: {
+          owner,
+          factoryState,
+          poolState: poolAState,
+          rewardMint: rewardToken.publicKey,
+        }
+      })
+      const poolStateData = await coreProgram.account.poolState.fetch(poolAState)
+      assert(poolStateData.rewardMint.equals(rewardToken.publicKey))
+      assert(poolStateData.rewardGrowthGlobalX32.eqn(0))
+
+      await expect(coreProgram.rpc.setRewardMint({
+        accounts: {
+          owner,
+          factoryState,
+          poolState: poolAState,
+          rewardMint: rewardToken.publicKey,
+        }
+      })).to.be.rejectedWith(Error)
+    })
+
+    it('accrues funded rewards to the in range position', async () => {
+      await coreProgram.rpc.mint(liquidity, new BN(0), {
+        accounts: {
+          minter: owner,
+          tokenAccount0: minterWallet0,
+          tokenAccount1: minterWallet1,
+          vault0: vaultA0,
+          vault1: vaultA1,
+          recipient: owner,
+          poolState: poolAState,
+          tickLowerState: tickLowerAState,
+          tickUpperState: tickUpperAState,
+          bitmapLowerState: bitmapLowerAState,
+          bitmapUpperState: bitmapUpperAState,
+          positionState: ownerPositionAState,
+          ownerLiquidityState: ownerLiquidityAState,
+          lastObservationState: lastObservationAState,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          callbackHandler: coreProgram.programId,
+        },
+        remainingAccounts: [{
+          pubkey: nextObservationAState,
+          isSigner: false,
+          isWritable: true
+        }],
+      })
+
+      const poolBefore = await coreProgram.account.poolState.fetch(poolAState)
+      await coreProgram.rpc.addRewards(new BN(rewardAmount), {
+        accounts: {
+          funder: owner,
+          funderWallet: rewardWallet,
+          poolState: poolAState,
+          rewardVault,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        }
+      })
+      const poolAfter = await coreProgram.account.poolState.fetch(poolAState)
+      const growth = new BN(rewardAmount).shln(32).div(poolBefore.liquidity)
+      assert(poolAfter.rewardGrowthGlobalX32.sub(poolBefore.rewardGrowthGlobalX32).eq(growth))
+
+      const vaultData = await rewardToken.getAccountInfo(rewardVault)
+      assert(vaultData.amount.eqn(rewardAmount))
+    })
+
+    it('collects the rewards earned over the interval', async () => {
+      const poolData = await coreProgram.account.poolState.fetch(poolAState)
+      const positionBefore = await coreProgram.account.positionState.fetch(ownerPositionAState)
+      const earned = poolData.rewardGrowthGlobalX32.sub(positionBefore.rewardGrowthInsideLastX32)
+        .mul(positionBefore.liquidity).shrn(32)
+      assert(earned.gtn(0))
+
+      // a partial collect leaves the remainder owed
+      await collectRewards(new BN(1))
+      let positionData = await coreProgram.account.positionState.fetch(ownerPositionAState)
+      assert(positionData.rewardsOwed.eq(earned.subn(1)))
+
+      await collectRewards(MaxU64)
+      positionData = await coreProgram.account.positionState.fetch(ownerPositionAState)
+      assert(positionData.rewardsOwed.eqn(0))
+      const walletData = await rewardToken.getAccountInfo(rewardWallet)
+      assert(walletData.amount.eq(earned))
+    })
+
+    it('keeps the reward checkpoint when migrating a current position', async () => {
+      const positionBefore = await coreProgram.account.positionState.fetch(ownerPositionAState)
+      await coreProgram.rpc.migratePosition({
+        accounts: {
+          payer: owner,
+          positionState: ownerPositionAState,
+          owner,
+          poolState: poolAState,
+          tickLowerState: tickLowerAState,
+          tickUpperState: tickUpperAState,
+          systemProgram: SystemProgram.programId,
+        }
+      })
+      const positionAfter = await coreProgram.account.positionState.fetch(ownerPositionAState)
+      assert(positionAfter.rewardGrowthInsideLastX32.eq(positionBefore.rewardGrowthInsideLastX32))
+      assert(positionAfter.rewardsOwed.eqn(0))
+    })
+
+    it('rejects a position not derived from the passed owner', async () => {
+      await expect(coreProgram.rpc.migratePosition({
+        accounts: {
+          payer: owner,
+          positionState: ownerPositionAState,
+          owner: notOwner.publicKey,
+          poolState: poolAState,
+          tickLowerState: tickLowerAState,
+          tickUpperState: tickUpperAState,
+          systemProgram: SystemProgram.programId,
+        }
+      })).to.be.rejectedWith(Error)
+    })
+
+    it('removes the rewarded liquidity', async () => {
+      await coreProgram.rpc.burn(liquidity, new BN(0), {
+        accounts: {
+          owner,
+          poolState: poolAState,
+          tickLowerState: tickLowerAState,
+          tickUpperState: tickUpperAState,
+          bitmapLowerState: bitmapLowerAState,
+          bitmapUpperState: bitmapUpperAState,
+          positionState: ownerPositionAState,
+          ownerLiquidityState: ownerLiquidityAState,
+          lastObservationState: lastObservationAState,
+        },
+        remainingAccounts: [{
+          pubkey: nextObservationAState,
+          isSigner: false,
+          isWritable: true
+        }],
+      })
+      await coreProgram.rpc.collect(MaxU64, MaxU64, null, new BN(0), {
+        accounts: {
+          owner,
+          poolState: poolAState,
+          tickLowerState: tickLowerAState,
+          tickUpperState: tickUpperAState,
+          positionState: ownerPositionAState,
+          vault0: vaultA0,
+          vault1: vaultA1,
+          recipientWallet0: minterWallet0,
+          recipientWallet1: minterWallet1,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        }
+      })
+      const positionData = await coreProgram.account.positionState.fetch(ownerPositionAState)
+      assert(positionData.liquidity.eqn(0))
+    })
+  })
+
+  describe('#collect without poke', () => {
+    it('mints liquidity to the wallet position before swapping', async () => {
+      await coreProgram.rpc.mint(new BN(1_000_000), new BN(0), {
+        accounts: {
+          minter: owner,
+          tokenAccount0: minterWallet0,
+          tokenAccount1: minterWallet1,
+          vault0: vaultA0,
+          vault1: vaultA1,
+          recipient: owner,
+          poolState: poolAState,
+          tickLowerState: tickLowerAState,
+          tickUpperState: tickUpperAState,
+          bitmapLowerState: bitmapLowerAState,
+          bitmapUpperState: bitmapUpperAState,
+          positionState: ownerPositionAState,
+          ownerLiquidityState: ownerLiquidityAState,
+          lastObservationState: lastObservationAState,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          callbackHandler: coreProgram.programId,
+        },
+        remainingAccounts: [{
+          pubkey: nextObservationAState,
+          isSigner: false,
+          isWritable: true
+        }],
+      })
+    })
+  })
+
+  describe('#exact_input_single', () => {
+    // before swapping, current tick = 10 and price = 4297115210
+    // active ticks are 0 and 10
+    // entire liquidity is in token_1
+
+    const deadline = new BN(Date.now() / 1000 + 1_000_000)
+
+    // resolves with the ticks crossed by the next swap in pool A. The swap event is emitted
+    // after all crossings of the swap, so the crossings are complete once it is received
+    const tickCrossesOfNextSwap = () => {
+      const listeners: number[] = []
+      return new Promise<any[]>((resolve) => {
+        const tickCrossEvents = []
+        listeners.push(coreProgram.addEventListener("TickCrossEvent", (event, _slot) => {
+          if ((event.poolState as web3.PublicKey).equals(poolAState)) {
+            tickCrossEvents.push(event)
+          }
+        }))
+        listeners.push(coreProgram.addEventListener("SwapEvent", (event, _slot) => {
+          if ((event.poolState as web3.PublicKey).equals(poolAState)) {
+            resolve(tickCrossEvents)
+          }
+        }))
+      }).then(async (tickCrossEvents) => {
+        for (const listener of listeners) {
+          await coreProgram.removeEventListener(listener)
+        }
+        return tickCrossEvents
+      })
+    }
+
+    it('fails if limit price is greater than current pool price', async () => {
+      const amountIn = new BN(100_000)
+      const amountOutMinimum = new BN(0)
+      const sqrtPriceLimitX32 = new BN(4297115220)
+
+      await expect(coreProgram.rpc.exactInputSingle(
+        deadline,
+        // true,
+        amountIn,
+        amountOutMinimum,
+        sqrtPriceLimitX32,
+        {
+          accounts: {
+            signer: owner,
+            factoryState,
+            poolState: poolAState,
+            inputTokenAccount: minterWallet0,
+            outputTokenAccount: minterWallet1,
+            inputVault: vaultA0,
+            outputVault: vaultA1,
+            lastObservationState: lastObservationAState,
+            // nextObservationState: nextObservationAState,
+            coreProgram: coreProgram.programId,
+            tokenProgram: TOKEN_PROGRAM_ID,
+          }, remainingAccounts: [{
+            pubkey: nextObservationAState,
+            isSigner: false,
+            isWritable: true
+          },{
+            pubkey: bitmapLowerAState,
+            isSigner: false,
+            isWritable: true
+          },
+          // price moves downwards in zero for one swap
+          {
+            pubkey: tickUpperAState,
+            isSigner: false,
+            isWritable: true
+          }, {
+            pubkey: tickLowerAState,
+            isSigner: false,
+            isWritable: true
+          }]
+        }
+      )).to.be.rejectedWith(Error)
+    })
+
+    it('fails if limit price is the current pool price', async () => {
+      const { sqrtPriceX32 } = await coreProgram.account.poolState.fetch(poolAState)
+
+      await expect(coreProgram.rpc.exactInputSingle(
+        deadline,
+        new BN(100_000),
+        new BN(0),
+        sqrtPriceX32,
+        {
+          accounts: {
+            signer: owner,
+            factoryState,
+            poolState: poolAState,
+            inputTokenAccount: minterWallet0,
+            outputTokenAccount: minterWallet1,
+            inputVault: vaultA0,
+            outputVault: vaultA1,
+            lastObservationState: lastObservationAState,
+            coreProgram: coreProgram.programId,
+            tokenProgram: TOKEN_PROGRAM_ID,
+          }, remainingAccounts: [{
+            pubkey: nextObservationAState,
+            isSigner: false,
+            isWritable: true
+          }, {
+            pubkey: bitmapLowerAState,
+            isSigner: false,
+            isWritable: true
+          }, {
+            pubkey: tickUpperAState,
+            isSigner: false,
+            isWritable: true
+          }, {
+            pubkey: tickLowerAState,
+            isSigner: false,
+            isWritable: true
+          }]
+        }
+      )).to.be.rejectedWith('Invalid price limit')
+    })
+
+    it('emits a tick cross event for every crossed tick, in the order of the swap', async () => {
+      // a zero for one swap down to the lower tick crosses both active ticks, and a one for zero
+      // swap back to the starting price crosses them again in reverse
+      const amountIn = new BN(10_000_000)
+      const { tick: currentTick, sqrtPriceX32: currentSqrtPriceX32, liquidity: currentLiquidity } = await coreProgram.account.poolState.fetch(poolAState)
+      const tickDataProvider = new SolanaTickDataProvider(coreProgram, {
+        token0: token0.publicKey,
+        token1: token1.publicKey,
+        fee,
+      })
+      await tickDataProvider.eagerLoadCache(currentTick, tickSpacing)
+      const pool = new Pool(
+        uniToken0,
+        uniToken1,
+        fee,
+        JSBI.BigInt(currentSqrtPriceX32),
+        JSBI.BigInt(currentLiquidity),
+        currentTick,
+        tickDataProvider
+      )
+
+      const sqrtPriceLimitDown = TickMath.getSqrtRatioAtTick(tickLower)
+      const [, poolDown, accountsDown] = await pool.getOutputAmount(
+        CurrencyAmount.fromRawAmount(uniToken0, amountIn.toNumber()),
+        sqrtPriceLimitDown
+      )
+      const sqrtPriceLimitUp = JSBI.BigInt(currentSqrtPriceX32.toString())
+      const [, , accountsUp] = await poolDown.getOutputAmount(
+        CurrencyAmount.fromRawAmount(uniToken1, amountIn.toNumber()),
+        sqrtPriceLimitUp
+      )
+
+      // the swaps may write observations, so later tests read the observation accounts from here
+      const refreshObservations = async () => {
+        const { observationIndex, observationCardinalityNext } = await coreProgram.account.poolState.fetch(poolAState)
+        const [lastObservationState, nextObservationState] = await Promise.all(
+          [observationIndex, (observationIndex + 1) % observationCardinalityNext].map(async index =>
+            (await PublicKey.findProgramAddress(
+              [
+                OBSERVATION_SEED,
+                token0.publicKey.toBuffer(),
+                token1.publicKey.toBuffer(),
+                u32ToSeed(fee),
+                u16ToSeed(index)
+              ],
+              coreProgram.programId
+            ))[0]
+          )
+        )
+        lastObservationAState = lastObservationState
+        nextObservationAState = nextObservationState
+      }
+
+      for (const [zeroForOne, sqrtPriceLimitX32, swapAccounts, expectedTicks] of [
+        [true, sqrtPriceLimitDown, accountsDown, [tickUpper, tickLower]],
+        [false, sqrtPriceLimitUp, accountsUp, [tickLower, tickUpper]],
+      ] as [boolean, JSBI, web3.AccountMeta[], number[]][]) {
+        await refreshObservations()
+
+        const tickCrossEvents = tickCrossesOfNextSwap()
+        await coreProgram.rpc.exactInputSingle(
+          deadline,
+          amountIn,
+          new BN(0),
+          new BN(sqrtPriceLimitX32.toString()),
+          {
+            accounts: {
+              signer: owner,
+              factoryState,
+              poolState: poolAState,
+              inputTokenAccount: zeroForOne ? minterWallet0 : minterWallet1,
+              outputTokenAccount: zeroForOne ? minterWallet1 : minterWallet0,
+              inputVault: zeroForOne ? vaultA0 : vaultA1,
+              outputVault: zeroForOne ? vaultA1 : vaultA0,
+              lastObservationState: lastObservationAState,
+              coreProgram: coreProgram.programId,
+              tokenProgram: TOKEN_PROGRAM_ID,
+            },
+            remainingAccounts: [
+              ...swapAccounts,
+              {
+                pubkey: nextObservationAState,
+                isSigner: false,
+                isWritable: true
+              },
+            ],
+          }
+        )
+
+        // descending ticks for zero for one, ascending for one for zero
+        const crossings = await tickCrossEvents
+        assert.deepEqual(crossings.map(event => event.tick), expectedTicks)
+        for (const event of crossings) {
+          assert.equal(event.zeroForOne, zeroForOne)
+        }
+      }
+      await refreshObservations()
+
+      const { sqrtPriceX32 } = await coreProgram.account.poolState.fetch(poolAState)
+      assert(sqrtPriceX32.eq(currentSqrtPriceX32))
+    })
+
+    it('swap upto a limit price for a zero to one swap', async () => {
+      const amountIn = new BN(100_000)
+      const amountOutMinimum = new BN(0)
+      const sqrtPriceLimitX32 = new BN(4297115200) // current price is 4297115210
+
+      const tickDataProvider = new SolanaTickDataProvider(coreProgram, {
+        token0: token0.publicKey,
+        token1: token1.publicKey,
+        fee,
+      })
+
+      const { tick: currentTick, sqrtPriceX32: currentSqrtPriceX32, liquidity: currentLiquidity } = await coreProgram.account.poolState.fetch(poolAState)
+      await tickDataProvider.eagerLoadCache(currentTick, tickSpacing)
+      // output is one tick behind actual (8 instead of 9)
+      uniPoolA = new Pool(
+        uniToken0,
+        uniToken1,
+        fee,
+        JSBI.BigInt(currentSqrtPriceX32),
+        JSBI.BigInt(currentLiquidity),
+        currentTick,
+        tickDataProvider
+      )
+
+      const [expectedAmountOut, expectedNewPool, bitmapAndTickAccounts] = await uniPoolA.getOutputAmount(
+        CurrencyAmount.fromRawAmount(uniToken0, amountIn.toNumber()),
+        JSBI.BigInt(sqrtPriceLimitX32),
+      )
+      assert.equal(expectedNewPool.sqrtRatioX32.toString(), sqrtPriceLimitX32.toString())
+
+      const tickCrossEvents = tickCrossesOfNextSwap()
+      await coreProgram.rpc.exactInputSingle(
+        deadline,
+        amountIn,
+        amountOutMinimum,
+        sqrtPriceLimitX32,
+        {
+          accounts: {
+            signer: owner,
+            factoryState,
+            poolState: poolAState,
+            inputTokenAccount: minterWallet0,
+            outputTokenAccount: minterWallet1,
+            inputVault: vaultA0,
+            outputVault: vaultA1,
+            lastObservationState: lastObservationAState,
+            // nextObservationState: nextObservationAState,
+            coreProgram: coreProgram.programId,
+            tokenProgram: TOKEN_PROGRAM_ID,
+          },
+          remainingAccounts: [
+            ...bitmapAndTickAccounts,
+            {
+              pubkey: nextObservationAState,
+              isSigner: false,
+              isWritable: true
+            },
+          ],
+        }
+      )
+      let poolStateData = await coreProgram.account.poolState.fetch(poolAState)
+      assert(poolStateData.sqrtPriceX32.eq(sqrtPriceLimitX32))
+
+      // the swap begins on the upper tick boundary and crosses it before reaching the limit price
+      const crossings = await tickCrossEvents
+      assert.equal(crossings.length, 1)
+      assert.equal(crossings[0].tick, tickUpper)
+      assert(crossings[0].zeroForOne)
+
+      console.log('tick after swap', poolStateData.tick, 'price', poolStateData.sqrtPriceX32.toString())
+      uniPoolA = expectedNewPool
+    })
+
+    it('performs a zero for one swap without a limit price', async () => {
+      let poolStateDataBefore = await coreProgram.account.poolState.fetch(poolAState)
+      console.log('pool price', poolStateDataBefore.sqrtPriceX32.toNumber())
+      console.log('pool tick', poolStateDataBefore.tick)
+
+      const {
+        observationIndex,
+        observationCardinalityNext
+      } = await coreProgram.account.poolState.fetch(poolAState)
+
+      lastObservationAState = (await PublicKey.findProgramAddress(
+        [
+          OBSERVATION_SEED,
+          token0.publicKey.toBuffer(),
+          token1.publicKey.toBuffer(),
+          u32ToSeed(fee),
+          u16ToSeed(observationIndex)
+        ],
+        coreProgram.programId
+      ))[0]
+
+      nextObservationAState = (await PublicKey.findProgramAddress(
+        [
+          OBSERVATION_SEED,
+          token0.publicKey.toBuffer(),
+          token1.publicKey.toBuffer(),
+          u32ToSeed(fee),
+          u16ToSeed((observationIndex + 1) % observationCardinalityNext)
+        ],
+        coreProgram.programId
+      ))[0]
+
+      const amountIn = new BN(100_000)
+      const amountOutMinimum = new BN(0)
+      const sqrtPriceLimitX32 = new BN(0)
+
+      console.log('pool tick', uniPoolA.tickCurrent, 'price', uniPoolA.sqrtRatioX32.toString())
+      const [expectedAmountOut, expectedNewPool, bitmapAndTickAccounts] = await uniPoolA.getOutputAmount(
+        CurrencyAmount.fromRawAmount(uniToken0, amountIn.toNumber())
+      )
+      console.log('expected pool', expectedNewPool)
+
+      await coreProgram.rpc.exactInputSingle(
+        deadline,
+        // true,
+        amountIn,
+        amountOutMinimum,
+        sqrtPriceLimitX32,
+        {
+          accounts: {
+            signer: owner,
+            factoryState,
+            poolState: poolAState,
+            inputTokenAccount: minterWallet0,
+            outputTokenAccount: minterWallet1,
+            inputVault: vaultA0,
+            outputVault: vaultA1,
+            lastObservationState: lastObservationAState,
+            // nextObservationState: nextObservationAState,
+            coreProgram: coreProgram.programId,
+            tokenProgram: TOKEN_PROGRAM_ID,
+          },
+          remainingAccounts: [
+            ...bitmapAndTickAccounts,
+            {
+              pubkey: nextObservationAState,
+              isSigner: false,
+              isWritable: true
+            },
+          ],
+        }
+      )
+      const poolStateDataAfter = await coreProgram.account.poolState.fetch(poolAState)
+      console.log('pool price after', poolStateDataAfter.sqrtPriceX32.toNumber())
+      console.log('pool tick after', poolStateDataAfter.tick)
+
+      uniPoolA = expectedNewPool
+    })
+  })
+
+  describe('#exact_input_single_with_oracle_check', () => {
+    const deadline = new BN(Date.now() / 1000 + 1_000_000)
+
+    const observationAddress = async (index: number) => (await PublicKey.findProgramAddress(
+      [
+        OBSERVATION_SEED,
+        token0.publicKey.toBuffer(),
+        token1.publicKey.toBuffer(),
+        u32ToSeed(fee),
+        u16ToSeed(index)
+      ],
+      coreProgram.programId
+    ))[0]
+
+    it('fails if the pool price deviates from the time weighted average price', async () => {
+      // Two swaps run before the checked swap in the same transaction. The first writes an observation
+      // at the current block time, so the ticks moved by the second are missing from the TWAP.
+      // The failed transaction leaves the pool untouched.
+      const {
+        tick: currentTick,
+        sqrtPriceX32: currentSqrtPriceX32,
+        liquidity: currentLiquidity,
+        observationIndex,
+        observationCardinalityNext
+      } = await coreProgram.account.poolState.fetch(poolAState)
+      const pastObservationState = await observationAddress(observationIndex)
+      const lastObservationState = await observationAddress((observationIndex + 1) % observationCardinalityNext)
+      const nextObservationState = await observationAddress((observationIndex + 2) % observationCardinalityNext)
+
+      // the first swap writes to the next observation account only in a later partition of 14 seconds,
+      // otherwise it overwrites the past observation
+      const { blockTimestamp: pastBlockTime } = await coreProgram.account.observationState.fetch(pastObservationState)
+      const blockTimestamp = await connection.getBlockTime(await connection.getSlot())
+      if (Math.floor(pastBlockTime / 14) == Math.floor(blockTimestamp / 14)) {
+        await new Promise(r => setTimeout(r, (14 - blockTimestamp % 14 + 1) * 1000))
+      }
+
+      const tickDataProvider = new SolanaTickDataProvider(coreProgram, {
+        token0: token0.publicKey,
+        token1: token1.publicKey,
+        fee,
+      })
+      await tickDataProvider.eagerLoadCache(currentTick, tickSpacing)
+      const pool = new Pool(
+        uniToken0,
+        uniToken1,
+        fee,
+        JSBI.BigInt(currentSqrtPriceX32),
+        JSBI.BigInt(currentLiquidity),
+        currentTick,
+        tickDataProvider
+      )
+
+      // zero for one swaps up to 2 and then 20 ticks below the current tick
+      const amountIn = new BN(10_000_000)
+      const sqrtPriceLimitFirst = TickMath.getSqrtRatioAtTick(currentTick - 2)
+      const sqrtPriceLimitSecond = TickMath.getSqrtRatioAtTick(currentTick - 20)
+      const [, poolAfterFirst, firstSwapAccounts] = await pool.getOutputAmount(
+        CurrencyAmount.fromRawAmount(uniToken0, amountIn.toNumber()),
+        sqrtPriceLimitFirst
+      )
+      const [, , secondSwapAccounts] = await poolAfterFirst.getOutputAmount(
+        CurrencyAmount.fromRawAmount(uniToken0, amountIn.toNumber()),
+        sqrtPriceLimitSecond
+      )
+      const swap = (
+        sqrtPriceLimitX32: JSBI,
+        lastObservationState: web3.PublicKey,
+        nextObservationState: web3.PublicKey,
+        swapAccounts: web3.AccountMeta[]
+      ) => coreProgram.instruction.swap(amountIn, new BN(sqrtPriceLimitX32.toString()), null, false, {
+        accounts: {
+          signer: owner,
+          inputTokenAccount: minterWallet0,
+          outputTokenAccount: minterWallet1,
+          inputVault: vaultA0,
+          outputVault: vaultA1,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          factoryState,
+          poolState: poolAState,
+          lastObservationState,
+          callbackHandler: coreProgram.programId,
+        },
+        remainingAccounts: [
+          ...swapAccounts,
+          {
+            pubkey: nextObservationState,
+            isSigner: false,
+            isWritable: true
+          },
+        ]
+      })
+
+      await expect(coreProgram.rpc.exactInputSingleWithOracleCheck(
+        deadline,
+        new BN(100_000),
+        new BN(0),
+        new BN(0),
+        1,
+        {
+          accounts: {
+            signer: owner,
+            factoryState,
+            poolState: poolAState,
+            inputTokenAccount: minterWallet0,
+            outputTokenAccount: minterWallet1,
+            inputVault: vaultA0,
+            outputVault: vaultA1,
+            lastObservationState,
+            pastObservationState,
+            coreProgram: coreProgram.programId,
+            tokenProgram: TOKEN_PROGRAM_ID,
+          },
+          remainingAccounts: [
+            ...secondSwapAccounts,
+            {
+              pubkey: nextObservationState,
+              isSigner: false,
+              isWritable: true
+            },
+          ],
+          instructions: [
+            swap(sqrtPriceLimitFirst, pastObservationState, lastObservationState, firstSwapAccounts),
+            swap(sqrtPriceLimitSecond, lastObservationState, nextObservationState, secondSwapAccounts),
+          ],
+        }
+      )).to.be.rejectedWith('Price deviation')
+
+      const { sqrtPriceX32 } = await coreProgram.account.poolState.fetch(poolAState)
+      assert(sqrtPriceX32.eq(currentSqrtPriceX32))
+    })
+  })
+
+  describe('#exact_input', () => {
+
+    const deadline = new BN(Date.now() / 1000 + 10_000)
+    it('performs a single pool swap', async () => {
+      const poolStateDataBefore = await coreProgram.account.poolState.fetch(poolAState)
+      console.log('pool price', poolStateDataBefore.sqrtPriceX32.toNumber())
+      console.log('pool tick', poolStateDataBefore.tick)
+
+      const {
+        observationIndex,
+        observationCardinalityNext
+      } = await coreProgram.account.poolState.fetch(poolAState)
+
+      lastObservationAState = (await PublicKey.findProgramAddress(
+        [
+          OBSERVATION_SEED,
+          token0.publicKey.toBuffer(),
+          token1.publicKey.toBuffer(),
+          u32ToSeed(fee),
+          u16ToSeed(observationIndex)
+        ],
+        coreProgram.programId
+      ))[0]
+
+      nextObservationAState = (await PublicKey.findProgramAddress(
+        [
+          OBSERVATION_SEED,
+          token0.publicKey.toBuffer(),
+          token1.publicKey.toBuffer(),
+          u32ToSeed(fee),
+          u16ToSeed((observationIndex + 1) % observationCardinalityNext)
+        ],
+        coreProgram.programId
+      ))[0]
+
+      const amountIn = new BN(100_000)
+      const amountOutMinimum = new BN(0)
+      const [expectedAmountOut, expectedNewPool, swapAccounts] = await uniPoolA.getOutputAmount(
+        CurrencyAmount.fromRawAmount(uniToken0, amountIn.toNumber())
+      )
+      console.log('expected pool', expectedNewPool)
+
+      await coreProgram.rpc.exactInput(
+        deadline,
+        amountIn,
+        amountOutMinimum,
+        Buffer.from([2]),
+        {
+          accounts: {
+            signer: owner,
+            factoryState,
+            inputTokenAccount: minterWallet0,
+            coreProgram: coreProgram.programId,
+            tokenProgram: TOKEN_PROGRAM_ID,
+          }, remainingAccounts: [{
+            pubkey: poolAState,
+            isSigner: false,
+            isWritable: true
+          }, {
+            pubkey: minterWallet1, // outputTokenAccount
+            isSigner: false,
+            isWritable: true
+          }, {
+            pubkey: vaultA0, // input vault
+            isSigner: false,
+            isWritable: true
+          }, {
+            pubkey: vaultA1, // output vault
+            isSigner: false,
+            isWritable: true
+          }, {
+            pubkey: lastObservationAState,
+            isSigner: false,
+            isWritable: true
+          },
+          ...swapAccounts,
+          {
+            pubkey: nextObservationAState,
+            isSigner: false,
+            isWritable: true
+          },
+          ]
+        }
+      )
+
+      const poolStateDataAfter = await coreProgram.account.poolState.fetch(poolAState)
+      console.log('pool price after', poolStateDataAfter.sqrtPriceX32.toNumber())
+      console.log('pool tick after', poolStateDataAfter.tick)
+    })
+
+    it('creates a second liquidity pool', async () => {
+      await coreProgram.rpc.createAndInitPool(initialPriceX32, 1, {
+        accounts: {
+          poolCreator: owner,
+          token0: token1.publicKey,
+          token1: token2.publicKey,
+          factoryState,
+          feeState,
+          poolState: poolBState,
+          initialObservationState: initialObservationStateB,
+          systemProgram: SystemProgram.programId,
+          rent: web3.SYSVAR_RENT_PUBKEY,
+        }
+      })
+      console.log('second pool created')
+
+      const {
+        observationIndex,
+        observationCardinalityNext
+      } = await coreProgram.account.poolState.fetch(poolBState)
+
+      latestObservationBState = (await PublicKey.findProgramAddress(
+        [
+          OBSERVATION_SEED,
+          token1.publicKey.toBuffer(),
+          token2.publicKey.toBuffer(),
+          u32ToSeed(fee),
+          u16ToSeed(observationIndex)
+        ],
+        coreProgram.programId
+      ))[0]
+
+      nextObservationBState = (await PublicKey.findProgramAddress(
+        [
+          OBSERVATION_SEED,
+          token1.publicKey.toBuffer(),
+          token2.publicKey.toBuffer(),
+          u32ToSeed(fee),
+          u16ToSeed((observationIndex + 1) % observationCardinalityNext)
+        ],
+        coreProgram.programId
+      ))[0]
+
+      // create tick and bitmap accounts
+      // can't combine with createTokenizedPosition due to size limit
+
+      const tx = new web3.Transaction()
+      tx.recentBlockhash = (await connection.getRecentBlockhash()).blockhash
+      tx.instructions = [
+        coreProgram.instruction.initTickAccount(tickLower, {
+          accounts: {
+            signer: owner,
+            poolState: poolBState,
+            tickState: tickLowerBState,
+            systemProgram: SystemProgram.programId,
+          }
+        }),
+        coreProgram.instruction.initTickAccount(tickUpper, {
+          accounts: {
+            signer: owner,
+            poolState: poolBState,
+            tickState: tickUpperBState,
+            systemProgram: SystemProgram.programId,
+          }
+        }),
+        coreProgram.instruction.initBitmapAccount(wordPosLower, {
+          accounts: {
+            signer: owner,
+            poolState: poolBState,
+            bitmapState: bitmapLowerBState,
+            systemProgram: SystemProgram.programId,
+          }
+        }),
+        coreProgram.instruction.initPositionAccount({
+          accounts: {
+            signer: owner,
+            recipient: factoryState,
+            poolState: poolBState,
+            tickLowerState: tickLowerBState,
+            tickUpperState: tickUpperBState,
+            positionState: corePositionBState,
+            systemProgram: SystemProgram.programId,
+          }
+        }),
+        coreProgram.instruction.initOwnerLiquidityAccount({
+          accounts: {
+            signer: owner,
+            owner: factoryState,
+            poolState: poolBState,
+            ownerLiquidityState: factoryLiquidityBState,
+            systemProgram: SystemProgram.programId,
+          }
+        })
+      ]
+      await anchor.getProvider().send(tx)
+
+      console.log('creating tokenized position')
+      await coreProgram.rpc.mintTokenizedPosition(amount0Desired,
+        amount1Desired,
+        new BN(0),
+        new BN(0),
+        deadline, {
+        accounts: {
+          minter: owner,
+          recipient: owner,
+          factoryState,
+          nftMint: nftMintBKeypair.publicKey,
+          nftAccount: positionBNftAccount,
+          poolState: poolBState,
+          corePositionState: corePositionBState,
+          ownerLiquidityState: factoryLiquidityBState,
+          tickLowerState: tickLowerBState,
+          tickUpperState: tickUpperBState,
+          bitmapLowerState: bitmapLowerBState,
+          bitmapUpperState: bitmapUpperBState,
+          tokenAccount0: minterWallet1,
+          tokenAccount1: minterWallet2,
+          vault0: vaultB1,
+          vault1: vaultB2,
+          lastObservationState: latestObservationBState,
+          tokenizedPositionState: tokenizedPositionBState,
+
+          coreProgram: coreProgram.programId,
+          systemProgram: SystemProgram.programId,
+          rent: web3.SYSVAR_RENT_PUBKEY,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID
+        },
+        remainingAccounts: [{
+          pubkey: nextObservationBState,
+          isSigner: false,
+          isWritable: true
+        }],
+        signers: [nftMintBKeypair],
+      })
+    })
+
+    it('perform a two pool swap', async () => {
+      const poolStateDataBefore = await coreProgram.account.poolState.fetch(poolAState)
+      console.log('pool price', poolStateDataBefore.sqrtPriceX32.toNumber())
+      console.log('pool tick', poolStateDataBefore.tick)
+
+      const {
+        observationIndex: observationAIndex,
+        observationCardinalityNext: observationCardinalityANext
+      } = await coreProgram.account.poolState.fetch(poolAState)
+
+      lastObservationAState = (await PublicKey.findProgramAddress(
+        [
+          OBSERVATION_SEED,
+          token0.publicKey.toBuffer(),
+          token1.publicKey.toBuffer(),
+          u32ToSeed(fee),
+          u16ToSeed(observationAIndex)
+        ],
+        coreProgram.programId
+      ))[0]
+
+      nextObservationAState = (await PublicKey.findProgramAddress(
+        [
+          OBSERVATION_SEED,
+          token0.publicKey.toBuffer(),
+          token1.publicKey.toBuffer(),
+          u32ToSeed(fee),
+          u16ToSeed((observationAIndex + 1) % observationCardinalityANext)
+        ],
+        coreProgram.programId
+      ))[0]
+
+      const {
+        observationIndex: observationBIndex,
+        observationCardinalityNext: observationCardinalityBNext
+      } = await coreProgram.account.poolState.fetch(poolBState)
+
+      latestObservationBState = (await PublicKey.findProgramAddress(
+        [
+          OBSERVATION_SEED,
+          token1.publicKey.toBuffer(),
+          token2.publicKey.toBuffer(),
+          u32ToSeed(fee),
+          u16ToSeed(observationBIndex)
+        ],
+        coreProgram.programId
+      ))[0]
+
+      nextObservationBState = (await PublicKey.findProgramAddress(
+        [
+          OBSERVATION_SEED,
+          token1.publicKey.toBuffer(),
+          token2.publicKey.toBuffer(),
+          u32ToSeed(fee),
+          u16ToSeed((observationBIndex + 1) % observationCardinalityBNext)
+        ],
+        coreProgram.programId
+      ))[0]
+
+      let vaultBalanceA0 = await token0.getAccountInfo(vaultA0)
+      let vaultBalanceA1 = await token1.getAccountInfo(vaultA1)
+      let vaultBalanceB1 = await token1.getAccountInfo(vaultB1)
+      let vaultBalanceB2 = await token2.getAccountInfo(vaultB2)
+      console.log(
+        'vault balances before',
+        vaultBalanceA0.amount.toNumber(),
+        vaultBalanceA1.amount.toNumber(),
+        vaultBalanceB1.amount.toNumber(),
+        vaultBalanceB2.amount.toNumber()
+      )
+      let token2AccountInfo = await token2.getAccountInfo(minterWallet2)
+      console.log('token 2 balance before', token2AccountInfo.amount.toNumber())
+
+      console.log('pool B address', poolBState.toString())
+
+      const amountIn = new BN(100_000)
+      const amountOutMinimum = new BN(0)
+      await coreProgram.rpc.exactInput(
+        deadline,
+        amountIn,
+        amountOutMinimum,
+        Buffer.from([2, 2]),
+        {
+          accounts: {
+            signer: owner,
+            factoryState,
+            inputTokenAccount: minterWallet0,
+            coreProgram: coreProgram.programId,
+            tokenProgram: TOKEN_PROGRAM_ID,
+          }, remainingAccounts: [{
+            pubkey: poolAState,
+            isSigner: false,
+            isWritable: true
+          },{
+            pubkey: minterWallet1, // outputTokenAccount
+            isSigner: false,
+            isWritable: true
+          },{
+            pubkey: vaultA0, // input vault
+            isSigner: false,
+            isWritable: true
+          },{
+            pubkey: vaultA1, // output vault
+            isSigner: false,
+            isWritable: true
+          },{
+            pubkey: lastObservationAState,
+            isSigner: false,
+            isWritable: true
+          },
+          {
+            pubkey: bitmapLowerAState,
+            isSigner: false,
+            isWritable: true
+          },
+          {
+            pubkey: nextObservationAState,
+            isSigner: false,
+            isWritable: true
+          },
+          // second pool
+          {
+            pubkey: poolBState,
+            isSigner: false,
+            isWritable: true
+          },{
+            pubkey: minterWallet2, // outputTokenAccount
+            isSigner: false,
+            isWritable: true
+          },{
+            pubkey: vaultB1, // input vault
+            isSigner: false,
+            isWritable: true
+          },{
+            pubkey: vaultB2, // output vault
+            isSigner: false,
+            isWritable: true
+          },{
+            pubkey: latestObservationBState,
+            isSigner: false,
+            isWritable: true
+          },
+            {
+            pubkey: bitmapLowerBState,
+            isSigner: false,
+            isWritable: true
+          }, {
+            pubkey: tickUpperBState,
+            isSigner: false,
+            isWritable: true
+          },
+          {
+            pubkey: nextObservationBState,
+            isSigner: false,
+            isWritable: true
+          },
+        ]
+        }
+      )
+      vaultBalanceA0 = await token0.getAccountInfo(vaultA0)
+      vaultBalanceA1 = await token1.getAccountInfo(vaultA1)
+      vaultBalanceB1 = await token1.getAccountInfo(vaultB1)
+      vaultBalanceB2 = await token2.getAccountInfo(vaultB2)
+      console.log(
+        'vault balances after',
+        vaultBalanceA0.amount.toNumber(),
+        vaultBalanceA1.amount.toNumber(),
+        vaultBalanceB1.amount.toNumber(),
+        vaultBalanceB2.amount.toNumber()
+      )
+
+      const poolStateDataAfter = await coreProgram.account.poolState.fetch(poolAState)
+      console.log('pool A price after', poolStateDataAfter.sqrtPriceX32.toNumber())
+      console.log('pool A tick after', poolStateDataAfter.tick)
+
+      token2AccountInfo = await token2.getAccountInfo(minterWallet2)
+      console.log('token 2 balance after', token2AccountInfo.amount.toNumber())
+    })
+  })
+
+  describe('#swap with referral', () => {
+    let referralWallet1: web3.PublicKey
+    const amountIn = new BN(100_000)
+
+    const setMaxReferralFee = (maxReferralFeeBps: number) => coreProgram.rpc.setMaxReferralFee(maxReferralFeeBps, {
+      accounts: {
+        owner,
+        factoryState,
+        poolState: poolAState,
+      }
+    })
+
+    const simulateSwap = async (referral: { tokenAccount: web3.PublicKey, feeBps: number } | null) => {
+      const [, , swapAccounts] = await uniPoolA.getOutputAmount(
+        CurrencyAmount.fromRawAmount(uniToken0, amountIn.toNumber())
+      )
+      return coreProgram.simulate.swap(amountIn, MIN_SQRT_RATIO.addn(1), referral, false, {
+        accounts: {
+          signer: owner,
+          inputTokenAccount: minterWallet0,
+          outputTokenAccount: minterWallet1,
+          inputVault: vaultA0,
+          outputVault: vaultA1,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          factoryState,
+          poolState: poolAState,
+          lastObservationState: lastObservationAState,
+          callbackHandler: coreProgram.programId,
+        },
+        remainingAccounts: [
+          ...swapAccounts,
+          {
+            pubkey: nextObservationAState,
+            isSigner: false,
+            isWritable: true
+          },
+          {
+            pubkey: referralWallet1,
+            isSigner: false,
+            isWritable: true
+          },
+        ]
+      })
+    }
+
+    before(async () => {
+      referralWallet1 = await token1.createAccount(notOwner.publicKey)
+    })
+
+    it('cannot be changed by addresses that are not owner', async () => {
+      await expect(coreProgram.rpc.setMaxReferralFee(50, {
+        accounts: {
+          owner: notOwner.publicKey,
+          factoryState,
+          poolState: poolAState,
+        },
+        signers: [notOwner]
+      })).to.be.rejectedWith(Error)
+    })
+
+    it('fails to set a maximum above MAX_REFERRAL_FEE_BPS', async () => {
+      await expect(setMaxReferralFee(1001)).to.be.rejectedWith(Error)
+    })
+
+    it('swaps without a referral', async () => {
+      await setMaxReferralFee(50)
+      const { events } = await simulateSwap(null)
+      const swapEvent = events.find(event => event.name == 'SwapEvent')
+      assert(!events.some(event => event.name == 'ReferralFeeEvent'))
+
+      // valued in the default quote token, token_0
+      assert((swapEvent.data.valueInQuote as BN).eq((swapEvent.data.amount0 as BN).abs()))
+    })
+
+    it('skims the referral fee from the output', async () => {
+      const { events } = await simulateSwap({ tokenAccount: referralWallet1, feeBps: 50 })
+      const swapEvent = events.find(event => event.name == 'SwapEvent')
+      const referralFeeEvent = events.find(event => event.name == 'ReferralFeeEvent')
+
+      const amountOut = (swapEvent.data.amount1 as BN).neg()
+      assert(referralFeeEvent.data.referralTokenAccount.equals(referralWallet1))
+      assert.equal(referralFeeEvent.data.feeBps, 50)
+      assert((referralFeeEvent.data.amount as BN).eq(amountOut.muln(50).divn(10_000)))
+    })
+
+    it('fails for a referral fee above the pool maximum', async () => {
+      await expect(simulateSwap({ tokenAccount: referralWallet1, feeBps: 51 })).to.be.rejectedWith(Error)
+
+      await setMaxReferralFee(0)
+      await expect(simulateSwap({ tokenAccount: referralWallet1, feeBps: 1 })).to.be.rejectedWith(Error)
+    })
+  })
+
+  describe('#swap across a liquidity gap', () => {
+    // more than pool A holds in range, with a limit in the empty word below the positions
+    const amountIn = new BN(10_000_000)
+    const sqrtPriceLimitX32 = TickMath.getSqrtRatioAtTick(-2550)
+
+    const simulateSwap = async (noGap: boolean) => {
+      const [, , swapAccounts] = await uniPoolA.getOutputAmount(
+        CurrencyAmount.fromRawAmount(uniToken0, amountIn.toNumber()),
+        sqrtPriceLimitX32
+      )
+      return coreProgram.simulate.swap(amountIn, new BN(sqrtPriceLimitX32.toString()), null, noGap, {
+        accounts: {
+          signer: owner,
+          inputTokenAccount: minterWallet0,
+          outputTokenAccount: minterWallet1,
+          inputVault: vaultA0,
+          outputVault: vaultA1,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          factoryState,
+          poolState: poolAState,
+          lastObservationState: lastObservationAState,
+          callbackHandler: coreProgram.programId,
+        },
+        remainingAccounts: [
+          ...swapAccounts,
+          {
+            pubkey: nextObservationAState,
+            isSigner: false,
+            isWritable: true
+          },
+        ]
+      })
+    }
+
+    it('partially fills through the gap without the flag', async () => {
+      const { events } = await simulateSwap(false)
+      const swapEvent = events.find(event => event.name == 'SwapEvent')
+
+      const amount0 = swapEvent.data.amount0 as BN
+      assert(amount0.gtn(0))
+      assert(amount0.lt(amountIn))
+      assert((swapEvent.data.sqrtPriceX32 as BN).eq(new BN(sqrtPriceLimitX32.toString())))
+    })
+
+    it('fails if the swap reaches the gap with the flag set', async () => {
+      await expect(simulateSwap(true)).to.be.rejectedWith('Liquidity gap traversed')
+    })
+  })
+
+  describe('#swap compute units', () => {
+    // the constants of swap_quote::estimate_compute_units, an estimate of base + crossed ticks * cross
+    const SWAP_BASE_COMPUTE_UNITS = 70_000
+    const TICK_CROSS_COMPUTE_UNITS = 25_000
+    const amountIn = new BN(10_000_000)
+
+    it('estimates no fewer compute units than swaps consume', async () => {
+      const {
+        tick: currentTick,
+        sqrtPriceX32: currentSqrtPriceX32,
+        liquidity: currentLiquidity,
+        observationIndex,
+        observationCardinalityNext
+      } = await coreProgram.account.poolState.fetch(poolAState)
+      const tickDataProvider = new SolanaTickDataProvider(coreProgram, {
+        token0: token0.publicKey,
+        token1: token1.publicKey,
+        fee,
+      })
+      await tickDataProvider.eagerLoadCache(currentTick, tickSpacing)
+      const pool = new Pool(
+        uniToken0,
+        uniToken1,
+        fee,
+        JSBI.BigInt(currentSqrtPriceX32),
+        JSBI.BigInt(currentLiquidity),
+        currentTick,
+        tickDataProvider
+      )
+
+      // zero for one swaps stopping just above each of the next initialized ticks below the
+      // price cross 0, 1, 2... ticks
+      const initializedTicks: number[] = []
+      let tick = currentTick
+      for (let i = 0; i < 20 && initializedTicks.length < 4; i++) {
+        const [nextTick, initialized] = tickDataProvider.nextInitializedTickWithinOneWord(tick, true, tickSpacing)
+        if (initialized) {
+          initializedTicks.push(nextTick)
+        }
+        tick = nextTick - 1
+      }
+
+      const observationAddress = async (index: number) => (await PublicKey.findProgramAddress(
+        [
+          OBSERVATION_SEED,
+          token0.publicKey.toBuffer(),
+          token1.publicKey.toBuffer(),
+          u32ToSeed(fee),
+          u16ToSeed(index)
+        ],
+        coreProgram.programId
+      ))[0]
+      const lastObservationState = await observationAddress(observationIndex)
+      const nextObservationState = await observationAddress((observationIndex + 1) % observationCardinalityNext)
+      const samples: { crossedTicks: number, computeUnits: number }[] = []
+      for (const initializedTick of initializedTicks) {
+        const sqrtPriceLimitX32 = JSBI.add(TickMath.getSqrtRatioAtTick(initializedTick), JSBI.BigInt(1))
+        if (!JSBI.lessThan(sqrtPriceLimitX32, JSBI.BigInt(currentSqrtPriceX32.toString()))) {
+          continue
+        }
+        const [, , swapAccounts] = await pool.getOutputAmount(
+          CurrencyAmount.fromRawAmount(uniToken0, amountIn.toNumber()),
+          sqrtPriceLimitX32
+        )
+        const { events, raw } = await coreProgram.simulate.swap(amountIn, new BN(sqrtPriceLimitX32.toString()), null, false, {
+          accounts: {
+            signer: owner,
+            inputTokenAccount: minterWallet0,
+            outputTokenAccount: minterWallet1,
+            inputVault: vaultA0,
+            outputVault: vaultA1,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            factoryState,
+            poolState: poolAState,
+            lastObservationState,
+            callbackHandler: coreProgram.programId,
+          },
+          remainingAccounts: [
+            ...swapAccounts,
+            {
+              pubkey: nextObservationState,
+              isSigner: false,
+              isWritable: true
+            },
+          ]
+        })
+
+        // the last log of the program is its top level invocation, which includes the callback
+        const prefix = `Program ${coreProgram.programId.toBase58()} consumed `
+        const consumedLog = raw.filter(log => log.startsWith(prefix)).pop()
+        samples.push({
+          crossedTicks: events.filter(event => event.name == 'TickCrossEvent').length,
+          computeUnits: Number(consumedLog.slice(prefix.length).split(' ')[0]),
+        })
+      }
+      console.log('swap compute units', samples)
+
+      assert(samples.some(({ crossedTicks }) => crossedTicks >= 2))
+      for (const { crossedTicks, computeUnits } of samples) {
+        assert(computeUnits <= SWAP_BASE_COMPUTE_UNITS + crossedTicks * TICK_CROSS_COMPUTE_UNITS)
+      }
+    })
+  })
+
+  describe('#swap with a transfer fee input token', () => {
+    // pays the pool 1% less than owed, as a token with a transfer fee would
+    const transferFeeCallbackHandler = new web3.PublicKey('5m9FmMadekDLSn2WNwgoKySfMfz4rXgn8oCEcReGz6Y2')
+    const amountIn = new BN(100_000)
+    const amountReceived = amountIn.sub(amountIn.divn(100))
+
+    const swapContext = async (sqrtPriceLimitX32: BN) => {
+      const [, , swapAccounts] = await uniPoolA.getOutputAmount(
+        CurrencyAmount.fromRawAmount(uniToken0, amountIn.toNumber()),
+        JSBI.BigInt(sqrtPriceLimitX32.toString())
+      )
+      return {
+        accounts: {
+          signer: owner,
+          inputTokenAccount: minterWallet0,
+          outputTokenAccount: minterWallet1,
+          inputVault: vaultA0,
+          outputVault: vaultA1,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          factoryState,
+          poolState: poolAState,
+          lastObservationState: lastObservationAState,
+          callbackHandler: transferFeeCallbackHandler,
+        },
+        remainingAccounts: [
+          ...swapAccounts,
+          {
+            pubkey: nextObservationAState,
+            isSigner: false,
+            isWritable: true
+          },
+        ]
+      }
+    }
+
+    it('swaps the amount received by the vault', async () => {
+      const sqrtPriceLimitX32 = MIN_SQRT_RATIO.addn(1)
+      const { events } = await coreProgram.simulate.swap(
+        amountIn, sqrtPriceLimitX32, null, false, await swapContext(sqrtPriceLimitX32)
+      )
+      const swapEvent = events.find(event => event.name == 'SwapEvent')
+
+      assert((swapEvent.data.amount0 as BN).eq(amountReceived))
+    })
+
+    it('refunds the input received but left unswapped at the price limit', async () => {
+      // a limit 1 bps below the current price fills a fraction of the input
+      const { sqrtPriceX32 } = await coreProgram.account.poolState.fetch(poolAState)
+      const sqrtPriceLimitX32 = sqrtPriceX32.muln(9999).divn(10_000)
+
+      const wallet0Before = (await token0.getAccountInfo(minterWallet0)).amount
+      const vault0Before = (await token0.getAccountInfo(vaultA0)).amount
+      await coreProgram.rpc.swap(amountIn, sqrtPriceLimitX32, null, false, await swapContext(sqrtPriceLimitX32))
+      const wallet0After = (await token0.getAccountInfo(minterWallet0)).amount
+      const vault0After = (await token0.getAccountInfo(vaultA0)).amount
+
+      // the vault keeps only the swapped input, the rest of the amount received is returned
+      const amountSwapped = vault0After.sub(vault0Before)
+      assert(amountSwapped.gtn(0))
+      assert(amountSwapped.lt(amountReceived))
+      assert(wallet0Before.sub(wallet0After).eq(amountSwapped))
+
+      const poolStateData = await coreProgram.account.poolState.fetch(poolAState)
+      assert(poolStateData.sqrtPriceX32.eq(sqrtPriceLimitX32))
+    })
+  })
+
+  describe('#swap in a zero fee pool', () => {
+    // a pool of token_0 and token_1 without a fee, with one wallet position around the price
+    const zeroFee = 0
+    const liquidity = new BN(1_000_000_000)
+    const [zeroFeeTickLower, zeroFeeTickUpper] = [0, 20]
+    const amountIn = new BN(100_000)
+    let zeroFeePoolState: web3.PublicKey
+    let zeroFeeVault0: web3.PublicKey
+    let zeroFeeVault1: web3.PublicKey
+    let zeroFeeObservationState: web3.PublicKey
+    let zeroFeeBitmapState: web3.PublicKey
+    let zeroFeeTickLowerState: web3.PublicKey
+    let zeroFeeTickUpperState: web3.PublicKey
+    let zeroFeePositionState: web3.PublicKey
+    let zeroFeeOwnerLiquidityState: web3.PublicKey
+
+    const address = async (seeds: Buffer[]) => (await PublicKey.findProgramAddress(seeds, coreProgram.programId))[0]
+    const poolSeeds = () => [token0.publicKey.toBuffer(), token1.publicKey.toBuffer(), u32ToSeed(zeroFee)]
+    const positionAccounts = () => ({
+      poolState: zeroFeePoolState,
+      tickLowerState: zeroFeeTickLowerState,
+      tickUpperState: zeroFeeTickUpperState,
+      bitmapLowerState: zeroFeeBitmapState,
+      bitmapUpperState: zeroFeeBitmapState,
+      positionState: zeroFeePositionState,
+      ownerLiquidityState: zeroFeeOwnerLiquidityState,
+      lastObservationState: zeroFeeObservationState,
+    })
+    // a single observation, so the next observation is the last one
+    const nextObservation = () => [{
+      pubkey: zeroFeeObservationState,
+      isSigner: false,
+      isWritable: true
+    }]
+
+    before(async () => {
+      const zeroFeeState = await address([FEE_SEED, u32ToSeed(zeroFee)])
+      await coreProgram.rpc.enableFeeAmount(zeroFee, tickSpacing, {
+        accounts: {
+          owner,
+          factoryState,
+          feeState: zeroFeeState,
+          systemProgram: SystemProgram.programId,
+        }
+      })
+
+      zeroFeePoolState = await address([POOL_SEED, ...poolSeeds()])
+      zeroFeeObservationState = await address([OBSERVATION_SEED, ...poolSeeds(), u16ToSeed(0)])
+      await coreProgram.rpc.createAndInitPool(initialPriceX32, 1, {
+        accounts: {
+          poolCreator: owner,
+          token0: token0.publicKey,
+          token1: token1.publicKey,
+          factoryState,
+          feeState: zeroFeeState,
+          poolState: zeroFeePoolState,
+          initialObservationState: zeroFeeObservationState,
+          systemProgram: SystemProgram.programId,
+          rent: web3.SYSVAR_RENT_PUBKEY,
+        }
+      })
+
+      zeroFeeVault0 = await Token.getAssociatedTokenAddress(
+        ASSOCIATED_TOKEN_PROGRAM_ID,
+        TOKEN_PROGRAM_ID,
+        token0.publicKey,
+        zeroFeePoolState,
+        true
+      )
+      zeroFeeVault1 = await Token.getAssociatedTokenAddress(
+        ASSOCIATED_TOKEN_PROGRAM_ID,
+        TOKEN_PROGRAM_ID,
+        token1.publicKey,
+        zeroFeePoolState,
+        true
+      )
+      zeroFeeBitmapState = await address([BITMAP_SEED, ...poolSeeds(), i16ToSeed(0)])
+      zeroFeeTickLowerState = await address([TICK_SEED, ...poolSeeds(), i32ToSeed(zeroFeeTickLower)])
+      zeroFeeTickUpperState = await address([TICK_SEED, ...poolSeeds(), i32ToSeed(zeroFeeTickUpper)])
+      zeroFeePositionState = await address([
+        POSITION_SEED,
+        ...poolSeeds(),
+        owner.toBuffer(),
+        i32ToSeed(zeroFeeTickLower),
+        i32ToSeed(zeroFeeTickUpper)
+      ])
+      zeroFeeOwnerLiquidityState = await address([OWNER_LIQUIDITY_SEED, ...poolSeeds(), owner.toBuffer()])
+
+      const tx = new Transaction()
+      tx.instructions = [
+        Token.createAssociatedTokenAccountInstruction(
+          ASSOCIATED_TOKEN_PROGRAM_ID,
+          TOKEN_PROGRAM_ID,
+          token0.publicKey,
+          zeroFeeVault0,
+          zeroFeePoolState,
+          owner
+        ),
+        Token.createAssociatedTokenAccountInstruction(
+          ASSOCIATED_TOKEN_PROGRAM_ID,
+          TOKEN_PROGRAM_ID,
+          token1.publicKey,
+          zeroFeeVault1,
+          zeroFeePoolState,
+          owner
+        ),
+        coreProgram.instruction.initBitmapAccount(0, {
+          accounts: {
+            signer: owner,
+            poolState: zeroFeePoolState,
+            bitmapState: zeroFeeBitmapState,
+            systemProgram: SystemProgram.programId,
+          }
+        }),
+        ...[[zeroFeeTickLower, zeroFeeTickLowerState], [zeroFeeTickUpper, zeroFeeTickUpperState]].map(([tick, tickState]) =>
+          coreProgram.instruction.initTickAccount(tick as number, {
+            accounts: {
+              signer: owner,
+              poolState: zeroFeePoolState,
+              tickState: tickState as web3.PublicKey,
+              systemProgram: SystemProgram.programId,
+            }
+          })
+        ),
+        coreProgram.instruction.initPositionAccount({
+          accounts: {
+            signer: owner,
+            recipient: owner,
+            poolState: zeroFeePoolState,
+            tickLowerState: zeroFeeTickLowerState,
+            tickUpperState: zeroFeeTickUpperState,
+            positionState: zeroFeePositionState,
+            systemProgram: SystemProgram.programId,
+          }
+        }),
+        coreProgram.instruction.initOwnerLiquidityAccount({
+          accounts: {
+            signer: owner,
+            owner,
+            poolState: zeroFeePoolState,
+            ownerLiquidityState: zeroFeeOwnerLiquidityState,
+            systemProgram: SystemProgram.programId,
+          }
+        }),
+      ]
+      await anchor.getProvider().send(tx)
+
+      await coreProgram.rpc.mint(liquidity, new BN(0), {
+        accounts: {
+          minter: owner,
+          tokenAccount0: minterWallet0,
+          tokenAccount1: minterWallet1,
+          vault0: zeroFeeVault0,
+          vault1: zeroFeeVault1,
+          recipient: owner,
+          ...positionAccounts(),
+          tokenProgram: TOKEN_PROGRAM_ID,
+          callbackHandler: coreProgram.programId,
+        },
+        remainingAccounts: nextObservation(),
+      })
+    })
+
+    it('swaps without growing the fees of liquidity providers', async () => {
+      for (const zeroForOne of [true, false]) {
+        const poolBefore = await coreProgram.account.poolState.fetch(zeroFeePoolState)
+        // both directions swap within the position, starting in the word of tick 0
+        await coreProgram.rpc.swap(amountIn, zeroForOne ? MIN_SQRT_RATIO.addn(1) : MAX_SQRT_RATIO.subn(1), null, false, {
+          accounts: {
+            signer: owner,
+            inputTokenAccount: zeroForOne ? minterWallet0 : minterWallet1,
+            outputTokenAccount: zeroForOne ? minterWallet1 : minterWallet0,
+            inputVault: zeroForOne ? zeroFeeVault0 : zeroFeeVault1,
+            outputVault: zeroForOne ? zeroFeeVault1 : zeroFeeVault0,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            factoryState,
+            poolState: zeroFeePoolState,
+            lastObservationState: zeroFeeObservationState,
+            callbackHandler: coreProgram.programId,
+          },
+          remainingAccounts: [
+            {
+              pubkey: zeroFeeBitmapState,
+              isSigner: false,
+              isWritable: false
+            },
+            ...nextObservation(),
+          ],
+        })
+
+        const poolAfter = await coreProgram.account.poolState.fetch(zeroFeePoolState)
+        assert(!poolAfter.sqrtPriceX32.eq(poolBefore.sqrtPriceX32))
+        assert(poolAfter.feeGrowthGlobal0X32.eq(poolBefore.feeGrowthGlobal0X32))
+        assert(poolAfter.feeGrowthGlobal1X32.eq(poolBefore.feeGrowthGlobal1X32))
+      }
+    })
+
+    it('credits no fees to the position', async () => {
+      // burning zero liquidity pokes the position
+      await coreProgram.rpc.burn(new BN(0), new BN(0), {
+        accounts: {
+          owner,
+          ...positionAccounts(),
+        },
+        remainingAccounts: nextObservation(),
+      })
+
+      const positionData = await coreProgram.account.positionState.fetch(zeroFeePositionState)
+      assert(positionData.liquidity.eq(liquidity))
+      assert(positionData.tokensOwed0.eqn(0))
+      assert(positionData.tokensOwed1.eqn(0))
+    })
+  })
+
+  describe('#swap_split', () => {
+    // splits a zero for one swap between pool A and the zero fee pool of the same pair
+    const deadline = new BN(Date.now() / 1000 + 1_000_000)
+    const amountIn = new BN(100_000)
+    const shareA = 600_000
+    const amountInA = amountIn.muln(shareA).divn(1_000_000)
+    const amountInB = amountIn.sub(amountInA)
+
+    const address = async (seeds: Buffer[]) => (await PublicKey.findProgramAddress(seeds, coreProgram.programId))[0]
+    const poolSeeds = (poolFee: number) => [token0.publicKey.toBuffer(), token1.publicKey.toBuffer(), u32ToSeed(poolFee)]
+
+    const splitContext = async () => {
+      const {
+        tick: currentTick,
+        sqrtPriceX32: currentSqrtPriceX32,
+        liquidity: currentLiquidity,
+        observationIndex,
+        observationCardinalityNext
+      } = await coreProgram.account.poolState.fetch(poolAState)
+      lastObservationAState = await address([OBSERVATION_SEED, ...poolSeeds(fee), u16ToSeed(observationIndex)])
+      nextObservationAState = await address([
+        OBSERVATION_SEED,
+        ...poolSeeds(fee),
+        u16ToSeed((observationIndex + 1) % observationCardinalityNext)
+      ])
+
+      const tickDataProvider = new SolanaTickDataProvider(coreProgram, {
+        token0: token0.publicKey,
+        token1: token1.publicKey,
+        fee,
+      })
+      await tickDataProvider.eagerLoadCache(currentTick, tickSpacing)
+      const [, , swapAccountsA] = await new Pool(
+        uniToken0,
+        uniToken1,
+        fee,
+        JSBI.BigInt(currentSqrtPriceX32),
+        JSBI.BigInt(currentLiquidity),
+        currentTick,
+        tickDataProvider
+      ).getOutputAmount(CurrencyAmount.fromRawAmount(uniToken0, amountInA.toNumber()))
+      const remainingAccountsA = [
+        ...swapAccountsA,
+        {
+          pubkey: nextObservationAState,
+          isSigner: false,
+          isWritable: true
+        },
+      ]
+
+      // the zero fee pool has a single observation, and its swaps stay in the word of tick 0
+      const zeroFeePoolState = await address([POOL_SEED, ...poolSeeds(0)])
+      const zeroFeeObservationState = await address([OBSERVATION_SEED, ...poolSeeds(0), u16ToSeed(0)])
+      const [zeroFeeVault0, zeroFeeVault1] = await Promise.all([token0, token1].map(token =>
+        Token.getAssociatedTokenAddress(
+          ASSOCIATED_TOKEN_PROGRAM_ID,
+          TOKEN_PROGRAM_ID,
+          token.publicKey,
+          zeroFeePoolState,
+          true
+        )
+      ))
+      const remainingAccountsB = [
+        {
+          pubkey: await address([BITMAP_SEED, ...poolSeeds(0), i16ToSeed(0)]),
+          isSigner: false,
+          isWritable: false
+        },
+        {
+          pubkey: zeroFeeObservationState,
+          isSigner: false,
+          isWritable: true
+        },
+      ]
 
-      await expect(coreProgram.rpc.exactInputSingle(
+      return {
+        accounts: {
+          signer: owner,
+          factoryState,
+          inputTokenAccount: minterWallet0,
+          outputTokenAccount: minterWallet1,
+          poolStateA: poolAState,
+          inputVaultA: vaultA0,
+          outputVaultA: vaultA1,
+          lastObservationStateA: lastObservationAState,
+          poolStateB: zeroFeePoolState,
+          inputVaultB: zeroFeeVault0,
+          outputVaultB: zeroFeeVault1,
+          lastObservationStateB: zeroFeeObservationState,
+          coreProgram: coreProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        },
+        remainingAccountsA,
+        remainingAccountsB,
+      }
+    }
+
+    it('fails if the accounts of the first pool exceed the remaining accounts', async () => {
+      const { accounts, remainingAccountsA, remainingAccountsB } = await splitContext()
+      const remainingAccounts = [...remainingAccountsA, ...remainingAccountsB]
+
+      await expect(coreProgram.rpc.swapSplit(
         deadline,
-        // true,
         amountIn,
-        amountOutMinimum,
-        sqrtPriceLimitX32,
+        new BN(0),
+        shareA,
+        remainingAccounts.length + 1,
+        { accounts, remainingAccounts }
+      )).to.be.rejectedWith('Invalid split')
+    })
+
+    it('swaps the input split between both pools', async () => {
+      const { accounts, remainingAccountsA, remainingAccountsB } = await splitContext()
+
+      const balances = async () => Promise.all(
+        [minterWallet0, vaultA0, accounts.inputVaultB].map(async tokenAccount =>
+          (await token0.getAccountInfo(tokenAccount)).amount
+        )
+      )
+      const [wallet0Before, vaultA0Before, vaultB0Before] = await balances()
+
+      await coreProgram.rpc.swapSplit(
+        deadline,
+        amountIn,
+        new BN(0),
+        shareA,
+        remainingAccountsA.length,
         {
-          accounts: {
-            signer: owner,
-            factoryState,
-            poolState: poolAState,
-            inputTokenAccount: minterWallet0,
-            outputTokenAccount: minterWallet1,
-            inputVault: vaultA0,
-            outputVault: vaultA1,
-            lastObservationState: lastObservationAState,
-            // nextObservationState: nextObservationAState,
-            coreProgram: coreProgram.programId,
-            tokenProgram: TOKEN_PROGRAM_ID,
-          }, remainingAccounts: [{
-            pubkey: nextObservationAState,
-            isSigner: false,
-            isWritable: true
-          },{
-            pubkey: bitmapLowerAState,
-            isSigner: false,
-            isWritable: true
-          },
-          // price moves downwards in zero for one swap
+          accounts,
+          remainingAccounts: [...remainingAccountsA, ...remainingAccountsB],
+        }
+      )
+      const [wallet0After, vaultA0After, vaultB0After] = await balances()
+
+      const amountSwappedA = vaultA0After.sub(vaultA0Before)
+      const amountSwappedB = vaultB0After.sub(vaultB0Before)
+      assert(amountSwappedA.eq(amountInA))
+      assert(amountSwappedB.eq(amountInB))
+      assert(amountSwappedA.add(amountSwappedB).eq(amountIn))
+      assert(wallet0Before.sub(wallet0After).eq(amountIn))
+    })
+  })
+
+  describe('#poke_positions', () => {
+    let emptyPositionAState: web3.PublicKey
+
+    const positionAccounts = (positionOwner: web3.PublicKey, positionState: web3.PublicKey) => [
+      { pubkey: positionOwner, isSigner: false, isWritable: false },
+      { pubkey: tickLowerAState, isSigner: false, isWritable: false },
+      { pubkey: tickUpperAState, isSigner: false, isWritable: false },
+      { pubkey: positionState, isSigner: false, isWritable: true },
+    ]
+
+    it('creates a position without liquidity', async () => {
+      emptyPositionAState = (await PublicKey.findProgramAddress([
+        POSITION_SEED,
+        token0.publicKey.toBuffer(),
+        token1.publicKey.toBuffer(),
+        u32ToSeed(fee),
+        notOwner.publicKey.toBuffer(),
+        i32ToSeed(tickLower),
+        i32ToSeed(tickUpper)
+      ],
+        coreProgram.programId
+      ))[0]
+
+      await coreProgram.rpc.initPositionAccount({
+        accounts: {
+          signer: owner,
+          recipient: notOwner.publicKey,
+          poolState: poolAState,
+          tickLowerState: tickLowerAState,
+          tickUpperState: tickUpperAState,
+          positionState: emptyPositionAState,
+          systemProgram: SystemProgram.programId,
+        }
+      })
+    })
+
+    it('fails if position accounts are not passed in groups of four', async () => {
+      await expect(coreProgram.rpc.pokePositions({
+        accounts: {
+          poolState: poolAState,
+        },
+        remainingAccounts: positionAccounts(owner, ownerPositionAState).slice(0, 3),
+      })).to.be.rejectedWith(Error)
+    })
+
+    it('fails for more positions than the batch limit, before poking any', async () => {
+      const positionBefore = await coreProgram.account.positionState.fetch(ownerPositionAState)
+
+      // MAX_BATCH + 1 groups. Repeated keys are deduplicated, so the transaction stays small
+      await expect(coreProgram.rpc.pokePositions({
+        accounts: {
+          poolState: poolAState,
+        },
+        remainingAccounts: Array(17).fill(positionAccounts(owner, ownerPositionAState)).flat(),
+      })).to.be.rejectedWith('Batch too large')
+
+      const positionAfter = await coreProgram.account.positionState.fetch(ownerPositionAState)
+      assert(positionAfter.feeGrowthInside0LastX32.eq(positionBefore.feeGrowthInside0LastX32))
+      assert(positionAfter.feeGrowthInside1LastX32.eq(positionBefore.feeGrowthInside1LastX32))
+      assert(positionAfter.tokensOwed0.eq(positionBefore.tokensOwed0))
+      assert(positionAfter.tokensOwed1.eq(positionBefore.tokensOwed1))
+    })
+
+    it('pokes liquid positions and skips positions without liquidity', async () => {
+      const ownerPositionBefore = await coreProgram.account.positionState.fetch(ownerPositionAState)
+      const corePositionBefore = await coreProgram.account.positionState.fetch(corePositionAState)
+      const emptyPositionBefore = await coreProgram.account.positionState.fetch(emptyPositionAState)
+      assert(ownerPositionBefore.liquidity.gtn(0))
+      assert(corePositionBefore.liquidity.gtn(0))
+      assert(emptyPositionBefore.liquidity.eqn(0))
+
+      await coreProgram.rpc.pokePositions({
+        accounts: {
+          poolState: poolAState,
+        },
+        remainingAccounts: [
+          ...positionAccounts(owner, ownerPositionAState),
+          ...positionAccounts(notOwner.publicKey, emptyPositionAState),
+          ...positionAccounts(factoryState, corePositionAState),
+        ],
+      })
+
+      // swaps since the last update earned fees for the liquid positions
+      const ownerPositionAfter = await coreProgram.account.positionState.fetch(ownerPositionAState)
+      const corePositionAfter = await coreProgram.account.positionState.fetch(corePositionAState)
+      for (const [before, after] of [
+        [ownerPositionBefore, ownerPositionAfter],
+        [corePositionBefore, corePositionAfter],
+      ]) {
+        assert(after.liquidity.eq(before.liquidity))
+        assert(
+          !after.feeGrowthInside0LastX32.eq(before.feeGrowthInside0LastX32) ||
+          !after.feeGrowthInside1LastX32.eq(before.feeGrowthInside1LastX32)
+        )
+        assert(after.tokensOwed0.gte(before.tokensOwed0))
+        assert(after.tokensOwed1.gte(before.tokensOwed1))
+      }
+
+      const emptyPositionAfter = await coreProgram.account.positionState.fetch(emptyPositionAState)
+      assert(emptyPositionAfter.feeGrowthInside0LastX32.eq(emptyPositionBefore.feeGrowthInside0LastX32))
+      assert(emptyPositionAfter.feeGrowthInside1LastX32.eq(emptyPositionBefore.feeGrowthInside1LastX32))
+      assert(emptyPositionAfter.tokensOwed0.eqn(0))
+      assert(emptyPositionAfter.tokensOwed1.eqn(0))
+    })
+
+    it('emits poke events in the order positions are passed', async () => {
+      const positions: [web3.PublicKey, web3.PublicKey][] = [
+        [factoryState, corePositionAState],
+        [owner, ownerPositionAState],
+        [notOwner.publicKey, emptyPositionAState],
+        [owner, ownerPositionAState],
+      ]
+      const { events } = await coreProgram.simulate.pokePositions({
+        accounts: {
+          poolState: poolAState,
+        },
+        remainingAccounts: positions.flatMap(([positionOwner, positionState]) =>
+          positionAccounts(positionOwner, positionState)
+        ),
+      })
+
+      const pokeEvents = events.filter(event => event.name == 'PokeEvent')
+      assert.equal(pokeEvents.length, positions.length)
+      pokeEvents.forEach((event, index) => {
+        assert.equal(event.data.positionIndex, index)
+        assert(event.data.owner.equals(positions[index][0]))
+        assert(event.data.poolState.equals(poolAState))
+      })
+    })
+  })
+
+  describe('#collect with a dust threshold', () => {
+    it('leaves tokens owed below the threshold accrued', async () => {
+      const positionBefore = await coreProgram.account.positionState.fetch(ownerPositionAState)
+      assert(positionBefore.tokensOwed0.gtn(0) || positionBefore.tokensOwed1.gtn(0))
+      const wallet0Before = (await token0.getAccountInfo(minterWallet0)).amount
+      const wallet1Before = (await token1.getAccountInfo(minterWallet1)).amount
+
+      const minCollectAmount = BN.max(positionBefore.tokensOwed0, positionBefore.tokensOwed1).addn(1)
+      await coreProgram.rpc.collect(MaxU64, MaxU64, minCollectAmount, new BN(0), {
+        accounts: {
+          owner,
+          poolState: poolAState,
+          tickLowerState: tickLowerAState,
+          tickUpperState: tickUpperAState,
+          positionState: ownerPositionAState,
+          vault0: vaultA0,
+          vault1: vaultA1,
+          recipientWallet0: minterWallet0,
+          recipientWallet1: minterWallet1,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        }
+      })
+
+      // no transfer, and the fees remain owed to the position
+      assert((await token0.getAccountInfo(minterWallet0)).amount.eq(wallet0Before))
+      assert((await token1.getAccountInfo(minterWallet1)).amount.eq(wallet1Before))
+      const positionAfter = await coreProgram.account.positionState.fetch(ownerPositionAState)
+      assert(positionAfter.tokensOwed0.gte(positionBefore.tokensOwed0))
+      assert(positionAfter.tokensOwed1.gte(positionBefore.tokensOwed1))
+    })
+  })
+
+  describe('#collect without poke', () => {
+    it('collects the fees of the swaps without a separate poke', async () => {
+      const q32 = new BN(1).shln(32)
+      const u64Modulo = new BN(1).shln(64)
+      const positionBefore = await coreProgram.account.positionState.fetch(ownerPositionAState)
+      const wallet0Before = (await token0.getAccountInfo(minterWallet0)).amount
+      const wallet1Before = (await token1.getAccountInfo(minterWallet1)).amount
+      const collectAccounts = {
+        owner,
+        poolState: poolAState,
+        tickLowerState: tickLowerAState,
+        tickUpperState: tickUpperAState,
+        positionState: ownerPositionAState,
+        vault0: vaultA0,
+        vault1: vaultA1,
+        recipientWallet0: minterWallet0,
+        recipientWallet1: minterWallet1,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      }
+
+      // callers read the collected amounts from the return data, as a CPI caller would
+      const { raw } = await coreProgram.simulate.collect(MaxU64, MaxU64, null, new BN(0), {
+        accounts: collectAccounts
+      })
+      const prefix = `Program return: ${coreProgram.programId.toBase58()} `
+      const returnLog = raw.find(log => log.startsWith(prefix))
+      const returnData = Buffer.from(returnLog.slice(prefix.length), 'base64')
+      const returned0 = new BN(returnData.subarray(0, 8), 'le')
+      const returned1 = new BN(returnData.subarray(8, 16), 'le')
+
+      await coreProgram.rpc.collect(MaxU64, MaxU64, null, new BN(0), {
+        accounts: collectAccounts
+      })
+
+      const positionAfter = await coreProgram.account.positionState.fetch(ownerPositionAState)
+      assert(positionAfter.tokensOwed0.eqn(0))
+      assert(positionAfter.tokensOwed1.eqn(0))
+
+      // fees accrued since the mint, from the fee growth realized by the collect itself
+      const expectedFees = (feeGrowthBefore: BN, feeGrowthAfter: BN, tokensOwed: BN) =>
+        feeGrowthAfter.sub(feeGrowthBefore).umod(u64Modulo)
+          .mul(positionBefore.liquidity).div(q32)
+          .add(tokensOwed)
+      const expected0 = expectedFees(
+        positionBefore.feeGrowthInside0LastX32,
+        positionAfter.feeGrowthInside0LastX32,
+        positionBefore.tokensOwed0
+      )
+      const expected1 = expectedFees(
+        positionBefore.feeGrowthInside1LastX32,
+        positionAfter.feeGrowthInside1LastX32,
+        positionBefore.tokensOwed1
+      )
+      assert(expected0.gtn(0) || expected1.gtn(0))
+
+      const wallet0After = (await token0.getAccountInfo(minterWallet0)).amount
+      const wallet1After = (await token1.getAccountInfo(minterWallet1)).amount
+      assert(wallet0After.sub(wallet0Before).eq(expected0))
+      assert(wallet1After.sub(wallet1Before).eq(expected1))
+      assert(returned0.eq(expected0))
+      assert(returned1.eq(expected1))
+    })
+
+    it('burns the wallet position so that its ticks can be closed', async () => {
+      const { observationIndex, observationCardinalityNext } = await coreProgram.account.poolState.fetch(poolAState)
+      lastObservationAState = (await PublicKey.findProgramAddress(
+        [
+          OBSERVATION_SEED,
+          token0.publicKey.toBuffer(),
+          token1.publicKey.toBuffer(),
+          u32ToSeed(fee),
+          u16ToSeed(observationIndex)
+        ],
+        coreProgram.programId
+      ))[0]
+      nextObservationAState = (await PublicKey.findProgramAddress(
+        [
+          OBSERVATION_SEED,
+          token0.publicKey.toBuffer(),
+          token1.publicKey.toBuffer(),
+          u32ToSeed(fee),
+          u16ToSeed((observationIndex + 1) % observationCardinalityNext)
+        ],
+        coreProgram.programId
+      ))[0]
+
+      const { liquidity } = await coreProgram.account.positionState.fetch(ownerPositionAState)
+      await coreProgram.rpc.burn(liquidity, new BN(0), {
+        accounts: {
+          owner,
+          poolState: poolAState,
+          tickLowerState: tickLowerAState,
+          tickUpperState: tickUpperAState,
+          bitmapLowerState: bitmapLowerAState,
+          bitmapUpperState: bitmapUpperAState,
+          positionState: ownerPositionAState,
+          ownerLiquidityState: ownerLiquidityAState,
+          lastObservationState: lastObservationAState,
+        },
+        remainingAccounts: [{
+          pubkey: nextObservationAState,
+          isSigner: false,
+          isWritable: true
+        }],
+      })
+      await coreProgram.rpc.collect(MaxU64, MaxU64, null, new BN(0), {
+        accounts: {
+          owner,
+          poolState: poolAState,
+          tickLowerState: tickLowerAState,
+          tickUpperState: tickUpperAState,
+          positionState: ownerPositionAState,
+          vault0: vaultA0,
+          vault1: vaultA1,
+          recipientWallet0: minterWallet0,
+          recipientWallet1: minterWallet1,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        }
+      })
+
+      const positionData = await coreProgram.account.positionState.fetch(ownerPositionAState)
+      assert(positionData.liquidity.eqn(0))
+    })
+  })
+
+  describe('#collect within the holding period', () => {
+    const setMinHoldingSlots = (minHoldingSlots: number) => coreProgram.rpc.setMinHoldingSlots(new BN(minHoldingSlots), {
+      accounts: {
+        owner,
+        factoryState,
+        poolState: poolAState,
+      }
+    })
+    const refreshObservations = async () => {
+      const { observationIndex, observationCardinalityNext } = await coreProgram.account.poolState.fetch(poolAState)
+      lastObservationAState = (await PublicKey.findProgramAddress(
+        [
+          OBSERVATION_SEED,
+          token0.publicKey.toBuffer(),
+          token1.publicKey.toBuffer(),
+          u32ToSeed(fee),
+          u16ToSeed(observationIndex)
+        ],
+        coreProgram.programId
+      ))[0]
+      nextObservationAState = (await PublicKey.findProgramAddress(
+        [
+          OBSERVATION_SEED,
+          token0.publicKey.toBuffer(),
+          token1.publicKey.toBuffer(),
+          u32ToSeed(fee),
+          u16ToSeed((observationIndex + 1) % observationCardinalityNext)
+        ],
+        coreProgram.programId
+      ))[0]
+    }
+    const collectAccounts = () => ({
+      owner,
+      poolState: poolAState,
+      tickLowerState: tickLowerAState,
+      tickUpperState: tickUpperAState,
+      positionState: ownerPositionAState,
+      vault0: vaultA0,
+      vault1: vaultA1,
+      recipientWallet0: minterWallet0,
+      recipientWallet1: minterWallet1,
+      tokenProgram: TOKEN_PROGRAM_ID,
+    })
+    const burnAccounts = () => ({
+      owner,
+      poolState: poolAState,
+      tickLowerState: tickLowerAState,
+      tickUpperState: tickUpperAState,
+      bitmapLowerState: bitmapLowerAState,
+      bitmapUpperState: bitmapUpperAState,
+      positionState: ownerPositionAState,
+      ownerLiquidityState: ownerLiquidityAState,
+      lastObservationState: lastObservationAState,
+    })
+
+    before(async () => {
+      // a holding period longer than the test, so that every step below happens within it
+      await setMinHoldingSlots(1_000_000)
+
+      await refreshObservations()
+      await coreProgram.rpc.mint(new BN(2_000_000_000), new BN(0), {
+        accounts: {
+          minter: owner,
+          tokenAccount0: minterWallet0,
+          tokenAccount1: minterWallet1,
+          vault0: vaultA0,
+          vault1: vaultA1,
+          recipient: owner,
+          poolState: poolAState,
+          tickLowerState: tickLowerAState,
+          tickUpperState: tickUpperAState,
+          bitmapLowerState: bitmapLowerAState,
+          bitmapUpperState: bitmapUpperAState,
+          positionState: ownerPositionAState,
+          ownerLiquidityState: ownerLiquidityAState,
+          lastObservationState: lastObservationAState,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          callbackHandler: coreProgram.programId,
+        },
+        remainingAccounts: [{
+          pubkey: nextObservationAState,
+          isSigner: false,
+          isWritable: true
+        }],
+      })
+      await refreshObservations()
+      const amountIn = new BN(300_000)
+      const [, , swapAccounts] = await uniPoolA.getOutputAmount(
+        CurrencyAmount.fromRawAmount(uniToken0, amountIn.toNumber())
+      )
+      await coreProgram.rpc.swap(amountIn, MIN_SQRT_RATIO.addn(1), null, false, {
+        accounts: {
+          signer: owner,
+          inputTokenAccount: minterWallet0,
+          outputTokenAccount: minterWallet1,
+          inputVault: vaultA0,
+          outputVault: vaultA1,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          factoryState,
+          poolState: poolAState,
+          lastObservationState: lastObservationAState,
+          callbackHandler: coreProgram.programId,
+        },
+        remainingAccounts: [
+          ...swapAccounts,
           {
-            pubkey: tickUpperAState,
-            isSigner: false,
-            isWritable: true
-          }, {
-            pubkey: tickLowerAState,
+            pubkey: nextObservationAState,
             isSigner: false,
             isWritable: true
-          }]
-        }
-      )).to.be.rejectedWith(Error)
+          },
+        ],
+      })
+      await refreshObservations()
     })
 
-    it('swap upto a limit price for a zero to one swap', async () => {
-      const amountIn = new BN(100_000)
-      const amountOutMinimum = new BN(0)
-      const sqrtPriceLimitX32 = new BN(4297115200) // current price is 4297115210
+    after(async () => {
+      await setMinHoldingSlots(0)
+    })
 
-      const tickDataProvider = new SolanaTickDataProvider(coreProgram, {
-        token0: token0.publicKey,
-        token1: token1.publicKey,
-        fee,
+    it('forfeits the fees of the swap instead of collecting them', async () => {
+      const wallet0Before = (await token0.getAccountInfo(minterWallet0)).amount
+      const wallet1Before = (await token1.getAccountInfo(minterWallet1)).amount
+
+      const { events } = await coreProgram.simulate.collect(MaxU64, MaxU64, null, new BN(0), {
+        accounts: collectAccounts()
       })
+      const forfeited = events.find(event => event.name == 'FeesForfeitedEvent').data
+      assert(forfeited.amount0.gtn(0) || forfeited.amount1.gtn(0))
 
-      const { tick: currentTick, sqrtPriceX32: currentSqrtPriceX32, liquidity: currentLiquidity } = await coreProgram.account.poolState.fetch(poolAState)
-      await tickDataProvider.eagerLoadCache(currentTick, tickSpacing)
-      // output is one tick behind actual (8 instead of 9)
-      uniPoolA = new Pool(
-        uniToken0,
-        uniToken1,
-        fee,
-        JSBI.BigInt(currentSqrtPriceX32),
-        JSBI.BigInt(currentLiquidity),
-        currentTick,
-        tickDataProvider
-      )
+      await coreProgram.rpc.collect(MaxU64, MaxU64, null, new BN(0), {
+        accounts: collectAccounts()
+      })
 
-      const [expectedAmountOut, expectedNewPool, bitmapAndTickAccounts] = await uniPoolA.getOutputAmount(
-        CurrencyAmount.fromRawAmount(uniToken0, amountIn.toNumber()),
-        JSBI.BigInt(sqrtPriceLimitX32),
-      )
-      assert.equal(expectedNewPool.sqrtRatioX32.toString(), sqrtPriceLimitX32.toString())
+      const wallet0After = (await token0.getAccountInfo(minterWallet0)).amount
+      const wallet1After = (await token1.getAccountInfo(minterWallet1)).amount
+      assert(wallet0After.eq(wallet0Before))
+      assert(wallet1After.eq(wallet1Before))
+    })
 
-      await coreProgram.rpc.exactInputSingle(
-        deadline,
-        amountIn,
-        amountOutMinimum,
-        sqrtPriceLimitX32,
-        {
-          accounts: {
-            signer: owner,
-            factoryState,
-            poolState: poolAState,
-            inputTokenAccount: minterWallet0,
-            outputTokenAccount: minterWallet1,
-            inputVault: vaultA0,
-            outputVault: vaultA1,
-            lastObservationState: lastObservationAState,
-            // nextObservationState: nextObservationAState,
-            coreProgram: coreProgram.programId,
-            tokenProgram: TOKEN_PROGRAM_ID,
-          },
-          remainingAccounts: [
-            ...bitmapAndTickAccounts,
-            {
-              pubkey: nextObservationAState,
-              isSigner: false,
-              isWritable: true
-            },
-          ],
-        }
-      )
-      let poolStateData = await coreProgram.account.poolState.fetch(poolAState)
-      assert(poolStateData.sqrtPriceX32.eq(sqrtPriceLimitX32))
+    it('returns only the principal on burn', async () => {
+      const { liquidity } = await coreProgram.account.positionState.fetch(ownerPositionAState)
+      const remainingAccounts = [{
+        pubkey: nextObservationAState,
+        isSigner: false,
+        isWritable: true
+      }]
+      const { events } = await coreProgram.simulate.burn(liquidity, new BN(0), {
+        accounts: burnAccounts(),
+        remainingAccounts,
+      })
+      const burned = events.find(event => event.name == 'BurnEvent').data
 
-      console.log('tick after swap', poolStateData.tick, 'price', poolStateData.sqrtPriceX32.toString())
-      uniPoolA = expectedNewPool
+      await coreProgram.rpc.burn(liquidity, new BN(0), {
+        accounts: burnAccounts(),
+        remainingAccounts,
+      })
+      const position = await coreProgram.account.positionState.fetch(ownerPositionAState)
+      assert(position.liquidity.eqn(0))
+      assert(position.tokensOwed0.eq(burned.amount0))
+      assert(position.tokensOwed1.eq(burned.amount1))
+
+      const wallet0Before = (await token0.getAccountInfo(minterWallet0)).amount
+      const wallet1Before = (await token1.getAccountInfo(minterWallet1)).amount
+      await coreProgram.rpc.collect(MaxU64, MaxU64, null, new BN(0), {
+        accounts: collectAccounts()
+      })
+      const wallet0After = (await token0.getAccountInfo(minterWallet0)).amount
+      const wallet1After = (await token1.getAccountInfo(minterWallet1)).amount
+      assert(wallet0After.sub(wallet0Before).eq(burned.amount0))
+      assert(wallet1After.sub(wallet1Before).eq(burned.amount1))
     })
+  })
 
-    it('performs a zero for one swap without a limit price', async () => {
-      let poolStateDataBefore = await coreProgram.account.poolState.fetch(poolAState)
-      console.log('pool price', poolStateDataBefore.sqrtPriceX32.toNumber())
-      console.log('pool tick', poolStateDataBefore.tick)
-
-      const {
-        observationIndex,
-        observationCardinalityNext
-      } = await coreProgram.account.poolState.fetch(poolAState)
+  describe('#transfer_position', () => {
+    const newOwner = new Keypair()
+    let newOwnerPositionAState: web3.PublicKey
+    let newOwnerLiquidityAState: web3.PublicKey
+    let newOwnerWallet0: web3.PublicKey
+    let newOwnerWallet1: web3.PublicKey
 
+    const refreshObservations = async () => {
+      const { observationIndex, observationCardinalityNext } = await coreProgram.account.poolState.fetch(poolAState)
       lastObservationAState = (await PublicKey.findProgramAddress(
         [
           OBSERVATION_SEED,
@@ -2782,7 +5947,6 @@ describe('cyclos-core', async () => {
         ],
         coreProgram.programId
       ))[0]
-
       nextObservationAState = (await PublicKey.findProgramAddress(
         [
           OBSERVATION_SEED,
@@ -2793,68 +5957,392 @@ describe('cyclos-core', async () => {
         ],
         coreProgram.programId
       ))[0]
+    }
+    const transferAccounts = (positionOwner: web3.PublicKey) => ({
+      owner: positionOwner,
+      newOwner: newOwner.publicKey,
+      poolState: poolAState,
+      tickLowerState: tickLowerAState,
+      tickUpperState: tickUpperAState,
+      positionState: ownerPositionAState,
+      newPositionState: newOwnerPositionAState,
+      ownerLiquidityState: ownerLiquidityAState,
+      newOwnerLiquidityState: newOwnerLiquidityAState,
+      systemProgram: SystemProgram.programId,
+    })
 
-      const amountIn = new BN(100_000)
-      const amountOutMinimum = new BN(0)
-      const sqrtPriceLimitX32 = new BN(0)
+    before(async () => {
+      newOwnerPositionAState = (await PublicKey.findProgramAddress([
+        POSITION_SEED,
+        token0.publicKey.toBuffer(),
+        token1.publicKey.toBuffer(),
+        u32ToSeed(fee),
+        newOwner.publicKey.toBuffer(),
+        i32ToSeed(tickLower),
+        i32ToSeed(tickUpper)
+      ],
+        coreProgram.programId
+      ))[0]
+      newOwnerWallet0 = await token0.createAccount(newOwner.publicKey)
+      newOwnerWallet1 = await token1.createAccount(newOwner.publicKey)
+      newOwnerLiquidityAState = (await PublicKey.findProgramAddress([
+        OWNER_LIQUIDITY_SEED,
+        token0.publicKey.toBuffer(),
+        token1.publicKey.toBuffer(),
+        u32ToSeed(fee),
+        newOwner.publicKey.toBuffer(),
+      ],
+        coreProgram.programId
+      ))[0]
+      await coreProgram.rpc.initOwnerLiquidityAccount({
+        accounts: {
+          signer: owner,
+          owner: newOwner.publicKey,
+          poolState: poolAState,
+          ownerLiquidityState: newOwnerLiquidityAState,
+          systemProgram: SystemProgram.programId,
+        }
+      })
 
-      console.log('pool tick', uniPoolA.tickCurrent, 'price', uniPoolA.sqrtRatioX32.toString())
-      const [expectedAmountOut, expectedNewPool, bitmapAndTickAccounts] = await uniPoolA.getOutputAmount(
+      // liquidity in the wallet position earns the fees of a swap
+      await refreshObservations()
+      await coreProgram.rpc.mint(new BN(2_000_000_000), new BN(0), {
+        accounts: {
+          minter: owner,
+          tokenAccount0: minterWallet0,
+          tokenAccount1: minterWallet1,
+          vault0: vaultA0,
+          vault1: vaultA1,
+          recipient: owner,
+          poolState: poolAState,
+          tickLowerState: tickLowerAState,
+          tickUpperState: tickUpperAState,
+          bitmapLowerState: bitmapLowerAState,
+          bitmapUpperState: bitmapUpperAState,
+          positionState: ownerPositionAState,
+          ownerLiquidityState: ownerLiquidityAState,
+          lastObservationState: lastObservationAState,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          callbackHandler: coreProgram.programId,
+        },
+        remainingAccounts: [{
+          pubkey: nextObservationAState,
+          isSigner: false,
+          isWritable: true
+        }],
+      })
+      await refreshObservations()
+      const amountIn = new BN(300_000)
+      const [, , swapAccounts] = await uniPoolA.getOutputAmount(
         CurrencyAmount.fromRawAmount(uniToken0, amountIn.toNumber())
       )
-      console.log('expected pool', expectedNewPool)
-
-      await coreProgram.rpc.exactInputSingle(
-        deadline,
-        // true,
-        amountIn,
-        amountOutMinimum,
-        sqrtPriceLimitX32,
-        {
-          accounts: {
-            signer: owner,
-            factoryState,
-            poolState: poolAState,
-            inputTokenAccount: minterWallet0,
-            outputTokenAccount: minterWallet1,
-            inputVault: vaultA0,
-            outputVault: vaultA1,
-            lastObservationState: lastObservationAState,
-            // nextObservationState: nextObservationAState,
-            coreProgram: coreProgram.programId,
-            tokenProgram: TOKEN_PROGRAM_ID,
+      await coreProgram.rpc.swap(amountIn, MIN_SQRT_RATIO.addn(1), null, false, {
+        accounts: {
+          signer: owner,
+          inputTokenAccount: minterWallet0,
+          outputTokenAccount: minterWallet1,
+          inputVault: vaultA0,
+          outputVault: vaultA1,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          factoryState,
+          poolState: poolAState,
+          lastObservationState: lastObservationAState,
+          callbackHandler: coreProgram.programId,
+        },
+        remainingAccounts: [
+          ...swapAccounts,
+          {
+            pubkey: nextObservationAState,
+            isSigner: false,
+            isWritable: true
           },
-          remainingAccounts: [
-            ...bitmapAndTickAccounts,
-            {
-              pubkey: nextObservationAState,
-              isSigner: false,
-              isWritable: true
-            },
-          ],
+        ],
+      })
+      await refreshObservations()
+
+      // credit the fees to the position before it is transferred
+      await coreProgram.rpc.pokePositions({
+        accounts: {
+          poolState: poolAState,
+        },
+        remainingAccounts: [
+          { pubkey: owner, isSigner: false, isWritable: false },
+          { pubkey: tickLowerAState, isSigner: false, isWritable: false },
+          { pubkey: tickUpperAState, isSigner: false, isWritable: false },
+          { pubkey: ownerPositionAState, isSigner: false, isWritable: true },
+        ],
+      })
+    })
+
+    it('fails if not signed by the position owner', async () => {
+      await expect(coreProgram.rpc.transferPosition({
+        accounts: transferAccounts(notOwner.publicKey),
+        signers: [notOwner],
+      })).to.be.rejectedWith(Error)
+    })
+
+    it('fails if the new owner would exceed the liquidity limit', async () => {
+      const { liquidity } = await coreProgram.account.positionState.fetch(ownerPositionAState)
+      const setMaxLiquidityPerOwner = (maxLiquidityPerOwner: BN) => coreProgram.rpc.setMaxLiquidityPerOwner(maxLiquidityPerOwner, {
+        accounts: {
+          owner,
+          factoryState,
+          poolState: poolAState,
         }
-      )
-      const poolStateDataAfter = await coreProgram.account.poolState.fetch(poolAState)
-      console.log('pool price after', poolStateDataAfter.sqrtPriceX32.toNumber())
-      console.log('pool tick after', poolStateDataAfter.tick)
+      })
+
+      await setMaxLiquidityPerOwner(liquidity.subn(1))
+      await expect(coreProgram.rpc.transferPosition({
+        accounts: transferAccounts(owner),
+      })).to.be.rejectedWith('Owner limit exceeded')
+      await setMaxLiquidityPerOwner(new BN(0))
+    })
+
+    it('moves a fee bearing position to the new owner', async () => {
+      const positionBefore = await coreProgram.account.positionState.fetch(ownerPositionAState)
+      assert(positionBefore.liquidity.gtn(0))
+      assert(positionBefore.tokensOwed0.gtn(0))
+      const ownerLiquidityBefore = await coreProgram.account.ownerLiquidityState.fetch(ownerLiquidityAState)
+
+      await coreProgram.rpc.transferPosition({
+        accounts: transferAccounts(owner),
+      })
+
+      // the liquidity is counted towards the new owner
+      const ownerLiquidityAfter = await coreProgram.account.ownerLiquidityState.fetch(ownerLiquidityAState)
+      assert(ownerLiquidityBefore.liquidity.sub(ownerLiquidityAfter.liquidity).eq(positionBefore.liquidity))
+      const newOwnerLiquidity = await coreProgram.account.ownerLiquidityState.fetch(newOwnerLiquidityAState)
+      assert(newOwnerLiquidity.liquidity.eq(positionBefore.liquidity))
+
+      const positionAfter = await coreProgram.account.positionState.fetch(newOwnerPositionAState)
+      assert(positionAfter.liquidity.eq(positionBefore.liquidity))
+      assert(positionAfter.feeGrowthInside0LastX32.eq(positionBefore.feeGrowthInside0LastX32))
+      assert(positionAfter.feeGrowthInside1LastX32.eq(positionBefore.feeGrowthInside1LastX32))
+      assert(positionAfter.tokensOwed0.eq(positionBefore.tokensOwed0))
+      assert(positionAfter.tokensOwed1.eq(positionBefore.tokensOwed1))
+
+      // the old position account is closed
+      assert.isNull(await connection.getAccountInfo(ownerPositionAState))
+    })
+
+    it('lets the new owner collect and burn the position', async () => {
+      const { liquidity, tokensOwed0, tokensOwed1 } = await coreProgram.account.positionState.fetch(newOwnerPositionAState)
+      const collectAccounts = {
+        owner: newOwner.publicKey,
+        poolState: poolAState,
+        tickLowerState: tickLowerAState,
+        tickUpperState: tickUpperAState,
+        positionState: newOwnerPositionAState,
+        vault0: vaultA0,
+        vault1: vaultA1,
+        recipientWallet0: newOwnerWallet0,
+        recipientWallet1: newOwnerWallet1,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      }
+      await coreProgram.rpc.collect(MaxU64, MaxU64, null, new BN(0), {
+        accounts: collectAccounts,
+        signers: [newOwner],
+      })
+      assert((await token0.getAccountInfo(newOwnerWallet0)).amount.eq(tokensOwed0))
+      assert((await token1.getAccountInfo(newOwnerWallet1)).amount.eq(tokensOwed1))
+
+      // burn so that the ticks can be closed
+      await coreProgram.rpc.burn(liquidity, new BN(0), {
+        accounts: {
+          owner: newOwner.publicKey,
+          poolState: poolAState,
+          tickLowerState: tickLowerAState,
+          tickUpperState: tickUpperAState,
+          bitmapLowerState: bitmapLowerAState,
+          bitmapUpperState: bitmapUpperAState,
+          positionState: newOwnerPositionAState,
+          ownerLiquidityState: newOwnerLiquidityAState,
+          lastObservationState: lastObservationAState,
+        },
+        remainingAccounts: [{
+          pubkey: nextObservationAState,
+          isSigner: false,
+          isWritable: true
+        }],
+        signers: [newOwner],
+      })
+      await coreProgram.rpc.collect(MaxU64, MaxU64, null, new BN(0), {
+        accounts: collectAccounts,
+        signers: [newOwner],
+      })
+      const positionData = await coreProgram.account.positionState.fetch(newOwnerPositionAState)
+      assert(positionData.liquidity.eqn(0))
+    })
+  })
+
+  describe('#protocol_fees', () => {
+    it('returns the uncollected protocol fees after swaps', async () => {
+      const { protocolFeesToken0, protocolFeesToken1 } = await coreProgram.account.poolState.fetch(poolAState)
+      assert(protocolFeesToken0.gtn(0) || protocolFeesToken1.gtn(0))
+
+      const { raw } = await coreProgram.simulate.protocolFees({
+        accounts: {
+          poolState: poolAState,
+        }
+      })
+      const prefix = `Program return: ${coreProgram.programId.toBase58()} `
+      const returnLog = raw.find(log => log.startsWith(prefix))
+      const returnData = Buffer.from(returnLog.slice(prefix.length), 'base64')
+
+      assert(new BN(returnData.subarray(0, 8), 'le').eq(protocolFeesToken0))
+      assert(new BN(returnData.subarray(8, 16), 'le').eq(protocolFeesToken1))
+    })
+  })
+
+  describe('#total_fees', () => {
+    it('returns the lifetime swap fees, including protocol fees', async () => {
+      const {
+        totalFeesToken0,
+        totalFeesToken1,
+        protocolFeesToken0,
+        protocolFeesToken1,
+      } = await coreProgram.account.poolState.fetch(poolAState)
+      assert(totalFeesToken0.gte(protocolFeesToken0))
+      assert(totalFeesToken1.gte(protocolFeesToken1))
+
+      const { raw } = await coreProgram.simulate.totalFees({
+        accounts: {
+          poolState: poolAState,
+        }
+      })
+      const prefix = `Program return: ${coreProgram.programId.toBase58()} `
+      const returnLog = raw.find(log => log.startsWith(prefix))
+      const returnData = Buffer.from(returnLog.slice(prefix.length), 'base64')
+
+      assert(new BN(returnData.subarray(0, 16), 'le').eq(totalFeesToken0))
+      assert(new BN(returnData.subarray(16, 32), 'le').eq(totalFeesToken1))
+    })
+  })
+
+  describe('#decrease_liquidity_and_collect', () => {
+    let recipientWallet0: web3.PublicKey
+    let recipientWallet1: web3.PublicKey
+    const accounts = () => ({
+      ownerOrDelegate: owner,
+      nftAccount: positionANftAccount,
+      tokenizedPositionState: tokenizedPositionAState,
+      factoryState,
+      poolState: poolAState,
+      corePositionState: corePositionAState,
+      ownerLiquidityState: factoryLiquidityAState,
+      tickLowerState: tickLowerAState,
+      tickUpperState: tickUpperAState,
+      bitmapLowerState: bitmapLowerAState,
+      bitmapUpperState: bitmapUpperAState,
+      lastObservationState: lastObservationAState,
+      vault0: vaultA0,
+      vault1: vaultA1,
+      recipientWallet0,
+      recipientWallet1,
+      coreProgram: coreProgram.programId,
+      tokenProgram: TOKEN_PROGRAM_ID,
+    })
+    const nextObservation = () => [{
+      pubkey: nextObservationAState,
+      isSigner: false,
+      isWritable: true
+    }]
+
+    before(async () => {
+      const { observationIndex, observationCardinalityNext } = await coreProgram.account.poolState.fetch(poolAState)
+      lastObservationAState = (await PublicKey.findProgramAddress(
+        [
+          OBSERVATION_SEED,
+          token0.publicKey.toBuffer(),
+          token1.publicKey.toBuffer(),
+          u32ToSeed(fee),
+          u16ToSeed(observationIndex)
+        ],
+        coreProgram.programId
+      ))[0]
+      nextObservationAState = (await PublicKey.findProgramAddress(
+        [
+          OBSERVATION_SEED,
+          token0.publicKey.toBuffer(),
+          token1.publicKey.toBuffer(),
+          u32ToSeed(fee),
+          u16ToSeed((observationIndex + 1) % observationCardinalityNext)
+        ],
+        coreProgram.programId
+      ))[0]
 
-      uniPoolA = expectedNewPool
+      recipientWallet0 = await token0.createAccount(notOwner.publicKey)
+      recipientWallet1 = await token1.createAccount(notOwner.publicKey)
     })
-  })
 
-  describe('#exact_input', () => {
+    it('fails if the withdrawn amounts are below the minimums', async () => {
+      const { liquidity } = await coreProgram.account.tokenizedPositionState.fetch(tokenizedPositionAState)
+      const deadline = new BN(Date.now() / 1000 + 10_000)
+      await expect(coreProgram.rpc.decreaseLiquidityAndCollect(
+        liquidity.divn(2),
+        MaxU64,
+        MaxU64,
+        deadline, {
+        accounts: accounts(),
+        remainingAccounts: nextObservation(),
+      })).to.be.rejectedWith(Error)
+    })
 
-    const deadline = new BN(Date.now() / 1000 + 10_000)
-    it('performs a single pool swap', async () => {
-      const poolStateDataBefore = await coreProgram.account.poolState.fetch(poolAState)
-      console.log('pool price', poolStateDataBefore.sqrtPriceX32.toNumber())
-      console.log('pool tick', poolStateDataBefore.tick)
+    it('returns the principal and fees in one transaction', async () => {
+      const {
+        liquidity,
+        tokensOwed0: owedBefore0,
+        tokensOwed1: owedBefore1,
+      } = await coreProgram.account.tokenizedPositionState.fetch(tokenizedPositionAState)
+      const deadline = new BN(Date.now() / 1000 + 10_000)
+
+      const { events } = await coreProgram.simulate.decreaseLiquidityAndCollect(
+        liquidity.divn(2),
+        new BN(0),
+        new BN(0),
+        deadline, {
+        accounts: accounts(),
+        remainingAccounts: nextObservation(),
+      })
+      const names = events.map(event => event.name)
+      assert(names.includes('BurnEvent'))
+      assert(names.includes('CollectEvent'))
+      const decrease = events.find(event => event.name == 'DecreaseLiquidityEvent').data
+      const principal0 = decrease.amount0 as BN
+      const principal1 = decrease.amount1 as BN
+
+      // the principal quoted by simulation is a valid minimum
+      await coreProgram.rpc.decreaseLiquidityAndCollect(
+        liquidity.divn(2),
+        principal0,
+        principal1,
+        deadline, {
+        accounts: accounts(),
+        remainingAccounts: nextObservation(),
+      })
+
+      const received0 = (await token0.getAccountInfo(recipientWallet0)).amount
+      const received1 = (await token1.getAccountInfo(recipientWallet1)).amount
+      // along with fees realized by the burn and those owed before
+      assert(received0.gte(principal0.add(owedBefore0)))
+      assert(received1.gte(principal1.add(owedBefore1)))
 
       const {
-        observationIndex,
-        observationCardinalityNext
-      } = await coreProgram.account.poolState.fetch(poolAState)
+        liquidity: liquidityAfter,
+        tokensOwed0,
+        tokensOwed1
+      } = await coreProgram.account.tokenizedPositionState.fetch(tokenizedPositionAState)
+      assert(liquidityAfter.eq(liquidity.sub(liquidity.divn(2))))
+      assert(tokensOwed0.eqn(0) && tokensOwed1.eqn(0))
+    })
+  })
 
+  describe('#collect_and_zap', () => {
+    let zapWallet0: web3.PublicKey
+    let zapWallet1: web3.PublicKey
+
+    const refreshObservations = async () => {
+      const { observationIndex, observationCardinalityNext } = await coreProgram.account.poolState.fetch(poolAState)
       lastObservationAState = (await PublicKey.findProgramAddress(
         [
           OBSERVATION_SEED,
@@ -2865,7 +6353,6 @@ describe('cyclos-core', async () => {
         ],
         coreProgram.programId
       ))[0]
-
       nextObservationAState = (await PublicKey.findProgramAddress(
         [
           OBSERVATION_SEED,
@@ -2876,365 +6363,629 @@ describe('cyclos-core', async () => {
         ],
         coreProgram.programId
       ))[0]
-
-      const amountIn = new BN(100_000)
-      const amountOutMinimum = new BN(0)
-      const [expectedAmountOut, expectedNewPool, swapAccounts] = await uniPoolA.getOutputAmount(
+    }
+    // swap accounts for a zero for one swap, followed by the next observation
+    const swapRemainingAccounts = async (amountIn: BN) => {
+      const [, , swapAccounts] = await uniPoolA.getOutputAmount(
         CurrencyAmount.fromRawAmount(uniToken0, amountIn.toNumber())
       )
-      console.log('expected pool', expectedNewPool)
-
-      await coreProgram.rpc.exactInput(
-        deadline,
-        amountIn,
-        amountOutMinimum,
-        Buffer.from([2]),
+      return [
+        ...swapAccounts,
         {
-          accounts: {
-            signer: owner,
-            factoryState,
-            inputTokenAccount: minterWallet0,
-            coreProgram: coreProgram.programId,
-            tokenProgram: TOKEN_PROGRAM_ID,
-          }, remainingAccounts: [{
-            pubkey: poolAState,
-            isSigner: false,
-            isWritable: true
-          }, {
-            pubkey: minterWallet1, // outputTokenAccount
-            isSigner: false,
-            isWritable: true
-          }, {
-            pubkey: vaultA0, // input vault
-            isSigner: false,
-            isWritable: true
-          }, {
-            pubkey: vaultA1, // output vault
-            isSigner: false,
-            isWritable: true
-          }, {
-            pubkey: lastObservationAState,
-            isSigner: false,
-            isWritable: true
-          },
-          ...swapAccounts,
-          {
-            pubkey: nextObservationAState,
-            isSigner: false,
-            isWritable: true
-          },
-          ]
-        }
-      )
-
-      const poolStateDataAfter = await coreProgram.account.poolState.fetch(poolAState)
-      console.log('pool price after', poolStateDataAfter.sqrtPriceX32.toNumber())
-      console.log('pool tick after', poolStateDataAfter.tick)
+          pubkey: nextObservationAState,
+          isSigner: false,
+          isWritable: true
+        },
+      ]
+    }
+    const zapAccounts = () => ({
+      ownerOrDelegate: owner,
+      nftAccount: positionANftAccount,
+      tokenizedPositionState: tokenizedPositionAState,
+      factoryState,
+      poolState: poolAState,
+      corePositionState: corePositionAState,
+      ownerLiquidityState: factoryLiquidityAState,
+      tickLowerState: tickLowerAState,
+      tickUpperState: tickUpperAState,
+      bitmapLowerState: bitmapLowerAState,
+      bitmapUpperState: bitmapUpperAState,
+      lastObservationState: lastObservationAState,
+      vault0: vaultA0,
+      vault1: vaultA1,
+      tokenAccount0: zapWallet0,
+      tokenAccount1: zapWallet1,
+      coreProgram: coreProgram.programId,
+      tokenProgram: TOKEN_PROGRAM_ID,
     })
 
-    it('creates a second liquidity pool', async () => {
-      await coreProgram.rpc.createAndInitPool(initialPriceX32, {
+    before(async () => {
+      zapWallet0 = await token0.createAccount(owner)
+      zapWallet1 = await token1.createAccount(owner)
+
+      // a zero for one swap accrues token_0 fees to the position
+      await refreshObservations()
+      const amountIn = new BN(100_000)
+      await coreProgram.rpc.swap(amountIn, MIN_SQRT_RATIO.addn(1), null, false, {
         accounts: {
-          poolCreator: owner,
-          token0: token1.publicKey,
-          token1: token2.publicKey,
-          feeState,
-          poolState: poolBState,
-          initialObservationState: initialObservationStateB,
-          systemProgram: SystemProgram.programId,
-          rent: web3.SYSVAR_RENT_PUBKEY,
-        }
+          signer: owner,
+          inputTokenAccount: minterWallet0,
+          outputTokenAccount: minterWallet1,
+          inputVault: vaultA0,
+          outputVault: vaultA1,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          factoryState,
+          poolState: poolAState,
+          lastObservationState: lastObservationAState,
+          callbackHandler: coreProgram.programId,
+        },
+        remainingAccounts: await swapRemainingAccounts(amountIn),
       })
-      console.log('second pool created')
+      await refreshObservations()
+    })
 
-      const {
-        observationIndex,
-        observationCardinalityNext
-      } = await coreProgram.account.poolState.fetch(poolBState)
+    it('fails if the swap receives less than the minimum', async () => {
+      await expect(coreProgram.rpc.collectAndZap(true, MaxU64, {
+        accounts: zapAccounts(),
+        remainingAccounts: await swapRemainingAccounts(new BN(1_000)),
+      })).to.be.rejectedWith(Error)
+    })
 
-      latestObservationBState = (await PublicKey.findProgramAddress(
+    it('swaps the collected token_0 fees for token_1', async () => {
+      const remainingAccounts = await swapRemainingAccounts(new BN(1_000))
+      const { events } = await coreProgram.simulate.collectAndZap(true, new BN(0), {
+        accounts: zapAccounts(),
+        remainingAccounts,
+      })
+      const collected = events.find(event => event.name == 'CollectTokenizedEvent').data
+      const swapEvent = events.find(event => event.name == 'SwapEvent').data
+      assert((collected.amount0 as BN).gtn(0))
+      assert((swapEvent.amount0 as BN).eq(collected.amount0))
+      const amountOut = (swapEvent.amount1 as BN).neg()
+
+      // the simulated output is a valid minimum
+      await coreProgram.rpc.collectAndZap(true, amountOut, {
+        accounts: zapAccounts(),
+        remainingAccounts,
+      })
+      assert((await token0.getAccountInfo(zapWallet0)).amount.eqn(0))
+      assert((await token1.getAccountInfo(zapWallet1)).amount.eq((collected.amount1 as BN).add(amountOut)))
+    })
+
+    it('skips the swap without fees of the input token', async () => {
+      // the zap swapped token_0 in, so that the position is owed token_0 fees only
+      await refreshObservations()
+      const balance0Before = (await token0.getAccountInfo(zapWallet0)).amount
+      const balance1Before = (await token1.getAccountInfo(zapWallet1)).amount
+
+      const { events } = await coreProgram.simulate.collectAndZap(false, MaxU64, {
+        accounts: zapAccounts(),
+        remainingAccounts: await swapRemainingAccounts(new BN(1_000)),
+      })
+      const collected = events.find(event => event.name == 'CollectTokenizedEvent').data
+      assert((collected.amount1 as BN).eqn(0))
+      assert(!events.some(event => event.name == 'SwapEvent'))
+
+      // no minimum applies without a swap
+      await coreProgram.rpc.collectAndZap(false, MaxU64, {
+        accounts: zapAccounts(),
+        remainingAccounts: await swapRemainingAccounts(new BN(1_000)),
+      })
+      assert((await token0.getAccountInfo(zapWallet0)).amount.eq(balance0Before.add(collected.amount0)))
+      assert((await token1.getAccountInfo(zapWallet1)).amount.eq(balance1Before))
+    })
+  })
+
+  describe('#rebalance', () => {
+    // an out of range position above the price, and a wide position around the price which
+    // also keeps liquidity in range for the rebalancing swaps
+    const liquidityAbove = new BN(10_000_000)
+    const liquidityWide = new BN(100_000_000)
+    let tickCurrent: number
+    let rangeAbove: { tickLowerState: web3.PublicKey, tickUpperState: web3.PublicKey, bitmapLowerState: web3.PublicKey, bitmapUpperState: web3.PublicKey, positionState: web3.PublicKey }
+    let rangeWide: typeof rangeAbove
+    let amount0Above: BN
+
+    const refreshObservations = async () => {
+      const { observationIndex, observationCardinalityNext } = await coreProgram.account.poolState.fetch(poolAState)
+      lastObservationAState = (await PublicKey.findProgramAddress(
         [
           OBSERVATION_SEED,
+          token0.publicKey.toBuffer(),
           token1.publicKey.toBuffer(),
-          token2.publicKey.toBuffer(),
           u32ToSeed(fee),
           u16ToSeed(observationIndex)
         ],
         coreProgram.programId
       ))[0]
-
-      nextObservationBState = (await PublicKey.findProgramAddress(
+      nextObservationAState = (await PublicKey.findProgramAddress(
         [
           OBSERVATION_SEED,
+          token0.publicKey.toBuffer(),
           token1.publicKey.toBuffer(),
-          token2.publicKey.toBuffer(),
           u32ToSeed(fee),
           u16ToSeed((observationIndex + 1) % observationCardinalityNext)
         ],
         coreProgram.programId
       ))[0]
-
-      // create tick and bitmap accounts
-      // can't combine with createTokenizedPosition due to size limit
-
-      const tx = new web3.Transaction()
-      tx.recentBlockhash = (await connection.getRecentBlockhash()).blockhash
-      tx.instructions = [
-        coreProgram.instruction.initTickAccount(tickLower, {
-          accounts: {
-            signer: owner,
-            poolState: poolBState,
-            tickState: tickLowerBState,
-            systemProgram: SystemProgram.programId,
-          }
-        }),
-        coreProgram.instruction.initTickAccount(tickUpper, {
+    }
+    const bitmapAccount = async (wordPos: number) => (await PublicKey.findProgramAddress([
+      BITMAP_SEED,
+      token0.publicKey.toBuffer(),
+      token1.publicKey.toBuffer(),
+      u32ToSeed(fee),
+      i16ToSeed(wordPos),
+    ],
+      coreProgram.programId
+    ))[0]
+    // creates the tick, bitmap and position accounts of an owner range, unless already created
+    const initRange = async (tickLower: number, tickUpper: number) => {
+      const ticks = await Promise.all([tickLower, tickUpper].map(async tick => {
+        const tickState = (await PublicKey.findProgramAddress([
+          TICK_SEED,
+          token0.publicKey.toBuffer(),
+          token1.publicKey.toBuffer(),
+          u32ToSeed(fee),
+          i32ToSeed(tick)
+        ],
+          coreProgram.programId
+        ))[0]
+        const wordPos = Math.floor(tick / tickSpacing) >> 8
+        return { tick, tickState, wordPos, bitmapState: await bitmapAccount(wordPos) }
+      }))
+      for (const { tick, tickState, wordPos, bitmapState } of ticks) {
+        if (await connection.getAccountInfo(tickState) == null) {
+          await coreProgram.rpc.initTickAccount(tick, {
+            accounts: {
+              signer: owner,
+              poolState: poolAState,
+              tickState,
+              systemProgram: SystemProgram.programId,
+            }
+          })
+        }
+        if (await connection.getAccountInfo(bitmapState) == null) {
+          await coreProgram.rpc.initBitmapAccount(wordPos, {
+            accounts: {
+              signer: owner,
+              poolState: poolAState,
+              bitmapState,
+              systemProgram: SystemProgram.programId,
+            }
+          })
+        }
+      }
+      const positionState = (await PublicKey.findProgramAddress([
+        POSITION_SEED,
+        token0.publicKey.toBuffer(),
+        token1.publicKey.toBuffer(),
+        u32ToSeed(fee),
+        owner.toBuffer(),
+        i32ToSeed(tickLower),
+        i32ToSeed(tickUpper)
+      ],
+        coreProgram.programId
+      ))[0]
+      if (await connection.getAccountInfo(positionState) == null) {
+        await coreProgram.rpc.initPositionAccount({
           accounts: {
             signer: owner,
-            poolState: poolBState,
-            tickState: tickUpperBState,
+            recipient: owner,
+            poolState: poolAState,
+            tickLowerState: ticks[0].tickState,
+            tickUpperState: ticks[1].tickState,
+            positionState,
             systemProgram: SystemProgram.programId,
           }
-        }),
-        coreProgram.instruction.initBitmapAccount(wordPosLower, {
+        })
+      }
+      return {
+        tickLowerState: ticks[0].tickState,
+        tickUpperState: ticks[1].tickState,
+        bitmapLowerState: ticks[0].bitmapState,
+        bitmapUpperState: ticks[1].bitmapState,
+        positionState,
+      }
+    }
+    const mint = async (range: typeof rangeAbove, liquidity: BN) => {
+      await refreshObservations()
+      await coreProgram.rpc.mint(liquidity, new BN(0), {
+        accounts: {
+          minter: owner,
+          tokenAccount0: minterWallet0,
+          tokenAccount1: minterWallet1,
+          vault0: vaultA0,
+          vault1: vaultA1,
+          recipient: owner,
+          poolState: poolAState,
+          ...range,
+          ownerLiquidityState: ownerLiquidityAState,
+          lastObservationState: lastObservationAState,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          callbackHandler: coreProgram.programId,
+        },
+        remainingAccounts: [{
+          pubkey: nextObservationAState,
+          isSigner: false,
+          isWritable: true
+        }],
+      })
+    }
+    const rebalance = async (from: typeof rangeAbove, to: typeof rangeAbove, liquidity: BN, zeroForOne: boolean) => {
+      await refreshObservations()
+      // the swap starts in the word of the current tick, and fills without crossing a tick
+      const compressed = Math.floor(tickCurrent / tickSpacing) + (zeroForOne ? 0 : 1)
+      const deadline = new BN(Date.now() / 1000 + 10_000)
+      return coreProgram.rpc.rebalance(liquidity, new BN(0), deadline, {
+        accounts: {
+          owner,
+          factoryState,
+          poolState: poolAState,
+          positionState: from.positionState,
+          tickLowerState: from.tickLowerState,
+          tickUpperState: from.tickUpperState,
+          bitmapLowerState: from.bitmapLowerState,
+          bitmapUpperState: from.bitmapUpperState,
+          newPositionState: to.positionState,
+          ownerLiquidityState: ownerLiquidityAState,
+          newTickLowerState: to.tickLowerState,
+          newTickUpperState: to.tickUpperState,
+          newBitmapLowerState: to.bitmapLowerState,
+          newBitmapUpperState: to.bitmapUpperState,
+          tokenAccount0: minterWallet0,
+          tokenAccount1: minterWallet1,
+          vault0: vaultA0,
+          vault1: vaultA1,
+          lastObservationState: lastObservationAState,
+          nextObservationState: nextObservationAState,
+          coreProgram: coreProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        },
+        remainingAccounts: [
+          {
+            pubkey: await bitmapAccount(compressed >> 8),
+            isSigner: false,
+            isWritable: false
+          },
+          {
+            pubkey: nextObservationAState,
+            isSigner: false,
+            isWritable: true
+          },
+        ],
+      })
+    }
+    const balances = async () => [
+      (await token0.getAccountInfo(minterWallet0)).amount,
+      (await token1.getAccountInfo(minterWallet1)).amount,
+    ]
+
+    before(async () => {
+      tickCurrent = (await coreProgram.account.poolState.fetch(poolAState)).tick
+      const spacedTick = Math.floor(tickCurrent / tickSpacing) * tickSpacing
+      rangeAbove = await initRange(spacedTick + tickSpacing, spacedTick + 2 * tickSpacing)
+      rangeWide = await initRange(spacedTick - 2 * tickSpacing, spacedTick + 3 * tickSpacing)
+
+      await mint(rangeWide, liquidityWide)
+      const [balance0Before] = await balances()
+      await mint(rangeAbove, liquidityAbove)
+      const [balance0After] = await balances()
+      amount0Above = balance0Before.sub(balance0After)
+    })
+
+    after(async () => {
+      // remove the liquidity left in both ranges
+      for (const range of [rangeAbove, rangeWide]) {
+        const { liquidity } = await coreProgram.account.positionState.fetch(range.positionState)
+        if (liquidity.eqn(0)) {
+          continue
+        }
+        await refreshObservations()
+        await coreProgram.rpc.burn(liquidity, new BN(0), {
           accounts: {
-            signer: owner,
-            poolState: poolBState,
-            bitmapState: bitmapLowerBState,
-            systemProgram: SystemProgram.programId,
-          }
-        }),
-        coreProgram.instruction.initPositionAccount({
+            owner,
+            poolState: poolAState,
+            ...range,
+            ownerLiquidityState: ownerLiquidityAState,
+            lastObservationState: lastObservationAState,
+          },
+          remainingAccounts: [{
+            pubkey: nextObservationAState,
+            isSigner: false,
+            isWritable: true
+          }],
+        })
+        await coreProgram.rpc.collect(MaxU64, MaxU64, null, new BN(0), {
           accounts: {
-            signer: owner,
-            recipient: factoryState,
-            poolState: poolBState,
-            tickLowerState: tickLowerBState,
-            tickUpperState: tickUpperBState,
-            positionState: corePositionBState,
-            systemProgram: SystemProgram.programId,
+            owner,
+            poolState: poolAState,
+            tickLowerState: range.tickLowerState,
+            tickUpperState: range.tickUpperState,
+            positionState: range.positionState,
+            vault0: vaultA0,
+            vault1: vaultA1,
+            recipientWallet0: minterWallet0,
+            recipientWallet1: minterWallet1,
+            tokenProgram: TOKEN_PROGRAM_ID,
           }
         })
-      ]
-      await anchor.getProvider().send(tx)
+      }
+      await refreshObservations()
+    })
 
-      console.log('creating tokenized position')
-      await coreProgram.rpc.mintTokenizedPosition(amount0Desired,
-        amount1Desired,
-        new BN(0),
-        new BN(0),
-        deadline, {
-        accounts: {
-          minter: owner,
-          recipient: owner,
-          factoryState,
-          nftMint: nftMintBKeypair.publicKey,
-          nftAccount: positionBNftAccount,
-          poolState: poolBState,
-          corePositionState: corePositionBState,
-          tickLowerState: tickLowerBState,
-          tickUpperState: tickUpperBState,
-          bitmapLowerState: bitmapLowerBState,
-          bitmapUpperState: bitmapUpperBState,
-          tokenAccount0: minterWallet1,
-          tokenAccount1: minterWallet2,
-          vault0: vaultB1,
-          vault1: vaultB2,
-          lastObservationState: latestObservationBState,
-          tokenizedPositionState: tokenizedPositionBState,
+    it('fails to rebalance no liquidity', async () => {
+      await expect(rebalance(rangeAbove, rangeWide, new BN(0), true)).to.be.rejectedWith('Burned liquidity should be greater than 0')
+    })
 
-          coreProgram: coreProgram.programId,
-          systemProgram: SystemProgram.programId,
-          rent: web3.SYSVAR_RENT_PUBKEY,
-          tokenProgram: TOKEN_PROGRAM_ID,
-          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID
-        },
-        remainingAccounts: [{
-          pubkey: nextObservationBState,
-          isSigner: false,
-          isWritable: true
-        }],
-        signers: [nftMintBKeypair],
-      })
+    it('recenters an out of range position around the price', async () => {
+      const [balance0Before, balance1Before] = await balances()
+      await rebalance(rangeAbove, rangeWide, liquidityAbove, true)
+      const [balance0After, balance1After] = await balances()
+
+      const positionAbove = await coreProgram.account.positionState.fetch(rangeAbove.positionState)
+      const positionWide = await coreProgram.account.positionState.fetch(rangeWide.positionState)
+      assert(positionAbove.liquidity.eqn(0))
+      assert(positionWide.liquidity.gt(liquidityWide))
+
+      // part of the token_0 withdrawn is swapped for token_1, and both are minted leaving dust
+      const leftover0 = balance0After.sub(balance0Before)
+      const leftover1 = balance1After.sub(balance1Before)
+      assert(leftover0.gten(0) && leftover1.gten(0))
+      assert(leftover0.add(leftover1).lt(amount0Above.divn(100)))
     })
 
-    it('perform a two pool swap', async () => {
-      const poolStateDataBefore = await coreProgram.account.poolState.fetch(poolAState)
-      console.log('pool price', poolStateDataBefore.sqrtPriceX32.toNumber())
-      console.log('pool tick', poolStateDataBefore.tick)
+    it('recenters single sided above the price with the required token only', async () => {
+      const [, balance1Before] = await balances()
+      const { liquidity } = await coreProgram.account.positionState.fetch(rangeWide.positionState)
+      await rebalance(rangeWide, rangeAbove, liquidity.divn(2), false)
+      const [, balance1After] = await balances()
 
-      const {
-        observationIndex: observationAIndex,
-        observationCardinalityNext: observationCardinalityANext
-      } = await coreProgram.account.poolState.fetch(poolAState)
+      // a range above the price holds only token_0, so all token_1 withdrawn is swapped
+      const positionAbove = await coreProgram.account.positionState.fetch(rangeAbove.positionState)
+      assert(positionAbove.liquidity.gtn(0))
+      assert(balance1After.eq(balance1Before))
+    })
+  })
 
+  describe('#collect_from_tokenized_batch', () => {
+    // three position NFTs over a range below zero, held by the owner like a vault
+    const [batchTickLower, batchTickUpper] = [-20, -10]
+    const batchNftMints = [new Keypair(), new Keypair(), new Keypair()]
+    const batchNftAccounts: web3.PublicKey[] = []
+    const batchTokenizedPositionStates: web3.PublicKey[] = []
+    let batchTickLowerState: web3.PublicKey
+    let batchTickUpperState: web3.PublicKey
+    let batchBitmapState: web3.PublicKey
+    let batchCorePositionState: web3.PublicKey
+    let distributionWallet0: web3.PublicKey
+    let distributionWallet1: web3.PublicKey
+
+    const positionAccounts = () => ({
+      factoryState,
+      poolState: poolAState,
+      corePositionState: batchCorePositionState,
+      ownerLiquidityState: factoryLiquidityAState,
+      tickLowerState: batchTickLowerState,
+      tickUpperState: batchTickUpperState,
+      bitmapLowerState: batchBitmapState,
+      bitmapUpperState: batchBitmapState,
+      lastObservationState: lastObservationAState,
+      coreProgram: coreProgram.programId,
+    })
+    const nextObservation = () => [{
+      pubkey: nextObservationAState,
+      isSigner: false,
+      isWritable: true
+    }]
+    const group = (index: number) => [
+      batchNftAccounts[index],
+      batchTokenizedPositionStates[index],
+      batchCorePositionState,
+      batchTickLowerState,
+      batchTickUpperState,
+      batchBitmapState,
+      batchBitmapState,
+    ].map(pubkey => ({ pubkey, isSigner: false, isWritable: true }))
+    const batchAccounts = {
+      ownerOrDelegate: owner,
+      factoryState,
+      poolState: poolAState,
+      ownerLiquidityState: factoryLiquidityAState,
+      lastObservationState: lastObservationAState,
+      vault0: vaultA0,
+      vault1: vaultA1,
+      recipientWallet0: distributionWallet0,
+      recipientWallet1: distributionWallet1,
+      coreProgram: coreProgram.programId,
+      tokenProgram: TOKEN_PROGRAM_ID,
+    }
+
+    before(async () => {
+      const { observationIndex, observationCardinalityNext } = await coreProgram.account.poolState.fetch(poolAState)
       lastObservationAState = (await PublicKey.findProgramAddress(
         [
           OBSERVATION_SEED,
           token0.publicKey.toBuffer(),
           token1.publicKey.toBuffer(),
           u32ToSeed(fee),
-          u16ToSeed(observationAIndex)
+          u16ToSeed(observationIndex)
         ],
         coreProgram.programId
       ))[0]
-
       nextObservationAState = (await PublicKey.findProgramAddress(
         [
           OBSERVATION_SEED,
           token0.publicKey.toBuffer(),
           token1.publicKey.toBuffer(),
           u32ToSeed(fee),
-          u16ToSeed((observationAIndex + 1) % observationCardinalityANext)
+          u16ToSeed((observationIndex + 1) % observationCardinalityNext)
         ],
         coreProgram.programId
       ))[0]
 
-      const {
-        observationIndex: observationBIndex,
-        observationCardinalityNext: observationCardinalityBNext
-      } = await coreProgram.account.poolState.fetch(poolBState)
+      const tickAccount = async (tick: number) => (await PublicKey.findProgramAddress([
+        TICK_SEED,
+        token0.publicKey.toBuffer(),
+        token1.publicKey.toBuffer(),
+        u32ToSeed(fee),
+        i32ToSeed(tick)
+      ],
+        coreProgram.programId
+      ))[0]
+      batchTickLowerState = await tickAccount(batchTickLower)
+      batchTickUpperState = await tickAccount(batchTickUpper)
 
-      latestObservationBState = (await PublicKey.findProgramAddress(
-        [
-          OBSERVATION_SEED,
-          token1.publicKey.toBuffer(),
-          token2.publicKey.toBuffer(),
-          u32ToSeed(fee),
-          u16ToSeed(observationBIndex)
-        ],
+      // both ticks lie in the word below zero
+      const wordPos = Math.floor(batchTickLower / tickSpacing) >> 8
+      batchBitmapState = (await PublicKey.findProgramAddress([
+        BITMAP_SEED,
+        token0.publicKey.toBuffer(),
+        token1.publicKey.toBuffer(),
+        u32ToSeed(fee),
+        i16ToSeed(wordPos),
+      ],
         coreProgram.programId
       ))[0]
+      if (await connection.getAccountInfo(batchBitmapState) == null) {
+        await coreProgram.rpc.initBitmapAccount(wordPos, {
+          accounts: {
+            signer: owner,
+            poolState: poolAState,
+            bitmapState: batchBitmapState,
+            systemProgram: SystemProgram.programId,
+          }
+        })
+      }
 
-      nextObservationBState = (await PublicKey.findProgramAddress(
-        [
-          OBSERVATION_SEED,
-          token1.publicKey.toBuffer(),
-          token2.publicKey.toBuffer(),
-          u32ToSeed(fee),
-          u16ToSeed((observationBIndex + 1) % observationCardinalityBNext)
-        ],
+      // created by #init_position_account
+      batchCorePositionState = (await PublicKey.findProgramAddress([
+        POSITION_SEED,
+        token0.publicKey.toBuffer(),
+        token1.publicKey.toBuffer(),
+        u32ToSeed(fee),
+        factoryState.toBuffer(),
+        i32ToSeed(batchTickLower),
+        i32ToSeed(batchTickUpper)
+      ],
         coreProgram.programId
       ))[0]
 
-      let vaultBalanceA0 = await token0.getAccountInfo(vaultA0)
-      let vaultBalanceA1 = await token1.getAccountInfo(vaultA1)
-      let vaultBalanceB1 = await token1.getAccountInfo(vaultB1)
-      let vaultBalanceB2 = await token2.getAccountInfo(vaultB2)
-      console.log(
-        'vault balances before',
-        vaultBalanceA0.amount.toNumber(),
-        vaultBalanceA1.amount.toNumber(),
-        vaultBalanceB1.amount.toNumber(),
-        vaultBalanceB2.amount.toNumber()
-      )
-      let token2AccountInfo = await token2.getAccountInfo(minterWallet2)
-      console.log('token 2 balance before', token2AccountInfo.amount.toNumber())
+      distributionWallet0 = await token0.createAccount(notOwner.publicKey)
+      distributionWallet1 = await token1.createAccount(notOwner.publicKey)
+      batchAccounts.lastObservationState = lastObservationAState
+      batchAccounts.recipientWallet0 = distributionWallet0
+      batchAccounts.recipientWallet1 = distributionWallet1
 
-      console.log('pool B address', poolBState.toString())
+      const deadline = new BN(Date.now() / 1000 + 10_000)
+      for (const nftMint of batchNftMints) {
+        const nftAccount = await Token.getAssociatedTokenAddress(
+          ASSOCIATED_TOKEN_PROGRAM_ID,
+          TOKEN_PROGRAM_ID,
+          nftMint.publicKey,
+          owner,
+        )
+        const tokenizedPositionState = (await PublicKey.findProgramAddress([
+          POSITION_SEED,
+          nftMint.publicKey.toBuffer()
+        ],
+          coreProgram.programId
+        ))[0]
+        batchNftAccounts.push(nftAccount)
+        batchTokenizedPositionStates.push(tokenizedPositionState)
 
-      const amountIn = new BN(100_000)
-      const amountOutMinimum = new BN(0)
-      await coreProgram.rpc.exactInput(
-        deadline,
-        amountIn,
-        amountOutMinimum,
-        Buffer.from([2, 2]),
-        {
+        await coreProgram.rpc.mintTokenizedPosition(new BN(1000), new BN(1000), new BN(0), new BN(0), deadline, {
           accounts: {
-            signer: owner,
-            factoryState,
-            inputTokenAccount: minterWallet0,
-            coreProgram: coreProgram.programId,
+            minter: owner,
+            recipient: owner,
+            nftMint: nftMint.publicKey,
+            nftAccount,
+            tokenAccount0: minterWallet0,
+            tokenAccount1: minterWallet1,
+            vault0: vaultA0,
+            vault1: vaultA1,
+            tokenizedPositionState,
+            systemProgram: SystemProgram.programId,
+            rent: web3.SYSVAR_RENT_PUBKEY,
             tokenProgram: TOKEN_PROGRAM_ID,
-          }, remainingAccounts: [{
-            pubkey: poolAState,
-            isSigner: false,
-            isWritable: true
-          },{
-            pubkey: minterWallet1, // outputTokenAccount
-            isSigner: false,
-            isWritable: true
-          },{
-            pubkey: vaultA0, // input vault
-            isSigner: false,
-            isWritable: true
-          },{
-            pubkey: vaultA1, // output vault
-            isSigner: false,
-            isWritable: true
-          },{
-            pubkey: lastObservationAState,
-            isSigner: false,
-            isWritable: true
-          },
-          {
-            pubkey: bitmapLowerAState,
-            isSigner: false,
-            isWritable: true
-          },
-          {
-            pubkey: nextObservationAState,
-            isSigner: false,
-            isWritable: true
-          },
-          // second pool
-          {
-            pubkey: poolBState,
-            isSigner: false,
-            isWritable: true
-          },{
-            pubkey: minterWallet2, // outputTokenAccount
-            isSigner: false,
-            isWritable: true
-          },{
-            pubkey: vaultB1, // input vault
-            isSigner: false,
-            isWritable: true
-          },{
-            pubkey: vaultB2, // output vault
-            isSigner: false,
-            isWritable: true
-          },{
-            pubkey: latestObservationBState,
-            isSigner: false,
-            isWritable: true
-          },
-            {
-            pubkey: bitmapLowerBState,
-            isSigner: false,
-            isWritable: true
-          }, {
-            pubkey: tickUpperBState,
-            isSigner: false,
-            isWritable: true
+            associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+            ...positionAccounts(),
           },
-          {
-            pubkey: nextObservationBState,
-            isSigner: false,
-            isWritable: true
+          remainingAccounts: nextObservation(),
+          signers: [nftMint],
+        })
+      }
+    })
+
+    it('fails for a position of another pool', async () => {
+      const positionBGroup = [
+        positionBNftAccount,
+        tokenizedPositionBState,
+        corePositionBState,
+        tickLowerBState,
+        tickUpperBState,
+        bitmapLowerBState,
+        bitmapUpperBState,
+      ].map(pubkey => ({ pubkey, isSigner: false, isWritable: true }))
+      await expect(coreProgram.rpc.collectFromTokenizedBatch({
+        accounts: batchAccounts,
+        remainingAccounts: [...group(0), ...positionBGroup],
+      })).to.be.rejectedWith(Error)
+    })
+
+    it('fails for an incomplete group of accounts', async () => {
+      await expect(coreProgram.rpc.collectFromTokenizedBatch({
+        accounts: batchAccounts,
+        remainingAccounts: group(0).slice(0, 6),
+      })).to.be.rejectedWith(Error)
+    })
+
+    it('collects across three tokenized positions to the distribution accounts', async () => {
+      // withdraw the liquidity of every position, so that each is owed its principal
+      const deadline = new BN(Date.now() / 1000 + 10_000)
+      let owed0 = new BN(0)
+      let owed1 = new BN(0)
+      for (const [index, tokenizedPositionState] of batchTokenizedPositionStates.entries()) {
+        const { liquidity } = await coreProgram.account.tokenizedPositionState.fetch(tokenizedPositionState)
+        await coreProgram.rpc.decreaseLiquidity(liquidity, new BN(0), new BN(0), deadline, {
+          accounts: {
+            ownerOrDelegate: owner,
+            nftAccount: batchNftAccounts[index],
+            tokenizedPositionState,
+            ...positionAccounts(),
           },
-        ]
-        }
-      )
-      vaultBalanceA0 = await token0.getAccountInfo(vaultA0)
-      vaultBalanceA1 = await token1.getAccountInfo(vaultA1)
-      vaultBalanceB1 = await token1.getAccountInfo(vaultB1)
-      vaultBalanceB2 = await token2.getAccountInfo(vaultB2)
-      console.log(
-        'vault balances after',
-        vaultBalanceA0.amount.toNumber(),
-        vaultBalanceA1.amount.toNumber(),
-        vaultBalanceB1.amount.toNumber(),
-        vaultBalanceB2.amount.toNumber()
+          remainingAccounts: nextObservation(),
+        })
+        const { tokensOwed0, tokensOwed1 } = await coreProgram.account.tokenizedPositionState.fetch(tokenizedPositionState)
+        owed0 = owed0.add(tokensOwed0)
+        owed1 = owed1.add(tokensOwed1)
+      }
+      assert(owed0.gtn(0) || owed1.gtn(0))
+
+      const remainingAccounts = [0, 1, 2].flatMap(group)
+      const { events } = await coreProgram.simulate.collectFromTokenizedBatch({
+        accounts: batchAccounts,
+        remainingAccounts,
+      })
+      const collectEvents = events.filter(event => event.name == 'CollectEvent')
+      assert.equal(collectEvents.length, 3)
+      const tokenizedEvents = events.filter(event => event.name == 'CollectTokenizedEvent')
+      assert.deepEqual(
+        tokenizedEvents.map(event => (event.data.tokenId as web3.PublicKey).toBase58()),
+        batchNftMints.map(nftMint => nftMint.publicKey.toBase58())
       )
 
-      const poolStateDataAfter = await coreProgram.account.poolState.fetch(poolAState)
-      console.log('pool A price after', poolStateDataAfter.sqrtPriceX32.toNumber())
-      console.log('pool A tick after', poolStateDataAfter.tick)
+      await coreProgram.rpc.collectFromTokenizedBatch({
+        accounts: batchAccounts,
+        remainingAccounts,
+      })
 
-      token2AccountInfo = await token2.getAccountInfo(minterWallet2)
-      console.log('token 2 balance after', token2AccountInfo.amount.toNumber())
+      // the owed tokens of all positions are transferred in aggregate
+      assert((await token0.getAccountInfo(distributionWallet0)).amount.eq(owed0))
+      assert((await token1.getAccountInfo(distributionWallet1)).amount.eq(owed1))
+      for (const tokenizedPositionState of batchTokenizedPositionStates) {
+        const { tokensOwed0, tokensOwed1 } = await coreProgram.account.tokenizedPositionState.fetch(tokenizedPositionState)
+        assert(tokensOwed0.eqn(0) && tokensOwed1.eqn(0))
+      }
     })
   })
 
@@ -3299,6 +7050,7 @@ describe('cyclos-core', async () => {
             factoryState,
             poolState: poolAState,
             corePositionState: corePositionAState,
+            ownerLiquidityState: factoryLiquidityAState,
             tickLowerState: tickLowerAState,
             tickUpperState: tickUpperAState,
             bitmapLowerState: bitmapLowerAState,
@@ -7,3 +7,21 @@ export const MIN_TICK = -221818
 export const MAX_TICK = 221818
 
 export const MaxU64 = new BN(2).pow(new BN(64)).subn(1)
+
+// Seed of the account tracking an owner's liquidity in a pool, as OWNER_LIQUIDITY_SEED in the program
+export const OWNER_LIQUIDITY_SEED = Buffer.from('ol')
+
+// Encodes a tick for PDA seeds like `tick_seed` in the program, as big endian two's complement.
+// Ticks below zero are not offset, e.g. -1 is ff ff ff ff
+export function i32ToSeed(tick: number): Buffer {
+  const seed = Buffer.alloc(4)
+  seed.writeInt32BE(tick)
+  return seed
+}
+
+// Encodes a bitmap word position for PDA seeds, as big endian two's complement
+export function i16ToSeed(wordPos: number): Buffer {
+  const seed = Buffer.alloc(2)
+  seed.writeInt16BE(wordPos)
+  return seed
+}